@@ -0,0 +1,447 @@
+//!
+//! Evaluation of the integer arithmetic expressions, used by `$(( ))`, `(( ))` and `for ((i = 0; i < 10; i++))`.
+//!
+//! The operators and their priorities are the ones of C, on 64 bits signed integers. The parameters (`$name`)
+//! are expanded before the evaluation, the variables can also be referenced by their name alone.
+//!
+
+use crate::shell::{ReadonlyVariableError, Shell};
+
+/// Evaluates the expression, the variables it assigns are modified in the shell state.
+/// An empty expression gives 0
+pub fn evaluate(expression: &str, shell: &mut Shell) -> Result<i64, ArithmeticError> {
+
+    let tokens = tokenize(expression)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+
+    let mut parser = ExpressionParser { tokens, position: 0 };
+    let ast = parser.parse_expression(0)?;
+    if let Some(token) = parser.tokens.get(parser.position) {
+        return Err(ArithmeticError::SyntaxError(format!("unexpected `{}`", token.text())));
+    }
+
+    ast.evaluate(shell)
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum ExpressionToken {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+    LeftParen,
+    RightParen,
+}
+
+impl ExpressionToken {
+
+    fn text(&self) -> String {
+        match self {
+            ExpressionToken::Number(n) => n.to_string(),
+            ExpressionToken::Name(name) => name.clone(),
+            ExpressionToken::Operator(op) => op.to_string(),
+            ExpressionToken::LeftParen => String::from("("),
+            ExpressionToken::RightParen => String::from(")"),
+        }
+    }
+}
+
+/// The operators, the longest ones first so that they are matched before their prefixes
+const OPERATORS: [&str; 37] = [
+    "<<=", ">>=",
+    "**", "++", "--", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+    "+", "-", "*", "/", "%", "<", ">", "!", "~", "=", "&", "|", "^", "?", ":", ",",
+];
+
+fn tokenize(expression: &str) -> Result<Vec<ExpressionToken>, ArithmeticError> {
+
+    let mut tokens = Vec::new();
+    // The position is a byte offset, the characters of the numbers, names and operators being ASCII
+    let mut position = 0;
+
+    while let Some(c) = expression[position..].chars().next() {
+        let rest = &expression[position..];
+
+        if c.is_whitespace() {
+            position += c.len_utf8();
+        } else if c.is_ascii_digit() {
+            let length = rest.bytes().take_while(|c| c.is_ascii_alphanumeric() || *c == b'#').count();
+            let literal = &rest[..length];
+            let number = parse_number(literal).ok_or(ArithmeticError::InvalidNumber(literal.to_string()))?;
+            tokens.push(ExpressionToken::Number(number));
+            position += length;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let length = rest.bytes().take_while(|c| c.is_ascii_alphanumeric() || *c == b'_').count();
+            tokens.push(ExpressionToken::Name(rest[..length].to_string()));
+            position += length;
+        } else if c == '(' {
+            tokens.push(ExpressionToken::LeftParen);
+            position += 1;
+        } else if c == ')' {
+            tokens.push(ExpressionToken::RightParen);
+            position += 1;
+        } else {
+            let operator = OPERATORS.iter().find(|op| rest.starts_with(**op))
+                .ok_or(ArithmeticError::SyntaxError(format!("unexpected `{c}`")))?;
+            tokens.push(ExpressionToken::Operator(operator));
+            position += operator.len();
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parses an integer constant : decimal, octal with a leading `0`, hexadecimal with `0x`, or `base#digits`
+/// with a base from 2 to 36
+fn parse_number(literal: &str) -> Option<i64> {
+
+    let (digits, radix) = if let Some((base, digits)) = literal.split_once('#') {
+        (digits, base.parse().ok().filter(|base| (2..=36).contains(base))?)
+    } else if let Some(digits) = literal.strip_prefix("0x").or_else(|| literal.strip_prefix("0X")) {
+        (digits, 16)
+    } else if literal.len() > 1 && literal.starts_with('0') {
+        (&literal[1..], 8)
+    } else {
+        (literal, 10)
+    };
+
+    // from_str_radix accepts a sign, which is an operator here
+    if digits.is_empty() || digits.starts_with(['+', '-']) {
+        return None;
+    }
+    i64::from_str_radix(digits, radix).ok()
+}
+
+/// The abstract syntax tree of an arithmetic expression
+#[derive(Debug)]
+enum Expression {
+    Number(i64),
+    Variable(String),
+    Unary(&'static str, Box<Expression>),
+    Binary(&'static str, Box<Expression>, Box<Expression>),
+    /// `name = value`, or `name op= value` when the operator is given
+    Assignment(String, Option<&'static str>, Box<Expression>),
+    /// `++name` or `--name` when `prefix` is true, `name++` or `name--` else
+    Increment { name: String, delta: i64, prefix: bool },
+    /// `condition ? value : other_value`
+    Conditional(Box<Expression>, Box<Expression>, Box<Expression>),
+}
+
+/// The priority of the assignment operators, which are right associative
+const ASSIGNMENT_PRIORITY: u8 = 2;
+/// The priority of the `?` of the conditional operator, which is right associative
+const CONDITIONAL_PRIORITY: u8 = 3;
+/// The priority of `**`, the only right associative binary operator
+const POWER_PRIORITY: u8 = 14;
+
+/// Returns the priority of a binary operator, the higher it is the tighter the operator binds
+fn binary_priority(operator: &str) -> Option<u8> {
+    match operator {
+        "," => Some(1),
+        "=" | "+=" | "-=" | "*=" | "/=" | "%=" | "<<=" | ">>=" | "&=" | "|=" | "^=" => Some(ASSIGNMENT_PRIORITY),
+        "?" => Some(CONDITIONAL_PRIORITY),
+        "||" => Some(4),
+        "&&" => Some(5),
+        "|" => Some(6),
+        "^" => Some(7),
+        "&" => Some(8),
+        "==" | "!=" => Some(9),
+        "<" | "<=" | ">" | ">=" => Some(10),
+        "<<" | ">>" => Some(11),
+        "+" | "-" => Some(12),
+        "*" | "/" | "%" => Some(13),
+        "**" => Some(POWER_PRIORITY),
+        _ => None,
+    }
+}
+
+/// Precedence climbing parser of the arithmetic expressions
+struct ExpressionParser {
+    tokens: Vec<ExpressionToken>,
+    position: usize,
+}
+
+impl ExpressionParser {
+
+    fn next(&mut self) -> Option<ExpressionToken> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    /// Parses the binary operations whose operators have a priority of at least `min_priority`
+    fn parse_expression(&mut self, min_priority: u8) -> Result<Expression, ArithmeticError> {
+
+        let mut left = self.parse_unary()?;
+
+        while let Some(ExpressionToken::Operator(operator)) = self.tokens.get(self.position).cloned() {
+            let Some(priority) = binary_priority(operator) else { break };
+            if priority < min_priority {
+                break;
+            }
+            self.position += 1;
+
+            left = match priority {
+                ASSIGNMENT_PRIORITY => {
+                    // Assignments are right associative: a = b = 1
+                    let Expression::Variable(name) = left else {
+                        return Err(ArithmeticError::SyntaxError(format!("attempted assignment to non-variable with `{operator}`")));
+                    };
+                    let value = Box::new(self.parse_expression(priority)?);
+                    let operation = operator.strip_suffix('=').filter(|op| !op.is_empty());
+                    Expression::Assignment(name, operation.map(binary_operator), value)
+                },
+                CONDITIONAL_PRIORITY => {
+                    let value = Box::new(self.parse_expression(1)?);
+                    if self.next() != Some(ExpressionToken::Operator(":")) {
+                        return Err(ArithmeticError::SyntaxError(String::from("expected `:` after `?`")));
+                    }
+                    // a ? b : c ? d : e is a ? b : (c ? d : e)
+                    let other_value = Box::new(self.parse_expression(priority)?);
+                    Expression::Conditional(Box::new(left), value, other_value)
+                },
+                POWER_PRIORITY => Expression::Binary(operator, Box::new(left), Box::new(self.parse_expression(priority)?)),
+                _ => Expression::Binary(operator, Box::new(left), Box::new(self.parse_expression(priority + 1)?)),
+            };
+        }
+
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, ArithmeticError> {
+
+        match self.next() {
+            Some(ExpressionToken::Number(n)) => Ok(Expression::Number(n)),
+            Some(ExpressionToken::Name(name)) => {
+                // postfix increment or decrement
+                match self.tokens.get(self.position) {
+                    Some(ExpressionToken::Operator(op @ ("++" | "--"))) => {
+                        let delta = if *op == "++" { 1 } else { -1 };
+                        self.position += 1;
+                        Ok(Expression::Increment { name, delta, prefix: false })
+                    },
+                    _ => Ok(Expression::Variable(name)),
+                }
+            },
+            Some(ExpressionToken::Operator(op @ ("++" | "--"))) => {
+                let Some(ExpressionToken::Name(name)) = self.next() else {
+                    return Err(ArithmeticError::SyntaxError(format!("`{op}` expects a variable")));
+                };
+                Ok(Expression::Increment { name, delta: if op == "++" { 1 } else { -1 }, prefix: true })
+            },
+            Some(ExpressionToken::Operator(op @ ("+" | "-" | "!" | "~"))) => {
+                Ok(Expression::Unary(op, Box::new(self.parse_unary()?)))
+            },
+            Some(ExpressionToken::LeftParen) => {
+                let expression = self.parse_expression(0)?;
+                match self.next() {
+                    Some(ExpressionToken::RightParen) => Ok(expression),
+                    _ => Err(ArithmeticError::SyntaxError(String::from("expected `)`"))),
+                }
+            },
+            Some(token) => Err(ArithmeticError::SyntaxError(format!("unexpected `{}`", token.text()))),
+            None => Err(ArithmeticError::SyntaxError(String::from("expected an operand"))),
+        }
+    }
+}
+
+/// Returns the static binary operator matching `operator`, used for the compound assignments like `+=`
+fn binary_operator(operator: &str) -> &'static str {
+    OPERATORS.iter().find(|op| **op == operator).copied().unwrap_or("+")
+}
+
+impl Expression {
+
+    fn evaluate(&self, shell: &mut Shell) -> Result<i64, ArithmeticError> {
+
+        match self {
+            Expression::Number(n) => Ok(*n),
+            Expression::Variable(name) => Ok(get_variable_value(name, shell)),
+            Expression::Unary(operator, operand) => {
+                let value = operand.evaluate(shell)?;
+                Ok(match *operator {
+                    "-" => value.checked_neg().ok_or(ArithmeticError::Overflow)?,
+                    "!" => (value == 0) as i64,
+                    "~" => !value,
+                    _ => value,
+                })
+            },
+            // && and || only evaluate their right operand if needed
+            Expression::Binary("&&", left, right) => {
+                Ok((left.evaluate(shell)? != 0 && right.evaluate(shell)? != 0) as i64)
+            },
+            Expression::Binary("||", left, right) => {
+                Ok((left.evaluate(shell)? != 0 || right.evaluate(shell)? != 0) as i64)
+            },
+            Expression::Binary(",", left, right) => {
+                left.evaluate(shell)?;
+                right.evaluate(shell)
+            },
+            Expression::Conditional(condition, value, other_value) => {
+                match condition.evaluate(shell)? {
+                    0 => other_value.evaluate(shell),
+                    _ => value.evaluate(shell),
+                }
+            },
+            Expression::Binary(operator, left, right) => {
+                let left = left.evaluate(shell)?;
+                let right = right.evaluate(shell)?;
+                apply_binary_operator(operator, left, right)
+            },
+            Expression::Assignment(name, operation, value) => {
+                let mut value = value.evaluate(shell)?;
+                if let Some(operator) = operation {
+                    value = apply_binary_operator(operator, get_variable_value(name, shell), value)?;
+                }
+                shell.assign_variable(name, &value.to_string())?;
+                Ok(value)
+            },
+            Expression::Increment { name, delta, prefix } => {
+                let old_value = get_variable_value(name, shell);
+                let new_value = old_value.checked_add(*delta).ok_or(ArithmeticError::Overflow)?;
+                shell.assign_variable(name, &new_value.to_string())?;
+                Ok(if *prefix { new_value } else { old_value })
+            },
+        }
+    }
+}
+
+fn apply_binary_operator(operator: &str, left: i64, right: i64) -> Result<i64, ArithmeticError> {
+
+    // The operations returning None overflow
+    let checked = |result: Option<i64>| result.ok_or(ArithmeticError::Overflow);
+    // A shift count out of the 0..64 range gives None
+    let shift_count = |count: i64| u32::try_from(count).ok().filter(|count| *count < i64::BITS);
+
+    Ok(match operator {
+        "+" => checked(left.checked_add(right))?,
+        "-" => checked(left.checked_sub(right))?,
+        "*" => checked(left.checked_mul(right))?,
+        "/" | "%" if right == 0 => return Err(ArithmeticError::DivisionByZero),
+        "/" => checked(left.checked_div(right))?,
+        "%" => checked(left.checked_rem(right))?,
+        "**" if right < 0 => return Err(ArithmeticError::NegativeExponent),
+        "**" => checked(u32::try_from(right).ok().and_then(|right| left.checked_pow(right)))?,
+        "<<" => checked(shift_count(right).and_then(|right| left.checked_shl(right)))?,
+        ">>" => checked(shift_count(right).and_then(|right| left.checked_shr(right)))?,
+        "&" => left & right,
+        "|" => left | right,
+        "^" => left ^ right,
+        "<" => (left < right) as i64,
+        "<=" => (left <= right) as i64,
+        ">" => (left > right) as i64,
+        ">=" => (left >= right) as i64,
+        "==" => (left == right) as i64,
+        "!=" => (left != right) as i64,
+        _ => return Err(ArithmeticError::SyntaxError(format!("unknown operator `{operator}`"))),
+    })
+}
+
+/// Returns the integer value of a variable, an unset or non numeric variable is 0
+fn get_variable_value(name: &str, shell: &Shell) -> i64 {
+    shell.get_variable(name).and_then(|value| parse_number(value.trim())).unwrap_or(0)
+}
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum ArithmeticError {
+
+    #[error("syntax error: {0}")]
+    SyntaxError(String),
+
+    #[error("invalid number: {0}")]
+    InvalidNumber(String),
+
+    #[error("division by 0")]
+    DivisionByZero,
+
+    #[error("exponent less than 0")]
+    NegativeExponent,
+
+    #[error("integer overflow")]
+    Overflow,
+
+    #[error(transparent)]
+    Readonly(#[from] ReadonlyVariableError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priorities() {
+        let mut shell = Shell::new();
+        assert_eq!(Ok(7), evaluate("1 + 2 * 3", &mut shell));
+        assert_eq!(Ok(9), evaluate("(1 + 2) * 3", &mut shell));
+        assert_eq!(Ok(1), evaluate("1 < 2 && 2 <= 2 || 0", &mut shell));
+        assert_eq!(Ok(-1), evaluate("-(3 - 2)", &mut shell));
+    }
+
+    #[test]
+    fn test_assignments_and_increments() {
+        let mut shell = Shell::new();
+        assert_eq!(Ok(5), evaluate("i = 5", &mut shell));
+        assert_eq!(Ok(5), evaluate("i++", &mut shell));
+        assert_eq!(Ok(7), evaluate("++i", &mut shell));
+        assert_eq!(Ok(14), evaluate("i *= 2", &mut shell));
+        assert_eq!(Some("14"), shell.get_variable("i"));
+
+        shell.make_readonly("i");
+        assert_eq!(Err(ArithmeticError::Readonly(ReadonlyVariableError("i".to_string()))), evaluate("i--", &mut shell));
+        assert_eq!(Some("14"), shell.get_variable("i"));
+    }
+
+    #[test]
+    fn test_bitwise_and_power_operators() {
+        let mut shell = Shell::new();
+        assert_eq!(Ok(6), evaluate("3 << 1 | 4 & 5 ^ 4", &mut shell));
+        assert_eq!(Ok(-8), evaluate("~7", &mut shell));
+        assert_eq!(Ok(512), evaluate("2 ** 3 ** 2", &mut shell));
+        assert_eq!(Ok(-8), evaluate("-2 ** 3", &mut shell));
+        assert_eq!(Ok(3), evaluate("x = 12, x >>= 2", &mut shell));
+    }
+
+    #[test]
+    fn test_conditional_operator() {
+        let mut shell = Shell::new();
+        assert_eq!(Ok(2), evaluate("0 ? 1 : 5 > 3 ? 2 : 3", &mut shell));
+        // Only the selected branch is evaluated
+        assert_eq!(Ok(1), evaluate("1 ? 1 : (y = 9)", &mut shell));
+        assert_eq!(None, shell.get_variable("y"));
+        assert!(matches!(evaluate("1 ? 2", &mut shell), Err(ArithmeticError::SyntaxError(_))));
+    }
+
+    #[test]
+    fn test_number_bases() {
+        let mut shell = Shell::new();
+        assert_eq!(Ok(255), evaluate("0xff", &mut shell));
+        assert_eq!(Ok(8), evaluate("010", &mut shell));
+        assert_eq!(Ok(5), evaluate("2#101", &mut shell));
+        assert_eq!(Err(ArithmeticError::InvalidNumber("09".to_string())), evaluate("09", &mut shell));
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let mut shell = Shell::new();
+        assert_eq!(Err(ArithmeticError::DivisionByZero), evaluate("1 / 0", &mut shell));
+        assert_eq!(Err(ArithmeticError::DivisionByZero), evaluate("1 % 0", &mut shell));
+    }
+
+    #[test]
+    fn test_overflow() {
+        let mut shell = Shell::new();
+        assert_eq!(Err(ArithmeticError::Overflow), evaluate("9223372036854775807 + 1", &mut shell));
+        assert_eq!(Err(ArithmeticError::Overflow), evaluate("2 ** 64", &mut shell));
+        assert_eq!(Err(ArithmeticError::Overflow), evaluate("1 << 64", &mut shell));
+        assert_eq!(Err(ArithmeticError::NegativeExponent), evaluate("2 ** -1", &mut shell));
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        let mut shell = Shell::new();
+        assert!(matches!(evaluate("1 +", &mut shell), Err(ArithmeticError::SyntaxError(_))));
+        assert!(matches!(evaluate("2 = 3", &mut shell), Err(ArithmeticError::SyntaxError(_))));
+        assert_eq!(Err(ArithmeticError::SyntaxError("unexpected `é`".to_string())), evaluate("1 +\u{a0}é", &mut shell));
+    }
+}
@@ -1,54 +1,249 @@
 use std::error::Error;
+use std::time::Instant;
 
+mod helper;
+mod highlight;
+mod history_search;
 mod interaction;
+mod key_bindings;
+mod startup;
 mod terminal_interaction;
 
 use crate::cli::interaction::{Interaction, UserInput};
+use crate::cli::startup::{StartupOptions, execute_startup_files};
 use crate::cli::terminal_interaction::TerminalInteraction;
 use crate::command::builtin::exit_shell;
+use crate::command::builtin::format::current_timestamp;
 use crate::command::{IoContext};
-use crate::parsing::{convert_to_command};
+use crate::config::{Config, config_path};
+use crate::history::{History, HistoryEntry, HistoryPolicy, history_path};
+use crate::history::expansion::expand_history;
+use crate::expansion::prompt::{DEFAULT_PS1, DEFAULT_PS2, DEFAULT_PS4, expand_prompt};
+use crate::parsing::{convert_to_command_with_aliases, is_blank_input, is_incomplete_input};
+use crate::shell::Shell;
 
 pub fn run_cli() {
 
+    let args: Vec<String> = std::env::args().collect();
+    let options = match StartupOptions::parse(&args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("rust_shell: {err}");
+            return exit_shell(2);
+        },
+    };
+
     let mut terminal = TerminalInteraction::try_new().expect("error terminal interaction creation");
+    let mut shell = Shell::new();
+
+    // The prompts are set before the configuration and the startup files, which may change them
+    for (name, default) in [("PS1", DEFAULT_PS1), ("PS2", DEFAULT_PS2), ("PS4", DEFAULT_PS4)] {
+        if shell.get_variable(name).is_none() {
+            shell.set_variable(name, default);
+        }
+    }
+    shell.set_option("histexpand", true);
+    shell.set_option("emacs", true);
+    if let Some(path) = config_path(&shell) {
+        match Config::load(&path) {
+            Ok(config) => config.apply(&mut shell),
+            Err(err) => eprintln!("rust_shell: {}: {err}", path.display()),
+        }
+    }
 
-    println!(" ____            _     ____  _          _ _ ");
-    println!("|  _ \\ _   _ ___| |_  / ___|| |__   ___| | |");
-    println!("| |_) | | | / __| __| \\___ \\| '_ \\ / _ \\ | |");
-    println!("|  _ <| |_| \\__ \\ |_   ___) | | | |  __/ | |");
-    println!("|_| \\_\\\\__,_|___/\\__| |____/|_| |_|\\___|_|_|\n");
+    if shell.config.banner {
+        println!(" ____            _     ____  _          _ _ ");
+        println!("|  _ \\ _   _ ___| |_  / ___|| |__   ___| | |");
+        println!("| |_) | | | / __| __| \\___ \\| '_ \\ / _ \\ | |");
+        println!("|  _ <| |_| \\__ \\ |_   ___) | | | |  __/ | |");
+        println!("|_| \\_\\\\__,_|___/\\__| |____/|_| |_|\\___|_|_|\n");
+    }
+
+    execute_startup_files(&options, &mut shell);
+
+    // The startup files may set HISTFILE and the variables of the policy
+    match History::open(history_path(&shell), &HistoryPolicy::from_variables(&shell)) {
+        Ok(history) => *shell.history.lock() = history,
+        Err(err) => eprintln!("rust_shell: history: {err}"),
+    }
 
     loop {
-        if let Err(err) = cli_loop_step(&mut terminal) {
-            println!("{err}");
+        if let Err(err) = cli_loop_step(&mut terminal, &mut shell) {
+            eprintln!("{err}");
         }
     }
 }
 
-/// Processes a single step on a loop
-pub fn cli_loop_step(terminal: &mut dyn Interaction) -> Result<(), Box<dyn Error>>{
+/// Processes a single step on a loop : runs PROMPT_COMMAND, then reads a command with the PS1 prompt
+/// (and PS2 for its next lines while it is incomplete) and executes it
+pub fn cli_loop_step(terminal: &mut dyn Interaction, shell: &mut Shell) -> Result<(), Box<dyn Error>>{
 
-    let user_input = terminal.receive_input()
-        // Propagate the error by specifying it is a user input error
-        .map_err(|e| Box::<dyn std::error::Error>::from(format!("Input error: {}", e)))?;
-
-    match user_input {
-        UserInput::String(input_string) => {
-            
-            let input_command = convert_to_command(&input_string)
-                .map_err(|e| Box::<dyn std::error::Error>::from(format!("Parsing error: {}", e)))?;
-            
-            input_command.execute( IoContext::default() )
-                .map_err(|e| Box::<dyn std::error::Error>::from(format!("Execution error: {}", e)))?; 
+    if let Some(prompt_command) = shell.get_variable("PROMPT_COMMAND").map(str::to_string) {
+        run_prompt_command(&prompt_command, shell);
+    }
 
+    let prompt = prompt_variable(shell, "PS1", DEFAULT_PS1);
+    let mut input_string = match receive_input(terminal, &prompt, shell)? {
+        UserInput::String(input_string) => expand_history_references(input_string, shell)?,
+        UserInput::NoSpecialInput => return Ok(()), // If no special input, ignore it
+        UserInput::BoundCommand { command, line, point } => {
+            run_bound_command(&command, &line, point, terminal, shell);
+            return Ok(());
         },
-        UserInput::NoSpecialInput => (), // If no special input, ignore it
         UserInput::Eof => {
             println!("exit");
-            exit_shell(0)
+            // Like `exit` without argument, the status is the one of the last command
+            exit_shell(shell.last_status);
+            return Ok(());
         },
+    };
+
+    // A comment alone is not a command
+    if is_blank_input(&input_string) {
+        return Ok(());
     }
 
+    let timestamp = current_timestamp();
+    let directory = shell.get_variable("PWD").unwrap_or_default().to_string();
+
+    let input_command = loop {
+        match convert_to_command_with_aliases(&input_string, shell.aliases()) {
+            Ok(command) => break command,
+            // The command continues on the next line
+            Err(err) if is_incomplete_input(err.as_ref()) => {
+                let prompt = prompt_variable(shell, "PS2", DEFAULT_PS2);
+                match receive_input(terminal, &prompt, shell)? {
+                    UserInput::String(line) => {
+                        input_string.push('\n');
+                        input_string.push_str(&expand_history_references(line, shell)?);
+                    },
+                    // ctrl c abandons the command, the keys bound to commands too
+                    UserInput::NoSpecialInput | UserInput::BoundCommand { .. } => return Ok(()),
+                    UserInput::Eof => return Err("Parsing error: unexpected end of file".into()),
+                }
+            },
+            // The line is kept in the history to be corrected
+            Err(e) => {
+                add_history_entry(shell, HistoryEntry { command: input_string, timestamp, directory, status: Some(2), ..HistoryEntry::default() });
+                return Err(format!("Parsing error: {}", e).into());
+            },
+        }
+    };
+
+    let start = Instant::now();
+    let result = input_command.execute( shell, IoContext::default() );
+
+    let status = result.as_ref().map_or_else(|err| err.exit_status(), |status| *status);
+    let entry = HistoryEntry { command: input_string, timestamp, directory, status: Some(status), duration: start.elapsed(), ..HistoryEntry::default() };
+    add_history_entry(shell, entry);
+
+    // Reported like the errors of the commands of a list, see `execute_reporting`
+    result?;
     Ok(())
 }
+
+/// Adds the command to the history following the policy of the variables, reporting the errors of its file
+fn add_history_entry(shell: &mut Shell, entry: HistoryEntry) {
+    let policy = HistoryPolicy::from_variables(shell);
+    if let Err(err) = shell.history.lock().add(entry, &policy) {
+        eprintln!("rust_shell: history: {err}");
+    }
+}
+
+/// Replaces the history references of the line (like `!!`) if `histexpand` is set, writing the line when it changes
+fn expand_history_references(line: String, shell: &Shell) -> Result<String, Box<dyn Error>> {
+
+    if !shell.is_option_set("histexpand") {
+        return Ok(line);
+    }
+    match expand_history(&line, shell.history.lock().entries()) {
+        Ok(Some(expanded)) => {
+            println!("{expanded}");
+            Ok(expanded)
+        },
+        Ok(None) => Ok(line),
+        Err(err) => Err(format!("rust_shell: {err}").into()),
+    }
+}
+
+/// Reads a line with the prompt
+fn receive_input(terminal: &mut dyn Interaction, prompt: &str, shell: &Shell) -> Result<UserInput, Box<dyn Error>> {
+    terminal.receive_input(prompt, shell)
+        // Propagate the error by specifying it is a user input error
+        .map_err(|e| Box::<dyn std::error::Error>::from(format!("Input error: {}", e)))
+}
+
+/// Returns the expansion of the prompt variable, or of its default value if it is not set
+fn prompt_variable(shell: &mut Shell, name: &str, default: &str) -> String {
+    let prompt = shell.get_variable(name).unwrap_or(default).to_string();
+    expand_prompt(&prompt, shell)
+}
+
+/// Executes the commands of PROMPT_COMMAND, reporting their errors
+fn run_prompt_command(prompt_command: &str, shell: &mut Shell) {
+
+    let result = convert_to_command_with_aliases(prompt_command, shell.aliases())
+        .map_err(|e| format!("PROMPT_COMMAND: Parsing error: {}", e))
+        .and_then(|command| {
+            // The status of the last command is kept for the prompt
+            let last_status = shell.last_status;
+            let result = command.execute(shell, IoContext::default()).map_err(|e| format!("PROMPT_COMMAND: {}", e));
+            shell.last_status = last_status;
+            result
+        });
+
+    if let Err(err) = result {
+        eprintln!("{err}");
+    }
+}
+
+/// Executes a command bound to a key with `bind -x`, below the line being edited, which the command can read and change
+/// through READLINE_LINE and READLINE_POINT (the position of the cursor, in characters). The edition goes on afterwards
+fn run_bound_command(command: &str, line: &str, point: usize, terminal: &mut dyn Interaction, shell: &mut Shell) {
+
+    shell.set_variable("READLINE_LINE", line);
+    shell.set_variable("READLINE_POINT", &line[..point].chars().count().to_string());
+
+    let result = convert_to_command_with_aliases(command, shell.aliases())
+        .map_err(|e| format!("Parsing error: {}", e))
+        .and_then(|command| command.execute(shell, IoContext::default()).map_err(|e| e.to_string()));
+    if let Err(err) = result {
+        eprintln!("{err}");
+    }
+
+    let line = shell.get_variable("READLINE_LINE").unwrap_or_default().to_string();
+    let point = shell.get_variable("READLINE_POINT").and_then(|point| point.parse::<usize>().ok()).unwrap_or(usize::MAX);
+    let point = line.char_indices().nth(point).map_or(line.len(), |(index, _)| index);
+    shell.unset_variable("READLINE_LINE");
+    shell.unset_variable("READLINE_POINT");
+    terminal.set_initial_input(&line, point);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arithmetic::ArithmeticError;
+    use crate::command::execution::ExecutionError;
+
+    /// Enters the lines one after the other
+    struct EnteredLines(Vec<&'static str>);
+
+    impl Interaction for EnteredLines {
+        fn receive_input(&mut self, _prompt: &str, _shell: &Shell) -> Result<UserInput, Box<dyn Error>> {
+            Ok(UserInput::String(self.0.remove(0).to_string()))
+        }
+
+        fn set_initial_input(&mut self, _line: &str, _point: usize) {}
+    }
+
+    #[test]
+    fn test_execution_errors() {
+        let mut shell = Shell::new();
+        let mut terminal = EnteredLines(vec!["(( 1 / 0 ))"]);
+
+        // The error of a whole line is written like the one of a command in a list
+        let err = cli_loop_step(&mut terminal, &mut shell).unwrap_err();
+        assert_eq!(ExecutionError::from(ArithmeticError::DivisionByZero).to_string(), err.to_string());
+        assert_eq!(1, shell.last_status);
+    }
+}
@@ -0,0 +1,122 @@
+//!
+//! The helper given to the line editor, which completes and colors the line being edited, and suggests its end
+//! from the history.
+//!
+//! It works on a copy of the shell taken before each prompt, for the functions, aliases and variables defined so far.
+//! The history is shared with the shell rather than copied.
+//! The completion functions defined with `complete -F` run in that copy, so their changes don't reach the shell.
+//!
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::{CmdKind, Highlighter};
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Cmd, ConditionalEventHandler, Context, Event, EventContext, Helper, RepeatCount};
+
+use crate::cli::highlight::highlight_line;
+use crate::completion::complete_line;
+use crate::shell::Shell;
+
+pub struct ShellHelper {
+    pub shell: RefCell<Shell>,
+    // The visible text of the prompt given to the editor, and the text written on the terminal for it
+    pub prompt: (String, String),
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        Ok(complete_line(line, pos, &mut self.shell.borrow_mut()))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+
+    /// Suggests the end of the line from the history when the cursor is at its end : a command entered
+    /// preferably in the working directory and successful
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<String> {
+
+        let shell = self.shell.borrow();
+        if !shell.config.features.suggestions || pos < line.len() || line.trim().is_empty() {
+            return None;
+        }
+
+        let history = shell.history.lock();
+        let command = history.suggest(line, shell.get_variable("PWD").unwrap_or_default())?;
+        Some(command[line.len()..].to_string())
+    }
+}
+
+impl Highlighter for ShellHelper {
+
+    /// The prompt is written with its non-printing sequences, which the editor doesn't count in its width
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(&'s self, prompt: &'p str, _default: bool) -> Cow<'b, str> {
+        let (visible, displayed) = &self.prompt;
+        match prompt == visible {
+            true => Cow::Borrowed(displayed),
+            false => Cow::Borrowed(prompt),
+        }
+    }
+
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        let shell = self.shell.borrow();
+        match shell.config.features.syntax_highlighting {
+            true => Cow::Owned(highlight_line(line, &shell)),
+            false => Cow::Borrowed(line),
+        }
+    }
+
+    /// The suggestion is dimmed, unless its color is configured
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("\x1b[{}m{hint}\x1b[0m", self.shell.borrow().config.colors.suggestion))
+    }
+
+    /// Any typed character may change the colors of the line
+    fn highlight_char(&self, _line: &str, _pos: usize, _kind: CmdKind) -> bool {
+        self.shell.borrow().config.features.syntax_highlighting
+    }
+}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+/// Accepts the next word of the suggestion (bound to Alt-F), which moves to the next word as usual if the cursor is not at the end
+pub struct AcceptHintWord;
+
+impl ConditionalEventHandler for AcceptHintWord {
+
+    fn handle(&self, _event: &Event, _count: RepeatCount, _positive: bool, ctx: &EventContext) -> Option<Cmd> {
+
+        if ctx.pos() < ctx.line().len() {
+            return None;
+        }
+        let word = next_word(ctx.hint_text()?);
+        (!word.is_empty()).then(|| Cmd::Insert(1, word.to_string()))
+    }
+}
+
+/// Returns the start of the text up to the end of its first word, with the blanks before it
+fn next_word(text: &str) -> &str {
+
+    let blanks = text.len() - text.trim_start().len();
+    let end = text[blanks..].find(char::is_whitespace).map_or(text.len(), |length| blanks + length);
+    &text[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_word() {
+        assert_eq!(" --release", next_word(" --release --all"));
+        assert_eq!("build", next_word("build"));
+        assert_eq!("", next_word(""));
+    }
+}
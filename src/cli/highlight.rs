@@ -0,0 +1,313 @@
+//!
+//! Coloring of the line being edited, which is read with the lexer of the parser so that the colors agree
+//! with how the line is executed : the commands (found or unknown), the builtins and the keywords, the strings,
+//! the expansions, the operators, the redirection targets and the paths that exist.
+//!
+
+use std::path::Path;
+
+use crate::command::builtin::is_builtin;
+use crate::completion::{KEYWORDS, is_executable_file, unquote};
+use crate::config::Colors;
+use crate::expansion::expand_tilde;
+use crate::parsing::Token;
+use crate::parsing::alias::WordPosition;
+use crate::parsing::lexer::tokenize_with_positions;
+use crate::shell::Shell;
+
+/// The colored parts of the line, whose colors are configured in the `[colors]` table of the configuration
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Style {
+    Command,
+    UnknownCommand,
+    Builtin,
+    Keyword,
+    String,
+    Expansion,
+    Operator,
+    RedirectionTarget,
+    Unterminated,
+    /// Added to the style of the parts of a word naming an existing file
+    Path,
+}
+
+impl Style {
+
+    /// Returns the SGR parameters of the style
+    fn sgr(self, colors: &Colors) -> &str {
+        match self {
+            Style::Command => &colors.command,
+            Style::UnknownCommand => &colors.unknown_command,
+            Style::Builtin => &colors.builtin,
+            Style::Keyword => &colors.keyword,
+            Style::String => &colors.string,
+            Style::Expansion => &colors.expansion,
+            Style::Operator => &colors.operator,
+            Style::RedirectionTarget => &colors.redirection_target,
+            Style::Unterminated => &colors.unterminated,
+            Style::Path => &colors.path,
+        }
+    }
+}
+
+/// Returns the line with the escape sequences coloring its parts. The text itself is unchanged
+pub fn highlight_line(line: &str, shell: &Shell) -> String {
+
+    let colors = &shell.config.colors;
+    let (tokens, error) = tokenize_with_positions(line);
+    // An unterminated word (like an open quote) goes to the end of the line
+    let unterminated_start = error.map(|(_, start)| start);
+
+    let mut highlighted = String::with_capacity(line.len() * 2);
+    let mut position = WordPosition::start();
+    let mut end = 0;
+
+    for token in tokens.iter().filter(|token| unterminated_start.is_none_or(|start| token.end <= start)) {
+        highlighted.push_str(&line[end..token.start]);
+        let text = &line[token.start..token.end];
+        let (command_position, redirection_target) = (position.command, position.redirection_target);
+        position.advance(&token.token);
+
+        match &token.token {
+            Token::Word(_) if redirection_target => push_word(&mut highlighted, text, Some(Style::RedirectionTarget), shell),
+            Token::Word(word) if command_position && KEYWORDS.contains(&word.as_str()) => push_styled(&mut highlighted, text, Style::Keyword.sgr(colors)),
+            // The name of a command, not an assignment before it
+            Token::Word(word) if command_position && !position.command => {
+                push_word(&mut highlighted, text, Some(command_style(word, shell)), shell);
+            },
+            Token::Word(_) => push_word(&mut highlighted, text, None, shell),
+            _ => push_styled(&mut highlighted, text, Style::Operator.sgr(colors)),
+        }
+        end = token.end;
+    }
+
+    match unterminated_start {
+        Some(start) => {
+            highlighted.push_str(&line[end..start]);
+            push_word(&mut highlighted, &line[start..], None, shell);
+        },
+        None => highlighted.push_str(&line[end..]),
+    }
+
+    highlighted
+}
+
+/// Returns the style of the command name : a builtin, or a function, an alias or a program that exists, or an unknown command
+fn command_style(word: &str, shell: &Shell) -> Style {
+
+    let name = unquote(word);
+    if is_builtin(&name) {
+        Style::Builtin
+    } else if shell.get_function(&name).is_some() || shell.aliases().contains_key(&name) || find_program(&name, shell) {
+        Style::Command
+    } else {
+        Style::UnknownCommand
+    }
+}
+
+/// Returns true if the command is a program, found in PATH if its name has no `/`
+fn find_program(name: &str, shell: &Shell) -> bool {
+
+    if name.contains('/') {
+        return resolve_path(name, shell).is_some_and(|path| is_executable_file(Path::new(&path)));
+    }
+    shell.get_variable("PATH").unwrap_or_default().split(':')
+        .filter(|directory| !directory.is_empty())
+        .any(|directory| is_executable_file(&Path::new(directory).join(name)))
+}
+
+/// Returns the path named by the word without its quotes, its tilde expanded and relative to PWD
+fn resolve_path(path: &str, shell: &Shell) -> Option<String> {
+
+    let path = match path.strip_prefix('~') {
+        Some(rest) => {
+            let (user, rest) = rest.split_once('/').unwrap_or((rest, ""));
+            format!("{}/{rest}", expand_tilde(user, shell)?.trim_end_matches('/'))
+        },
+        None => path.to_string(),
+    };
+
+    match path.starts_with('/') {
+        true => Some(path),
+        false => Some(format!("{}/{path}", shell.get_variable("PWD")?)),
+    }
+}
+
+/// Writes the word with its strings and expansions colored, on top of the style of the whole word if there is one.
+/// A word naming an existing file is underlined
+fn push_word(highlighted: &mut String, word: &str, style: Option<Style>, shell: &Shell) {
+
+    // A word with expansions or patterns is only known when it is executed
+    let is_path = !word.is_empty() && !word.contains(['$', '`', '*', '?', '['])
+        && resolve_path(&unquote(word), shell).is_some_and(|path| Path::new(&path).exists());
+
+    for (text, part_style) in split_word(word) {
+        let styles: Vec<&str> = [is_path.then_some(Style::Path), part_style.or(style)].into_iter().flatten()
+            .map(|style| style.sgr(&shell.config.colors)).collect();
+        match styles.is_empty() {
+            true => highlighted.push_str(text),
+            false => push_styled(highlighted, text, &styles.join(";")),
+        }
+    }
+}
+
+fn push_styled(highlighted: &mut String, text: &str, style: &str) {
+    highlighted.push_str(&format!("\x1b[{style}m{text}\x1b[0m"));
+}
+
+/// Splits the word into its parts : the plain text, the quoted strings, the expansions (`$name`, `${...}`, `$(...)`, backquotes)
+/// and an unterminated quote, with their style
+fn split_word(word: &str) -> Vec<(&str, Option<Style>)> {
+
+    let bytes = word.as_bytes();
+    let mut parts = Vec::new();
+    let mut plain_start = 0;
+    let mut position = 0;
+
+    while position < bytes.len() {
+        match bytes[position] {
+            b'\\' => position += 2,
+            b'\'' => {
+                let (end, style) = match word[position + 1..].find('\'') {
+                    Some(length) => (position + length + 2, Style::String),
+                    None => (word.len(), Style::Unterminated),
+                };
+                push_part(&mut parts, word, &mut plain_start, position, end, style);
+                position = end;
+            },
+            b'"' => {
+                let Some(closing) = closing_double_quote(word, position) else {
+                    push_part(&mut parts, word, &mut plain_start, position, word.len(), Style::Unterminated);
+                    break;
+                };
+                // The expansions inside the string keep their own style
+                let mut string_start = position;
+                let mut end = position + 1;
+                while end < closing {
+                    match bytes[end] {
+                        b'\\' => end += 2,
+                        b'$' | b'`' => {
+                            let expansion_end = expansion_end(word, end).min(closing);
+                            push_part(&mut parts, word, &mut plain_start, string_start, end, Style::String);
+                            push_part(&mut parts, word, &mut plain_start, end, expansion_end, Style::Expansion);
+                            string_start = expansion_end;
+                            end = expansion_end;
+                        },
+                        _ => end += 1,
+                    }
+                }
+                push_part(&mut parts, word, &mut plain_start, string_start, closing + 1, Style::String);
+                position = closing + 1;
+            },
+            b'$' | b'`' => {
+                let end = expansion_end(word, position);
+                let style = match bytes[position] == b'`' && !word[position + 1..end].ends_with('`') {
+                    true => Style::Unterminated,
+                    false => Style::Expansion,
+                };
+                push_part(&mut parts, word, &mut plain_start, position, end, style);
+                position = end;
+            },
+            _ => position += 1,
+        }
+    }
+
+    if plain_start < word.len() {
+        parts.push((&word[plain_start..], None));
+    }
+    parts
+}
+
+/// Adds the part of the word between `start` and `end` with its style, preceded by the plain text since the previous part
+fn push_part<'a>(parts: &mut Vec<(&'a str, Option<Style>)>, word: &'a str, plain_start: &mut usize, start: usize, end: usize, style: Style) {
+
+    if *plain_start < start {
+        parts.push((&word[*plain_start..start], None));
+    }
+    if start < end {
+        parts.push((&word[start..end], Some(style)));
+    }
+    *plain_start = end;
+}
+
+/// Returns the position of the quote closing the double-quoted string starting at `start`
+fn closing_double_quote(word: &str, start: usize) -> Option<usize> {
+
+    let bytes = word.as_bytes();
+    let mut position = start + 1;
+    while position < bytes.len() {
+        match bytes[position] {
+            b'\\' => position += 2,
+            b'"' => return Some(position),
+            b'$' if bytes.get(position + 1) == Some(&b'(') => position = expansion_end(word, position),
+            _ => position += 1,
+        }
+    }
+
+    None
+}
+
+/// Returns the end of the expansion starting at the `$` or the backquote, the end of the word if it is not terminated
+fn expansion_end(word: &str, start: usize) -> usize {
+
+    let bytes = word.as_bytes();
+    let closing_delimiter = |open: u8, close: u8, from: usize| {
+        let mut depth = 0;
+        for (index, &byte) in bytes.iter().enumerate().skip(from) {
+            if byte == open {
+                depth += 1;
+            } else if byte == close {
+                depth -= 1;
+                if depth == 0 {
+                    return index + 1;
+                }
+            }
+        }
+        word.len()
+    };
+
+    match (bytes[start], bytes.get(start + 1)) {
+        (b'`', _) => word[start + 1..].find('`').map_or(word.len(), |length| start + length + 2),
+        (_, Some(b'{')) => closing_delimiter(b'{', b'}', start + 1),
+        (_, Some(b'(')) => closing_delimiter(b'(', b')', start + 1),
+        (_, Some(byte)) if byte.is_ascii_alphabetic() || *byte == b'_' => {
+            let length = bytes[start + 1..].iter().take_while(|byte| byte.is_ascii_alphanumeric() || **byte == b'_').count();
+            start + 1 + length
+        },
+        // The special parameters like `$?` and `$1`
+        (_, Some(byte)) if byte.is_ascii_digit() || b"?#@*$!-".contains(byte) => start + 2,
+        // A `$` alone is a character
+        _ => start + 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn styled(text: &str, style: Style) -> String {
+        format!("\x1b[{}m{text}\x1b[0m", style.sgr(&Colors::default()))
+    }
+
+    #[test]
+    fn test_word_parts() {
+        assert_eq!(vec![("a", None), ("'b c'", Some(Style::String)), ("\\$d", None), ("$e", Some(Style::Expansion))], split_word("a'b c'\\$d$e"));
+        assert_eq!(vec![("\"x ", Some(Style::String)), ("${y}", Some(Style::Expansion)), ("\"", Some(Style::String))], split_word("\"x ${y}\""));
+        assert_eq!(vec![("$(a (b))", Some(Style::Expansion)), ("$", Some(Style::Expansion)), ("'open", Some(Style::Unterminated))], split_word("$(a (b))$'open"));
+    }
+
+    #[test]
+    fn test_highlighting() {
+        let mut shell = Shell::new();
+        shell.set_variable("PWD", "/");
+        shell.set_variable("PATH", "/bin:/usr/bin");
+        shell.define_alias("ll", "ls -l");
+
+        let expected = format!("x=1 {} {} {} {} {} {}", styled("echo", Style::Builtin), styled("$x", Style::Expansion), styled("|", Style::Operator),
+            styled("unknown_command_name", Style::UnknownCommand), styled(">", Style::Operator), styled("out", Style::RedirectionTarget));
+        assert_eq!(expected, highlight_line("x=1 echo $x | unknown_command_name > out", &shell));
+
+        assert_eq!(format!("{} {} {}", styled("if", Style::Keyword), styled("ll", Style::Command), styled("tmp", Style::Path)), highlight_line("if ll tmp", &shell));
+        assert_eq!(format!("{} {}", styled("echo", Style::Builtin), styled("\"open $", Style::Unterminated)), highlight_line("echo \"open $", &shell));
+    }
+}
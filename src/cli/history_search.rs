@@ -0,0 +1,359 @@
+//!
+//! The full-screen fuzzy search in the history bound to Ctrl-R, which replaces the line being edited by the chosen command.
+//!
+//! It is drawn on the alternate screen of the terminal, while the line editor waits for its result, and reads the keys
+//! from the terminal left in raw mode by the editor : the typed characters refine the query, the arrows choose a command,
+//! Tab switches between all the commands, the ones run in the working directory and the ones of this session,
+//! Enter takes the command and Escape (or Ctrl-C, Ctrl-G) cancels the search.
+//!
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use rustyline::{Cmd, ConditionalEventHandler, Event, EventContext, Movement, RepeatCount};
+
+use crate::command::builtin::format::current_timestamp;
+use crate::history::SharedHistory;
+use crate::history::search::{SearchMatch, SearchScope, format_age, search_history};
+
+/// How long to wait for the rest of an escape sequence before taking it for the Escape key, in milliseconds
+const ESCAPE_TIMEOUT: i32 = 30;
+
+const SELECTED_STYLE: &str = "7";
+const MATCHED_STYLE: &str = "1;33";
+const SUCCESS_STYLE: &str = "32";
+const FAILURE_STYLE: &str = "31";
+const DIMMED_STYLE: &str = "2";
+
+/// The history searched, shared with the shell, and the context of the search, taken from the shell before each prompt
+#[derive(Debug, Default)]
+pub struct SearchedHistory {
+    pub history: SharedHistory,
+    pub directory: String,
+    pub home: String,
+}
+
+/// The handler of Ctrl-R
+pub struct HistorySearch {
+    pub history: Arc<Mutex<SearchedHistory>>,
+}
+
+impl ConditionalEventHandler for HistorySearch {
+
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext) -> Option<Cmd> {
+
+        let history = self.history.lock().ok()?;
+        match run_search(&history, ctx.line()) {
+            // Inserting the rest of the command leaves the cursor at its end, unlike replacing the line
+            Ok(Some(command)) if ctx.pos() == ctx.line().len() && command.starts_with(ctx.line()) => {
+                Some(Cmd::Insert(1, command[ctx.line().len()..].to_string()))
+            },
+            Ok(Some(command)) => Some(Cmd::Replace(Movement::WholeBuffer, Some(command))),
+            Ok(None) => Some(Cmd::Repaint),
+            // The default reverse search is done if the terminal can't be used
+            Err(_) => None,
+        }
+    }
+}
+
+/// A key read from the terminal
+#[derive(Debug, PartialEq)]
+enum Key {
+    Char(char),
+    Control(char),
+    Enter,
+    Escape,
+    Backspace,
+    Tab,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Other,
+}
+
+/// The state of the search screen
+struct SearchScreen {
+    query: String,
+    scope: SearchScope,
+    /// The index of the chosen command among the matching ones, and the index of the first one shown
+    selected: usize,
+    first_shown: usize,
+}
+
+/// Shows the search screen until a command is chosen, starting with the line being edited as the query.
+///
+/// Returns the chosen command, or None if the search is cancelled
+fn run_search(history: &SearchedHistory, line: &str) -> io::Result<Option<String>> {
+
+    let mut stdout = io::stdout();
+    // The alternate screen keeps the content of the terminal, which is restored when the search ends
+    stdout.write_all(b"\x1b[?1049h")?;
+
+    let result = search_loop(history, line, &mut stdout);
+
+    stdout.write_all(b"\x1b[?1049l")?;
+    stdout.flush()?;
+    result
+}
+
+fn search_loop(history: &SearchedHistory, line: &str, stdout: &mut io::Stdout) -> io::Result<Option<String>> {
+
+    let mut screen = SearchScreen { query: line.to_string(), scope: SearchScope::All, selected: 0, first_shown: 0 };
+    let mut keys = KeyReader { pending: VecDeque::new() };
+    let now = current_timestamp();
+    let shared_history = history.history.lock();
+    let (entries, session) = (shared_history.entries(), shared_history.session());
+
+    loop {
+        let matches = search_history(entries, &screen.query, screen.scope, &history.directory, session);
+        let (width, height) = terminal_size();
+        // The first two lines show the scopes and the query, the last one the keys
+        let rows = height.saturating_sub(3).max(1);
+
+        screen.selected = screen.selected.min(matches.len().saturating_sub(1));
+        if screen.selected < screen.first_shown {
+            screen.first_shown = screen.selected;
+        } else if screen.selected >= screen.first_shown + rows {
+            screen.first_shown = screen.selected + 1 - rows;
+        }
+
+        stdout.write_all(render(&screen, &matches, entries.len(), history, width, rows, now).as_bytes())?;
+        stdout.flush()?;
+
+        let key = keys.next_key()?;
+        let page = rows.max(1);
+        match key {
+            Key::Enter => return Ok(matches.get(screen.selected).map(|found| found.entry.command.clone())),
+            Key::Escape | Key::Control('c') | Key::Control('g') | Key::Control('d') => return Ok(None),
+            Key::Char(c) => {
+                screen.query.push(c);
+                screen.selected = 0;
+            },
+            Key::Backspace | Key::Control('h') => {
+                screen.query.pop();
+                screen.selected = 0;
+            },
+            Key::Control('u') => {
+                screen.query.clear();
+                screen.selected = 0;
+            },
+            Key::Control('w') => {
+                let kept = screen.query.trim_end().rfind(' ').map_or(0, |space| space + 1);
+                screen.query.truncate(kept);
+                screen.selected = 0;
+            },
+            Key::Tab => {
+                screen.scope = screen.scope.next();
+                screen.selected = 0;
+            },
+            Key::Up | Key::Control('p') => screen.selected = screen.selected.saturating_sub(1),
+            // Ctrl-R goes to the next command, like the reverse search
+            Key::Down | Key::Control('n') | Key::Control('r') => screen.selected += 1,
+            Key::PageUp => screen.selected = screen.selected.saturating_sub(page),
+            Key::PageDown => screen.selected += page,
+            _ => (),
+        }
+    }
+}
+
+/// Draws the whole screen : the scopes with the number of matches among the `searched` commands, the query,
+/// the matching commands and the keys
+fn render(screen: &SearchScreen, matches: &[SearchMatch], searched: usize, history: &SearchedHistory, width: usize, rows: usize, now: i64) -> String {
+
+    let mut frame = String::from("\x1b[H");
+
+    let scopes: Vec<String> = SearchScope::ALL.iter().map(|scope| match *scope == screen.scope {
+        true => styled(&format!(" {} ", scope.name()), SELECTED_STYLE),
+        false => format!(" {} ", scope.name()),
+    }).collect();
+    frame.push_str(&format!("\x1b[2K{}  {}\r\n", scopes.join(""), styled(&format!("{}/{searched}", matches.len()), DIMMED_STYLE)));
+    frame.push_str(&format!("\x1b[2K> {}\r\n", screen.query));
+
+    // The directories take at most a quarter of the width
+    let directory_width = (width / 4).clamp(8, 30);
+    for row in 0..rows {
+        frame.push_str("\x1b[2K");
+        if let Some(found) = matches.get(screen.first_shown + row) {
+            frame.push_str(&render_match(found, screen.first_shown + row == screen.selected, history, width, directory_width, now));
+        }
+        frame.push_str("\r\n");
+    }
+
+    frame.push_str(&format!("\x1b[2K{}", styled("Enter: choose  Tab: scope  Up/Down: move  Esc: cancel", DIMMED_STYLE)));
+    // The cursor goes back to the end of the query
+    frame.push_str(&format!("\x1b[2;{}H", screen.query.chars().count() + 3));
+    frame
+}
+
+/// Draws a line of the list : the status, the age, the directory and the command with its matched characters highlighted
+fn render_match(found: &SearchMatch, selected: bool, history: &SearchedHistory, width: usize, directory_width: usize, now: i64) -> String {
+
+    let entry = found.entry;
+    let status = match entry.status {
+        Some(0) => styled("  ok", SUCCESS_STYLE),
+        Some(status) => styled(&format!("{status:>4}"), FAILURE_STYLE),
+        None => "    ".to_string(),
+    };
+    let directory = match entry.directory.strip_prefix(&history.home).filter(|rest| !history.home.is_empty() && (rest.is_empty() || rest.starts_with('/'))) {
+        Some(rest) => format!("~{rest}"),
+        None => entry.directory.clone(),
+    };
+    let directory = truncate_start(&directory, directory_width);
+
+    let marker = if selected { '>' } else { ' ' };
+    let prefix = format!("{marker} {status} {:>4} {directory:<directory_width$} ", format_age(entry.timestamp, now));
+    // The marker, the status and the age take 12 columns with their spaces, and the `…` of a long command one more
+    let command_width = width.saturating_sub(directory_width + 13);
+
+    // The lines of the command are shown on a single line
+    let mut command = String::new();
+    for (index, c) in entry.command.chars().take(command_width).enumerate() {
+        let c = if c == '\n' { '↵' } else if c.is_control() { ' ' } else { c };
+        match found.positions.contains(&index) {
+            true => command.push_str(&styled(&c.to_string(), MATCHED_STYLE)),
+            false => command.push(c),
+        }
+    }
+    if entry.command.chars().count() > command_width {
+        command.push('…');
+    }
+
+    let line = format!("{prefix}{command}");
+    match selected {
+        // The style of the selection is restored after the highlighted characters
+        true => styled(&line.replace("\x1b[0m", &format!("\x1b[0m\x1b[{SELECTED_STYLE}m")), SELECTED_STYLE),
+        false => line,
+    }
+}
+
+fn styled(text: &str, style: &str) -> String {
+    format!("\x1b[{style}m{text}\x1b[0m")
+}
+
+/// Keeps the end of the text if it is longer than the width, starting it with `…`
+fn truncate_start(text: &str, width: usize) -> String {
+
+    let length = text.chars().count();
+    match length > width {
+        true => format!("…{}", text.chars().skip(length + 1 - width).collect::<String>()),
+        false => text.to_string(),
+    }
+}
+
+/// Returns the number of columns and lines of the terminal, 80x24 if it is not known
+fn terminal_size() -> (usize, usize) {
+
+    // SAFETY: winsize is a plain C struct filled by the ioctl
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    // SAFETY: TIOCGWINSZ only writes the given struct
+    match unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } {
+        0 if size.ws_col > 0 && size.ws_row > 0 => (size.ws_col as usize, size.ws_row as usize),
+        _ => (80, 24),
+    }
+}
+
+/// Reads the keys from the terminal in raw mode, several keys possibly coming in a single read
+struct KeyReader {
+    pending: VecDeque<u8>,
+}
+
+impl KeyReader {
+
+    fn next_key(&mut self) -> io::Result<Key> {
+
+        // A resize of the terminal interrupts the wait, the screen is drawn again
+        let Some(byte) = self.next_byte(-1)? else {
+            return Ok(Key::Other);
+        };
+        let key = match byte {
+            b'\r' | b'\n' => Key::Enter,
+            b'\t' => Key::Tab,
+            0x7f => Key::Backspace,
+            0x1b => match self.next_byte(ESCAPE_TIMEOUT)? {
+                None => Key::Escape,
+                Some(b'[' | b'O') => self.read_escape_sequence()?,
+                Some(_) => Key::Other,
+            },
+            0x01..=0x1a => Key::Control((b'a' + byte - 1) as char),
+            byte if byte < 0x20 => Key::Other,
+            byte => {
+                // The other bytes of the UTF-8 character
+                let length = match byte {
+                    0xf0.. => 4,
+                    0xe0.. => 3,
+                    0xc0.. => 2,
+                    _ => 1,
+                };
+                let mut bytes = vec![byte];
+                for _ in 1..length {
+                    bytes.extend(self.next_byte(ESCAPE_TIMEOUT)?);
+                }
+                std::str::from_utf8(&bytes).ok().and_then(|text| text.chars().next()).map_or(Key::Other, Key::Char)
+            },
+        };
+
+        Ok(key)
+    }
+
+    /// Reads the end of a CSI or SS3 sequence, its parameters being followed by a final byte
+    fn read_escape_sequence(&mut self) -> io::Result<Key> {
+
+        let mut parameters = String::new();
+        while let Some(byte) = self.next_byte(ESCAPE_TIMEOUT)? {
+            match byte {
+                b'0'..=b'9' | b';' => parameters.push(byte as char),
+                b'A' => return Ok(Key::Up),
+                b'B' => return Ok(Key::Down),
+                b'~' if parameters == "5" => return Ok(Key::PageUp),
+                b'~' if parameters == "6" => return Ok(Key::PageDown),
+                _ => return Ok(Key::Other),
+            }
+        }
+
+        Ok(Key::Other)
+    }
+
+    /// Returns the next byte of the standard input, or None if none comes within the timeout in milliseconds (-1 to wait)
+    fn next_byte(&mut self, timeout: i32) -> io::Result<Option<u8>> {
+
+        if let Some(byte) = self.pending.pop_front() {
+            return Ok(Some(byte));
+        }
+
+        let mut poll_fd = libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 };
+        // SAFETY: poll only reads and writes the given struct
+        match unsafe { libc::poll(&mut poll_fd, 1, timeout) } {
+            0 => return Ok(None),
+            -1 => return match io::Error::last_os_error() {
+                err if err.kind() == io::ErrorKind::Interrupted => Ok(None),
+                err => Err(err),
+            },
+            _ => (),
+        }
+
+        let mut buffer = [0u8; 64];
+        // SAFETY: read writes at most buffer.len() bytes in the buffer
+        let length = unsafe { libc::read(libc::STDIN_FILENO, buffer.as_mut_ptr().cast(), buffer.len()) };
+        match length {
+            0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            ..0 => return Err(io::Error::last_os_error()),
+            _ => (),
+        }
+        self.pending.extend(&buffer[..length as usize]);
+
+        Ok(self.pending.pop_front())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncation() {
+        assert_eq!("…/c/d", truncate_start("/a/b/c/d", 5));
+        assert_eq!("/a", truncate_start("/a", 5));
+    }
+}
@@ -5,16 +5,23 @@
 
 use std::{error::Error};
 
+use crate::shell::Shell;
+
 /// Represents the contract that an interaction with the user should respect
 pub trait Interaction {
-    fn receive_input(&mut self) -> Result<UserInput, Box<dyn Error>>;
-    fn save_history(&mut self) -> Result<(), Box<dyn Error>>;
+    /// Prints the prompt and returns the line entered by the user, the shell giving the context of its completion
+    fn receive_input(&mut self, prompt: &str, shell: &Shell) -> Result<UserInput, Box<dyn Error>>;
+
+    /// Makes the next input start with the line, the cursor being at the byte index `point`
+    fn set_initial_input(&mut self, line: &str, point: usize);
 }
 
 /// Represents what a user input could be, it could be just a string, or an action 
 pub enum UserInput {
     String(String),
     Eof,          // ctrl d
+    /// A key bound to a command with `bind -x` was pressed while the line was edited, the cursor being at the byte index `point`
+    BoundCommand { command: String, line: String, point: usize },
     NoSpecialInput // a generic variant when no special action should happen
 }
 
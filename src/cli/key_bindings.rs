@@ -0,0 +1,125 @@
+//!
+//! The key bindings defined with `bind` applied to the line editor : the keys become its events, the functions
+//! its commands, and the shell commands interrupt the edition to be executed by the shell, which goes back to the line.
+//!
+
+use std::sync::{Arc, Mutex};
+
+use rustyline::{At, Cmd, ConditionalEventHandler, Event, EventContext, KeyEvent, Modifiers, Movement, RepeatCount, Word};
+
+use crate::key_binding::{Key, KeyCode};
+
+/// A command bound with `bind -x` whose key was pressed, with the line being edited and the position of the cursor
+#[derive(Debug)]
+pub struct PendingCommand {
+    pub command: String,
+    pub line: String,
+    pub point: usize,
+}
+
+/// The handler of a key bound to a shell command, which ends the edition for the shell to execute the command
+pub struct BoundCommand {
+    pub command: String,
+    pub pending: Arc<Mutex<Option<PendingCommand>>>,
+}
+
+impl ConditionalEventHandler for BoundCommand {
+
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext) -> Option<Cmd> {
+
+        let mut pending = self.pending.lock().ok()?;
+        *pending = Some(PendingCommand { command: self.command.clone(), line: ctx.line().to_string(), point: ctx.pos() });
+        Some(Cmd::Interrupt)
+    }
+}
+
+/// Returns the event of the line editor for the key sequence
+pub fn key_sequence_event(keys: &[Key]) -> Event {
+    Event::KeySeq(keys.iter().map(key_event).collect())
+}
+
+fn key_event(key: &Key) -> KeyEvent {
+
+    let mut modifiers = Modifiers::NONE;
+    if key.control {
+        modifiers |= Modifiers::CTRL;
+    }
+    if key.meta {
+        modifiers |= Modifiers::ALT;
+    }
+    let code = match key.code {
+        KeyCode::Char(c) => rustyline::KeyCode::Char(c),
+        KeyCode::Tab => rustyline::KeyCode::Tab,
+        KeyCode::Enter => rustyline::KeyCode::Enter,
+        KeyCode::Escape => rustyline::KeyCode::Esc,
+        KeyCode::Backspace => rustyline::KeyCode::Backspace,
+        KeyCode::Delete => rustyline::KeyCode::Delete,
+        KeyCode::Up => rustyline::KeyCode::Up,
+        KeyCode::Down => rustyline::KeyCode::Down,
+        KeyCode::Left => rustyline::KeyCode::Left,
+        KeyCode::Right => rustyline::KeyCode::Right,
+        KeyCode::Home => rustyline::KeyCode::Home,
+        KeyCode::End => rustyline::KeyCode::End,
+    };
+    KeyEvent::normalize(KeyEvent(code, modifiers))
+}
+
+/// Returns the command of the line editor doing the function
+pub fn editor_command(function: &str) -> Option<Cmd> {
+
+    let backward_word = |count| Movement::BackwardWord(count, Word::Emacs);
+    let forward_word = |count| Movement::ForwardWord(count, At::AfterEnd, Word::Emacs);
+    Some(match function {
+        "abort" => Cmd::Abort,
+        "accept-line" => Cmd::AcceptLine,
+        "backward-char" => Cmd::Move(Movement::BackwardChar(1)),
+        "backward-delete-char" => Cmd::Kill(Movement::BackwardChar(1)),
+        "backward-kill-line" | "unix-line-discard" => Cmd::Kill(Movement::BeginningOfLine),
+        "backward-kill-word" => Cmd::Kill(backward_word(1)),
+        "backward-word" => Cmd::Move(backward_word(1)),
+        "beginning-of-history" => Cmd::BeginningOfHistory,
+        "beginning-of-line" => Cmd::Move(Movement::BeginningOfLine),
+        "capitalize-word" => Cmd::CapitalizeWord,
+        "clear-screen" => Cmd::ClearScreen,
+        "complete" => Cmd::Complete,
+        "delete-char" => Cmd::Kill(Movement::ForwardChar(1)),
+        "downcase-word" => Cmd::DowncaseWord,
+        "end-of-history" => Cmd::EndOfHistory,
+        "end-of-line" => Cmd::Move(Movement::EndOfLine),
+        "forward-char" => Cmd::Move(Movement::ForwardChar(1)),
+        "forward-search-history" => Cmd::ForwardSearchHistory,
+        "forward-word" => Cmd::Move(forward_word(1)),
+        "history-search-backward" => Cmd::HistorySearchBackward,
+        "history-search-forward" => Cmd::HistorySearchForward,
+        "kill-line" => Cmd::Kill(Movement::EndOfLine),
+        "kill-whole-line" => Cmd::Kill(Movement::WholeLine),
+        "kill-word" => Cmd::Kill(forward_word(1)),
+        "next-history" => Cmd::NextHistory,
+        "previous-history" => Cmd::PreviousHistory,
+        "quoted-insert" => Cmd::QuotedInsert,
+        "reverse-search-history" => Cmd::ReverseSearchHistory,
+        "transpose-chars" => Cmd::TransposeChars,
+        "transpose-words" => Cmd::TransposeWords(1),
+        "undo" => Cmd::Undo(1),
+        "unix-word-rubout" => Cmd::Kill(Movement::BackwardWord(1, Word::Big)),
+        "upcase-word" => Cmd::UpcaseWord,
+        "yank" => Cmd::Yank(1, rustyline::Anchor::Before),
+        "yank-pop" => Cmd::YankPop,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_binding::{EDITOR_FUNCTIONS, parse_key_sequence};
+
+    #[test]
+    fn test_editor_functions() {
+        for function in EDITOR_FUNCTIONS {
+            assert!(editor_command(function).is_some(), "{function}");
+        }
+        assert_eq!(Event::from(KeyEvent::ctrl('R')), key_sequence_event(&parse_key_sequence("\\C-r").unwrap()));
+        assert_eq!(Event::from(KeyEvent::alt('f')), key_sequence_event(&parse_key_sequence("\\ef").unwrap()));
+    }
+}
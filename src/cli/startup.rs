@@ -0,0 +1,123 @@
+//!
+//! Startup of the shell : the command line options, and the files executed before the first prompt.
+//!
+
+use std::path::PathBuf;
+
+use crate::command::IoContext;
+use crate::command::script::source_file;
+use crate::expansion::expand_word;
+use crate::shell::Shell;
+
+/// The file executed by the interactive shells, in the home directory
+const RC_FILE: &str = ".rust_shellrc";
+
+/// The options given to the shell on the command line
+#[derive(Debug, Default, PartialEq)]
+pub struct StartupOptions {
+    /// The shell is a login shell (`-l`, `--login`, or a name starting with `-`) which executes the profile files
+    pub login: bool,
+    /// `--norc` : neither $ENV nor ~/.rust_shellrc are executed
+    pub norc: bool,
+    /// `--noprofile` : the profile files of a login shell are not executed
+    pub noprofile: bool,
+}
+
+impl StartupOptions {
+
+    /// Parses the arguments of the shell, the first one being its name
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+
+        let mut options = StartupOptions {
+            login: args.first().is_some_and(|name| name.starts_with('-')),
+            ..StartupOptions::default()
+        };
+
+        for arg in args.iter().skip(1) {
+            match arg.as_str() {
+                "-l" | "--login" => options.login = true,
+                "--norc" => options.norc = true,
+                "--noprofile" => options.noprofile = true,
+                _ => return Err(format!("{arg}: invalid option\nusage: rust_shell [-l | --login] [--norc] [--noprofile]")),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Returns the files to execute at startup, in this order :
+/// - /etc/profile then ~/.profile for a login shell
+/// - the file named by $ENV (after its expansion), then ~/.rust_shellrc
+pub fn startup_files(options: &StartupOptions, shell: &mut Shell) -> Vec<PathBuf> {
+
+    let home = shell.get_variable("HOME").map(PathBuf::from).or_else(std::env::home_dir);
+    let mut files = Vec::new();
+
+    if options.login && !options.noprofile {
+        files.push(PathBuf::from("/etc/profile"));
+        files.extend(home.as_ref().map(|home| home.join(".profile")));
+    }
+
+    if !options.norc {
+        let env_file = shell.get_variable("ENV").map(str::to_string)
+            .and_then(|env| expand_word(&env, shell).ok())
+            .filter(|env| !env.is_empty());
+        files.extend(env_file.map(PathBuf::from));
+
+        let rc_file = home.map(|home| home.join(RC_FILE));
+        files.extend(rc_file.filter(|rc_file| !files.contains(rc_file)));
+    }
+
+    files
+}
+
+/// Executes the existing startup files in the shell, their errors being reported
+pub fn execute_startup_files(options: &StartupOptions, shell: &mut Shell) {
+
+    for path in startup_files(options, shell) {
+        if !path.is_file() {
+            continue;
+        }
+        if let Err(err) = source_file(&path, &[], shell, &IoContext::new()) {
+            eprintln!("{}: {err}", path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_options() {
+        assert_eq!(StartupOptions::default(), StartupOptions::parse(&strings(&["rust_shell"])).unwrap());
+
+        let options = StartupOptions::parse(&strings(&["-rust_shell", "--norc"])).unwrap();
+        assert_eq!(StartupOptions { login: true, norc: true, noprofile: false }, options);
+
+        assert!(StartupOptions::parse(&strings(&["rust_shell", "--unknown"])).is_err());
+    }
+
+    #[test]
+    fn test_startup_files() {
+        let mut shell = Shell::new();
+        shell.set_variable("HOME", "/home/me");
+        shell.set_variable("ENV", "$HOME/.env");
+
+        let login = StartupOptions { login: true, ..StartupOptions::default() };
+        let files: Vec<PathBuf> = ["/etc/profile", "/home/me/.profile", "/home/me/.env", "/home/me/.rust_shellrc"].iter().map(PathBuf::from).collect();
+        assert_eq!(files, startup_files(&login, &mut shell));
+
+        let options = StartupOptions { login: true, norc: true, noprofile: true };
+        assert!(startup_files(&options, &mut shell).is_empty());
+
+        // The rc file is not executed twice
+        shell.set_variable("ENV", "~/.rust_shellrc");
+        assert_eq!(files[3..], startup_files(&StartupOptions::default(), &mut shell));
+    }
+}
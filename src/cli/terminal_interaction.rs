@@ -1,21 +1,42 @@
-
 //! 
 //! note: This module encapsulates the third party lib used for
 //! enriched user input (navigation in the input with arrow, shortcuts handling (ctrl c, selecting text, copy paste) etc...) 
 //! 
 //! 
-use std::{env, error::Error, path::PathBuf};
-use rustyline::{DefaultEditor, error::ReadlineError};
-
-use crate::{cli::interaction::{Interaction, UserInput}, command::builtin::get_working_directory};
+use std::{cell::RefCell, error::Error, sync::{Arc, Mutex}};
+use rustyline::{Cmd, CompletionType, Config, EditMode, Editor, Event, EventHandler, KeyEvent, config::Configurer, error::ReadlineError, history::{History, MemHistory}};
+
+use crate::cli::helper::{AcceptHintWord, ShellHelper};
+use crate::cli::history_search::{HistorySearch, SearchedHistory};
+use crate::cli::interaction::{Interaction, UserInput};
+use crate::cli::key_bindings::{BoundCommand, PendingCommand, editor_command, key_sequence_event};
+use crate::config::CompletionStyle;
+use crate::expansion::prompt::split_prompt;
+use crate::history::HistoryPolicy;
+use crate::key_binding::{KeyAction, KeyBinding};
+use crate::shell::Shell;
+
+/// The number of commands of the history that the up arrow goes through
+const EDITOR_HISTORY_SIZE: usize = 10_000;
 
 /// Represents what an interaction via the terminal with the users contains.
 /// 
 /// 
 pub struct TerminalInteraction { 
-    rusty_lines_editor: DefaultEditor,
-    // The path to the file where the history is saved
-    history_path: PathBuf
+    rusty_lines_editor: Editor<ShellHelper, MemHistory>,
+    // The number of commands the history of the shell had when it was last copied into the history of the editor,
+    // and its revision then
+    copied_history_added: u64,
+    copied_history_revision: u64,
+    // The commands searched with Ctrl-R
+    searched_history: Arc<Mutex<SearchedHistory>>,
+    // The bindings of the shell given to the editor, whether Ctrl-R is the full-screen search, and the command
+    // bound with `bind -x` whose key was pressed
+    applied_key_bindings: Vec<KeyBinding>,
+    fuzzy_history_search: bool,
+    pending_command: Arc<Mutex<Option<PendingCommand>>>,
+    // The text the next line starts with, and the position of the cursor in it
+    initial_input: Option<(String, usize)>,
 }
 
 impl TerminalInteraction {
@@ -25,34 +46,124 @@ impl TerminalInteraction {
     pub fn try_new() -> Result<Self, Box<dyn std::error::Error>> {    
 
         // The creation of rusty_lines objects may fail 
-        let mut rusty_lines_editor = DefaultEditor::new()?;
+        // The candidates of the completion are listed like in bash
+        let config = Config::builder().completion_type(CompletionType::List).max_history_size(EDITOR_HISTORY_SIZE)?.build();
+        let history = MemHistory::with_config(&config);
+        let mut rusty_lines_editor = Editor::with_history(config, history)?;
+        rusty_lines_editor.set_helper(Some(ShellHelper { shell: RefCell::new(Shell::new()), prompt: Default::default() }));
 
-        let mut temp_path: PathBuf = env::temp_dir();
-        // Creates a file in temporary folder (/tmp on linux for example) where the history will be saved
-        temp_path.push("rust_shell_history.txt");
-        let _ = rusty_lines_editor.load_history(&temp_path);
-
-        Ok(TerminalInteraction {
+        let mut terminal = TerminalInteraction {
             rusty_lines_editor,
-            history_path: temp_path
-        })
+            copied_history_added: 0,
+            copied_history_revision: 0,
+            searched_history: Arc::new(Mutex::new(SearchedHistory::default())),
+            applied_key_bindings: Vec::new(),
+            fuzzy_history_search: true,
+            pending_command: Arc::new(Mutex::new(None)),
+            initial_input: None,
+        };
+        for (event, handler) in terminal.default_bindings() {
+            terminal.rusty_lines_editor.bind_sequence(event, handler);
+        }
+        Ok(terminal)
     }
-        
-    /// Returns the prefix on the left of the user input with the following format: `$ currentdirectory>`
-    /// 
-    fn get_prompt_string(&self) -> String {
 
-        let mut prompt_string = String::new();
-        
-        let working_dir = get_working_directory()
-            .unwrap_or_else(|_| String::from("unknown"));
+    /// Returns the bindings of the shell to the editor, which `bind` may replace
+    fn default_bindings(&self) -> Vec<(Event, EventHandler)> {
+        let mut bindings = vec![
+            // The right arrow accepts the whole suggestion, and Alt-F its next word
+            (KeyEvent::alt('f').into(), EventHandler::Conditional(Box::new(AcceptHintWord))),
+        ];
+        // Ctrl-R searches the history on the whole screen, unless the feature is disabled
+        if self.fuzzy_history_search {
+            bindings.push((KeyEvent::ctrl('r').into(), EventHandler::Conditional(Box::new(self.history_search()))));
+        }
+        bindings
+    }
 
-        // pretty colored shell prompt
-        prompt_string.push_str(&format!("$ \x1b[1;34m{}\x1b[0m> ", working_dir));
-        
-        prompt_string
+    fn history_search(&self) -> HistorySearch {
+        HistorySearch { history: Arc::clone(&self.searched_history) }
+    }
+
+    /// Applies the editing mode chosen with `set -o vi` or `set -o emacs`, the completion and the search of the history
+    /// of the configuration, and the key bindings defined with `bind` since the last prompt
+    fn apply_editor_settings(&mut self, shell: &Shell) {
+
+        let edit_mode = if shell.is_option_set("vi") { EditMode::Vi } else { EditMode::Emacs };
+        if self.rusty_lines_editor.config_mut().edit_mode() != edit_mode {
+            self.rusty_lines_editor.set_edit_mode(edit_mode);
+        }
+        let completion = &shell.config.completion;
+        self.rusty_lines_editor.set_completion_type(match completion.style {
+            CompletionStyle::List => CompletionType::List,
+            CompletionStyle::Circular => CompletionType::Circular,
+        });
+        self.rusty_lines_editor.set_completion_prompt_limit(completion.prompt_limit);
+
+        let fuzzy_history_search = shell.config.features.fuzzy_history_search;
+        if shell.key_bindings() == self.applied_key_bindings && fuzzy_history_search == self.fuzzy_history_search {
+            return;
+        }
+        for binding in &self.applied_key_bindings {
+            self.rusty_lines_editor.unbind_sequence(key_sequence_event(&binding.keys));
+        }
+        if !fuzzy_history_search {
+            self.rusty_lines_editor.unbind_sequence(KeyEvent::ctrl('r'));
+        }
+        self.fuzzy_history_search = fuzzy_history_search;
+        // The keys which are no longer bound get their default binding back
+        for (event, handler) in self.default_bindings() {
+            self.rusty_lines_editor.bind_sequence(event, handler);
+        }
+        for binding in shell.key_bindings() {
+            let handler = match &binding.action {
+                KeyAction::Function(function) => match editor_command(function) {
+                    Some(Cmd::ReverseSearchHistory) if fuzzy_history_search => EventHandler::Conditional(Box::new(self.history_search())),
+                    Some(command) => EventHandler::Simple(command),
+                    None => continue,
+                },
+                KeyAction::Macro(text) => EventHandler::Simple(Cmd::Insert(1, text.clone())),
+                KeyAction::Command(command) => {
+                    let pending = Arc::clone(&self.pending_command);
+                    EventHandler::Conditional(Box::new(BoundCommand { command: command.clone(), pending }))
+                },
+            };
+            self.rusty_lines_editor.bind_sequence(key_sequence_event(&binding.keys), handler);
+        }
+        self.applied_key_bindings = shell.key_bindings().to_vec();
     }
 
+    /// Copies the commands added to the history of the shell since the last prompt into the history of the editor,
+    /// or all of them if some were removed, and gives the history to the search of Ctrl-R
+    fn copy_history(&mut self, shell: &Shell) -> Result<(), Box<dyn Error>> {
+
+        let shell_history = shell.history.lock();
+        let entries = shell_history.entries();
+        let history = self.rusty_lines_editor.history_mut();
+        // The editor drops the oldest commands beyond HISTSIZE by itself, like the shell
+        let size = HistoryPolicy::from_variables(shell).size.map_or(EDITOR_HISTORY_SIZE, |size| size.min(EDITOR_HISTORY_SIZE));
+        history.set_max_len(size)?;
+
+        let mut new_entries = shell_history.added().saturating_sub(self.copied_history_added) as usize;
+        if shell_history.revision() != self.copied_history_revision || shell_history.added() < self.copied_history_added {
+            history.clear()?;
+            new_entries = entries.len();
+            self.copied_history_revision = shell_history.revision();
+        }
+
+        let start = entries.len().saturating_sub(new_entries.min(size));
+        for entry in &entries[start..] {
+            history.add(&entry.command)?;
+        }
+        self.copied_history_added = shell_history.added();
+
+        let mut searched = self.searched_history.lock().map_err(|_| "the searched history is poisoned")?;
+        searched.history = shell.history.clone();
+        searched.directory = shell.get_variable("PWD").unwrap_or_default().to_string();
+        searched.home = shell.get_variable("HOME").unwrap_or_default().to_string();
+
+        Ok(())
+    }
 }
 
 impl Interaction for TerminalInteraction {
@@ -60,24 +171,35 @@ impl Interaction for TerminalInteraction {
     /// Returns the input entered by the user on the stdin
     /// 
     /// Side effects: Prints the prompt string and modifies some attributes in the struct 
-    fn receive_input(&mut self) -> Result<UserInput, Box<dyn Error>> {
+    fn receive_input(&mut self, prompt: &str, shell: &Shell) -> Result<UserInput, Box<dyn Error>> {
+
+        // The editor counts the width of the visible text of the prompt, the helper writes it with its non-printing sequences
+        let (displayed_prompt, prompt) = split_prompt(prompt);
+
+        // The completion uses the functions, aliases and variables of the shell
+        if let Some(helper) = self.rusty_lines_editor.helper_mut() {
+            *helper.shell.get_mut() = shell.clone();
+            helper.prompt = (prompt.clone(), displayed_prompt);
+        }
+        self.copy_history(shell)?;
+        self.apply_editor_settings(shell);
 
         // side effect: also prints the prompt string
-        let readline = self.rusty_lines_editor.readline(&self.get_prompt_string());
+        let readline = match self.initial_input.take() {
+            Some((line, point)) => self.rusty_lines_editor.readline_with_initial(&prompt, line.split_at(point)),
+            None => self.rusty_lines_editor.readline(&prompt),
+        };
         match readline {
-            // do nothing if the string is empty or is a bunch of spaces
-            Ok(s) if s.trim().is_empty() => {
-                Ok(UserInput::NoSpecialInput) 
-            },
             Ok(line) => {
-                // side effect: saves the line in the history
-                self.rusty_lines_editor.add_history_entry(&line)?;
-                self.save_history()?;
-
                 Ok(UserInput::String(line))
             },
             Err(ReadlineError::Interrupted) => {
-                Ok(UserInput::NoSpecialInput) // if it is ctrl c, just ignore it
+                // A key bound to a command interrupts the edition too
+                let pending = self.pending_command.lock().map_err(|_| "the pending command is poisoned")?.take();
+                Ok(match pending {
+                    Some(PendingCommand { command, line, point }) => UserInput::BoundCommand { command, line, point },
+                    None => UserInput::NoSpecialInput, // if it is ctrl c, just ignore it
+                })
             },
             Err(ReadlineError::Eof) => {
                 Ok(UserInput::Eof)
@@ -87,10 +209,8 @@ impl Interaction for TerminalInteraction {
 
     }
 
-    /// Save the previous inputs strings in the history, returns an error if any problem occurs during the saving
-    /// 
-    fn save_history(&mut self) -> Result<(), Box<dyn Error>> {
-        self.rusty_lines_editor.save_history(&self.history_path)?;
-        Ok(())
+    fn set_initial_input(&mut self, line: &str, point: usize) {
+        let point = (0..=point.min(line.len())).rev().find(|index| line.is_char_boundary(*index)).unwrap_or_default();
+        self.initial_input = Some((line.to_string(), point));
     }
 }
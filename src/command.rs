@@ -1,17 +1,21 @@
-
 //! Module related with commands execution, treatment etc
-//! 
-//! 
+//!
+//!
 
-use std::{process::Stdio};
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::OwnedFd;
+use std::rc::Rc;
 
 pub mod execution;
 pub mod builtin;
+pub mod script;
 
 /// Represents a command executable by a shell.
-/// 
+///
 /// This enum represents the abstract syntax tree of a shell command created by the parsing module.
-/// 
+/// The words are stored as they were written, they are expanded (variables, quotes...) during the execution.
+///
 #[derive(PartialEq, Debug)]
 pub enum Command {
     Simple {
@@ -30,7 +34,7 @@ pub enum Command {
     Separator { // ;
         left: Box<Command>,
         right: Box<Command>,
-    },    
+    },
     LogicalOr { // ||
         left: Box<Command>,
         right: Box<Command>,
@@ -38,7 +42,94 @@ pub enum Command {
     LogicalAnd { // &&
         left: Box<Command>,
         right: Box<Command>,
-    }
+    },
+    While { // while condition; do body; done
+        condition: Box<Command>,
+        body: Box<Command>,
+    },
+    Until { // until condition; do body; done
+        condition: Box<Command>,
+        body: Box<Command>,
+    },
+    For { // for variable in words; do body; done
+        variable: String,
+        // None when the "in" part is omitted, the loop then iterates over the positional parameters
+        words: Option<Vec<String>>,
+        body: Box<Command>,
+    },
+    ArithmeticFor { // for ((init; condition; step)); do body; done
+        init: String,
+        condition: String,
+        step: String,
+        body: Box<Command>,
+    },
+    Arithmetic { // (( expression ))
+        expression: String,
+    },
+    If { // if condition; then then_branch; else else_branch; fi
+        condition: Box<Command>,
+        then_branch: Box<Command>,
+        // An elif is represented by a nested If in the else branch
+        else_branch: Option<Box<Command>>,
+    },
+    Case { // case word in pattern | pattern) body;; esac
+        word: String,
+        items: Vec<CaseItem>,
+    },
+    Subshell { // ( body )
+        body: Box<Command>,
+    },
+    Group { // { body; }
+        body: Box<Command>,
+    },
+    Conditional { // [[ expression ]]
+        expression: ConditionalExpression,
+    },
+    Assignment { // name=value [command]
+        assignments: Vec<(String, String)>,
+        // The simple command for which the variables are assigned, None if they are assigned in the shell itself
+        command: Option<Box<Command>>,
+    },
+    FunctionDefinition { // name() { body; }
+        name: String,
+        // Shared with the shell state, which keeps the function once defined
+        body: Rc<Command>,
+    },
+}
+
+/// A branch of a case command, executed if the word matches one of its glob patterns
+#[derive(PartialEq, Debug)]
+pub struct CaseItem {
+    pub patterns: Vec<String>,
+    // None when the branch has no command
+    pub body: Option<Command>,
+    pub terminator: CaseTerminator,
+}
+
+/// An expression of the `[[ ]]` command, whose words are expanded without field splitting nor filename expansion
+#[derive(PartialEq, Debug)]
+pub enum ConditionalExpression {
+    Word(String), // true if the word is not empty
+    Unary { // -f file
+        operator: String,
+        operand: String,
+    },
+    Binary { // word == pattern
+        operator: String,
+        left: String,
+        right: String,
+    },
+    Not(Box<ConditionalExpression>),
+    And(Box<ConditionalExpression>, Box<ConditionalExpression>),
+    Or(Box<ConditionalExpression>, Box<ConditionalExpression>),
+}
+
+/// What a case command does after executing the body of a branch
+#[derive(Clone, PartialEq, Debug)]
+pub enum CaseTerminator {
+    Break,       // ;; stops the case command
+    FallThrough, // ;& also executes the body of the next branch
+    Continue,    // ;;& keeps testing the patterns of the next branches
 }
 
 #[derive(Clone, PartialEq, Debug)]
@@ -52,22 +143,58 @@ pub enum RedirectionType {
 /// Struct containing what stdin should be and where stdout and stderr should go.
 /// It may be used to specify redirections and pipe destinations, and be used for testing
 pub struct IoContext {
-    // pass Stdio as Option to make consuming the ownership easier, 
-    // None represents a IO that will be inherited from parent during execution 
-    pub stdin: Option<Stdio>, 
-    pub stdout: Option<Stdio>,
-    pub stderr: Option<Stdio>,
+    // Stored as file descriptors so that the context can be duplicated for each command of a list,
+    // None represents a IO that will be inherited from parent during execution
+    pub stdin: Option<OwnedFd>,
+    pub stdout: Option<OwnedFd>,
+    pub stderr: Option<OwnedFd>,
 }
 
 impl IoContext {
-    
+
     pub fn new() -> Self {
-        IoContext { 
-            stdin: None, 
-            stdout: None, 
-            stderr: None 
+        IoContext {
+            stdin: None,
+            stdout: None,
+            stderr: None
         }
     }
+
+    /// Duplicates the context, the file descriptors of both contexts refer to the same files
+    pub fn try_clone(&self) -> io::Result<Self> {
+
+        let clone_fd = |fd: &Option<OwnedFd>| fd.as_ref().map(OwnedFd::try_clone).transpose();
+
+        Ok(IoContext {
+            stdin: clone_fd(&self.stdin)?,
+            stdout: clone_fd(&self.stdout)?,
+            stderr: clone_fd(&self.stderr)?,
+        })
+    }
+
+    /// Duplicates only the stderr of the context, to report the errors of a command to which the context is given
+    pub fn try_clone_stderr(&self) -> io::Result<Self> {
+        Ok(IoContext {
+            stderr: self.stderr.as_ref().map(OwnedFd::try_clone).transpose()?,
+            ..IoContext::new()
+        })
+    }
+
+    /// Returns a writer on the stdout of the context, or on the shell stdout if it is inherited
+    pub fn output(&self) -> io::Result<Box<dyn Write>> {
+        Ok(match &self.stdout {
+            Some(fd) => Box::new(File::from(fd.try_clone()?)),
+            None => Box::new(io::stdout()),
+        })
+    }
+
+    /// Returns a writer on the stderr of the context, or on the shell stderr if it is inherited
+    pub fn error_output(&self) -> io::Result<Box<dyn Write>> {
+        Ok(match &self.stderr {
+            Some(fd) => Box::new(File::from(fd.try_clone()?)),
+            None => Box::new(io::stderr()),
+        })
+    }
 }
 
 impl Default for IoContext {
@@ -75,4 +202,4 @@ impl Default for IoContext {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
@@ -4,27 +4,211 @@
 //!  
 
 pub mod execution;
+pub mod condition;
+pub mod format;
+pub mod read;
+pub mod directory_stack;
 
+use std::os::unix::fs::MetadataExt;
+
+use crate::shell::Shell;
+
+/// The names of the commands executed by the shell itself
+pub const BUILTINS: [&str; 31] = [
+    "exit", "cd", "pwd", "break", "continue", "return", "local", "test", "[", "echo", "printf", "true", "false", ":", "read",
+    "export", "readonly", "unset", "set", "pushd", "popd", "dirs", "alias", "unalias", "source", ".", "complete", "compgen",
+    "history", "bind", "config",
+];
+
+/// The options of the shell changed by `set -o name` or `set -letter`
+pub const SHELL_OPTIONS: [(Option<char>, &str); 4] = [
+    (Some('x'), "xtrace"),
+    (Some('H'), "histexpand"),
+    (None, "emacs"),
+    (None, "vi"),
+];
+
+pub fn is_builtin(name: &str) -> bool {
+    BUILTINS.contains(&name)
+}
 
 // utils for builtin commands
 pub fn exit_shell(exit_code: i32) {
     std::process::exit(exit_code)
 }
 
-pub fn change_directory(to: &str) -> Result<(), Box<dyn std::error::Error>> {
-    
-    let path = std::path::Path::new(to);
-    std::env::set_current_dir(path)?;
+/// Returns the description of an io error like bash writes it, without the " (os error N)" suffix
+pub fn describe_io_error(err: &std::io::Error) -> String {
+
+    let message = err.to_string();
+    match message.rfind(" (os error ") {
+        Some(end) => message[..end].to_string(),
+        None => message,
+    }
+}
+
+/// Changes the working directory, and updates PWD and OLDPWD.
+///
+/// The path is followed logically unless `physical` is true : `..` removes the last component of $PWD
+/// instead of going to the parent of the target of a symbolic link, and PWD keeps the symbolic links
+pub fn change_directory(to: &str, shell: &mut Shell, physical: bool) -> std::io::Result<()> {
+
+    let old_directory = get_logical_working_directory(shell)?;
+
+    let new_directory = match physical {
+        true => None,
+        false => {
+            let path = normalize_logical_path(&old_directory, to);
+            // The logical path may not exist (its `..` crossing a symbolic link), the physical one is used then
+            std::env::set_current_dir(&path).ok().map(|()| path)
+        },
+    };
+    let new_directory = match new_directory {
+        Some(path) => path,
+        None => {
+            std::env::set_current_dir(to)?;
+            std::env::current_dir()?.to_string_lossy().into_owned()
+        },
+    };
+
+    shell.set_variable("OLDPWD", &old_directory);
+    shell.set_variable("PWD", &new_directory);
 
     Ok(())
 }
 
+/// Returns the physical working directory, without symbolic links
 pub fn get_working_directory() -> Result<String, Box<dyn std::error::Error>> {
     
     let path = std::env::current_dir()?;
     Ok(path.to_string_lossy().into_owned())
 }
 
+/// Returns the logical working directory, which is PWD if it is an absolute path to the working directory
+pub fn get_logical_working_directory(shell: &Shell) -> std::io::Result<String> {
+
+    let current = std::env::current_dir()?;
+    let is_current = |pwd: &&str| {
+        let (Ok(pwd), Ok(current)) = (std::fs::metadata(pwd), std::fs::metadata(&current)) else { return false };
+        pwd.dev() == current.dev() && pwd.ino() == current.ino()
+    };
+
+    match shell.get_variable("PWD").filter(|pwd| pwd.starts_with('/')).filter(is_current) {
+        Some(pwd) => Ok(pwd.to_string()),
+        None => Ok(current.to_string_lossy().into_owned()),
+    }
+}
+
+/// Joins the path to the directory and removes its `.` and `..` components without resolving the symbolic links
+pub fn normalize_logical_path(directory: &str, path: &str) -> String {
+
+    let full_path = match path.starts_with('/') {
+        true => path.to_string(),
+        false => format!("{directory}/{path}"),
+    };
+
+    let mut components: Vec<&str> = Vec::new();
+    for component in full_path.split('/') {
+        match component {
+            "" | "." => (),
+            ".." => {
+                components.pop();
+            },
+            component => components.push(component),
+        }
+    }
+
+    format!("/{}", components.join("/"))
+}
+
+/// Looks for the directory in the directories of CDPATH (the empty ones being the working directory),
+/// unless it is absolute or starts with `.` or `..`.
+///
+/// Returns the path found, and true if it was found in a directory explicitly given in CDPATH
+pub fn search_cdpath(directory: &str, shell: &Shell) -> Option<(String, bool)> {
+
+    let first_component = directory.split('/').next().unwrap_or_default();
+    if directory.starts_with('/') || first_component == "." || first_component == ".." {
+        return None;
+    }
+
+    shell.get_variable("CDPATH")?.split(':').find_map(|prefix| {
+        let path = match prefix {
+            "" => directory.to_string(),
+            prefix => format!("{}/{directory}", prefix.trim_end_matches('/')),
+        };
+        std::path::Path::new(&path).is_dir().then_some((path, !prefix.is_empty()))
+    })
+}
+
+/// Returns the number of loops exited by `break` or `continue`, given as an optional argument greater than 0
+pub fn parse_loop_count(name: &str, args: &[String]) -> Result<usize, Box<dyn std::error::Error>> {
+
+    match args {
+        [] => Ok(1),
+        [count] => match count.parse::<usize>() {
+            Ok(count) if count > 0 => Ok(count),
+            _ => Err(format!("{name}: {count}: loop count out of range").into()),
+        },
+        _ => Err(format!("{name}: too many arguments").into()),
+    }
+}
+
+/// Returns the status given to `exit` or `return` (the `name` of the built-in), the last exit status by default.
+/// Like an exit code, it is reduced modulo 256
+pub fn parse_exit_status(name: &str, args: &[String], last_status: i32) -> Result<i32, Box<dyn std::error::Error>> {
+
+    match args {
+        [] => Ok(last_status),
+        [status] => match status.parse::<i64>() {
+            Ok(status) => Ok(status.rem_euclid(256) as i32),
+            Err(_) => Err(format!("{name}: {status}: numeric argument required").into()),
+        },
+        _ => Err(format!("{name}: too many arguments").into()),
+    }
+}
+
+/// Separates the leading options of a built-in (like `-n` or `-fv`) from its arguments, `--` ending the options.
+///
+/// Returns the option letters, which must be in `allowed`, and the remaining arguments
+pub fn split_options<'a>(name: &str, args: &'a [String], allowed: &str) -> Result<(String, &'a [String]), Box<dyn std::error::Error>> {
+
+    let mut options = String::new();
+    let mut position = 0;
+
+    while let Some(arg) = args.get(position) {
+        if arg == "--" {
+            position += 1;
+            break;
+        }
+        let Some(letters) = arg.strip_prefix('-').filter(|letters| !letters.is_empty()) else { break };
+
+        for letter in letters.chars() {
+            if !allowed.contains(letter) {
+                return Err(format!("{name}: -{letter}: invalid option").into());
+            }
+            options.push(letter);
+        }
+        position += 1;
+    }
+
+    Ok((options, &args[position..]))
+}
+
+/// Splits an argument of `local`, `export` or `readonly` like `name[=value]`, checking that the name is valid
+pub fn parse_declaration<'a>(name: &str, arg: &'a str) -> Result<(&'a str, Option<&'a str>), Box<dyn std::error::Error>> {
+
+    let (variable, value) = match arg.split_once('=') {
+        Some((variable, value)) => (variable, Some(value)),
+        None => (arg, None),
+    };
+    if !crate::shell::is_valid_variable_name(variable) {
+        return Err(format!("{name}: `{arg}': not a valid identifier").into());
+    }
+
+    Ok((variable, value))
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -32,16 +216,52 @@ mod tests {
 
     #[test]
     fn cd_root_sets_working_directory_to_root() {
-        change_directory("/").unwrap();
+        let mut shell = Shell::new();
+        change_directory("/", &mut shell, false).unwrap();
         let working_dir = get_working_directory().unwrap();
         assert_eq!("/", working_dir);
+        assert_eq!(Some("/"), shell.get_variable("PWD"));
     }
 
     #[test]
     fn cd_home_sets_working_directory_to_home() {
         let home = std::env::home_dir().unwrap();
-        change_directory(home.to_str().unwrap()).unwrap();
+        change_directory(home.to_str().unwrap(), &mut Shell::new(), true).unwrap();
         let working_dir = get_working_directory().unwrap();
         assert_eq!(home.to_str().unwrap(), working_dir);
     }
+
+    #[test]
+    fn logical_path_keeps_symbolic_links() {
+        assert_eq!("/a/c", normalize_logical_path("/a/link", "../b/./../c/"));
+        assert_eq!("/", normalize_logical_path("/a", "../../.."));
+        assert_eq!("/tmp", normalize_logical_path("/a", "/tmp"));
+    }
+
+    #[test]
+    fn loop_count_defaults_to_one() {
+        assert_eq!(1, parse_loop_count("break", &[]).unwrap());
+        assert_eq!(3, parse_loop_count("break", &["3".to_string()]).unwrap());
+        assert!(parse_loop_count("continue", &["0".to_string()]).is_err());
+    }
+
+    #[test]
+    fn exit_status_defaults_to_last_status() {
+        assert_eq!(3, parse_exit_status("return", &[], 3).unwrap());
+        assert_eq!(255, parse_exit_status("return", &["-1".to_string()], 0).unwrap());
+        assert_eq!(2, parse_exit_status("exit", &["258".to_string()], 0).unwrap());
+        assert!(parse_exit_status("exit", &["x".to_string()], 0).is_err());
+    }
+
+    #[test]
+    fn options_end_at_the_first_argument() {
+        let args: Vec<String> = ["-fv", "-n", "--", "-x", "y"].iter().map(|arg| arg.to_string()).collect();
+        let (options, rest) = split_options("unset", &args, "fvn").unwrap();
+        assert_eq!("fvn", options);
+        assert_eq!(&args[3..], rest);
+
+        assert!(split_options("unset", &args, "fv").is_err());
+        assert_eq!(("a", Some("b=c")), parse_declaration("export", "a=b=c").unwrap());
+        assert!(parse_declaration("export", "a-b").is_err());
+    }
 }
\ No newline at end of file
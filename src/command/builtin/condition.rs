@@ -0,0 +1,357 @@
+//!
+//! Evaluation of the conditions of `test`, `[` and `[[ ]]` : file, string and integer tests
+//!
+
+use std::error::Error;
+use std::ffi::CString;
+use std::fs::{self, Metadata};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+use crate::arithmetic;
+use crate::command::ConditionalExpression;
+use crate::command::execution::ExecutionError;
+use crate::expansion::{expand_pattern, expand_regex, expand_word, glob};
+use crate::shell::Shell;
+
+/// The operators testing a single word, mostly a file
+pub const UNARY_OPERATORS: [&str; 22] = [
+    "-b", "-c", "-d", "-e", "-f", "-g", "-h", "-k", "-L", "-n", "-p",
+    "-r", "-s", "-S", "-t", "-u", "-v", "-w", "-x", "-z", "-O", "-G",
+];
+
+/// The operators comparing two words. `[[ ]]` also has `=~`, which compares a word to a regular expression
+pub const BINARY_OPERATORS: [&str; 14] = [
+    "=", "==", "!=", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+/// Evaluates the arguments of `test` (or of `[`, without the closing `]`).
+///
+/// The operators are, by increasing priority : `-o`, `-a`, `!`, then the tests and the parentheses
+pub fn evaluate_test(args: &[String], shell: &Shell) -> Result<bool, Box<dyn Error>> {
+
+    // Without arguments, the condition is false
+    if args.is_empty() {
+        return Ok(false);
+    }
+
+    let mut parser = TestParser { args, position: 0, shell };
+    let result = parser.parse_or()?;
+
+    match args.get(parser.position) {
+        Some(arg) => Err(format!("test: {arg}: unexpected argument").into()),
+        None => Ok(result),
+    }
+}
+
+/// Recursive descent parser evaluating the arguments of `test` as it reads them
+struct TestParser<'a> {
+    args: &'a [String],
+    position: usize,
+    shell: &'a Shell,
+}
+
+impl<'a> TestParser<'a> {
+
+    fn peek(&self, offset: usize) -> Option<&'a str> {
+        self.args.get(self.position + offset).map(String::as_str)
+    }
+
+    fn parse_or(&mut self) -> Result<bool, Box<dyn Error>> {
+        let mut result = self.parse_and()?;
+        while self.peek(0) == Some("-o") {
+            self.position += 1;
+            // Both operands are parsed, so that the syntax errors are always detected
+            result |= self.parse_and()?;
+        }
+        Ok(result)
+    }
+
+    fn parse_and(&mut self) -> Result<bool, Box<dyn Error>> {
+        let mut result = self.parse_not()?;
+        while self.peek(0) == Some("-a") {
+            self.position += 1;
+            result &= self.parse_not()?;
+        }
+        Ok(result)
+    }
+
+    fn parse_not(&mut self) -> Result<bool, Box<dyn Error>> {
+        // `test ! = x` compares "!" to "x"
+        if self.peek(0) == Some("!") && !self.peek(1).is_some_and(|arg| BINARY_OPERATORS.contains(&arg)) {
+            self.position += 1;
+            return Ok(!self.parse_not()?);
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<bool, Box<dyn Error>> {
+
+        let Some(arg) = self.peek(0) else {
+            return Err("test: argument expected".into());
+        };
+
+        if let Some(operator) = self.peek(1).filter(|operator| BINARY_OPERATORS.contains(operator)) {
+            let right = self.peek(2).ok_or_else(|| format!("test: {operator}: argument expected"))?;
+            let result = binary_test(operator, arg, right)?;
+            self.position += 3;
+            return Ok(result);
+        }
+
+        if UNARY_OPERATORS.contains(&arg) && let Some(operand) = self.peek(1) {
+            let result = unary_test(arg, operand, self.shell)?;
+            self.position += 2;
+            return Ok(result);
+        }
+
+        if arg == "(" && self.peek(1).is_some() {
+            self.position += 1;
+            let result = self.parse_or()?;
+            if self.peek(0) != Some(")") {
+                return Err("test: `)' expected".into());
+            }
+            self.position += 1;
+            return Ok(result);
+        }
+
+        // A single word is true if it is not empty
+        self.position += 1;
+        Ok(!arg.is_empty())
+    }
+}
+
+/// Evaluates the expression of a `[[ ]]` command.
+///
+/// The right operand of `==` and `!=` is a glob pattern and the one of `=~` an extended regular expression,
+/// whose groups are stored in the `BASH_REMATCH` array. The integer comparisons evaluate their operands as arithmetic expressions
+pub fn evaluate_conditional(expression: &ConditionalExpression, shell: &mut Shell) -> Result<bool, ExecutionError> {
+
+    Ok(match expression {
+        ConditionalExpression::Word(word) => !expand_word(word, shell)?.is_empty(),
+        ConditionalExpression::Unary { operator, operand } => unary_test(operator, &expand_word(operand, shell)?, shell)?,
+        ConditionalExpression::Binary { operator, left, right } => {
+            let left = expand_word(left, shell)?;
+
+            match operator.as_str() {
+                "=" | "==" => glob::matches(&expand_pattern(right, shell)?, &left),
+                "!=" => !glob::matches(&expand_pattern(right, shell)?, &left),
+                "=~" => {
+                    let groups = regex_match(&expand_regex(right, shell)?, &left)?;
+                    let matched = groups.is_some();
+                    shell.set_array("BASH_REMATCH", groups.unwrap_or_default());
+                    matched
+                },
+                "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" => {
+                    let left = arithmetic::evaluate(&left, shell)?;
+                    let right = arithmetic::evaluate(&expand_word(right, shell)?, shell)?;
+                    compare_integers(operator, left, right)
+                },
+                _ => binary_test(operator, &left, &expand_word(right, shell)?)?,
+            }
+        },
+        ConditionalExpression::Not(expression) => !evaluate_conditional(expression, shell)?,
+        ConditionalExpression::And(left, right) => evaluate_conditional(left, shell)? && evaluate_conditional(right, shell)?,
+        ConditionalExpression::Or(left, right) => evaluate_conditional(left, shell)? || evaluate_conditional(right, shell)?,
+    })
+}
+
+/// Evaluates a test on a single word, for instance `-f file` or `-n string`
+pub fn unary_test(operator: &str, operand: &str, shell: &Shell) -> Result<bool, Box<dyn Error>> {
+
+    // The tests on files are false if the file doesn't exist
+    let metadata = || fs::metadata(operand).ok();
+    let has_mode = |mode: u32| metadata().is_some_and(|metadata| metadata.mode() & mode != 0);
+
+    Ok(match operator {
+        "-n" => !operand.is_empty(),
+        "-z" => operand.is_empty(),
+        "-v" => shell.get_variable(operand).is_some(),
+        "-t" => {
+            let fd: i32 = operand.parse().map_err(|_| format!("test: {operand}: integer expression expected"))?;
+            // SAFETY: isatty only reads the file descriptor
+            unsafe { libc::isatty(fd) == 1 }
+        },
+        "-e" => metadata().is_some(),
+        "-f" => metadata().is_some_and(|metadata| metadata.is_file()),
+        "-d" => metadata().is_some_and(|metadata| metadata.is_dir()),
+        "-b" => metadata().is_some_and(|metadata| metadata.file_type().is_block_device()),
+        "-c" => metadata().is_some_and(|metadata| metadata.file_type().is_char_device()),
+        "-p" => metadata().is_some_and(|metadata| metadata.file_type().is_fifo()),
+        "-S" => metadata().is_some_and(|metadata| metadata.file_type().is_socket()),
+        "-h" | "-L" => fs::symlink_metadata(operand).is_ok_and(|metadata| metadata.file_type().is_symlink()),
+        "-s" => metadata().is_some_and(|metadata| metadata.len() > 0),
+        "-u" => has_mode(0o4000),
+        "-g" => has_mode(0o2000),
+        "-k" => has_mode(0o1000),
+        // SAFETY: geteuid and getegid can't fail
+        "-O" => metadata().is_some_and(|metadata| metadata.uid() == unsafe { libc::geteuid() }),
+        "-G" => metadata().is_some_and(|metadata| metadata.gid() == unsafe { libc::getegid() }),
+        "-r" => is_accessible(operand, libc::R_OK),
+        "-w" => is_accessible(operand, libc::W_OK),
+        "-x" => is_accessible(operand, libc::X_OK),
+        _ => return Err(format!("test: {operator}: unary operator expected").into()),
+    })
+}
+
+/// Evaluates a comparison of two words, for instance `a = b` or `1 -lt 2`
+pub fn binary_test(operator: &str, left: &str, right: &str) -> Result<bool, Box<dyn Error>> {
+
+    let modified = |path: &str| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+
+    Ok(match operator {
+        "=" | "==" => left == right,
+        "!=" => left != right,
+        "<" => left < right,
+        ">" => left > right,
+        "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" => compare_integers(operator, parse_integer(left)?, parse_integer(right)?),
+        // A file that doesn't exist is older than any other
+        "-nt" => match (modified(left), modified(right)) {
+            (Some(left), Some(right)) => left > right,
+            (left, _) => left.is_some(),
+        },
+        "-ot" => match (modified(left), modified(right)) {
+            (Some(left), Some(right)) => left < right,
+            (_, right) => right.is_some(),
+        },
+        "-ef" => match (fs::metadata(left), fs::metadata(right)) {
+            (Ok(left), Ok(right)) => is_same_file(&left, &right),
+            _ => false,
+        },
+        _ => return Err(format!("test: {operator}: binary operator expected").into()),
+    })
+}
+
+fn compare_integers(operator: &str, left: i64, right: i64) -> bool {
+    match operator {
+        "-eq" => left == right,
+        "-ne" => left != right,
+        "-lt" => left < right,
+        "-le" => left <= right,
+        "-gt" => left > right,
+        _ => left >= right,
+    }
+}
+
+fn parse_integer(word: &str) -> Result<i64, Box<dyn Error>> {
+    word.trim().parse().map_err(|_| format!("test: {word}: integer expression expected").into())
+}
+
+fn is_same_file(left: &Metadata, right: &Metadata) -> bool {
+    left.dev() == right.dev() && left.ino() == right.ino()
+}
+
+/// Returns true if the current user has the access `mode` (read, write or execute) to the file
+fn is_accessible(path: &str, mode: libc::c_int) -> bool {
+    let Ok(path) = CString::new(path) else { return false };
+    // SAFETY: the path is a valid C string that outlives the call
+    unsafe { libc::access(path.as_ptr(), mode) == 0 }
+}
+
+/// Matches the text against an extended regular expression.
+///
+/// Returns None if it doesn't match, else the matched text followed by the text of each group (empty if the group didn't match)
+fn regex_match(regex: &str, text: &str) -> Result<Option<Vec<String>>, Box<dyn Error>> {
+
+    let invalid_regex = || format!("{regex}: invalid regular expression");
+    let c_regex = CString::new(regex).map_err(|_| invalid_regex())?;
+    let c_text = CString::new(text)?;
+
+    // SAFETY: regex_t is a plain C struct, initialized by regcomp before being used
+    let mut compiled: libc::regex_t = unsafe { std::mem::zeroed() };
+    // SAFETY: the regex is a valid C string and compiled is a valid regex_t
+    if unsafe { libc::regcomp(&mut compiled, c_regex.as_ptr(), libc::REG_EXTENDED) } != 0 {
+        return Err(invalid_regex().into());
+    }
+
+    let mut matches = vec![libc::regmatch_t { rm_so: -1, rm_eo: -1 }; 1 + count_groups(regex)];
+    // SAFETY: the matches vec has the given length, and the compiled regex is freed once, after its last use
+    let result = unsafe {
+        let result = libc::regexec(&compiled, c_text.as_ptr(), matches.len(), matches.as_mut_ptr(), 0);
+        libc::regfree(&mut compiled);
+        result
+    };
+
+    if result != 0 {
+        return Ok(None);
+    }
+
+    let groups = matches.iter().map(|group| match (usize::try_from(group.rm_so), usize::try_from(group.rm_eo)) {
+        (Ok(start), Ok(end)) => String::from_utf8_lossy(&text.as_bytes()[start..end]).into_owned(),
+        _ => String::new(),
+    }).collect();
+
+    Ok(Some(groups))
+}
+
+/// Returns the number of groups of a regular expression, which are its opening parentheses
+/// that are neither escaped nor in a bracket expression
+fn count_groups(regex: &str) -> usize {
+
+    let mut count = 0;
+    let mut chars = regex.chars().peekable();
+    let mut in_bracket = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if !in_bracket => {
+                chars.next();
+            },
+            '[' if !in_bracket => {
+                in_bracket = true;
+                // A ] at the start of the bracket expression is a literal one
+                chars.next_if_eq(&'^');
+                chars.next_if_eq(&']');
+            },
+            ']' if in_bracket => in_bracket = false,
+            '(' if !in_bracket => count += 1,
+            _ => (),
+        }
+    }
+
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test(args: &str) -> bool {
+        let args: Vec<String> = args.split(' ').map(String::from).collect();
+        evaluate_test(&args, &Shell::new()).unwrap()
+    }
+
+    #[test]
+    fn test_string_and_integer_tests() {
+        assert!(test("abc = abc"));
+        assert!(test("-n x -a 3 -gt 2"));
+        assert!(test("! -z x"));
+        assert!(test("1 -eq 2 -o ( a != b )"));
+        assert!(!test("( 1 -eq 2 -o 1 -eq 3 ) -a x"));
+        assert!(test("-z"));
+        assert!(!test(""));
+    }
+
+    #[test]
+    fn test_file_tests() {
+        assert!(test("-d / -a -e /"));
+        assert!(!test("-f /"));
+        assert!(test("/ -ef /."));
+        assert!(!test("-e /nonexistent_rust_shell_file"));
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        let args = vec!["1".to_string(), "-lt".to_string(), "x".to_string()];
+        assert!(evaluate_test(&args, &Shell::new()).is_err());
+        let args = vec!["(".to_string(), "a".to_string()];
+        assert!(evaluate_test(&args, &Shell::new()).is_err());
+    }
+
+    #[test]
+    fn test_regex_groups() {
+        let groups = regex_match("^([a-z]+)-([0-9]+)?(x)?$", "abc-12").unwrap();
+        assert_eq!(Some(vec!["abc-12".to_string(), "abc".to_string(), "12".to_string(), String::new()]), groups);
+        assert_eq!(None, regex_match("^a$", "b").unwrap());
+        assert_eq!(2, count_groups("(a)[(]\\((b)"));
+        assert!(regex_match("(", "a").is_err());
+    }
+}
@@ -0,0 +1,101 @@
+//!
+//! The directory stack used by `pushd`, `popd` and `dirs`, and by the `~N` tilde expansion.
+//!
+//! Its first entry is always the working directory, followed by the directories saved in the shell state
+//!
+
+use crate::shell::Shell;
+
+/// Returns all the entries of the stack, starting with the working directory
+pub fn stack_entries(shell: &Shell) -> Vec<String> {
+    let working_directory = shell.get_variable("PWD").unwrap_or_default().to_string();
+    std::iter::once(working_directory).chain(shell.directory_stack.iter().cloned()).collect()
+}
+
+/// Converts `+N` (counting from the left, from 0) or `-N` (counting from the right) into an index of the stack.
+/// A number without sign counts from the left if `unsigned` is true, like in `~N`
+pub fn parse_stack_index(arg: &str, length: usize, unsigned: bool) -> Option<usize> {
+
+    let (from_right, number) = match arg.as_bytes().first()? {
+        b'+' => (false, &arg[1..]),
+        b'-' => (true, &arg[1..]),
+        _ if unsigned => (false, arg),
+        _ => return None,
+    };
+    if number.is_empty() || !number.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let number: usize = number.parse().ok()?;
+    match from_right {
+        false => (number < length).then_some(number),
+        true => length.checked_sub(number + 1),
+    }
+}
+
+/// Returns the entry of the stack given by `N`, `+N` or `-N`, used by the `~N` tilde expansion
+pub fn stack_entry(shell: &Shell, index: &str) -> Option<String> {
+    let mut entries = stack_entries(shell);
+    let index = parse_stack_index(index, entries.len(), true)?;
+    Some(entries.swap_remove(index))
+}
+
+/// Replaces the home directory at the start of the path by `~`
+pub fn abbreviate_home(path: &str, shell: &Shell) -> String {
+
+    match shell.get_variable("HOME").filter(|home| !home.is_empty() && *home != "/") {
+        Some(home) if path == home => "~".to_string(),
+        Some(home) => match path.strip_prefix(home).filter(|rest| rest.starts_with('/')) {
+            Some(rest) => format!("~{rest}"),
+            None => path.to_string(),
+        },
+        None => path.to_string(),
+    }
+}
+
+/// Formats the stack like `dirs` : on one line, one entry per line (`per_line`), or numbered one per line (`numbered`).
+/// The home directory is abbreviated unless `long` is true
+pub fn format_stack(shell: &Shell, long: bool, per_line: bool, numbered: bool) -> String {
+
+    let entries: Vec<String> = stack_entries(shell).iter()
+        .map(|entry| if long { entry.clone() } else { abbreviate_home(entry, shell) })
+        .collect();
+
+    if numbered {
+        entries.iter().enumerate().map(|(index, entry)| format!("{index:2}  {entry}\n")).collect()
+    } else if per_line {
+        entries.iter().map(|entry| format!("{entry}\n")).collect()
+    } else {
+        format!("{}\n", entries.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stack_index() {
+        assert_eq!(Some(0), parse_stack_index("+0", 3, false));
+        assert_eq!(Some(2), parse_stack_index("-0", 3, false));
+        assert_eq!(Some(0), parse_stack_index("-2", 3, false));
+        assert_eq!(None, parse_stack_index("-3", 3, false));
+        assert_eq!(None, parse_stack_index("+3", 3, false));
+        assert_eq!(None, parse_stack_index("1", 3, false));
+        assert_eq!(Some(1), parse_stack_index("1", 3, true));
+        assert_eq!(None, parse_stack_index("+x", 3, true));
+    }
+
+    #[test]
+    fn test_stack_formatting() {
+        let mut shell = Shell::new();
+        shell.set_variable("HOME", "/home/user");
+        shell.set_variable("PWD", "/home/user/src");
+        shell.directory_stack = vec!["/tmp".to_string(), "/home/user".to_string()];
+
+        assert_eq!("~/src /tmp ~\n", format_stack(&shell, false, false, false));
+        assert_eq!(" 0  /home/user/src\n 1  /tmp\n 2  /home/user\n", format_stack(&shell, true, false, true));
+        assert_eq!(Some("/tmp".to_string()), stack_entry(&shell, "-1"));
+        assert_eq!("/home/username", abbreviate_home("/home/username", &shell));
+    }
+}
@@ -1,36 +1,860 @@
 //!
-//! Manages the built in commands execution 
-//! 
-//! 
+//! Manages the built in commands execution
+//!
+//!
 
-use std::error::Error;
+use std::io::Write;
+use std::os::fd::AsRawFd;
 
 use crate::command::IoContext;
 use crate::command::builtin::*;
+use crate::command::builtin::condition::evaluate_test;
+use crate::command::builtin::format::{echo_text, format_local_time, format_printf, shell_quote, single_quote};
+use crate::command::builtin::directory_stack::{abbreviate_home, format_stack, parse_stack_index, stack_entries};
+use crate::command::builtin::read::{ReadEnd, is_input_available, parse_read_options, read_line, split_read_fields};
+use crate::command::execution::ExecutionError;
+use crate::command::script::{find_sourced_file, source_file};
+use crate::completion::spec::{CompletionContext, parse_complete_arguments};
+use crate::config::{Config, config_path};
+use crate::history::HistoryPolicy;
+use crate::key_binding::{EDITOR_FUNCTIONS, KeyAction, parse_binding, parse_key_sequence};
+use crate::parsing::alias::is_valid_alias_name;
+use crate::shell::{ReadonlyVariableError, Shell, is_valid_variable_name};
 
 
 /// Attempts to execute the command if the `cmd_path` is built-in command
 /// Take io_context as a reference and not ownership because we do not want to transform it
-/// 
+///
 /// Returns :
-/// - Ok(Some(())) if `cmd_path` is a built-in command
+/// - Ok(Some(exit_status)) if `cmd_path` is a built-in command
 /// - Ok(None) else
-/// - Err(_) if an error occured during execution
-///  
-pub fn try_execute_builtin(cmd_path: &str, cmd_args: &[String], _io_context: &IoContext) -> Result<Option<()>, Box<dyn Error>> {
-    
+/// - Err(_) if an error occured during execution, or if the built-in changes the control flow (break, continue, return)
+///
+pub fn try_execute_builtin(cmd_path: &str, cmd_args: &[String], shell: &mut Shell, io_context: &IoContext) -> Result<Option<i32>, ExecutionError> {
+
     match cmd_path {
-        "exit" => exit_shell(0),
-        // For now, cd takes no more arguments than the path
-        "cd" => change_directory(cmd_args.first().ok_or("cd: missing arg")?)?,
+        "exit" => {
+            let status = match parse_exit_status("exit", cmd_args, shell.last_status) {
+                Ok(status) => status,
+                // Like in bash, an invalid status still exits, with the status 2, but too many arguments don't
+                Err(err) if cmd_args.len() == 1 => {
+                    writeln!(io_context.error_output()?, "{err}")?;
+                    2
+                },
+                Err(err) => return Err(err.into()),
+            };
+            exit_shell(status);
+        },
+        "cd" => return report_failure(execute_cd(cmd_args, shell, io_context), io_context).map(Some),
         "pwd" => {
-            let working_dir = get_working_directory()?;
-            println!("{working_dir}"); // TODO write on io_context.stdout
+            let (options, _) = split_options("pwd", cmd_args, "LP")?;
+            let working_dir = match options.ends_with('P') {
+                true => get_working_directory()?,
+                false => get_logical_working_directory(shell)?,
+            };
+            writeln!(io_context.output()?, "{working_dir}")?;
+        },
+        "break" | "continue" => {
+            if shell.loop_depth == 0 {
+                return Err(ExecutionError::BuiltinExecError(format!("{cmd_path}: only meaningful in a loop")));
+            }
+            // A count greater than the number of loops exits all of them
+            let count = parse_loop_count(cmd_path, cmd_args)?.min(shell.loop_depth);
+
+            return Err(match cmd_path {
+                "break" => ExecutionError::Break(count),
+                _ => ExecutionError::Continue(count),
+            });
+        },
+        "true" | ":" => (),
+        "false" => return Ok(Some(1)),
+        "echo" => {
+            let mut output = io_context.output()?;
+            output.write_all(echo_text(cmd_args).as_bytes())?;
+            output.flush()?;
+        },
+        "printf" => return execute_printf(cmd_args, shell, io_context).map(Some),
+        "read" => return execute_read(cmd_args, shell, io_context).map(Some),
+        "export" | "readonly" => return execute_declaration(cmd_path, cmd_args, shell, io_context).map(Some),
+        "unset" => return execute_unset(cmd_args, shell, io_context).map(Some),
+        "set" => execute_set(cmd_args, shell, io_context)?,
+        "pushd" => return report_failure(execute_pushd(cmd_args, shell, io_context), io_context).map(Some),
+        "popd" => return report_failure(execute_popd(cmd_args, shell, io_context), io_context).map(Some),
+        "dirs" => return report_failure(execute_dirs(cmd_args, shell, io_context), io_context).map(Some),
+        "source" | "." => return execute_source(cmd_path, cmd_args, shell, io_context).map(Some),
+        "alias" => return execute_alias(cmd_args, shell, io_context).map(Some),
+        "unalias" => return execute_unalias(cmd_args, shell, io_context).map(Some),
+        "complete" => return execute_complete(cmd_args, shell, io_context).map(Some),
+        "compgen" => return execute_compgen(cmd_args, shell, io_context).map(Some),
+        "history" => execute_history(cmd_args, shell, io_context)?,
+        "bind" => return execute_bind(cmd_args, shell, io_context).map(Some),
+        "config" => execute_config(cmd_args, shell, io_context)?,
+        "test" | "[" => return execute_test(cmd_path, cmd_args, shell, io_context).map(Some),
+        "return" => {
+            if shell.function_depth() == 0 && shell.source_depth == 0 {
+                return Err(ExecutionError::BuiltinExecError("return: can only `return' from a function or sourced script".to_string()));
+            }
+            return Err(ExecutionError::Return(parse_exit_status("return", cmd_args, shell.last_status)?));
+        },
+        "local" => {
+            for arg in cmd_args {
+                let (name, value) = parse_declaration("local", arg)?;
+                if value.is_some() && shell.is_readonly(name) {
+                    return Err(ReadonlyVariableError(name.to_string()).into());
+                }
+                if !shell.declare_local(name, value) {
+                    return Err(ExecutionError::BuiltinExecError("local: can only be used in a function".to_string()));
+                }
+            }
         },
         _ => {
             return Ok(None);
         }
     }
 
-    Ok(Some(()))
-}
\ No newline at end of file
+    Ok(Some(0))
+}
+
+/// Writes the diagnostic of a built-in that failed on the stderr of the context, and turns it into the status 1
+fn report_failure(result: Result<(), ExecutionError>, io_context: &IoContext) -> Result<i32, ExecutionError> {
+
+    match result {
+        Ok(()) => Ok(0),
+        Err(ExecutionError::BuiltinExecError(message)) => {
+            writeln!(io_context.error_output()?, "{message}")?;
+            Ok(1)
+        },
+        Err(err) => Err(err),
+    }
+}
+
+/// Executes `test` or `[`. A malformed expression is reported on the stderr of the context, with the status 2
+fn execute_test(cmd_path: &str, args: &[String], shell: &Shell, io_context: &IoContext) -> Result<i32, ExecutionError> {
+
+    let args = match cmd_path {
+        "[" => match args.split_last() {
+            Some((last, args)) if last == "]" => args,
+            _ => {
+                writeln!(io_context.error_output()?, "[: missing `]'")?;
+                return Ok(2);
+            },
+        },
+        _ => args,
+    };
+
+    match evaluate_test(args, shell) {
+        Ok(result) => Ok(if result { 0 } else { 1 }),
+        Err(err) => {
+            writeln!(io_context.error_output()?, "{err}")?;
+            Ok(2)
+        },
+    }
+}
+
+/// Executes `printf [-v variable] format [arguments]`, the result being written or assigned to the variable.
+/// The invalid numbers are reported but don't stop the formatting
+fn execute_printf(args: &[String], shell: &mut Shell, io_context: &IoContext) -> Result<i32, ExecutionError> {
+
+    let args = args.strip_prefix(&["--".to_string()]).unwrap_or(args);
+    let (variable, args) = match args {
+        [option, variable, args @ ..] if option == "-v" => {
+            if !is_valid_variable_name(variable) {
+                return Err(ExecutionError::BuiltinExecError(format!("printf: `{variable}': not a valid identifier")));
+            }
+            (Some(variable), args)
+        },
+        _ => (None, args),
+    };
+    let Some((format, args)) = args.split_first() else {
+        return Err(ExecutionError::BuiltinExecError("printf: usage: printf [-v var] format [arguments]".to_string()));
+    };
+
+    let output = format_printf(format, args).map_err(ExecutionError::BuiltinExecError)?;
+
+    match variable {
+        Some(variable) => shell.assign_variable(variable, &output.text)?,
+        None => {
+            let mut writer = io_context.output()?;
+            writer.write_all(output.text.as_bytes())?;
+            writer.flush()?;
+        },
+    }
+
+    let mut error_output = io_context.error_output()?;
+    for arg in &output.invalid_numbers {
+        writeln!(error_output, "printf: {arg}: invalid number")?;
+    }
+
+    Ok(if output.invalid_numbers.is_empty() { 0 } else { 1 })
+}
+
+/// The status of `read` when the timeout expires, as if it was interrupted by SIGALRM
+const READ_TIMEOUT_STATUS: i32 = 128 + libc::SIGALRM;
+
+/// Executes `read [options] [name...]`, assigning the fields of a line of the input to the variables.
+/// The status is 1 at the end of the input, the variables receiving what was read anyway
+fn execute_read(args: &[String], shell: &mut Shell, io_context: &IoContext) -> Result<i32, ExecutionError> {
+
+    let options = parse_read_options(args)?;
+    let fd = options.fd.unwrap_or_else(|| io_context.stdin.as_ref().map_or(0, |stdin| stdin.as_raw_fd()));
+
+    // `read -t 0` only checks if there is something to read
+    if options.timeout.is_some_and(|timeout| timeout.is_zero()) {
+        return Ok(if is_input_available(fd) { 0 } else { 1 });
+    }
+
+    // SAFETY: isatty only reads the file descriptor
+    if let Some(prompt) = options.prompt.as_ref().filter(|_| unsafe { libc::isatty(fd) } == 1) {
+        let mut error_output = io_context.error_output()?;
+        error_output.write_all(prompt.as_bytes())?;
+        error_output.flush()?;
+    }
+
+    let (chars, end) = read_line(fd, &options)?;
+    if end == ReadEnd::TimedOut {
+        return Ok(READ_TIMEOUT_STATUS);
+    }
+
+    if let Some(name) = options.names.iter().chain(&options.array).find(|name| shell.is_readonly(name)) {
+        return Err(ReadonlyVariableError(name.clone()).into());
+    }
+
+    let ifs = shell.get_variable("IFS").unwrap_or(" \t\n").to_string();
+    if let Some(array) = &options.array {
+        shell.set_array(array, split_read_fields(&chars, &ifs, None));
+    } else if options.names.is_empty() {
+        // REPLY receives the whole line, without splitting nor trimming
+        shell.set_variable("REPLY", &chars.iter().map(|(c, _)| c).collect::<String>());
+    } else {
+        let mut fields = split_read_fields(&chars, &ifs, Some(options.names.len())).into_iter();
+        for name in &options.names {
+            shell.set_variable(name, &fields.next().unwrap_or_default());
+        }
+    }
+
+    Ok(if end == ReadEnd::EndOfFile { 1 } else { 0 })
+}
+
+/// Executes `export [-n] [-p] [name[=value]...]` or `readonly [-p] [name[=value]...]`,
+/// which assign the variables and give them the attribute, or list the variables having it
+fn execute_declaration(builtin: &str, args: &[String], shell: &mut Shell, io_context: &IoContext) -> Result<i32, ExecutionError> {
+
+    let allowed = if builtin == "export" { "np" } else { "p" };
+    let (options, args) = split_options(builtin, args, allowed)?;
+
+    if args.is_empty() {
+        let names: Vec<&str> = match builtin {
+            "export" => shell.exported_variables().into_iter().map(|(name, _)| name).collect(),
+            _ => shell.readonly_variables(),
+        };
+        let mut listing = String::new();
+        for name in names {
+            match shell.get_variable(name) {
+                Some(value) => listing.push_str(&format!("{builtin} {name}={}\n", shell_quote(value))),
+                None => listing.push_str(&format!("{builtin} {name}\n")),
+            }
+        }
+        let mut output = io_context.output()?;
+        output.write_all(listing.as_bytes())?;
+        output.flush()?;
+        return Ok(0);
+    }
+
+    for arg in args {
+        let (name, value) = parse_declaration(builtin, arg)?;
+        if let Some(value) = value {
+            shell.assign_variable(name, value)?;
+        }
+        match (builtin, options.contains('n')) {
+            ("export", true) => shell.unexport_variable(name),
+            ("export", false) => shell.export_variable(name),
+            _ => shell.make_readonly(name),
+        }
+    }
+
+    Ok(0)
+}
+
+/// Executes `unset [-f] [-v] name...`, which removes variables (or functions with `-f`).
+/// A name that is not a variable is removed from the functions if no option is given
+fn execute_unset(args: &[String], shell: &mut Shell, io_context: &IoContext) -> Result<i32, ExecutionError> {
+
+    let (options, names) = split_options("unset", args, "fv")?;
+    let mut status = 0;
+
+    for name in names {
+        let error = if options.contains('f') {
+            shell.remove_function(name);
+            None
+        } else if !is_valid_variable_name(name) {
+            Some(format!("unset: `{name}': not a valid identifier"))
+        } else if shell.is_readonly(name) {
+            Some(format!("unset: {name}: cannot unset: readonly variable"))
+        } else {
+            if shell.get_variable(name).is_none() && !options.contains('v') {
+                shell.remove_function(name);
+            }
+            shell.unset_variable(name);
+            None
+        };
+
+        if let Some(error) = error {
+            writeln!(io_context.error_output()?, "{error}")?;
+            status = 1;
+        }
+    }
+
+    Ok(status)
+}
+
+/// Executes `set [--] [argument...]`, which replaces the positional parameters by the arguments,
+/// or lists all the variables without argument
+fn execute_set(args: &[String], shell: &mut Shell, io_context: &IoContext) -> Result<(), ExecutionError> {
+
+    match args.split_first() {
+        None => {
+            let mut listing = String::new();
+            for (name, value) in shell.variables() {
+                listing.push_str(&format!("{name}={}\n", shell_quote(value)));
+            }
+            for (name, values) in shell.arrays() {
+                let values: Vec<String> = values.iter().map(|value| shell_quote(value)).collect();
+                listing.push_str(&format!("{name}=({})\n", values.join(" ")));
+            }
+            let mut output = io_context.output()?;
+            output.write_all(listing.as_bytes())?;
+            output.flush()?;
+        },
+        // `set -` ends the options without changing the parameters if nothing follows
+        Some((first, [])) if first == "-" => (),
+        Some((first, rest)) if first == "--" || first == "-" => shell.positional_parameters = rest.to_vec(),
+        Some((first, _)) if first.len() > 1 && (first.starts_with('-') || first.starts_with('+')) => {
+            return execute_set_options(args, shell, io_context);
+        },
+        Some(_) => shell.positional_parameters = args.to_vec(),
+    }
+
+    Ok(())
+}
+
+/// Executes `set [-+]letters... [-+o name]... [args]`, enabling the options with `-` and disabling them with `+`.
+/// `set -o` and `set +o` alone list the options, as a table or as commands restoring them
+fn execute_set_options(args: &[String], shell: &mut Shell, io_context: &IoContext) -> Result<(), ExecutionError> {
+
+    let invalid = |option: &str| ExecutionError::BuiltinExecError(format!("set: {option}: invalid option"));
+    let mut position = 0;
+
+    while let Some(arg) = args.get(position) {
+        let enabled = arg.starts_with('-');
+        if arg == "--" || arg == "-" {
+            shell.positional_parameters = args[position + 1..].to_vec();
+            return Ok(());
+        }
+        if arg.len() < 2 || !(enabled || arg.starts_with('+')) {
+            break;
+        }
+        position += 1;
+
+        for letter in arg[1..].chars() {
+            let name = match letter {
+                'o' => match args.get(position) {
+                    Some(name) => {
+                        position += 1;
+                        SHELL_OPTIONS.iter().find(|(_, option)| option == name).map(|(_, option)| *option)
+                            .ok_or_else(|| invalid(name))?
+                    },
+                    None => {
+                        let listing: String = SHELL_OPTIONS.iter().map(|(_, name)| {
+                            let set = shell.is_option_set(name);
+                            match enabled {
+                                true => format!("{name:15}\t{}\n", if set { "on" } else { "off" }),
+                                false => format!("set {}o {name}\n", if set { '-' } else { '+' }),
+                            }
+                        }).collect();
+                        let mut output = io_context.output()?;
+                        output.write_all(listing.as_bytes())?;
+                        output.flush()?;
+                        continue;
+                    },
+                },
+                letter => SHELL_OPTIONS.iter().find(|(option, _)| *option == Some(letter)).map(|(_, name)| *name)
+                    .ok_or_else(|| invalid(&format!("{}{letter}", &arg[..1])))?,
+            };
+            shell.set_option(name, enabled);
+            // The editing modes exclude each other
+            match (name, enabled) {
+                ("vi", true) => shell.set_option("emacs", false),
+                ("emacs", true) => shell.set_option("vi", false),
+                _ => (),
+            }
+        }
+    }
+
+    if position < args.len() {
+        shell.positional_parameters = args[position..].to_vec();
+    }
+
+    Ok(())
+}
+
+/// Executes `cd [-L|-P] [directory]`, the directory being $HOME by default and $OLDPWD for `-`.
+/// The new directory is written if it was found with CDPATH or if it is the previous one
+fn execute_cd(args: &[String], shell: &mut Shell, io_context: &IoContext) -> Result<(), ExecutionError> {
+
+    let (options, args) = split_options("cd", args, "LP")?;
+    let variable = |shell: &Shell, name: &str| shell.get_variable(name).map(str::to_string)
+        .ok_or_else(|| ExecutionError::BuiltinExecError(format!("cd: {name} not set")));
+
+    let (directory, print) = match args {
+        [] => (variable(shell, "HOME")?, false),
+        [directory] if directory == "-" => (variable(shell, "OLDPWD")?, true),
+        [directory] => search_cdpath(directory, shell).unwrap_or_else(|| (directory.clone(), false)),
+        _ => return Err(ExecutionError::BuiltinExecError("cd: too many arguments".to_string())),
+    };
+
+    // An empty directory keeps the working directory
+    if directory.is_empty() {
+        return Ok(());
+    }
+
+    change_directory(&directory, shell, options.ends_with('P'))
+        .map_err(|err| ExecutionError::BuiltinExecError(format!("cd: {directory}: {}", describe_io_error(&err))))?;
+
+    if print {
+        writeln!(io_context.output()?, "{}", shell.get_variable("PWD").unwrap_or_default())?;
+    }
+
+    Ok(())
+}
+
+/// Changes the working directory for `pushd` or `popd`, the errors being prefixed by the built-in name
+fn change_stack_directory(builtin: &str, directory: &str, shell: &mut Shell) -> Result<(), ExecutionError> {
+    change_directory(directory, shell, false)
+        .map_err(|err| ExecutionError::BuiltinExecError(format!("{builtin}: {directory}: {}", describe_io_error(&err))))
+}
+
+/// Writes the directory stack on one line, like `dirs` does after `pushd` and `popd`
+fn write_stack(shell: &Shell, io_context: &IoContext) -> Result<(), ExecutionError> {
+    let mut output = io_context.output()?;
+    output.write_all(format_stack(shell, false, false, false).as_bytes())?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Executes `pushd [-n] [directory | +N | -N]`, which saves the working directory on the stack before changing it.
+/// Without argument the two first entries are exchanged, and `+N`/`-N` rotate the stack to bring the entry N first.
+/// With `-n`, the directory is added to the stack without changing the working directory
+fn execute_pushd(args: &[String], shell: &mut Shell, io_context: &IoContext) -> Result<(), ExecutionError> {
+
+    let (options, args) = split_options("pushd", args, "n")?;
+    let no_change = options.contains('n');
+    let mut entries = stack_entries(shell);
+
+    match args {
+        [] => {
+            if entries.len() < 2 {
+                return Err(ExecutionError::BuiltinExecError("pushd: no other directory".to_string()));
+            }
+            if !no_change {
+                change_stack_directory("pushd", &entries[1], shell)?;
+            }
+            shell.directory_stack[0] = entries.swap_remove(0);
+        },
+        [arg] if arg.starts_with(['+', '-']) && arg.len() > 1 => {
+            let Some(index) = parse_stack_index(arg, entries.len(), false) else {
+                return Err(ExecutionError::BuiltinExecError(format!("pushd: {arg}: directory stack index out of range")));
+            };
+            entries.rotate_left(index);
+            if !no_change {
+                change_stack_directory("pushd", &entries[0], shell)?;
+            }
+            shell.directory_stack = entries.split_off(1);
+        },
+        [directory] => {
+            if no_change {
+                shell.directory_stack.insert(0, directory.clone());
+            } else {
+                change_stack_directory("pushd", directory, shell)?;
+                shell.directory_stack.insert(0, entries.swap_remove(0));
+            }
+        },
+        _ => return Err(ExecutionError::BuiltinExecError("pushd: too many arguments".to_string())),
+    }
+
+    write_stack(shell, io_context)
+}
+
+/// Executes `popd [-n] [+N | -N]`, which removes the first entry of the stack and changes to the next one,
+/// or removes the entry N. With `-n`, the working directory is kept and the entry after it is removed instead
+fn execute_popd(args: &[String], shell: &mut Shell, io_context: &IoContext) -> Result<(), ExecutionError> {
+
+    let (options, args) = split_options("popd", args, "n")?;
+    let no_change = options.contains('n');
+    let entries = stack_entries(shell);
+
+    if entries.len() < 2 {
+        return Err(ExecutionError::BuiltinExecError("popd: directory stack empty".to_string()));
+    }
+
+    let index = match args {
+        [] if no_change => 1,
+        [] => 0,
+        [arg] => parse_stack_index(arg, entries.len(), false)
+            .ok_or_else(|| ExecutionError::BuiltinExecError(format!("popd: {arg}: directory stack index out of range")))?,
+        _ => return Err(ExecutionError::BuiltinExecError("popd: too many arguments".to_string())),
+    };
+
+    match index {
+        0 => {
+            if !no_change {
+                change_stack_directory("popd", &entries[1], shell)?;
+            }
+            shell.directory_stack.remove(0);
+        },
+        index => {
+            shell.directory_stack.remove(index - 1);
+        },
+    }
+
+    write_stack(shell, io_context)
+}
+
+/// Executes `dirs [-clpv] [+N | -N]`, which writes the directory stack or one of its entries, or clears it (`-c`).
+/// The home directory is written `~` unless `-l` is given, `-p` writes an entry per line and `-v` numbers them
+fn execute_dirs(args: &[String], shell: &mut Shell, io_context: &IoContext) -> Result<(), ExecutionError> {
+
+    // `-N` is an index, not options
+    let (options, args) = match args.first().filter(|arg| arg.len() > 1 && arg[1..].bytes().all(|c| c.is_ascii_digit())) {
+        Some(_) => (String::new(), args),
+        None => split_options("dirs", args, "clpv")?,
+    };
+
+    if options.contains('c') {
+        shell.directory_stack.clear();
+        return Ok(());
+    }
+
+    let listing = match args {
+        [] => format_stack(shell, options.contains('l'), options.contains('p'), options.contains('v')),
+        [arg] => {
+            let mut entries = stack_entries(shell);
+            let Some(index) = parse_stack_index(arg, entries.len(), false) else {
+                return Err(ExecutionError::BuiltinExecError(format!("dirs: {arg}: directory stack index out of range")));
+            };
+            let entry = entries.swap_remove(index);
+            match options.contains('l') {
+                true => format!("{entry}\n"),
+                false => format!("{}\n", abbreviate_home(&entry, shell)),
+            }
+        },
+        _ => return Err(ExecutionError::BuiltinExecError("dirs: too many arguments".to_string())),
+    };
+
+    let mut output = io_context.output()?;
+    output.write_all(listing.as_bytes())?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Executes `alias [-p] [name[=value]...]`, which defines the aliases or writes their definition,
+/// all of them without argument. The aliases are replaced from the next parsed input
+fn execute_alias(args: &[String], shell: &mut Shell, io_context: &IoContext) -> Result<i32, ExecutionError> {
+
+    let (_, args) = split_options("alias", args, "p")?;
+    let mut listing = String::new();
+    let mut status = 0;
+
+    if args.is_empty() {
+        let mut names: Vec<&String> = shell.aliases().keys().collect();
+        names.sort();
+        for name in names {
+            listing.push_str(&format!("alias {name}={}\n", single_quote(&shell.aliases()[name])));
+        }
+    }
+
+    let mut error_output = io_context.error_output()?;
+    for arg in args {
+        match arg.split_once('=') {
+            Some((name, _)) if !is_valid_alias_name(name) => {
+                writeln!(error_output, "alias: `{name}': invalid alias name")?;
+                status = 1;
+            },
+            Some((name, value)) => shell.define_alias(name, value),
+            None => match shell.aliases().get(arg) {
+                Some(value) => listing.push_str(&format!("alias {arg}={}\n", single_quote(value))),
+                None => {
+                    writeln!(error_output, "alias: {arg}: not found")?;
+                    status = 1;
+                },
+            },
+        }
+    }
+
+    let mut output = io_context.output()?;
+    output.write_all(listing.as_bytes())?;
+    output.flush()?;
+
+    Ok(status)
+}
+
+/// Executes `unalias [-a] name...`, which removes the aliases, all of them with `-a`
+fn execute_unalias(args: &[String], shell: &mut Shell, io_context: &IoContext) -> Result<i32, ExecutionError> {
+
+    let (options, names) = split_options("unalias", args, "a")?;
+    if options.contains('a') {
+        shell.clear_aliases();
+        return Ok(0);
+    }
+    if names.is_empty() {
+        return Err(ExecutionError::BuiltinExecError("unalias: usage: unalias [-a] name [name ...]".to_string()));
+    }
+
+    let mut status = 0;
+    for name in names {
+        if !shell.remove_alias(name) {
+            writeln!(io_context.error_output()?, "unalias: {name}: not found")?;
+            status = 1;
+        }
+    }
+
+    Ok(status)
+}
+
+/// Executes `complete [-pr] [options] [name...]`, which defines how the arguments of the commands are completed,
+/// prints the definitions with `-p` (or without options) and removes them with `-r`
+fn execute_complete(args: &[String], shell: &mut Shell, io_context: &IoContext) -> Result<i32, ExecutionError> {
+
+    let arguments = parse_complete_arguments("complete", args)?;
+    let mut status = 0;
+
+    if arguments.remove {
+        if arguments.names.is_empty() {
+            shell.clear_completion_specs();
+        }
+        for name in &arguments.names {
+            if !shell.remove_completion_spec(name) {
+                writeln!(io_context.error_output()?, "complete: {name}: no completion specification")?;
+                status = 1;
+            }
+        }
+        return Ok(status);
+    }
+
+    if arguments.print || args.is_empty() {
+        let mut listing = String::new();
+        if arguments.names.is_empty() {
+            for (name, spec) in shell.completion_specs() {
+                listing.push_str(&format!("{}\n", spec.to_command(name)));
+            }
+        }
+        for name in &arguments.names {
+            match shell.completion_spec(name) {
+                Some(spec) => listing.push_str(&format!("{}\n", spec.to_command(name))),
+                None => {
+                    writeln!(io_context.error_output()?, "complete: {name}: no completion specification")?;
+                    status = 1;
+                },
+            }
+        }
+
+        let mut output = io_context.output()?;
+        output.write_all(listing.as_bytes())?;
+        output.flush()?;
+        return Ok(status);
+    }
+
+    if arguments.names.is_empty() {
+        return Err(ExecutionError::BuiltinExecError("complete: usage: complete [-pr] [-abcdefjkuv] [-o option] [-A action] [-W wordlist] [-F function] [-C command] [-X filterpat] [-P prefix] [-S suffix] [name ...]".to_string()));
+    }
+    for name in &arguments.names {
+        shell.define_completion_spec(name, arguments.spec.clone());
+    }
+
+    Ok(0)
+}
+
+/// Executes `compgen [options] [word]`, which prints the candidates of the options completing the word, one per line.
+/// The status is 1 if there is none
+fn execute_compgen(args: &[String], shell: &mut Shell, io_context: &IoContext) -> Result<i32, ExecutionError> {
+
+    let arguments = parse_complete_arguments("compgen", args)?;
+    let word = match arguments.names.as_slice() {
+        [] => String::new(),
+        [word] => word.clone(),
+        _ => return Err(ExecutionError::BuiltinExecError("compgen: too many arguments".to_string())),
+    };
+
+    let context = CompletionContext { words: vec![word.clone()], line: word.clone(), point: word.len(), word };
+    let candidates = arguments.spec.generate(&context, shell)?;
+
+    let mut output = io_context.output()?;
+    for candidate in &candidates {
+        writeln!(output, "{candidate}")?;
+    }
+    output.flush()?;
+
+    Ok(if candidates.is_empty() { 1 } else { 0 })
+}
+
+/// Executes `history [n]`, which lists the last n commands (all of them by default) with their number, preceded
+/// by their time formatted with HISTTIMEFORMAT if it is set.
+///
+/// `-c` clears the history and `-d offset` removes a command, a negative offset counting from the end. `-a`, `-r` and `-w`
+/// append the commands of this session to the file (HISTFILE by default), read it, and write it with all the commands
+fn execute_history(args: &[String], shell: &mut Shell, io_context: &IoContext) -> Result<(), ExecutionError> {
+
+    let error = |message: String| ExecutionError::BuiltinExecError(format!("history: {message}"));
+    let file_size = HistoryPolicy::from_variables(shell).file_size;
+    let mut history = shell.history.lock();
+
+    // A negative offset is not an option
+    if args.first().is_some_and(|option| option == "-d") {
+        let [_, offset] = args else {
+            return Err(error("-d: option requires an argument".to_string()));
+        };
+        let length = history.entries().len() as i64;
+        let index = match offset.parse::<i64>() {
+            Ok(offset) if offset < 0 => length + offset,
+            Ok(offset) => offset - 1,
+            Err(_) => return Err(error(format!("{offset}: numeric argument required"))),
+        };
+        if index < 0 || history.remove(index as usize).is_none() {
+            return Err(error(format!("{offset}: history position out of range")));
+        }
+        return Ok(());
+    }
+
+    let (options, args) = split_options("history", args, "carw")?;
+    if options.contains('c') {
+        history.clear();
+    }
+    if let Some(option) = options.chars().find(|option| "arw".contains(*option)) {
+        let path = match args {
+            [] => history.path().map(std::path::Path::to_path_buf).ok_or_else(|| error("no history file".to_string()))?,
+            [file] => std::path::PathBuf::from(file),
+            _ => return Err(error("too many arguments".to_string())),
+        };
+        let result = match option {
+            'a' => history.append_session(&path, file_size),
+            'r' => history.read_file(&path),
+            _ => history.write_file(&path),
+        };
+        return result.map_err(|err| error(format!("{}: {err}", path.display())));
+    }
+    if !options.is_empty() {
+        return Ok(());
+    }
+
+    let entries = history.entries();
+    let count = match args {
+        [] => entries.len(),
+        [count] => count.parse::<usize>().map_err(|_| error(format!("{count}: numeric argument required")))?,
+        _ => return Err(error("too many arguments".to_string())),
+    };
+    let time_format = shell.get_variable("HISTTIMEFORMAT");
+
+    let mut listing = String::new();
+    for (index, entry) in entries.iter().enumerate().skip(entries.len().saturating_sub(count)) {
+        let time = match time_format {
+            // The time of the commands read from a file without it is unknown
+            Some(_) if entry.timestamp == 0 => "??".to_string(),
+            Some(format) => format_local_time(entry.timestamp, format),
+            None => String::new(),
+        };
+        listing.push_str(&format!("{:5}  {time}{}\n", index + 1, entry.command));
+    }
+
+    let mut output = io_context.output()?;
+    output.write_all(listing.as_bytes())?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Executes `bind [-x] definition...`, which binds key sequences to functions of the line editor or to macros,
+/// like `bind '"\C-a": end-of-line'`, or to shell commands with `-x`, like `bind -x '"\C-g": git status'`.
+/// `bind 'set editing-mode vi'` is `set -o vi`.
+///
+/// `-l` lists the names of the functions, `-p` and `-X` print the bindings to functions or macros and to commands,
+/// and `-r` removes the bindings of the key sequences
+fn execute_bind(args: &[String], shell: &mut Shell, io_context: &IoContext) -> Result<i32, ExecutionError> {
+
+    let (options, args) = split_options("bind", args, "lpXrx")?;
+    let mut listing = String::new();
+    let mut status = 0;
+
+    if options.contains('l') {
+        for name in EDITOR_FUNCTIONS {
+            listing.push_str(&format!("{name}\n"));
+        }
+    }
+    for binding in shell.key_bindings() {
+        let command = matches!(binding.action, KeyAction::Command(_));
+        if (command && options.contains('X')) || (!command && options.contains('p')) {
+            listing.push_str(&format!("{}\n", binding.to_definition()));
+        }
+    }
+
+    for arg in args {
+        let result = match arg.strip_prefix("set ") {
+            _ if options.contains('r') => parse_key_sequence(arg).and_then(|keys| match shell.unbind_keys(&keys) {
+                true => Ok(()),
+                false => Err(format!("{arg}: not bound")),
+            }),
+            Some(variable) => match variable.split_whitespace().collect::<Vec<_>>().as_slice() {
+                ["editing-mode", mode @ ("vi" | "emacs")] => {
+                    shell.set_option(mode, true);
+                    shell.set_option(if *mode == "vi" { "emacs" } else { "vi" }, false);
+                    Ok(())
+                },
+                _ => Err(format!("{variable}: unknown variable or value")),
+            },
+            None => parse_binding(arg, options.contains('x')).map(|binding| shell.bind_keys(binding)),
+        };
+        if let Err(err) = result {
+            writeln!(io_context.error_output()?, "bind: {err}")?;
+            status = 1;
+        }
+    }
+
+    let mut output = io_context.output()?;
+    output.write_all(listing.as_bytes())?;
+    output.flush()?;
+    Ok(status)
+}
+
+/// Executes `config reload`, which reads the configuration file again and applies it, `config check [file]`, which
+/// only validates it (or another file), and `config path`, which prints where it is
+fn execute_config(args: &[String], shell: &mut Shell, io_context: &IoContext) -> Result<(), ExecutionError> {
+
+    let error = |message: String| ExecutionError::BuiltinExecError(format!("config: {message}"));
+    let path = config_path(shell).ok_or_else(|| error("HOME not set".to_string()))?;
+    let load = |path: &std::path::Path| Config::load(path).map_err(|err| error(format!("{}: {err}", path.display())));
+
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["reload"] => load(&path)?.apply(shell),
+        ["check"] => {
+            load(&path)?;
+        },
+        ["check", file] => {
+            let text = std::fs::read_to_string(file).map_err(|err| error(format!("{file}: {err}")))?;
+            Config::parse(&text).map_err(|err| error(format!("{file}: {err}")))?;
+        },
+        ["path"] => writeln!(io_context.output()?, "{}", path.display())?,
+        _ => return Err(error("usage: config reload | check [file] | path".to_string())),
+    }
+
+    Ok(())
+}
+
+/// Executes `source file [argument...]` (or `. file`), which executes the commands of the file in the current shell
+fn execute_source(builtin: &str, args: &[String], shell: &mut Shell, io_context: &IoContext) -> Result<i32, ExecutionError> {
+
+    let Some((name, args)) = args.split_first() else {
+        return Err(ExecutionError::BuiltinExecError(format!("{builtin}: filename argument required")));
+    };
+    let Some(path) = find_sourced_file(name, shell) else {
+        return Err(ExecutionError::BuiltinExecError(format!("{builtin}: {name}: file not found")));
+    };
+
+    source_file(&path, args, shell, io_context)
+}
@@ -0,0 +1,615 @@
+//!
+//! Formatting of the text written by `echo` and `printf`
+//!
+
+/// Returns the text written by `echo`.
+///
+/// The options come first : `-n` removes the trailing newline, `-e` interprets the backslash escapes and `-E` doesn't.
+/// An argument that is not only made of these options is the start of the text
+pub fn echo_text(args: &[String]) -> String {
+
+    let mut newline = true;
+    let mut escapes = false;
+    let mut position = 0;
+
+    while let Some(flags) = args.get(position).and_then(|arg| arg.strip_prefix('-')) {
+        if flags.is_empty() || !flags.chars().all(|flag| matches!(flag, 'n' | 'e' | 'E')) {
+            break;
+        }
+        for flag in flags.chars() {
+            match flag {
+                'n' => newline = false,
+                'e' => escapes = true,
+                _ => escapes = false,
+            }
+        }
+        position += 1;
+    }
+
+    let mut text = args[position..].join(" ");
+    if escapes {
+        let (interpreted, stopped) = interpret_escapes(&text, true);
+        // \c stops the output, the newline included
+        if stopped {
+            return interpreted;
+        }
+        text = interpreted;
+    }
+    if newline {
+        text.push('\n');
+    }
+
+    text
+}
+
+/// Replaces the backslash escapes of the text (`\n`, `\t`, `\x41`...) by the characters they represent.
+///
+/// The octal escapes are `\0nnn` if `zero_prefixed_octal` is true (`echo -e` and `%b`), else `\nnn` (`printf` format).
+/// Returns the text and true if it was cut by `\c`, which means that nothing else should be written
+pub fn interpret_escapes(text: &str, zero_prefixed_octal: bool) -> (String, bool) {
+
+    let chars: Vec<char> = text.chars().collect();
+    let mut result = String::new();
+    let mut position = 0;
+
+    while position < chars.len() {
+        let c = chars[position];
+        position += 1;
+
+        let Some(&escaped) = chars.get(position).filter(|_| c == '\\') else {
+            result.push(c);
+            continue;
+        };
+        position += 1;
+
+        let code = match escaped {
+            'a' => Some(0x07),
+            'b' => Some(0x08),
+            'e' | 'E' => Some(0x1b),
+            'f' => Some(0x0c),
+            'n' => Some(0x0a),
+            'r' => Some(0x0d),
+            't' => Some(0x09),
+            'v' => Some(0x0b),
+            '\\' => Some(0x5c),
+            'c' => return (result, true),
+            '0' if zero_prefixed_octal => Some(read_digits(&chars, &mut position, 8, 3).unwrap_or(0)),
+            '0'..='7' if !zero_prefixed_octal => {
+                // the first digit is already read
+                position -= 1;
+                read_digits(&chars, &mut position, 8, 3)
+            },
+            'x' => read_digits(&chars, &mut position, 16, 2),
+            'u' => read_digits(&chars, &mut position, 16, 4),
+            'U' => read_digits(&chars, &mut position, 16, 8),
+            _ => None,
+        };
+
+        match code {
+            // The octal escapes represent a byte
+            Some(code) if matches!(escaped, '0'..='7') => result.push(char::from((code & 0xff) as u8)),
+            Some(code) => result.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)),
+            // Not an escape, or an escape without its digits, which is kept as is
+            None => {
+                result.push('\\');
+                result.push(escaped);
+            },
+        }
+    }
+
+    (result, false)
+}
+
+/// Reads at most `max_length` digits in the radix, returns None if there is none
+fn read_digits(chars: &[char], position: &mut usize, radix: u32, max_length: usize) -> Option<u32> {
+
+    let mut value = None;
+    for _ in 0..max_length {
+        let Some(digit) = chars.get(*position).and_then(|c| c.to_digit(radix)) else { break };
+        value = Some(value.unwrap_or(0) * radix + digit);
+        *position += 1;
+    }
+
+    value
+}
+
+/// The result of `printf`
+pub struct PrintfOutput {
+    pub text: String,
+    /// The arguments that were not valid numbers, which were formatted as 0
+    pub invalid_numbers: Vec<String>,
+}
+
+/// Formats the arguments like the `printf` command.
+///
+/// The format is reused as long as there are arguments left, and the missing arguments are empty strings or zeros.
+/// Returns an error if the format is invalid
+pub fn format_printf(format: &str, args: &[String]) -> Result<PrintfOutput, String> {
+
+    let pieces = parse_format(format)?;
+    let mut arguments = Arguments { args, position: 0, invalid_numbers: Vec::new() };
+    let mut text = String::new();
+
+    'format: loop {
+        for piece in &pieces {
+            match piece {
+                FormatPiece::Text(literal) => text.push_str(literal),
+                FormatPiece::Stop => break 'format,
+                FormatPiece::Conversion(specification) => {
+                    if specification.format(&mut arguments, &mut text) {
+                        break 'format;
+                    }
+                },
+            }
+        }
+
+        // The format is used once if it doesn't consume the arguments
+        if arguments.position == 0 || arguments.position >= args.len() {
+            break;
+        }
+    }
+
+    Ok(PrintfOutput { text, invalid_numbers: arguments.invalid_numbers })
+}
+
+enum FormatPiece {
+    /// Text written as is, whose escapes are already interpreted
+    Text(String),
+    /// A conversion like `%-5d`
+    Conversion(ConversionSpecification),
+    /// `\c`, which stops the output
+    Stop,
+}
+
+/// A width or a precision, given in the format or by an argument with `*`
+enum Count {
+    Fixed(usize),
+    FromArgument,
+}
+
+struct ConversionSpecification {
+    flags: String,
+    width: Option<Count>,
+    precision: Option<Count>,
+    conversion: char,
+}
+
+fn parse_format(format: &str) -> Result<Vec<FormatPiece>, String> {
+
+    let chars: Vec<char> = format.chars().collect();
+    let mut pieces = Vec::new();
+    let mut literal = String::new();
+    let mut position = 0;
+
+    while position < chars.len() {
+        if chars[position] != '%' {
+            // The literal text is read until the next conversion, then its escapes are interpreted
+            let end = chars[position..].iter().position(|c| *c == '%').map_or(chars.len(), |offset| position + offset);
+            literal.extend(&chars[position..end]);
+            position = end;
+            continue;
+        }
+
+        if chars.get(position + 1) == Some(&'%') {
+            literal.push_str("%%");
+            position += 2;
+            continue;
+        }
+
+        push_literal(&mut pieces, std::mem::take(&mut literal));
+        let (specification, end) = parse_conversion(&chars, position + 1)?;
+        pieces.push(FormatPiece::Conversion(specification));
+        position = end;
+    }
+    push_literal(&mut pieces, literal);
+
+    Ok(pieces)
+}
+
+/// Pushes the literal text with its escapes interpreted, followed by a stop if it contains `\c`
+fn push_literal(pieces: &mut Vec<FormatPiece>, literal: String) {
+
+    if literal.is_empty() {
+        return;
+    }
+
+    let (text, stopped) = interpret_escapes(&literal, false);
+    pieces.push(FormatPiece::Text(text.replace("%%", "%")));
+    if stopped {
+        pieces.push(FormatPiece::Stop);
+    }
+}
+
+/// Parses the conversion specification starting after the `%`, returns it with the position after it
+fn parse_conversion(chars: &[char], mut position: usize) -> Result<(ConversionSpecification, usize), String> {
+
+    let start = position;
+
+    let flags: String = chars[position..].iter().take_while(|c| matches!(c, '-' | '+' | ' ' | '#' | '0')).collect();
+    position += flags.len();
+
+    let read_count = |position: &mut usize| -> Option<Count> {
+        if chars.get(*position) == Some(&'*') {
+            *position += 1;
+            return Some(Count::FromArgument);
+        }
+        let digits: String = chars[*position..].iter().take_while(|c| c.is_ascii_digit()).collect();
+        *position += digits.len();
+        digits.parse().ok().map(Count::Fixed)
+    };
+
+    let width = read_count(&mut position);
+    let precision = match chars.get(position) {
+        Some('.') => {
+            position += 1;
+            // A precision without digits is 0
+            Some(read_count(&mut position).unwrap_or(Count::Fixed(0)))
+        },
+        _ => None,
+    };
+
+    match chars.get(position) {
+        Some(&conversion) if "diouxXeEfFgGcsbq".contains(conversion) => {
+            Ok((ConversionSpecification { flags, width, precision, conversion }, position + 1))
+        },
+        Some(c) => Err(format!("printf: %{}{c}: invalid format character", chars[start..position].iter().collect::<String>())),
+        None => Err(String::from("printf: missing format character")),
+    }
+}
+
+/// The arguments of printf, consumed by the conversions
+struct Arguments<'a> {
+    args: &'a [String],
+    position: usize,
+    invalid_numbers: Vec<String>,
+}
+
+impl Arguments<'_> {
+
+    fn next_string(&mut self) -> String {
+        let arg = self.args.get(self.position).cloned().unwrap_or_default();
+        self.position += 1;
+        arg
+    }
+
+    /// Returns the next argument as an integer : decimal, octal, hexadecimal, or the code of the character following a quote
+    fn next_integer(&mut self) -> i64 {
+        let arg = self.next_string();
+        let trimmed = arg.trim_start();
+
+        if let Some(quoted) = trimmed.strip_prefix(['\'', '"']) {
+            return quoted.chars().next().map_or(0, |c| c as i64);
+        }
+        if trimmed.is_empty() {
+            return 0;
+        }
+
+        let (negative, digits) = match trimmed.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+        };
+        let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+            u64::from_str_radix(hex, 16)
+        } else if digits.len() > 1 && digits.starts_with('0') {
+            u64::from_str_radix(&digits[1..], 8)
+        } else {
+            digits.parse::<u64>()
+        };
+
+        match value {
+            // Like in C, the values are converted to 64 bits signed integers
+            Ok(value) if negative => (value as i64).wrapping_neg(),
+            Ok(value) => value as i64,
+            Err(_) => {
+                self.invalid_numbers.push(arg);
+                0
+            },
+        }
+    }
+
+    fn next_float(&mut self) -> f64 {
+        let arg = self.next_string();
+        let trimmed = arg.trim();
+
+        if let Some(quoted) = trimmed.strip_prefix(['\'', '"']) {
+            return quoted.chars().next().map_or(0.0, |c| c as u32 as f64);
+        }
+        if trimmed.is_empty() {
+            return 0.0;
+        }
+
+        trimmed.parse().unwrap_or_else(|_| {
+            self.invalid_numbers.push(arg);
+            0.0
+        })
+    }
+
+    fn next_count(&mut self, count: &Option<Count>) -> Option<usize> {
+        match count {
+            Some(Count::Fixed(count)) => Some(*count),
+            // A negative width is a left aligned one, handled with the flags
+            Some(Count::FromArgument) => Some(self.next_integer().unsigned_abs() as usize),
+            None => None,
+        }
+    }
+}
+
+impl ConversionSpecification {
+
+    /// Formats the next arguments and pushes the result to the text.
+    /// Returns true if the output should stop, after a `\c` in a `%b` argument
+    fn format(&self, arguments: &mut Arguments, text: &mut String) -> bool {
+
+        let width = arguments.next_count(&self.width);
+        let precision = arguments.next_count(&self.precision);
+        let has_flag = |flag: char| self.flags.contains(flag);
+
+        // The sign or the base prefix of a number, and its digits
+        let (prefix, body, numeric) = match self.conversion {
+            'd' | 'i' => {
+                let value = arguments.next_integer();
+                let sign = if value < 0 { "-" } else if has_flag('+') { "+" } else if has_flag(' ') { " " } else { "" };
+                (sign.to_string(), pad_digits(value.unsigned_abs().to_string(), precision), true)
+            },
+            'o' | 'u' | 'x' | 'X' => {
+                let value = arguments.next_integer() as u64;
+                let digits = match self.conversion {
+                    'o' => format!("{value:o}"),
+                    'u' => value.to_string(),
+                    'x' => format!("{value:x}"),
+                    _ => format!("{value:X}"),
+                };
+                let digits = pad_digits(digits, precision);
+                let prefix = match self.conversion {
+                    'o' if has_flag('#') && !digits.starts_with('0') => "0",
+                    'x' if has_flag('#') && value != 0 => "0x",
+                    'X' if has_flag('#') && value != 0 => "0X",
+                    _ => "",
+                };
+                (prefix.to_string(), digits, true)
+            },
+            'e' | 'E' | 'f' | 'F' | 'g' | 'G' => {
+                let value = arguments.next_float();
+                let sign = if value.is_sign_negative() && !value.is_nan() { "-" } else if has_flag('+') { "+" } else if has_flag(' ') { " " } else { "" };
+                (sign.to_string(), format_float(value.abs(), self.conversion, precision.unwrap_or(6), has_flag('#')), value.is_finite())
+            },
+            'c' => (String::new(), arguments.next_string().chars().take(1).collect(), false),
+            'b' => {
+                let (value, stopped) = interpret_escapes(&arguments.next_string(), true);
+                text.push_str(&pad(String::new(), truncate(value, precision), width, &self.flags, false));
+                return stopped;
+            },
+            'q' => (String::new(), shell_quote(&arguments.next_string()), false),
+            _ => (String::new(), truncate(arguments.next_string(), precision), false),
+        };
+
+        // The 0 flag is ignored by the integer conversions with a precision
+        let zero_padded = numeric && !(precision.is_some() && "diouxX".contains(self.conversion));
+        text.push_str(&pad(prefix, body, width, &self.flags, zero_padded));
+        false
+    }
+}
+
+/// Adds leading zeros to the digits so that there are at least `precision` digits
+fn pad_digits(digits: String, precision: Option<usize>) -> String {
+    match precision {
+        Some(0) if digits == "0" => String::new(),
+        Some(precision) if digits.len() < precision => format!("{}{digits}", "0".repeat(precision - digits.len())),
+        _ => digits,
+    }
+}
+
+fn truncate(text: String, precision: Option<usize>) -> String {
+    match precision {
+        Some(precision) => text.chars().take(precision).collect(),
+        None => text,
+    }
+}
+
+/// Pads the prefix and the body to the width : on the right with the `-` flag, with zeros after the prefix
+/// with the `0` flag if `zero_padded` is true, else on the left with spaces
+fn pad(prefix: String, body: String, width: Option<usize>, flags: &str, zero_padded: bool) -> String {
+
+    let length = prefix.chars().count() + body.chars().count();
+    let padding = width.unwrap_or(0).saturating_sub(length);
+
+    if flags.contains('-') {
+        format!("{prefix}{body}{}", " ".repeat(padding))
+    } else if flags.contains('0') && zero_padded {
+        format!("{prefix}{}{body}", "0".repeat(padding))
+    } else {
+        format!("{}{prefix}{body}", " ".repeat(padding))
+    }
+}
+
+/// Formats a positive float like C does with `%f`, `%e` and `%g`
+fn format_float(value: f64, conversion: char, precision: usize, alternate: bool) -> String {
+
+    if !value.is_finite() {
+        let text = if value.is_nan() { "nan" } else { "inf" };
+        return if conversion.is_uppercase() { text.to_uppercase() } else { text.to_string() };
+    }
+
+    let text = match conversion.to_ascii_lowercase() {
+        'f' => format!("{value:.precision$}"),
+        'e' => format_exponent(value, precision),
+        _ => {
+            // %g uses the shortest of %e and %f, with `precision` significant digits
+            let precision = precision.max(1);
+            let exponent = decimal_exponent(value, precision);
+            let text = if exponent < -4 || exponent >= precision as i32 {
+                format_exponent(value, precision - 1)
+            } else {
+                format!("{value:.*}", (precision as i32 - 1 - exponent) as usize)
+            };
+            if alternate { text } else { remove_trailing_zeros(text) }
+        },
+    };
+
+    if conversion.is_uppercase() { text.to_uppercase() } else { text }
+}
+
+/// Formats the value as `d.ddde+XX`, the exponent having at least two digits
+fn format_exponent(value: f64, precision: usize) -> String {
+
+    let text = format!("{value:.precision$e}");
+    let (mantissa, exponent) = text.split_once('e').unwrap_or((&text, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+
+    format!("{mantissa}e{}{:02}", if exponent < 0 { '-' } else { '+' }, exponent.abs())
+}
+
+/// Returns the exponent of the value written in scientific notation with `precision` significant digits
+fn decimal_exponent(value: f64, precision: usize) -> i32 {
+    let text = format!("{value:.*e}", precision - 1);
+    text.split_once('e').and_then(|(_, exponent)| exponent.parse().ok()).unwrap_or(0)
+}
+
+/// Removes the trailing zeros of the decimal part of the mantissa, and the decimal point if nothing is left after it
+fn remove_trailing_zeros(text: String) -> String {
+
+    let (mantissa, exponent) = match text.find('e') {
+        Some(index) => text.split_at(index),
+        None => (text.as_str(), ""),
+    };
+
+    if !mantissa.contains('.') {
+        return text;
+    }
+    format!("{}{exponent}", mantissa.trim_end_matches('0').trim_end_matches('.'))
+}
+
+/// Quotes the text between single quotes, like `alias` writes the values
+pub fn single_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// Quotes the text between single quotes only if it is empty or has special characters, like the traces of `set -x`
+pub fn quote_if_needed(text: &str) -> String {
+    match text.is_empty() || !text.chars().all(|c| c.is_alphanumeric() || "_-./:,+@%=".contains(c)) {
+        true => single_quote(text),
+        false => text.to_string(),
+    }
+}
+
+/// Formats the time given in seconds since the epoch in the local time zone, with a `strftime` format
+pub fn format_local_time(timestamp: i64, format: &str) -> String {
+
+    let Ok(format) = std::ffi::CString::new(format) else { return String::new() };
+    let time = timestamp as libc::time_t;
+
+    // SAFETY: tm is a plain C struct filled by localtime_r
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    // SAFETY: localtime_r only reads the time and writes the given struct
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return String::new();
+    }
+
+    let mut buffer = vec![0u8; 256];
+    // SAFETY: strftime writes at most buffer.len() bytes, and returns the number of bytes written
+    let length = unsafe { libc::strftime(buffer.as_mut_ptr().cast(), buffer.len(), format.as_ptr(), &tm) };
+    String::from_utf8_lossy(&buffer[..length]).into_owned()
+}
+
+/// Returns the current time in seconds since the epoch
+pub fn current_timestamp() -> i64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |duration| duration.as_secs() as i64)
+}
+
+/// Quotes the text so that it can be reused as a shell word, like `printf %q`
+pub fn shell_quote(text: &str) -> String {
+
+    if text.is_empty() {
+        return String::from("''");
+    }
+
+    // The control characters can only be written in a $'...' string
+    if text.chars().any(char::is_control) {
+        let mut quoted = String::from("$'");
+        for c in text.chars() {
+            match c {
+                '\n' => quoted.push_str("\\n"),
+                '\t' => quoted.push_str("\\t"),
+                '\r' => quoted.push_str("\\r"),
+                '\x1b' => quoted.push_str("\\E"),
+                '\'' | '\\' => {
+                    quoted.push('\\');
+                    quoted.push(c);
+                },
+                c if c.is_control() => quoted.push_str(&format!("\\{:03o}", c as u32)),
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('\'');
+        return quoted;
+    }
+
+    let mut quoted = String::new();
+    for c in text.chars() {
+        if !(c.is_alphanumeric() || "_-./:,+@%=".contains(c)) {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn printf(format: &str, args: &[&str]) -> String {
+        format_printf(format, &strings(args)).unwrap().text
+    }
+
+    #[test]
+    fn test_echo_options() {
+        assert_eq!("a b\n", echo_text(&strings(&["a", "b"])));
+        assert_eq!("a\tb", echo_text(&strings(&["-ne", "a\\tb"])));
+        assert_eq!("a\\tb\n", echo_text(&strings(&["-eE", "a\\tb"])));
+        assert_eq!("-x a\n", echo_text(&strings(&["-x", "a"])));
+        assert_eq!("A", echo_text(&strings(&["-e", "\\0101\\c", "never"])));
+    }
+
+    #[test]
+    fn test_printf_conversions() {
+        assert_eq!("[  42|-7  |+003|2a|0X2A|017]", printf("[%4d|%-4d|%+04d|%x|%#X|%#o]", &["42", "-7", "3", "42", "42", "15"]));
+        assert_eq!("[abc|  ab|a]", printf("[%s|%4.2s|%c]", &["abc", "abc", "abc"]));
+        assert_eq!("3.14 1.500000e+03 0.0001 1e+06 65", printf("%.2f %e %g %g %d", &["3.14159", "1500", "0.0001", "1000000", "'A"]));
+        assert_eq!("  x", printf("%*s", &["3", "x"]));
+        assert_eq!("100%\n", printf("%d%%\\n", &["100"]));
+    }
+
+    #[test]
+    fn test_printf_reuses_the_format() {
+        assert_eq!("a=1\nb=2\nc=\n", printf("%s=%s\\n", &["a", "1", "b", "2", "c"]));
+        assert_eq!("x", printf("x", &["unused"]));
+    }
+
+    #[test]
+    fn test_printf_escapes_and_quoting() {
+        assert_eq!("a\nb", printf("%b", &["a\\nb\\c", "never"]));
+        assert_eq!("A\x1b", printf("\\101\\e", &[]));
+        assert_eq!("a\\ b\\'c '' $'x\\ny'", printf("%q %q %q", &["a b'c", "", "x\ny"]));
+    }
+
+    #[test]
+    fn test_printf_errors() {
+        let output = format_printf("%d %d", &strings(&["12", "abc"])).unwrap();
+        assert_eq!("12 0", output.text);
+        assert_eq!(vec!["abc".to_string()], output.invalid_numbers);
+
+        assert!(format_printf("%z", &[]).is_err());
+    }
+
+    #[test]
+    fn test_quoting_and_time() {
+        assert_eq!("plain-word.txt 'a b' '' 'it'\\''s'", ["plain-word.txt", "a b", "", "it's"].map(quote_if_needed).join(" "));
+        assert_eq!("1970", format_local_time(3600 * 24 * 180, "%Y"));
+        assert_eq!("", format_local_time(0, ""));
+    }
+}
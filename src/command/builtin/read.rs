@@ -0,0 +1,372 @@
+//!
+//! The `read` built-in, which reads a line from its standard input into variables.
+//!
+//! The input is read byte by byte, so that nothing after the line is consumed : the next command
+//! reading the same input starts right after it.
+//!
+
+use std::error::Error;
+use std::io;
+use std::os::fd::RawFd;
+use std::time::{Duration, Instant};
+
+/// The options of `read [-rs] [-a array] [-d delim] [-n count] [-p prompt] [-t timeout] [-u fd] [name...]`
+#[derive(Debug, PartialEq)]
+pub struct ReadOptions {
+    /// -r : the backslashes are not escape characters
+    pub raw: bool,
+    /// -s : the characters typed on a terminal are not echoed
+    pub silent: bool,
+    /// -a : the fields are assigned to this array instead of the names
+    pub array: Option<String>,
+    /// -d : the line ends with this character instead of a newline, the empty string being the NUL character
+    pub delimiter: u8,
+    /// -n : the reading stops after this number of characters
+    pub count: Option<usize>,
+    /// -p : written on stderr before reading, if the input is a terminal
+    pub prompt: Option<String>,
+    /// -t : the reading fails if the line is not complete after this duration
+    pub timeout: Option<Duration>,
+    /// -u : the file descriptor to read instead of the standard input
+    pub fd: Option<RawFd>,
+    /// The variables receiving the fields, REPLY receiving the whole line if there is none
+    pub names: Vec<String>,
+}
+
+/// Parses the options of `read`, which may be grouped like `-rp prompt` or `-n1`
+pub fn parse_read_options(args: &[String]) -> Result<ReadOptions, Box<dyn Error>> {
+
+    let mut options = ReadOptions {
+        raw: false,
+        silent: false,
+        array: None,
+        delimiter: b'\n',
+        count: None,
+        prompt: None,
+        timeout: None,
+        fd: None,
+        names: Vec::new(),
+    };
+
+    let mut position = 0;
+    while let Some(arg) = args.get(position) {
+        position += 1;
+
+        if arg == "--" {
+            break;
+        }
+        let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+            position -= 1;
+            break;
+        };
+
+        for (index, flag) in flags.char_indices() {
+            match flag {
+                'r' => options.raw = true,
+                's' => options.silent = true,
+                'a' | 'd' | 'n' | 'p' | 't' | 'u' => {
+                    // The value is the rest of the argument, or the next argument
+                    let value = match &flags[index + flag.len_utf8()..] {
+                        "" => {
+                            position += 1;
+                            args.get(position - 1).ok_or_else(|| format!("read: -{flag}: option requires an argument"))?.as_str()
+                        },
+                        rest => rest,
+                    };
+                    set_option_value(&mut options, flag, value)?;
+                    break;
+                },
+                _ => return Err(format!("read: -{flag}: invalid option").into()),
+            }
+        }
+    }
+
+    options.names = args[position..].to_vec();
+    for name in options.names.iter().chain(&options.array) {
+        if !crate::shell::is_valid_variable_name(name) {
+            return Err(format!("read: `{name}': not a valid identifier").into());
+        }
+    }
+
+    Ok(options)
+}
+
+fn set_option_value(options: &mut ReadOptions, flag: char, value: &str) -> Result<(), Box<dyn Error>> {
+
+    match flag {
+        'a' => options.array = Some(value.to_string()),
+        'd' => options.delimiter = value.bytes().next().unwrap_or(0),
+        'n' => options.count = Some(value.parse().map_err(|_| format!("read: {value}: invalid number"))?),
+        'p' => options.prompt = Some(value.to_string()),
+        't' => {
+            let seconds: f64 = value.parse().ok().filter(|seconds: &f64| *seconds >= 0.0)
+                .ok_or_else(|| format!("read: {value}: invalid timeout specification"))?;
+            options.timeout = Some(Duration::from_secs_f64(seconds));
+        },
+        _ => options.fd = Some(value.parse().map_err(|_| format!("read: {value}: invalid file descriptor specification"))?),
+    }
+
+    Ok(())
+}
+
+/// How the reading of a line ended
+#[derive(Debug, PartialEq)]
+pub enum ReadEnd {
+    /// The delimiter was read, or the number of characters given by `-n`
+    Complete,
+    /// The input ended before the delimiter
+    EndOfFile,
+    /// The timeout expired before the line was complete
+    TimedOut,
+}
+
+/// Reads a line from the file descriptor, each character being marked as escaped by a backslash or not.
+///
+/// The terminal is put in the mode required by `-s` and `-n` while reading, if the input is one
+pub fn read_line(fd: RawFd, options: &ReadOptions) -> io::Result<(Vec<(char, bool)>, ReadEnd)> {
+
+    let _terminal_mode = TerminalModeGuard::set(fd, options)?;
+    let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+
+    let mut bytes = Vec::new();
+    let mut escaped = Vec::new();
+    let mut escaping = false;
+    let mut characters = 0;
+
+    let end = loop {
+        if options.count.is_some_and(|count| characters >= count) {
+            break ReadEnd::Complete;
+        }
+
+        let byte = match read_byte(fd, deadline)? {
+            Some(byte) => byte,
+            None if deadline.is_some_and(|deadline| Instant::now() >= deadline) => break ReadEnd::TimedOut,
+            None => break ReadEnd::EndOfFile,
+        };
+
+        let is_escaped = escaping || is_continuation(byte) && escaped.last().copied().unwrap_or(false);
+        if escaping {
+            escaping = false;
+            // An escaped newline continues the line
+            if byte == b'\n' {
+                continue;
+            }
+        } else if byte == options.delimiter {
+            break ReadEnd::Complete;
+        } else if byte == b'\\' && !options.raw {
+            escaping = true;
+            continue;
+        }
+
+        bytes.push(byte);
+        escaped.push(is_escaped);
+        if !is_continuation(byte) {
+            characters += 1;
+        }
+    };
+
+    Ok((mark_escaped_characters(&bytes, &escaped), end))
+}
+
+/// The continuation bytes of an UTF-8 character don't count as characters, and are escaped with their first byte
+fn is_continuation(byte: u8) -> bool {
+    byte & 0xc0 == 0x80
+}
+
+/// Decodes the bytes into characters, keeping the escaped mark of their first byte
+fn mark_escaped_characters(bytes: &[u8], escaped: &[bool]) -> Vec<(char, bool)> {
+
+    let text = String::from_utf8_lossy(bytes);
+    let mut chars = Vec::new();
+    let mut offset = 0;
+
+    for c in text.chars() {
+        chars.push((c, escaped.get(offset).copied().unwrap_or(false)));
+        offset += c.len_utf8();
+    }
+
+    chars
+}
+
+/// Reads one byte, returns None at the end of the input or if the deadline is passed
+fn read_byte(fd: RawFd, deadline: Option<Instant>) -> io::Result<Option<u8>> {
+
+    loop {
+        if let Some(deadline) = deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let mut poll_fd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+            let timeout = remaining.as_millis().min(i32::MAX as u128) as i32;
+
+            // SAFETY: poll only writes the events of the given pollfd
+            match unsafe { libc::poll(&mut poll_fd, 1, timeout) } {
+                0 => return Ok(None),
+                -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+                -1 => return Err(io::Error::last_os_error()),
+                _ => (),
+            }
+        }
+
+        let mut byte = 0u8;
+        // SAFETY: read writes at most one byte in the given buffer
+        match unsafe { libc::read(fd, (&mut byte as *mut u8).cast(), 1) } {
+            1 => return Ok(Some(byte)),
+            0 => return Ok(None),
+            _ if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            _ => return Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+/// Returns true if there is something to read on the file descriptor, used by `read -t 0`
+pub fn is_input_available(fd: RawFd) -> bool {
+    let mut poll_fd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+    // SAFETY: poll only writes the events of the given pollfd
+    unsafe { libc::poll(&mut poll_fd, 1, 0) > 0 }
+}
+
+/// Disables the echo (for `-s`) and the line buffering (for `-n`) of a terminal, restored when dropped
+struct TerminalModeGuard {
+    fd: RawFd,
+    original: libc::termios,
+}
+
+impl TerminalModeGuard {
+
+    fn set(fd: RawFd, options: &ReadOptions) -> io::Result<Option<Self>> {
+
+        // SAFETY: isatty only reads the file descriptor
+        if !(options.silent || options.count.is_some()) || unsafe { libc::isatty(fd) } != 1 {
+            return Ok(None);
+        }
+
+        // SAFETY: termios is a plain C struct filled by tcgetattr
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        // SAFETY: tcgetattr writes in the given termios
+        if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut mode = original;
+        if options.silent {
+            mode.c_lflag &= !libc::ECHO;
+        }
+        if options.count.is_some() {
+            mode.c_lflag &= !libc::ICANON;
+            mode.c_cc[libc::VMIN] = 1;
+            mode.c_cc[libc::VTIME] = 0;
+        }
+        // SAFETY: tcsetattr only reads the given termios
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &mode) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Some(TerminalModeGuard { fd, original }))
+    }
+}
+
+impl Drop for TerminalModeGuard {
+
+    fn drop(&mut self) {
+        // SAFETY: tcsetattr only reads the given termios
+        unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &self.original) };
+    }
+}
+
+/// Splits the line into `count` fields on the characters of IFS, the last field getting the rest of the line.
+/// All the fields are returned if `count` is None. The escaped characters never separate fields.
+///
+/// The leading and trailing IFS whitespaces are removed, and a separator that is not a whitespace
+/// delimits a field even if it is empty
+pub fn split_read_fields(chars: &[(char, bool)], ifs: &str, count: Option<usize>) -> Vec<String> {
+
+    let is_separator = |&(c, escaped): &(char, bool)| !escaped && ifs.contains(c);
+    let is_whitespace_separator = |char: &(char, bool)| is_separator(char) && char.0.is_whitespace();
+
+    let mut fields = Vec::new();
+    let mut position = chars.iter().take_while(|c| is_whitespace_separator(c)).count();
+
+    while position < chars.len() {
+        // The last field is the rest of the line, without its trailing whitespaces
+        if count.is_some_and(|count| fields.len() + 1 == count) {
+            let rest = &chars[position..];
+            let length = rest.len() - rest.iter().rev().take_while(|c| is_whitespace_separator(c)).count();
+            fields.push(rest[..length].iter().map(|(c, _)| c).collect());
+            return fields;
+        }
+
+        let length = chars[position..].iter().take_while(|c| !is_separator(c)).count();
+        fields.push(chars[position..position + length].iter().map(|(c, _)| c).collect());
+        position += length;
+
+        // The separator is made of whitespaces around at most one other IFS character
+        position += chars[position..].iter().take_while(|c| is_whitespace_separator(c)).count();
+        if chars.get(position).is_some_and(|c| is_separator(c) && !c.0.is_whitespace()) {
+            position += 1;
+            position += chars[position..].iter().take_while(|c| is_whitespace_separator(c)).count();
+        }
+    }
+
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn unescaped(text: &str) -> Vec<(char, bool)> {
+        text.chars().map(|c| (c, false)).collect()
+    }
+
+    #[test]
+    fn test_options() {
+        let options = parse_read_options(&strings(&["-rsp", "> ", "-n1", "-d", "", "-t", "0.5", "first", "rest"])).unwrap();
+        assert!(options.raw && options.silent);
+        assert_eq!(Some("> ".to_string()), options.prompt);
+        assert_eq!(Some(1), options.count);
+        assert_eq!(0, options.delimiter);
+        assert_eq!(Some(Duration::from_millis(500)), options.timeout);
+        assert_eq!(strings(&["first", "rest"]), options.names);
+
+        assert!(parse_read_options(&strings(&["-x"])).is_err());
+        assert!(parse_read_options(&strings(&["-n"])).is_err());
+        assert!(parse_read_options(&strings(&["1name"])).is_err());
+    }
+
+    #[test]
+    fn test_fields_splitting() {
+        let chars = unescaped("  one  two   three  ");
+        assert_eq!(strings(&["one", "two   three"]), split_read_fields(&chars, " \t\n", Some(2)));
+        assert_eq!(strings(&["one", "two", "three"]), split_read_fields(&chars, " \t\n", None));
+
+        let chars = unescaped("a:b::c");
+        assert_eq!(strings(&["a", "b", "", "c"]), split_read_fields(&chars, ":", Some(5)));
+
+        // An escaped separator is part of the field
+        let mut chars = unescaped("a b c");
+        chars[1].1 = true;
+        assert_eq!(strings(&["a b", "c"]), split_read_fields(&chars, " ", None));
+    }
+
+    #[test]
+    fn test_read_line_stops_after_the_delimiter() {
+        let (reader, writer) = io::pipe().unwrap();
+        std::io::Write::write_all(&mut &writer, b"first \\\nline\\x\nsecond").unwrap();
+        drop(writer);
+
+        let fd = std::os::fd::AsRawFd::as_raw_fd(&reader);
+        let options = parse_read_options(&[]).unwrap();
+
+        let (chars, end) = read_line(fd, &options).unwrap();
+        assert_eq!(ReadEnd::Complete, end);
+        assert_eq!("first linex", chars.iter().map(|(c, _)| c).collect::<String>());
+        assert_eq!(Some(&('x', true)), chars.last());
+
+        let (chars, end) = read_line(fd, &options).unwrap();
+        assert_eq!(ReadEnd::EndOfFile, end);
+        assert_eq!(unescaped("second"), chars);
+    }
+}
@@ -1,97 +1,328 @@
 //! Functions related with command execution
-//! 
-//! 
+//!
+//!
 
-use std::process::{Child, Stdio};
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, ExitStatus, Stdio};
 use std::fs::OpenOptions;
+use std::rc::Rc;
 
+use crate::arithmetic::{self, ArithmeticError};
+use crate::command::builtin::condition::evaluate_conditional;
 use crate::command::builtin::execution::try_execute_builtin;
-use crate::command::{IoContext, RedirectionType};
+use crate::command::builtin::format::quote_if_needed;
+use crate::command::builtin::is_builtin;
+use crate::command::{CaseItem, CaseTerminator, ConditionalExpression, IoContext, RedirectionType};
 use crate::command::Command;
+use crate::expansion::{ExpansionError, expand_assignment_value, expand_pattern, expand_word, expand_word_list, expand_words, glob};
+use crate::expansion::prompt::{DEFAULT_PS4, expand_prompt, split_prompt};
+use crate::shell::{ReadonlyVariableError, Shell, is_valid_variable_name};
 
 impl Command {
 
     /// Executes the command and waits for it to complete if necessary.
-    /// 
-    /// 
-    pub fn execute(&self, io_context: IoContext)-> Result<(), ExecutionError> {
+    ///
+    /// Returns the exit status of the command, which is also saved in the shell state
+    pub fn execute(&self, shell: &mut Shell, io_context: IoContext) -> Result<i32, ExecutionError> {
 
-        // Execute the command and waiting the child process if any
-        if let Some(mut child_process) = self.execute_recursive(io_context)? {
-            child_process.wait()?;
-        }
+        let result = self.execute_recursive(shell, io_context);
 
-        Ok(())
+        shell.last_status = match &result {
+            Ok(status) => *status,
+            Err(err) => err.exit_status(),
+        };
+
+        result
     }
 
     /// Recursively executes the command depending on its type by propagating a transformed IO context.
-    /// 
-    /// Depending on the command variant, this function may executes a simple command, 
+    ///
+    /// Depending on the command variant, this function may executes a simple command,
     /// Or recursively call functions for composed commands like redirections, pipes etc...
-    /// 
+    ///
     /// Returns either :
-    /// - Ok(None) if there is no child process to wait (the case for the built-in commands)
-    /// - Ok(Some(_)) if there is a child process executed
-    /// - Err(_) if there is error during the command execution
-    fn execute_recursive(&self, io_context: IoContext) -> Result<Option<Child>, ExecutionError>{
+    /// - Ok(exit_status) once the command and its child processes are completed
+    /// - Err(_) if there is error during the command execution, or if a built-in changes the control flow (break, continue, return)
+    fn execute_recursive(&self, shell: &mut Shell, io_context: IoContext) -> Result<i32, ExecutionError>{
         // `io_context`: Passed by ownership because it will be transformed throught the recursive calls
-        
-        match self {
-            Command::Simple{cmd_path, cmd_args} => {
-
-                // Execute the built in command if it is 
-                if let Some(()) = try_execute_builtin(cmd_path, cmd_args, &io_context).map_err(|_| ExecutionError::BuiltinExecError )? { // TODO more detail from builtin error
-                    // Built-in functions are not executed in child processes, so return None
-                    return Ok(None);
-                }
-                // If not treat it like any other simple command 
-                Ok(Some(execute_simple_command(cmd_path, cmd_args, io_context)?))
 
+        let status = match self {
+            Command::Simple{cmd_path, cmd_args} => {
+                execute_simple_command(cmd_path, cmd_args, &[], shell, io_context)
+            },
+            Command::Assignment { assignments, command } => {
+                execute_assignment_command(assignments, command.as_deref(), shell, io_context)
             },
             Command::Redirection { kind, command, file } => {
-                execute_redirection_command(kind, command, file, io_context)
+                execute_redirection_command(kind, command, file, shell, io_context)
             },
             Command::Pipe { left, right } => {
-                execute_pipe_command(left, right, io_context)
+                execute_pipe_command(left, right, shell, io_context)
             },
             Command::Separator { left, right } => {
-                execute_separator_command(left, right, io_context)
+                execute_separator_command(left, right, shell, io_context)
             },
             Command::LogicalOr { left, right } => {
-                execute_logical_op_command(left, right, io_context, true)
+                execute_logical_op_command(left, right, shell, io_context, true)
             },
             Command::LogicalAnd { left, right } => {
-                execute_logical_op_command(left, right, io_context, false)
+                execute_logical_op_command(left, right, shell, io_context, false)
+            },
+            Command::While { condition, body } => {
+                execute_while_command(condition, body, shell, io_context, false)
+            },
+            Command::Until { condition, body } => {
+                execute_while_command(condition, body, shell, io_context, true)
+            },
+            Command::For { variable, words, body } => {
+                execute_for_command(variable, words.as_deref(), body, shell, io_context)
+            },
+            Command::ArithmeticFor { init, condition, step, body } => {
+                execute_arithmetic_for_command(init, condition, step, body, shell, io_context)
+            },
+            Command::Arithmetic { expression } => {
+                // The command succeeds if the expression is not 0
+                Ok(if evaluate_arithmetic(expression, shell)? != 0 { 0 } else { 1 })
+            },
+            Command::If { condition, then_branch, else_branch } => {
+                execute_if_command(condition, then_branch, else_branch.as_deref(), shell, io_context)
+            },
+            Command::Case { word, items } => {
+                execute_case_command(word, items, shell, io_context)
+            },
+            Command::Subshell { body } => {
+                fork_subshell(body, shell, io_context)?.wait()
+            },
+            Command::Group { body } => {
+                body.execute_recursive(shell, io_context)
+            },
+            Command::Conditional { expression } => {
+                execute_conditional_command(expression, shell, io_context)
+            },
+            Command::FunctionDefinition { name, body } => {
+                shell.define_function(name, Rc::clone(body));
+                Ok(0)
+            },
+        }?;
+
+        shell.last_status = status;
+        Ok(status)
+    }
+
+    /// Executes the command like `execute_recursive`, but writes the errors on the stderr of the context
+    /// and turns them into a failure status. It is used for the commands followed by other ones,
+    /// which should run even if an error occured, and for the redirected commands, whose errors are redirected too.
+    ///
+    /// The changes of control flow (break, continue, return) are still returned as errors
+    fn execute_reporting(&self, shell: &mut Shell, io_context: IoContext) -> Result<i32, ExecutionError> {
+
+        let error_context = io_context.try_clone_stderr()?;
+
+        match self.execute_recursive(shell, io_context) {
+            Err(err) if !err.is_control_flow() => {
+                writeln!(error_context.error_output()?, "{err}")?;
+                shell.last_status = err.exit_status();
+                Ok(shell.last_status)
+            },
+            result => result,
+        }
+    }
+}
+
+/// Executes a simple command after expanding its words, with the variables of its prefix assigned (`FOO=bar cmd`).
+/// A function or a built-in command is executed by the shell itself, any other command is executed in a child process
+///
+fn execute_simple_command(cmd_path: &str, cmd_args: &[String], assignments: &[(String, String)], shell: &mut Shell, io_context: IoContext) -> Result<i32, ExecutionError> {
+
+    let words: Vec<String> = std::iter::once(cmd_path.to_string()).chain(cmd_args.iter().cloned()).collect();
+    let fields = expand_words(&words, shell)?;
+
+    // The expansion may give no command at all, for instance with an empty variable, the assignments are then kept
+    let Some((cmd_path, cmd_args)) = fields.split_first() else {
+        return execute_assignment_command(assignments, None, shell, io_context);
+    };
+
+    with_temporary_variables(assignments, shell, |shell| {
+        trace_command(&fields, shell);
+
+        // The functions are looked up first, so that they can replace a built-in command
+        if let Some(body) = shell.get_function(cmd_path) {
+            return execute_function(&body, cmd_args, shell, io_context);
+        }
+
+        // Execute the built in command if it is
+        if let Some(status) = try_execute_builtin(cmd_path, cmd_args, shell, &io_context)? {
+            return Ok(status);
+        }
+
+        // If not treat it like any other simple command
+        let mut child = spawn_program(cmd_path, cmd_args, shell, io_context)?;
+        Ok(exit_status_code(child.wait()?))
+    })
+}
+
+/// Writes the expanded words of a command on stderr, preceded by the expansion of PS4, if the xtrace option is set
+fn trace_command(words: &[String], shell: &mut Shell) {
+
+    if !shell.is_option_set("xtrace") {
+        return;
+    }
+
+    let words: Vec<String> = words.iter().map(|word| match word.split_once('=') {
+        // The value of an assignment is quoted, not its name
+        Some((name, value)) if is_valid_variable_name(name) => format!("{name}={}", quote_if_needed(value)),
+        _ => quote_if_needed(word),
+    }).collect();
+    trace_line(&words.join(" "), shell);
+}
+
+/// Writes a traced line on stderr, preceded by the expansion of PS4, if the xtrace option is set
+fn trace_line(line: &str, shell: &mut Shell) {
+
+    if !shell.is_option_set("xtrace") {
+        return;
+    }
+
+    // The option is unset while PS4 is expanded, not to trace the command substitutions it contains
+    shell.set_option("xtrace", false);
+    let ps4 = shell.get_variable("PS4").unwrap_or(DEFAULT_PS4).to_string();
+    let (prefix, _) = split_prompt(&expand_prompt(&ps4, shell));
+    shell.set_option("xtrace", true);
+
+    eprintln!("{prefix}{line}");
+}
+
+/// Assigns the variables in the shell, or only for the duration of the command if there is one
+fn execute_assignment_command(assignments: &[(String, String)], command: Option<&Command>, shell: &mut Shell, io_context: IoContext) -> Result<i32, ExecutionError> {
+
+    match command {
+        Some(Command::Simple { cmd_path, cmd_args }) => execute_simple_command(cmd_path, cmd_args, assignments, shell, io_context),
+        Some(command) => with_temporary_variables(assignments, shell, |shell| command.execute_recursive(shell, io_context)),
+        None => {
+            // Each value can use the variables assigned before it
+            shell.substitution_status = None;
+            for (name, value) in assignments {
+                if let Some(elements) = value.strip_prefix('(').and_then(|value| value.strip_suffix(')')) {
+                    assign_array(name, elements, shell)?;
+                    continue;
+                }
+                let value = expand_assignment_value(value, shell)?;
+                trace_command(&[format!("{name}={value}")], shell);
+                shell.assign_variable(name, &value)?;
             }
-            
+            // The status is the one of the last command substitution, like in `output=$(command)`
+            Ok(shell.substitution_status.take().unwrap_or(0))
+        },
+    }
+}
+
+/// Assigns the words of `name=(elements)` to an array, each one expanded like a command argument
+fn assign_array(name: &str, elements: &str, shell: &mut Shell) -> Result<(), ExecutionError> {
+
+    let values = expand_word_list(elements, shell)?;
+
+    let quoted: Vec<String> = values.iter().map(|value| quote_if_needed(value)).collect();
+    trace_line(&format!("{name}=({})", quoted.join(" ")), shell);
+    shell.assign_array(name, values)?;
+    Ok(())
+}
+
+/// Runs a command with variables assigned and exported for its duration only, then restores their previous state
+fn with_temporary_variables(assignments: &[(String, String)], shell: &mut Shell, run: impl FnOnce(&mut Shell) -> Result<i32, ExecutionError>) -> Result<i32, ExecutionError> {
+
+    if assignments.is_empty() {
+        return run(shell);
+    }
+
+    // The value and export status of each variable before the assignment
+    let mut saved = Vec::new();
+    let mut assign = |shell: &mut Shell| -> Result<(), ExecutionError> {
+        for (name, value) in assignments {
+            let value = expand_assignment_value(value, shell)?;
+            trace_command(&[format!("{name}={value}")], shell);
+            saved.push((name, shell.get_variable(name).map(str::to_string), shell.is_exported(name)));
+            shell.assign_variable(name, &value)?;
+            shell.export_variable(name);
+        }
+        Ok(())
+    };
+    let result = assign(shell).and_then(|()| run(shell));
+
+    for (name, value, exported) in saved.into_iter().rev() {
+        match value {
+            Some(value) => shell.set_variable(name, &value),
+            None => shell.unset_variable(name),
+        }
+        match exported {
+            true => shell.export_variable(name),
+            false => shell.unexport_variable(name),
         }
     }
 
+    result
+}
+
+/// Maximum number of nested function calls, which prevents an infinite recursion from overflowing the stack
+const MAX_FUNCTION_DEPTH: usize = 1000;
+
+/// Executes the body of a function with the arguments as positional parameters, in a new scope for its local variables
+fn execute_function(body: &Command, args: &[String], shell: &mut Shell, io_context: IoContext) -> Result<i32, ExecutionError> {
+
+    if shell.function_depth() >= MAX_FUNCTION_DEPTH {
+        return Err(ExecutionError::BuiltinExecError(format!("maximum function nesting level exceeded ({MAX_FUNCTION_DEPTH})")));
+    }
+
+    let saved_parameters = std::mem::replace(&mut shell.positional_parameters, args.to_vec());
+    // The loops of the caller can't be exited from the function
+    let saved_loop_depth = std::mem::take(&mut shell.loop_depth);
+    shell.enter_function_scope();
+
+    let result = body.execute_recursive(shell, io_context);
 
+    shell.leave_function_scope();
+    shell.loop_depth = saved_loop_depth;
+    shell.positional_parameters = saved_parameters;
+
+    match result {
+        Err(ExecutionError::Return(status)) => Ok(status),
+        result => result,
+    }
 }
 
-/// Executes a simple command by creating a child process with the io_context as stdin/stdout/stderr
+/// Creates a child process executing the program with the io_context as stdin/stdout/stderr,
+/// and the exported variables of the shell as environment.
 /// This function does not executes built-in commands (such as pwd or cd)
-/// 
+///
 /// Returns the child process executing the command
-/// 
-fn execute_simple_command(cmd_path: &str, cmd_args: &[String], io_context: IoContext) -> Result<Child, ExecutionError> {  
+///
+fn spawn_program(cmd_path: &str, cmd_args: &[String], shell: &Shell, io_context: IoContext) -> Result<Child, ExecutionError> {
 
     let child = std::process::Command::new(cmd_path)
         .args(cmd_args)
-        // If no io context, pass the parent process standard io 
-        .stdin(io_context.stdin.unwrap_or(Stdio::inherit()))
-        .stdout(io_context.stdout.unwrap_or(Stdio::inherit()))
-        .stderr(io_context.stderr.unwrap_or(Stdio::inherit()))
+        .env_clear()
+        .envs(shell.exported_variables())
+        // If no io context, pass the parent process standard io
+        .stdin(io_context.stdin.map_or(Stdio::inherit(), Stdio::from))
+        .stdout(io_context.stdout.map_or(Stdio::inherit(), Stdio::from))
+        .stderr(io_context.stderr.map_or(Stdio::inherit(), Stdio::from))
         .spawn()?;
 
     Ok(child)
 
 }
 
-fn execute_redirection_command(kind: &RedirectionType, command: &Command, file_path: &str, io_context: IoContext) -> Result<Option<Child>, ExecutionError>  {
+/// Returns the exit code of a process, or 128 + the signal number if it was killed by a signal
+fn exit_status_code(status: ExitStatus) -> i32 {
+    status.code().unwrap_or_else(|| 128 + status.signal().unwrap_or(0))
+}
+
+fn execute_redirection_command(kind: &RedirectionType, command: &Command, file_path: &str, shell: &mut Shell, io_context: IoContext) -> Result<i32, ExecutionError>  {
 
-    // Select the options creation/read depending on the kind 
+    let file_path = expand_word(file_path, shell)?;
+
+    // Select the options creation/read depending on the kind
     let mut options = OpenOptions::new();
     match kind {
         RedirectionType::In => {
@@ -104,92 +335,180 @@ fn execute_redirection_command(kind: &RedirectionType, command: &Command, file_p
             options.write(true).create(true).append(true);
         },
     }
-    let file = options.open(file_path)?;
+    let file = options.open(&file_path)
+        .map_err(|err| ExecutionError::RedirectionError(file_path, err))?;
+    let file = OwnedFd::from(file);
 
-    let mut new_io_context = io_context; 
+    let mut new_io_context = io_context;
     match kind {
-        RedirectionType::In => new_io_context.stdin = Some(Stdio::from(file)),
-        RedirectionType::Out | RedirectionType::Append => new_io_context.stdout = Some(Stdio::from(file)),
-        RedirectionType::Err => new_io_context.stderr = Some(Stdio::from(file)),
+        RedirectionType::In => new_io_context.stdin = Some(file),
+        RedirectionType::Out | RedirectionType::Append => new_io_context.stdout = Some(file),
+        RedirectionType::Err => new_io_context.stderr = Some(file),
     }
-    
-    let child_process = command.execute_recursive(new_io_context)?;
 
-    Ok(child_process)
+    command.execute_reporting(shell, new_io_context)
 }
 
 
-fn execute_pipe_command(left_cmd: &Command, right_cmd: &Command, mut io_context: IoContext) -> Result<Option<Child>, ExecutionError> {
+fn execute_pipe_command(left_cmd: &Command, right_cmd: &Command, shell: &mut Shell, mut io_context: IoContext) -> Result<i32, ExecutionError> {
+
+    let (reader, writer) = io::pipe()?;
 
     let new_io_context = IoContext {
         stdin: io_context.stdin.take(),
-        stdout: Some(Stdio::piped()),
-        stderr: io_context.stderr.take(),
+        stdout: Some(OwnedFd::from(writer)),
+        stderr: io_context.stderr.as_ref().map(OwnedFd::try_clone).transpose()?,
     };
 
-    let left = left_cmd.execute_recursive(new_io_context)?;
-
-    let mut left_child_process = left.ok_or(ExecutionError::MissingChildProcess)?;
+    let mut left_child_process = start_pipeline_command(left_cmd, shell, new_io_context)?;
 
     let right_io_context = IoContext {
-        stdin: Some(Stdio::from(
-            left_child_process.stdout.take().ok_or(ExecutionError::IoContextError)?
-        )),
+        stdin: Some(OwnedFd::from(reader)),
         stdout: io_context.stdout.take(),
         stderr: io_context.stderr.take(),
     };
 
-    let mut right_child_process = right_cmd.execute_recursive(right_io_context)?.ok_or(ExecutionError::MissingChildProcess)?;
+    // The right command may be another pipe, which starts its own commands
+    let right_status = match right_cmd {
+        Command::Pipe { .. } => right_cmd.execute_recursive(shell, right_io_context),
+        _ => start_pipeline_command(right_cmd, shell, right_io_context).and_then(|mut child| child.wait()),
+    };
 
     // Prevent the child from being zombie processes
-    right_child_process.wait()?;
     left_child_process.wait()?;
 
-    Ok(Some(right_child_process))
+    right_status
+}
+
+/// Starts a command of a pipeline without waiting for it.
+///
+/// A program is directly spawned, the other commands (built-ins, loops...) are executed in a forked copy of the shell
+fn start_pipeline_command(command: &Command, shell: &mut Shell, io_context: IoContext) -> Result<ChildProcess, ExecutionError> {
+
+    // The stage is already executed in a subshell
+    if let Command::Subshell { body } = command {
+        return fork_subshell(body, shell, io_context);
+    }
+
+    if let Command::Simple { cmd_path, cmd_args } = command {
+        let words: Vec<String> = std::iter::once(cmd_path).chain(cmd_args).cloned().collect();
+        let fields = expand_words(&words, shell)?;
+
+        let is_program = |cmd_path: &String| !is_builtin(cmd_path) && shell.get_function(cmd_path).is_none();
+        if let Some((cmd_path, cmd_args)) = fields.split_first().filter(|(cmd_path, _)| is_program(cmd_path)) {
+            let error_context = io_context.try_clone_stderr()?;
+            return match spawn_program(cmd_path, cmd_args, shell, io_context) {
+                Ok(child) => Ok(ChildProcess::Program(child)),
+                // The other commands of the pipeline still run, like when a program fails
+                Err(err) => {
+                    writeln!(error_context.error_output()?, "{err}")?;
+                    Ok(ChildProcess::Exited(err.exit_status()))
+                },
+            };
+        }
+    }
+
+    fork_subshell(command, shell, io_context)
+}
+
+/// Executes the command in a forked copy of the shell and returns what it wrote on its standard output,
+/// used by the command substitution. Its exit status is saved as the status of the last substitution
+pub fn capture_output(command: &Command, shell: &mut Shell) -> Result<String, ExecutionError> {
+
+    let (mut reader, writer) = io::pipe()?;
+    let io_context = IoContext { stdin: None, stdout: Some(OwnedFd::from(writer)), stderr: None };
+
+    // The writer is closed in the parent once the child is started, so the reading ends with the child output
+    let mut child = fork_subshell(command, shell, io_context)?;
+    let mut output = Vec::new();
+    let read_result = reader.read_to_end(&mut output);
+
+    shell.substitution_status = Some(child.wait()?);
+    read_result?;
+
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// A process started by the shell, that should be waited for
+enum ChildProcess {
+    /// A program executed in a child process
+    Program(Child),
+    /// A forked copy of the shell, identified by its pid
+    Subshell(libc::pid_t),
+    /// A program that could not be started, with its exit status
+    Exited(i32),
 }
 
-fn execute_separator_command(left_cmd: &Command, right_cmd: &Command, io_context: IoContext) -> Result<Option<Child>, ExecutionError> {
+impl ChildProcess {
 
-    let left = left_cmd.execute_recursive(io_context);
-    
-    match left {
-        Ok(Some(mut child)) => {
-            child.wait()?;
+    /// Waits for the process to exit and returns its exit status
+    fn wait(&mut self) -> Result<i32, ExecutionError> {
+
+        match self {
+            ChildProcess::Program(child) => Ok(exit_status_code(child.wait()?)),
+            ChildProcess::Exited(status) => Ok(*status),
+            ChildProcess::Subshell(pid) => {
+                let mut status = 0;
+                // SAFETY: waitpid only writes the status in the given integer
+                while unsafe { libc::waitpid(*pid, &mut status, 0) } == -1 {
+                    let err = io::Error::last_os_error();
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err.into());
+                    }
+                }
+                Ok(exit_status_code(ExitStatus::from_raw(status)))
+            },
+        }
+    }
+}
+
+/// Executes the command in a forked copy of the shell, so that its modifications of the shell state
+/// (variables, current directory...) do not reach the current shell
+fn fork_subshell(command: &Command, shell: &mut Shell, io_context: IoContext) -> Result<ChildProcess, ExecutionError> {
+
+    // SAFETY: the child process only executes the command and exits, it never returns to the caller
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error().into()),
+        0 => {
+            let status = execute_in_subshell(command, shell, io_context);
+            // SAFETY: _exit terminates the process without running the destructors and handlers of the parent
+            unsafe { libc::_exit(status) }
         },
-        Err(err) => eprintln!("{err}"),
-        Ok(None) => ()
+        pid => Ok(ChildProcess::Subshell(pid)),
     }
+}
 
-    let right_io_context = IoContext::default();
-    let mut right = right_cmd.execute_recursive(right_io_context)?;
+/// Executes the command in the forked child process, whose standard io become the ones of the io context
+fn execute_in_subshell(command: &Command, shell: &mut Shell, io_context: IoContext) -> i32 {
 
-    if let Some(right_child) = &mut right {
-        right_child.wait()?;
+    for (fd, standard_fd) in [(io_context.stdin, 0), (io_context.stdout, 1), (io_context.stderr, 2)] {
+        if let Some(fd) = fd {
+            // SAFETY: dup2 only duplicates the file descriptor, the original one is closed when dropped
+            unsafe { libc::dup2(fd.as_raw_fd(), standard_fd) };
+        }
     }
 
-    Ok(None)
+    let status = match command.execute_reporting(shell, IoContext::default()) {
+        Ok(status) => status,
+        Err(err) => err.exit_status(),
+    };
+
+    let _ = io::stdout().flush();
+    status
+}
+
+fn execute_separator_command(left_cmd: &Command, right_cmd: &Command, shell: &mut Shell, io_context: IoContext) -> Result<i32, ExecutionError> {
+
+    left_cmd.execute_reporting(shell, io_context.try_clone()?)?;
+
+    right_cmd.execute_recursive(shell, io_context)
 }
 
 /// Executes either the || or the && operator command depending on the `or` argument
-fn execute_logical_op_command(left_cmd: &Command, right_cmd: &Command, io_context: IoContext, or: bool ) -> Result<Option<Child>, ExecutionError> {
-
-    let left = left_cmd.execute_recursive(io_context);
-    
-    let is_left_success : bool = match left {
-        Ok(Some(mut child)) => {
-            let status = child.wait()?;
-            if status.success() {
-                true
-            } else {
-                false
-            }
-        },
-        Err(err) => {
-            eprintln!("{err}");
-            false
-        },
-        Ok(None) => true // todo handle this case
-    };
+fn execute_logical_op_command(left_cmd: &Command, right_cmd: &Command, shell: &mut Shell, io_context: IoContext, or: bool ) -> Result<i32, ExecutionError> {
+
+    let left_status = left_cmd.execute_reporting(shell, io_context.try_clone()?)?;
+    let is_left_success = left_status == 0;
 
     // if it's the || operator, the left should be a failure to execute the next commands
     // if it's the && operator, the left should be a success to execute the next commands
@@ -199,16 +518,194 @@ fn execute_logical_op_command(left_cmd: &Command, right_cmd: &Command, io_contex
     };
 
     if should_run_right {
+        right_cmd.execute_recursive(shell, io_context)
+    } else {
+        Ok(left_status)
+    }
+}
 
-        let right_io_context = IoContext::default();
-        let mut right = right_cmd.execute_recursive(right_io_context)?;
+fn execute_if_command(condition: &Command, then_branch: &Command, else_branch: Option<&Command>, shell: &mut Shell, io_context: IoContext) -> Result<i32, ExecutionError> {
 
-        if let Some(right_child) = &mut right {
-            right_child.wait()?;
+    let condition_status = condition.execute_reporting(shell, io_context.try_clone()?)?;
+
+    match (condition_status, else_branch) {
+        (0, _) => then_branch.execute_recursive(shell, io_context),
+        (_, Some(else_branch)) => else_branch.execute_recursive(shell, io_context),
+        (_, None) => Ok(0),
+    }
+}
+
+/// Executes the body of the first branch whose patterns match the word, then the following ones depending on its terminator
+fn execute_case_command(word: &str, items: &[CaseItem], shell: &mut Shell, io_context: IoContext) -> Result<i32, ExecutionError> {
+
+    let word = expand_word(word, shell)?;
+    let mut status = 0;
+    // True when the previous branch ended with ;& so this one is executed without testing its patterns
+    let mut falling_through = false;
+
+    for item in items {
+        if !falling_through && !case_item_matches(item, &word, shell)? {
+            continue;
         }
 
+        if let Some(body) = &item.body {
+            status = body.execute_recursive(shell, io_context.try_clone()?)?;
+        }
+
+        match item.terminator {
+            CaseTerminator::Break => break,
+            CaseTerminator::FallThrough => falling_through = true,
+            CaseTerminator::Continue => falling_through = false,
+        }
     }
-    Ok(None)
+
+    Ok(status)
+}
+
+fn case_item_matches(item: &CaseItem, word: &str, shell: &mut Shell) -> Result<bool, ExecutionError> {
+
+    for pattern in &item.patterns {
+        if glob::matches(&expand_pattern(pattern, shell)?, word) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Executes a `[[ ]]` command. Like with `test`, a malformed expression (an invalid operator, integer or regex)
+/// is reported on the stderr of the context with the status 2
+fn execute_conditional_command(expression: &ConditionalExpression, shell: &mut Shell, io_context: IoContext) -> Result<i32, ExecutionError> {
+
+    match evaluate_conditional(expression, shell) {
+        Ok(result) => Ok(if result { 0 } else { 1 }),
+        Err(ExecutionError::BuiltinExecError(err)) => {
+            writeln!(io_context.error_output()?, "{err}")?;
+            Ok(2)
+        },
+        Err(err) => Err(err),
+    }
+}
+
+/// What a loop should do after executing one of its parts
+enum LoopFlow {
+    /// The part completed with the given exit status
+    Completed(i32),
+    /// A `continue` targeting this loop was executed
+    Continue,
+    /// A `break` targeting this loop was executed
+    Break,
+}
+
+/// Executes a part of a loop (its condition or its body), catching the `break` and `continue` that target this loop
+fn execute_loop_part(command: &Command, shell: &mut Shell, io_context: &IoContext) -> Result<LoopFlow, ExecutionError> {
+
+    match command.execute_reporting(shell, io_context.try_clone()?) {
+        Ok(status) => Ok(LoopFlow::Completed(status)),
+        Err(ExecutionError::Break(1)) => Ok(LoopFlow::Break),
+        Err(ExecutionError::Continue(1)) => Ok(LoopFlow::Continue),
+        // The break or continue targets an enclosing loop, so this one is exited
+        Err(ExecutionError::Break(count)) => Err(ExecutionError::Break(count - 1)),
+        Err(ExecutionError::Continue(count)) => Err(ExecutionError::Continue(count - 1)),
+        Err(err) => Err(err),
+    }
+}
+
+/// Runs a loop while counting it in the shell loop depth, which tells `break` and `continue` how many loops can be exited
+fn with_loop_depth(shell: &mut Shell, run_loop: impl FnOnce(&mut Shell) -> Result<i32, ExecutionError>) -> Result<i32, ExecutionError> {
+
+    shell.loop_depth += 1;
+    let result = run_loop(shell);
+    shell.loop_depth -= 1;
+
+    result
+}
+
+/// Executes either the while or the until loop depending on the `until` argument
+fn execute_while_command(condition: &Command, body: &Command, shell: &mut Shell, io_context: IoContext, until: bool) -> Result<i32, ExecutionError> {
+
+    with_loop_depth(shell, |shell| {
+        let mut status = 0;
+
+        loop {
+            match execute_loop_part(condition, shell, &io_context)? {
+                // while loops stop when the condition fails, until loops when it succeeds
+                LoopFlow::Completed(condition_status) if (condition_status == 0) == until => break,
+                LoopFlow::Completed(_) => (),
+                LoopFlow::Continue => continue,
+                LoopFlow::Break => break,
+            }
+
+            match execute_loop_part(body, shell, &io_context)? {
+                LoopFlow::Completed(body_status) => status = body_status,
+                LoopFlow::Continue => status = 0,
+                LoopFlow::Break => {
+                    status = 0;
+                    break;
+                },
+            }
+        }
+
+        Ok(status)
+    })
+}
+
+fn execute_for_command(variable: &str, words: Option<&[String]>, body: &Command, shell: &mut Shell, io_context: IoContext) -> Result<i32, ExecutionError> {
+
+    let values = match words {
+        Some(words) => expand_words(words, shell)?,
+        None => shell.positional_parameters.clone(),
+    };
+
+    with_loop_depth(shell, |shell| {
+        let mut status = 0;
+
+        for value in values {
+            shell.assign_variable(variable, &value)?;
+
+            match execute_loop_part(body, shell, &io_context)? {
+                LoopFlow::Completed(body_status) => status = body_status,
+                LoopFlow::Continue => status = 0,
+                LoopFlow::Break => {
+                    status = 0;
+                    break;
+                },
+            }
+        }
+
+        Ok(status)
+    })
+}
+
+/// Expands the parameters of the arithmetic expression, then evaluates it
+fn evaluate_arithmetic(expression: &str, shell: &mut Shell) -> Result<i64, ExecutionError> {
+
+    let expression = expand_word(expression, shell)?;
+    Ok(arithmetic::evaluate(&expression, shell)?)
+}
+
+fn execute_arithmetic_for_command(init: &str, condition: &str, step: &str, body: &Command, shell: &mut Shell, io_context: IoContext) -> Result<i32, ExecutionError> {
+
+    evaluate_arithmetic(init, shell)?;
+
+    with_loop_depth(shell, |shell| {
+        let mut status = 0;
+
+        // An empty condition is always true
+        while condition.is_empty() || evaluate_arithmetic(condition, shell)? != 0 {
+            match execute_loop_part(body, shell, &io_context)? {
+                LoopFlow::Completed(body_status) => status = body_status,
+                LoopFlow::Continue => status = 0,
+                LoopFlow::Break => {
+                    status = 0;
+                    break;
+                },
+            }
+            evaluate_arithmetic(step, shell)?;
+        }
+
+        Ok(status)
+    })
 }
 
 
@@ -218,12 +715,54 @@ pub enum ExecutionError {
     #[error("Command execution error: {0}")]
     CommandError(#[from] std::io::Error),
 
-    #[error("Error occured during built-in command execution")]
-    BuiltinExecError,
+    #[error("{0}")]
+    BuiltinExecError(String),
 
-    #[error("Execution error with IO")]
-    IoContextError,
+    #[error("{0}: {1}")]
+    RedirectionError(String, std::io::Error),
 
-    #[error("Expected a child process")]
-    MissingChildProcess
-}
\ No newline at end of file
+    #[error("Expansion error: {0}")]
+    ExpansionError(#[from] ExpansionError),
+
+    #[error("Arithmetic error: {0}")]
+    ArithmeticError(#[from] ArithmeticError),
+
+    #[error("{0}")]
+    ReadonlyError(#[from] ReadonlyVariableError),
+
+    // The changes of control flow are propagated as errors up to the loop or the function they target
+    #[error("break: only meaningful in a loop")]
+    Break(usize),
+
+    #[error("continue: only meaningful in a loop")]
+    Continue(usize),
+
+    #[error("return: can only `return' from a function or sourced script")]
+    Return(i32),
+}
+
+impl ExecutionError {
+
+    /// Returns true if the error is a change of control flow (break, continue, return) rather than an actual error
+    pub fn is_control_flow(&self) -> bool {
+        matches!(self, ExecutionError::Break(_) | ExecutionError::Continue(_) | ExecutionError::Return(_))
+    }
+
+    /// Returns the exit status given to a command that failed with this error
+    pub fn exit_status(&self) -> i32 {
+        match self {
+            ExecutionError::CommandError(err) if err.kind() == io::ErrorKind::NotFound => 127,
+            ExecutionError::CommandError(err) if err.kind() == io::ErrorKind::PermissionDenied => 126,
+            ExecutionError::Break(_) | ExecutionError::Continue(_) => 0,
+            ExecutionError::Return(status) => *status,
+            _ => 1,
+        }
+    }
+}
+
+impl From<Box<dyn std::error::Error>> for ExecutionError {
+
+    fn from(err: Box<dyn std::error::Error>) -> Self {
+        ExecutionError::BuiltinExecError(err.to_string())
+    }
+}
@@ -0,0 +1,145 @@
+//!
+//! Execution of the scripts read from files, by `source` and at the startup of the shell.
+//!
+//! A script is executed command by command, so that the aliases and functions defined by a command
+//! can be used by the next ones.
+//!
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::command::IoContext;
+use crate::command::execution::ExecutionError;
+use crate::parsing::{convert_to_command_with_aliases, is_blank_input, is_incomplete_input};
+use crate::shell::Shell;
+
+/// The exit status of a script containing a syntax error
+const SYNTAX_ERROR_STATUS: i32 = 2;
+
+/// Executes the commands of the script, `name` being used in the error messages.
+///
+/// The lines are gathered until they form a complete command, which is parsed and executed before the next lines.
+/// The execution errors are reported and don't stop the script, unlike a syntax error.
+/// The changes of control flow (like `return`) stop the script and are returned as errors
+pub fn execute_script(script: &str, name: &str, shell: &mut Shell, io_context: &IoContext) -> Result<i32, ExecutionError> {
+
+    let mut status = 0;
+    let mut input = String::new();
+    let mut first_line = 1;
+
+    for (index, line) in script.lines().enumerate() {
+        if input.is_empty() {
+            first_line = index + 1;
+        }
+        input.push_str(line);
+        input.push('\n');
+
+        if is_blank_input(&input) {
+            input.clear();
+            continue;
+        }
+
+        let command = match convert_to_command_with_aliases(&input, shell.aliases()) {
+            Ok(command) => command,
+            Err(err) if is_incomplete_input(err.as_ref()) => continue,
+            Err(err) => {
+                writeln!(io_context.error_output()?, "{name}: line {first_line}: Parsing error: {err}")?;
+                return Ok(SYNTAX_ERROR_STATUS);
+            },
+        };
+        input.clear();
+
+        status = match command.execute(shell, io_context.try_clone()?) {
+            Err(err) if !err.is_control_flow() => {
+                writeln!(io_context.error_output()?, "{err}")?;
+                err.exit_status()
+            },
+            result => result?,
+        };
+    }
+
+    // The script ended in the middle of a command
+    if !input.is_empty() {
+        writeln!(io_context.error_output()?, "{name}: line {first_line}: Parsing error: unexpected end of file")?;
+        return Ok(SYNTAX_ERROR_STATUS);
+    }
+
+    Ok(status)
+}
+
+/// Executes the file in the current shell, with the arguments as positional parameters if there are some.
+///
+/// `return` can be used in the file to stop it, its status being the status of the file
+pub fn source_file(path: &Path, args: &[String], shell: &mut Shell, io_context: &IoContext) -> Result<i32, ExecutionError> {
+
+    let script = std::fs::read_to_string(path)
+        .map_err(|err| ExecutionError::RedirectionError(path.to_string_lossy().into_owned(), err))?;
+
+    let saved_parameters = match args.is_empty() {
+        true => None,
+        false => Some(std::mem::replace(&mut shell.positional_parameters, args.to_vec())),
+    };
+    shell.source_depth += 1;
+
+    let result = execute_script(&script, &path.to_string_lossy(), shell, io_context);
+
+    shell.source_depth -= 1;
+    if let Some(parameters) = saved_parameters {
+        shell.positional_parameters = parameters;
+    }
+
+    match result {
+        Err(ExecutionError::Return(status)) => Ok(status),
+        result => result,
+    }
+}
+
+/// Finds the file given to `source` : a name without slash is searched in the directories of PATH,
+/// then in the working directory
+pub fn find_sourced_file(name: &str, shell: &Shell) -> Option<PathBuf> {
+
+    if name.contains('/') {
+        return Some(PathBuf::from(name));
+    }
+
+    let directories = shell.get_variable("PATH").unwrap_or_default().split(':');
+    directories.filter(|directory| !directory.is_empty())
+        .map(|directory| Path::new(directory).join(name))
+        .find(|path| path.is_file())
+        .or_else(|| Path::new(name).is_file().then(|| PathBuf::from(name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script_uses_previous_definitions() {
+        let path = std::env::temp_dir().join(format!("rust_shell_script_{}.sh", std::process::id()));
+        let script = "# comment\nalias greet='value=hello'\ngreet\nf() {\n  other=\"$value $1\"\n  return 3\n  other=no\n}\nf world\n";
+        std::fs::write(&path, script).unwrap();
+
+        let mut shell = Shell::new();
+        let status = source_file(&path, &[], &mut shell, &IoContext::new()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(3, status);
+        assert_eq!(Some("hello world"), shell.get_variable("other"));
+    }
+
+    #[test]
+    fn test_script_stops_at_syntax_errors_and_return() {
+        let mut shell = Shell::new();
+        shell.source_depth = 1;
+
+        let status = execute_script("a=1\nfi\na=2", "test", &mut shell, &IoContext::new()).unwrap();
+        assert_eq!((SYNTAX_ERROR_STATUS, Some("1")), (status, shell.get_variable("a")));
+
+        let result = execute_script("b=1\nreturn 4\nb=2", "test", &mut shell, &IoContext::new());
+        assert!(matches!(result, Err(ExecutionError::Return(4))));
+        assert_eq!(Some("1"), shell.get_variable("b"));
+
+        let status = execute_script("if true; then", "test", &mut shell, &IoContext::new()).unwrap();
+        assert_eq!(SYNTAX_ERROR_STATUS, status);
+    }
+}
@@ -0,0 +1,504 @@
+//!
+//! Completion of the word under the cursor when Tab is pressed.
+//!
+//! The line is read with the lexer of the parser to know what the word is : a command name in command position
+//! (builtins, functions, aliases and the programs of PATH), a file after a redirection operator or as an argument,
+//! a variable after `$`, a user after `~`, a job after `%`. The completed names are quoted like the word being
+//! completed.
+//!
+//! The arguments of a command are completed by the specification defined for it with `complete`, or by a native
+//! completer (for `git`, `make`, `ssh`...), the files being completed otherwise.
+//!
+
+pub mod native;
+pub mod spec;
+
+use std::collections::BTreeSet;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use rustyline::completion::Pair;
+
+use crate::command::builtin::BUILTINS;
+use crate::completion::spec::{CompletionContext, CompletionOption};
+use crate::expansion::expand_tilde;
+use crate::parsing::{ParsingError, Token};
+use crate::parsing::alias::WordPosition;
+use crate::parsing::lexer::tokenize_with_positions;
+use crate::shell::Shell;
+
+/// The reserved words starting a compound command, completed in command position
+const COMPOUND_KEYWORDS: [&str; 6] = ["if", "while", "until", "for", "case", "function"];
+
+/// The reserved words, completed by `compgen -k`
+pub const KEYWORDS: [&str; 19] = [
+    "!", "[[", "]]", "case", "do", "done", "elif", "else", "esac", "fi", "for", "function", "if", "in", "then", "until", "while", "{", "}",
+];
+
+/// The characters escaped with a backslash in an unquoted completed name
+const SPECIAL_CHARACTERS: &str = " \t\n'\"\\$`|&;<>()*?[]{}!#";
+
+/// The quote in which a character of a word is
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Quote {
+    None,
+    Single,
+    Double,
+}
+
+/// The word under the cursor
+#[derive(Debug, PartialEq)]
+struct CompletedWord<'a> {
+    /// The position of its first byte in the line
+    start: usize,
+    /// The word as it was written, up to the cursor
+    raw: &'a str,
+    position: WordPosition,
+    /// The words of the command before it, starting with the command name, as they were written
+    command_words: Vec<String>,
+}
+
+/// Returns the start of the word before the cursor and the candidates replacing it.
+///
+/// The shell is mutable because the completion functions defined with `complete -F` are executed in it
+pub fn complete_line(line: &str, cursor: usize, shell: &mut Shell) -> (usize, Vec<Pair>) {
+
+    let word = find_completed_word(&line[..cursor]);
+    let raw = word.raw;
+    let argument = !word.position.command && !word.position.redirection_target && !word.command_words.is_empty();
+
+    let (offset, candidates) = if let Some((offset, candidates)) = complete_variable(raw, shell) {
+        (offset, candidates)
+    } else if let Some(prefix) = raw.strip_prefix('~').filter(|prefix| !prefix.contains('/')) {
+        (0, complete_user(prefix))
+    } else if raw.starts_with('%') && !word.position.redirection_target {
+        let candidates = job_names(&raw[1..], shell).into_iter()
+            .map(|name| Pair { replacement: format!("%{name} "), display: format!("%{name}") })
+            .collect();
+        (0, candidates)
+    } else if word.position.command && !word.position.redirection_target && !raw.contains('/') {
+        let candidates = command_names(&unquote(raw), shell).into_iter()
+            .map(|name| Pair { replacement: format!("{} ", escape(&name, Quote::None)), display: name })
+            .collect();
+        (0, candidates)
+    } else if let Some(candidates) = argument.then(|| complete_argument(&word, line, cursor, shell)).flatten() {
+        (0, candidates)
+    } else {
+        complete_path(raw, shell, word.position.command && !word.position.redirection_target)
+    };
+
+    (word.start + offset, candidates)
+}
+
+/// Completes an argument with the specification of the command, or its native completer.
+/// Returns None if the files should be completed instead
+fn complete_argument(word: &CompletedWord, line: &str, cursor: usize, shell: &mut Shell) -> Option<Vec<Pair>> {
+
+    let command = unquote(&word.command_words[0]);
+    let current = unquote(word.raw);
+
+    // A command run with its path, like `/usr/bin/git`, is also completed by the specification of its name
+    let name = command.rsplit('/').next().unwrap_or_default();
+    let spec = shell.completion_spec(&command).or_else(|| shell.completion_spec(name)).cloned();
+
+    let Some(spec) = spec else {
+        if !shell.config.completion.native {
+            return None;
+        }
+        let words: Vec<String> = word.command_words.iter().map(|word| unquote(word)).collect();
+        let candidates = native::complete_arguments(name, &words, &current, shell)?;
+        return Some(candidate_pairs(word.raw, candidates, false, false));
+    };
+
+    let mut words = word.command_words.clone();
+    words.push(word.raw.to_string());
+    let context = CompletionContext { words, word: current.clone(), line: line.to_string(), point: cursor };
+    let candidates = spec.generate(&context, shell).unwrap_or_default();
+
+    let no_space = spec.options.contains(&CompletionOption::NoSpace);
+    match candidates.is_empty() {
+        true if spec.options.contains(&CompletionOption::Default) => None,
+        true if spec.options.contains(&CompletionOption::DirNames) => {
+            let directories = file_names(&current, shell).into_iter().filter(|(_, is_directory)| *is_directory).map(|(path, _)| path).collect();
+            Some(candidate_pairs(word.raw, directories, true, no_space))
+        },
+        _ => Some(candidate_pairs(word.raw, candidates, spec.completes_files(), no_space)),
+    }
+}
+
+/// Makes the candidates replacing the whole word, quoted like its beginning.
+///
+/// If they are files, the directories are completed with a `/` and only the last component of the path is displayed
+fn candidate_pairs(raw: &str, candidates: Vec<String>, files: bool, no_space: bool) -> Vec<Pair> {
+
+    let (quote, opening, closing) = match raw.chars().next() {
+        Some('\'') => (Quote::Single, "'", "'"),
+        Some('"') => (Quote::Double, "\"", "\""),
+        _ => (Quote::None, "", ""),
+    };
+    let space = if no_space { "" } else { " " };
+
+    candidates.into_iter().map(|candidate| {
+        let is_directory = files && Path::new(&candidate).is_dir();
+        let replacement = match is_directory {
+            true => format!("{opening}{}/", escape(&candidate, quote)),
+            false => format!("{opening}{}{closing}{space}", escape(&candidate, quote)),
+        };
+        let display = match files {
+            true => candidate.trim_end_matches('/').rsplit('/').next().unwrap_or_default().to_string() + if is_directory { "/" } else { "" },
+            false => candidate,
+        };
+        Pair { display, replacement }
+    }).collect()
+}
+
+/// Finds the word ending at the end of the line (empty after a blank or an operator), and its position in the command
+fn find_completed_word(line: &str) -> CompletedWord<'_> {
+
+    let (tokens, error) = tokenize_with_positions(line);
+
+    let (start, previous_tokens) = match (error, tokens.last()) {
+        // An unterminated word (like an open quote) is completed
+        (Some((ParsingError::IncompleteInput(_), start)), _) => (start, &tokens[..]),
+        // Otherwise the words are separated by the blanks
+        (Some(_), _) => (line.rfind(char::is_whitespace).map_or(0, |position| position + 1), &tokens[..]),
+        (None, Some(last)) if last.end == line.len() && matches!(last.token, Token::Word(_)) => (last.start, &tokens[..tokens.len() - 1]),
+        (None, _) => (line.len(), &tokens[..]),
+    };
+
+    let mut position = WordPosition::start();
+    let mut command_words = Vec::new();
+    for token in previous_tokens.iter().filter(|token| token.end <= start) {
+        let (command_position, redirection_target) = (position.command, position.redirection_target);
+        position.advance(&token.token);
+
+        // The assignments and the keywords before the command name, and the redirection targets, are not its words
+        match &token.token {
+            Token::Word(word) if command_position && !position.command && !redirection_target => command_words = vec![word.clone()],
+            Token::Word(word) if !command_position && !redirection_target => command_words.push(word.clone()),
+            Token::Word(_) | Token::RedirectOp(_) => (),
+            _ if position.command => command_words.clear(),
+            _ => (),
+        }
+    }
+
+    CompletedWord { start, raw: &line[start..], position, command_words }
+}
+
+/// Completes a variable name after the last `$` (or `${`) of the word, if it is not quoted.
+/// Returns the offset of the name in the word and the candidates
+fn complete_variable(raw: &str, shell: &Shell) -> Option<(usize, Vec<Pair>)> {
+
+    let dollar = raw.rfind('$')?;
+    if quote_state(&raw[..dollar]) == Quote::Single || raw[..dollar].ends_with('\\') {
+        return None;
+    }
+    let (braced, prefix) = match raw[dollar + 1..].strip_prefix('{') {
+        Some(prefix) => (true, prefix),
+        None => (false, &raw[dollar + 1..]),
+    };
+    if !prefix.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
+    }
+
+    let candidates = variable_names(prefix, shell).into_iter().map(|name| Pair {
+        display: name.clone(),
+        replacement: if braced { format!("{name}}}") } else { name.to_string() },
+    }).collect();
+
+    Some((dollar + 1 + braced as usize, candidates))
+}
+
+/// Completes `~prefix` with the names of the users
+fn complete_user(prefix: &str) -> Vec<Pair> {
+    user_names(prefix).into_iter().map(|name| Pair { display: format!("~{name}"), replacement: format!("~{name}/") }).collect()
+}
+
+/// Returns the names of the variables and of the arrays starting with the prefix
+pub fn variable_names(prefix: &str, shell: &Shell) -> BTreeSet<String> {
+
+    shell.variables().into_iter().map(|(name, _)| name)
+        .chain(shell.arrays().into_iter().map(|(name, _)| name))
+        .filter(|name| name.starts_with(prefix))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Returns the names of the jobs starting with the prefix, completed after `%` : the background jobs the shell
+/// tracks, none for now as it runs no command in the background
+pub fn job_names(_prefix: &str, _shell: &Shell) -> BTreeSet<String> {
+    BTreeSet::new()
+}
+
+/// Returns the names of the users starting with the prefix
+pub fn user_names(prefix: &str) -> BTreeSet<String> {
+
+    let mut names = BTreeSet::new();
+    // SAFETY: the entries of the password database are read one by one, their name being copied before the next one
+    unsafe {
+        libc::setpwent();
+        loop {
+            let entry = libc::getpwent();
+            if entry.is_null() {
+                break;
+            }
+            let name = std::ffi::CStr::from_ptr((*entry).pw_name).to_string_lossy().into_owned();
+            if name.starts_with(prefix) {
+                names.insert(name);
+            }
+        }
+        libc::endpwent();
+    }
+
+    names
+}
+
+/// Returns the command names starting with the prefix : the builtins, the functions, the aliases, the keywords and the programs of PATH
+pub fn command_names(prefix: &str, shell: &Shell) -> BTreeSet<String> {
+
+    let mut names: BTreeSet<String> = BUILTINS.iter().copied()
+        .chain(COMPOUND_KEYWORDS)
+        .chain(shell.function_names())
+        .chain(shell.aliases().keys().map(String::as_str))
+        .filter(|name| name.starts_with(prefix))
+        .map(str::to_string)
+        .collect();
+
+    let directories = shell.get_variable("PATH").unwrap_or_default().split(':').filter(|directory| !directory.is_empty());
+    for directory in directories {
+        let Ok(entries) = std::fs::read_dir(directory) else { continue };
+        let programs = entries.filter_map(Result::ok)
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with(prefix))
+            .filter(|name| is_executable_file(&Path::new(directory).join(name)));
+        names.extend(programs);
+    }
+
+    names
+}
+
+/// Returns the paths of the files starting with the path prefix (not quoted), and whether they are directories
+pub fn file_names(prefix: &str, shell: &Shell) -> Vec<(String, bool)> {
+
+    let (directory, name) = match prefix.rfind('/') {
+        Some(slash) => prefix.split_at(slash + 1),
+        None => ("", prefix),
+    };
+
+    directory_entries(directory, name, shell).into_iter()
+        .map(|(name, path)| (format!("{directory}{name}"), path.is_dir()))
+        .collect()
+}
+
+/// Returns the names and the paths of the files of the directory (the working directory if it is empty) starting with the prefix,
+/// sorted. The directory may start with a tilde, and the hidden files are only included if the prefix starts with `.`
+fn directory_entries(directory: &str, prefix: &str, shell: &Shell) -> Vec<(String, PathBuf)> {
+
+    let mut directory = directory.to_string();
+    if let Some(rest) = directory.strip_prefix('~') {
+        let (user, rest) = rest.split_once('/').unwrap_or((rest, ""));
+        if let Some(home) = expand_tilde(user, shell) {
+            directory = format!("{}/{rest}", home.trim_end_matches('/'));
+        }
+    }
+
+    let lookup = if directory.is_empty() { "." } else { directory.as_str() };
+    let Ok(entries) = std::fs::read_dir(lookup) else { return Vec::new() };
+
+    let mut files: Vec<(String, PathBuf)> = entries.filter_map(Result::ok)
+        .map(|entry| (entry.file_name().to_string_lossy().into_owned(), entry.path()))
+        .filter(|(name, _)| name.starts_with(prefix) && (!name.starts_with('.') || prefix.starts_with('.')))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Completes the last component of a path with the files of its directory, only the directories and the programs
+/// if it is a command. Returns the offset of the component in the word and the candidates
+fn complete_path(raw: &str, shell: &Shell, commands_only: bool) -> (usize, Vec<Pair>) {
+
+    let (directory_raw, name_raw) = match raw.rfind('/') {
+        Some(slash) => raw.split_at(slash + 1),
+        None => ("", raw),
+    };
+
+    // The directory is read with its tilde expanded, but it is kept as it was written in the line
+    let directory = unquote(directory_raw);
+    let prefix = unquote(name_raw);
+
+    // The completed name continues the quote open in the directory, or opened at its start
+    let (quote, opening) = match (quote_state(directory_raw), name_raw.chars().next()) {
+        (Quote::None, Some('\'')) => (Quote::Single, "'"),
+        (Quote::None, Some('"')) => (Quote::Double, "\""),
+        (quote, _) => (quote, ""),
+    };
+    let closing = match quote {
+        Quote::None => "",
+        Quote::Single => "'",
+        Quote::Double => "\"",
+    };
+
+    let files = directory_entries(&directory, &prefix, shell).into_iter()
+        .map(|(name, path)| (name, path.is_dir(), path))
+        .filter(|(_, is_directory, path)| !commands_only || *is_directory || is_executable_file(path))
+        .map(|(name, is_directory, _)| (name, is_directory));
+
+    let candidates = files.into_iter().map(|(name, is_directory)| {
+        let replacement = match is_directory {
+            true => format!("{opening}{}/", escape(&name, quote)),
+            false => format!("{opening}{}{closing} ", escape(&name, quote)),
+        };
+        Pair { display: if is_directory { format!("{name}/") } else { name }, replacement }
+    }).collect();
+
+    (directory_raw.len(), candidates)
+}
+
+pub fn is_executable_file(path: &Path) -> bool {
+    path.metadata().is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// Returns the quote open at the end of the text
+fn quote_state(text: &str) -> Quote {
+
+    let mut quote = Quote::None;
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        quote = match (quote, c) {
+            (Quote::Single, '\'') => Quote::None,
+            (Quote::Single, _) => Quote::Single,
+            (_, '\\') => {
+                chars.next();
+                quote
+            },
+            (Quote::None, '\'') => Quote::Single,
+            (Quote::None, '"') => Quote::Double,
+            (Quote::Double, '"') => Quote::None,
+            (quote, _) => quote,
+        };
+    }
+
+    quote
+}
+
+/// Removes the quotes and the backslashes of the word as it was written
+pub fn unquote(text: &str) -> String {
+
+    let mut unquoted = String::new();
+    let mut quote = Quote::None;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Quote::Single, '\'') | (Quote::Double, '"') => quote = Quote::None,
+            (Quote::None, '\'') => quote = Quote::Single,
+            (Quote::None, '"') => quote = Quote::Double,
+            (Quote::None, '\\') => unquoted.extend(chars.next()),
+            // In double quotes, a backslash only escapes the characters special there
+            (Quote::Double, '\\') if chars.peek().is_some_and(|next| "\"\\$`".contains(*next)) => unquoted.extend(chars.next()),
+            (_, c) => unquoted.push(c),
+        }
+    }
+
+    unquoted
+}
+
+/// Escapes the name to be written inside the quote
+fn escape(name: &str, quote: Quote) -> String {
+
+    let mut escaped = String::new();
+    for c in name.chars() {
+        match quote {
+            Quote::None if SPECIAL_CHARACTERS.contains(c) => escaped.push('\\'),
+            Quote::Double if "\"\\$`".contains(c) => escaped.push('\\'),
+            Quote::Single if c == '\'' => {
+                escaped.push_str("'\\''");
+                continue;
+            },
+            _ => (),
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::completion::spec::CompletionSpec;
+
+    fn replacements(line: &str, shell: &mut Shell) -> (usize, Vec<String>) {
+        let (start, candidates) = complete_line(line, line.len(), shell);
+        (start, candidates.into_iter().map(|candidate| candidate.replacement).collect())
+    }
+
+    #[test]
+    fn test_word_positions() {
+        let word = find_completed_word("x=1 ls -l > fi");
+        assert_eq!((12, "fi", false, true), (word.start, word.raw, word.position.command, word.position.redirection_target));
+
+        let word = find_completed_word("if true; then echo 'a b");
+        assert_eq!((19, "'a b", false), (word.start, word.raw, word.position.command));
+
+        let word = find_completed_word("cat file | ");
+        assert_eq!((11, "", true), (word.start, word.raw, word.position.command));
+
+        // The assignments, the keywords and the redirections are not words of the command
+        let word = find_completed_word("ls a; if x=1 git -C dir > out check");
+        assert_eq!(vec!["git", "-C", "dir"], word.command_words);
+    }
+
+    #[test]
+    fn test_quoting() {
+        assert_eq!(Quote::Double, quote_state("a'b'\"c\\\""));
+        assert_eq!("a b$c\\", unquote("a\\ 'b'\"\\$c\\\\\""));
+        assert_eq!("a\\ b\\'\\$", escape("a b'$", Quote::None));
+        assert_eq!("it'\\''s", escape("it's", Quote::Single));
+    }
+
+    #[test]
+    fn test_completions() {
+        let directory = std::env::temp_dir().join(format!("rust_shell_completion_{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("sub dir")).unwrap();
+        std::fs::write(directory.join("some file"), "").unwrap();
+        std::fs::write(directory.join(".hidden"), "").unwrap();
+        let path = directory.to_str().unwrap();
+
+        let mut shell = Shell::new();
+        shell.set_variable("COMPLETION_TEST", "1");
+        shell.set_variable("HOME", path);
+        shell.define_alias("completion_alias", "ls");
+
+        assert_eq!((6, vec!["COMPLETION_TEST".to_string()]), replacements("echo $COMPLETION_T", &mut shell));
+        assert_eq!((8, vec!["COMPLETION_TEST}".to_string()]), replacements("echo \"${COMPLETION_T", &mut shell));
+        assert_eq!((0, vec!["completion_alias ".to_string()]), replacements("completion_al", &mut shell));
+        assert!(replacements("ech", &mut shell).1.contains(&"echo ".to_string()));
+
+        let (start, candidates) = replacements(&format!("cat < {path}/so"), &mut shell);
+        assert_eq!((path.len() + 7, vec!["some\\ file ".to_string()]), (start, candidates));
+        assert_eq!(vec!["sub\\ dir/".to_string(), "some\\ file ".to_string()], replacements("ls ~/s", &mut shell).1.into_iter().rev().collect::<Vec<_>>());
+        assert_eq!(vec!["\"some file\" ".to_string()], replacements("ls ~/\"so", &mut shell).1);
+        assert_eq!(vec![".hidden ".to_string()], replacements("ls ~/.h", &mut shell).1);
+        // Only the directories and the programs are commands
+        assert_eq!(vec!["sub\\ dir/".to_string()], replacements("~/s", &mut shell).1);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_argument_completions() {
+        let mut shell = Shell::new();
+        shell.define_completion_spec("tool", CompletionSpec { word_list: Some("start stop 'with space'".to_string()), ..CompletionSpec::default() });
+        let spec = CompletionSpec { function: Some("_other".to_string()), options: vec![CompletionOption::NoSpace], ..CompletionSpec::default() };
+        shell.define_completion_spec("other", spec);
+        let function = crate::parsing::convert_to_command("_other() { COMPREPLY=(\"$2-$COMP_CWORD\" \"$3\"); }").unwrap();
+        function.execute(&mut shell, crate::command::IoContext::new()).unwrap();
+
+        assert_eq!((5, vec!["start ".to_string(), "stop ".to_string()]), replacements("tool st", &mut shell));
+        assert_eq!(vec!["'with space' ".to_string()], replacements("/usr/bin/tool 'w", &mut shell).1);
+        assert_eq!(vec!["a\\ b-2".to_string(), "x".to_string()], replacements("other x a\\ b", &mut shell).1);
+        assert_eq!(None, shell.get_variable("COMP_LINE"));
+    }
+}
@@ -0,0 +1,264 @@
+//!
+//! The completion of the arguments of common commands that have no specification defined with `complete` :
+//! the subcommands, references and remotes of `git`, the targets of `make` and the hosts of `ssh`,
+//! read from the files those commands use rather than by running them.
+//!
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::git::{alias_names, reference_names, remote_names};
+use crate::shell::Shell;
+
+/// The subcommands of git, besides the aliases of its configuration
+const GIT_COMMANDS: [&str; 40] = [
+    "add", "am", "apply", "archive", "bisect", "blame", "branch", "checkout", "cherry-pick", "clean", "clone", "commit", "config",
+    "describe", "diff", "fetch", "format-patch", "gc", "grep", "help", "init", "log", "merge", "mv", "notes", "pull", "push",
+    "rebase", "reflog", "remote", "reset", "restore", "revert", "rm", "show", "stash", "status", "submodule", "switch", "tag",
+];
+
+/// The git subcommands whose arguments are references
+const GIT_REFERENCE_COMMANDS: [&str; 11] = ["branch", "checkout", "cherry-pick", "diff", "log", "merge", "rebase", "reset", "revert", "show", "switch"];
+
+/// The git subcommands whose first argument is a remote, followed by references
+const GIT_REMOTE_COMMANDS: [&str; 3] = ["fetch", "pull", "push"];
+
+/// The git options before the subcommand that take a value
+const GIT_OPTIONS_WITH_VALUE: [&str; 5] = ["-C", "-c", "--git-dir", "--work-tree", "--namespace"];
+
+/// The files read by make when no `-f` option is given, in this order
+const MAKEFILES: [&str; 3] = ["GNUmakefile", "makefile", "Makefile"];
+
+/// The make options whose value is a file, besides `-C` and `-f`
+const MAKE_FILE_OPTIONS: [&str; 6] = ["-I", "-o", "-W", "--include-dir", "--old-file", "--what-if"];
+
+/// The ssh options that take a value
+const SSH_OPTIONS_WITH_VALUE: [&str; 19] = [
+    "-B", "-b", "-c", "-D", "-E", "-e", "-F", "-I", "-i", "-J", "-L", "-l", "-m", "-O", "-o", "-p", "-R", "-S", "-W",
+];
+
+/// Returns the candidates completing the word of the command, whose words before it are given without their quotes.
+///
+/// Returns None if the command has no native completer, or if it is not known for this argument, the files being completed then
+pub fn complete_arguments(command: &str, words: &[String], word: &str, shell: &Shell) -> Option<Vec<String>> {
+
+    if word.starts_with('-') {
+        return None;
+    }
+
+    let candidates = match command {
+        "git" => complete_git(words, shell)?,
+        "make" | "gmake" => complete_make(words, shell)?,
+        "ssh" | "sftp" => return complete_ssh(words, word, shell),
+        _ => return None,
+    };
+
+    Some(candidates.into_iter().filter(|candidate| candidate.starts_with(word)).collect())
+}
+
+/// Returns the working directory of the shell
+fn working_directory(shell: &Shell) -> PathBuf {
+    shell.get_variable("PWD").map(PathBuf::from).or_else(|| std::env::current_dir().ok()).unwrap_or_default()
+}
+
+/// Completes the subcommands of git, then the references or the remotes they take
+fn complete_git(words: &[String], shell: &Shell) -> Option<Vec<String>> {
+
+    let mut directory = working_directory(shell);
+    let mut subcommand = None;
+    let mut arguments = Vec::new();
+
+    let mut args = words.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            _ if subcommand.is_some() => arguments.push(arg.as_str()),
+            "-C" => directory = directory.join(args.next()?),
+            option if GIT_OPTIONS_WITH_VALUE.contains(&option) => {
+                args.next();
+            },
+            option if option.starts_with('-') => (),
+            name => subcommand = Some(name),
+        }
+    }
+
+    let Some(subcommand) = subcommand else {
+        let home = shell.get_variable("HOME").map(Path::new);
+        let mut names: BTreeSet<String> = GIT_COMMANDS.iter().map(|name| name.to_string()).collect();
+        names.extend(alias_names(&directory, home));
+        return Some(names.into_iter().collect());
+    };
+
+    // The paths follow `--`
+    if arguments.contains(&"--") {
+        return None;
+    }
+    let has_remote = arguments.iter().any(|argument| !argument.starts_with('-'));
+
+    match subcommand {
+        remote_command if GIT_REMOTE_COMMANDS.contains(&remote_command) && !has_remote => Some(remote_names(&directory)),
+        command if GIT_REMOTE_COMMANDS.contains(&command) || GIT_REFERENCE_COMMANDS.contains(&command) => Some(reference_names(&directory)),
+        _ => None,
+    }
+}
+
+/// Completes the targets of the makefile, found in the directory given with `-C` or given with `-f`
+fn complete_make(words: &[String], shell: &Shell) -> Option<Vec<String>> {
+
+    // The values of these options are files
+    if words.last().is_some_and(|last| MAKE_FILE_OPTIONS.contains(&last.as_str())) {
+        return None;
+    }
+
+    let mut directory = working_directory(shell);
+    let mut makefile = None;
+
+    let mut args = words.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-C" | "--directory" => directory = directory.join(args.next()?),
+            "-f" | "--file" | "--makefile" => makefile = Some(args.next()?.clone()),
+            arg => {
+                if let Some(path) = arg.strip_prefix("--directory=") {
+                    directory = directory.join(path);
+                } else if let Some(path) = arg.strip_prefix("--file=").or_else(|| arg.strip_prefix("--makefile=")) {
+                    makefile = Some(path.to_string());
+                }
+            },
+        }
+    }
+
+    let path = match makefile {
+        Some(makefile) => directory.join(makefile),
+        None => MAKEFILES.iter().map(|name| directory.join(name)).find(|path| path.is_file())?,
+    };
+    let content = std::fs::read_to_string(path).ok()?;
+
+    Some(make_targets(&content).into_iter().collect())
+}
+
+/// Returns the explicit targets of the rules of a makefile, without the special targets (like `.PHONY`) and the patterns
+fn make_targets(content: &str) -> BTreeSet<String> {
+
+    let mut targets = BTreeSet::new();
+
+    for line in content.lines() {
+        // The recipes start with a tab
+        if line.starts_with('\t') || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let Some((names, rest)) = line.split_once(':') else { continue };
+        // `name := value` and `name ::= value` are assignments, like `name = a:b`
+        if rest.starts_with('=') || rest.starts_with(":=") || names.contains('=') {
+            continue;
+        }
+
+        let names = names.split_whitespace().filter(|name| !name.starts_with('.') && !name.contains(['%', '$']));
+        targets.extend(names.map(str::to_string));
+    }
+
+    targets
+}
+
+/// Completes the host of ssh, which may be preceded by `user@`, with the hosts of `~/.ssh/config` and `~/.ssh/known_hosts`
+fn complete_ssh(words: &[String], word: &str, shell: &Shell) -> Option<Vec<String>> {
+
+    // The arguments after the host are the remote command
+    let mut args = words.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            option if SSH_OPTIONS_WITH_VALUE.contains(&option) => {
+                // The value of the option is completed as a file
+                args.next()?;
+            },
+            option if option.starts_with('-') => (),
+            _ => return None,
+        }
+    }
+
+    let (user, prefix) = match word.split_once('@') {
+        Some((user, host)) => (format!("{user}@"), host),
+        None => (String::new(), word),
+    };
+
+    let directory = Path::new(shell.get_variable("HOME").unwrap_or_default()).join(".ssh");
+    let mut hosts: BTreeSet<String> = config_hosts(&std::fs::read_to_string(directory.join("config")).unwrap_or_default()).into_iter().collect();
+    hosts.extend(known_hosts(&std::fs::read_to_string(directory.join("known_hosts")).unwrap_or_default()));
+
+    Some(hosts.into_iter().filter(|host| host.starts_with(prefix)).map(|host| format!("{user}{host}")).collect())
+}
+
+/// Returns the hosts of the `Host` lines of an ssh configuration file, except the patterns
+fn config_hosts(content: &str) -> Vec<String> {
+
+    let mut hosts = Vec::new();
+
+    for line in content.lines().map(str::trim) {
+        // The keyword is case insensitive, and may be separated from its value by `=`
+        let Some((keyword, value)) = line.split_once(|c: char| c.is_whitespace() || c == '=') else { continue };
+        if !keyword.eq_ignore_ascii_case("host") {
+            continue;
+        }
+
+        let names = value.trim_start_matches([' ', '\t', '=']).split_whitespace().filter(|name| !name.contains(['*', '?', '!']));
+        hosts.extend(names.map(str::to_string));
+    }
+
+    hosts
+}
+
+/// Returns the hosts of a `known_hosts` file, except the hashed ones. `[host]:port` gives the host
+fn known_hosts(content: &str) -> Vec<String> {
+
+    let mut hosts = Vec::new();
+
+    for line in content.lines().map(str::trim) {
+        // The comments start with `#`, the hashed hosts with `|`, and the markers like `@revoked` precede the hosts
+        let Some(names) = line.split_whitespace().next().filter(|names| !names.starts_with(['#', '|', '@'])) else { continue };
+
+        for name in names.split(',') {
+            let host = match name.strip_prefix('[') {
+                Some(bracketed) => bracketed.split(']').next().unwrap_or_default(),
+                None => name,
+            };
+            if !host.is_empty() && !host.contains(['*', '?', '!']) {
+                hosts.push(host.to_string());
+            }
+        }
+    }
+
+    hosts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_make_targets() {
+        let makefile = "CC := gcc\nFLAGS = a:b\n.PHONY: all clean\nall: build test\n\tcc -o $@\nbuild test:\n%.o: %.c\n$(OUT): x\ninstall:: all\n# doc: no\n";
+        assert_eq!(vec!["all", "build", "install", "test"], make_targets(makefile).into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_ssh_hosts() {
+        let config = "Host server backup\n  HostName 10.0.0.1\nhost=*.example.com web?\nMatch all\n";
+        assert_eq!(vec!["server", "backup"], config_hosts(config));
+
+        let known = "git.example.com,192.168.1.2 ssh-ed25519 AAAA\n[alt]:2222 ssh-rsa AAAA\n|1|hash= ssh-rsa AAAA\n@revoked bad ssh-rsa AAAA\n";
+        assert_eq!(vec!["git.example.com", "192.168.1.2", "alt"], known_hosts(known));
+    }
+
+    #[test]
+    fn test_native_completion() {
+        let mut shell = Shell::new();
+        shell.set_variable("PWD", "/");
+        shell.unset_variable("HOME");
+        let words = |words: &[&str]| words.iter().map(|word| word.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(Some(vec!["checkout".to_string(), "cherry-pick".to_string()]), complete_arguments("git", &words(&["git", "-C", "/"]), "ch", &shell));
+        assert_eq!(None, complete_arguments("git", &words(&["git", "add"]), "", &shell));
+        assert_eq!(None, complete_arguments("git", &words(&["git", "checkout", "--"]), "", &shell));
+        assert_eq!(None, complete_arguments("ssh", &words(&["ssh", "host"]), "", &shell));
+        assert_eq!(None, complete_arguments("ls", &words(&["ls"]), "", &shell));
+    }
+}
@@ -0,0 +1,354 @@
+//!
+//! The completions defined for the arguments of a command with the `complete` builtin, and the generation
+//! of their candidates, which `compgen` prints.
+//!
+//! A specification combines actions (the files, the commands, the variables...), a word list, a shell function
+//! filling the COMPREPLY array and a command printing the candidates, then filters them and adds a prefix and a suffix.
+//!
+
+use std::collections::BTreeSet;
+use std::error::Error;
+
+use crate::command::builtin::BUILTINS;
+use crate::command::builtin::format::{quote_if_needed, single_quote};
+use crate::command::execution::{ExecutionError, capture_output};
+use crate::command::{Command, IoContext};
+use crate::completion::{KEYWORDS, command_names, file_names, job_names, user_names, variable_names};
+use crate::expansion::{expand_word_list, glob};
+use crate::parsing::convert_to_command_with_aliases;
+use crate::shell::Shell;
+
+/// A kind of names to complete, given by its letter (like `-f`) or its name (like `-A file`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Alias,
+    Builtin,
+    Command,
+    Directory,
+    Export,
+    File,
+    Function,
+    Job,
+    Keyword,
+    User,
+    Variable,
+}
+
+/// The actions with their option letter, if they have one, and their name
+const ACTIONS: [(Action, Option<char>, &str); 11] = [
+    (Action::Alias, Some('a'), "alias"),
+    (Action::Builtin, Some('b'), "builtin"),
+    (Action::Command, Some('c'), "command"),
+    (Action::Directory, Some('d'), "directory"),
+    (Action::Export, Some('e'), "export"),
+    (Action::File, Some('f'), "file"),
+    (Action::Function, None, "function"),
+    (Action::Job, Some('j'), "job"),
+    (Action::Keyword, Some('k'), "keyword"),
+    (Action::User, Some('u'), "user"),
+    (Action::Variable, Some('v'), "variable"),
+];
+
+/// The options given with `-o name`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompletionOption {
+    /// The files are completed if there is no candidate
+    Default,
+    /// The directories are completed if there is no candidate
+    DirNames,
+    /// The candidates are files : the directories get a trailing `/`
+    FileNames,
+    /// No space is added after the completed word
+    NoSpace,
+}
+
+const COMPLETION_OPTIONS: [(CompletionOption, &str); 4] = [
+    (CompletionOption::Default, "default"),
+    (CompletionOption::DirNames, "dirnames"),
+    (CompletionOption::FileNames, "filenames"),
+    (CompletionOption::NoSpace, "nospace"),
+];
+
+/// How the arguments of a command are completed
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompletionSpec {
+    pub actions: Vec<Action>,
+    pub options: Vec<CompletionOption>,
+    /// -W : the words, split and expanded when the completion happens
+    pub word_list: Option<String>,
+    /// -F : the function setting the COMPREPLY array
+    pub function: Option<String>,
+    /// -C : the command printing the candidates, one per line
+    pub command: Option<String>,
+    /// -X : the pattern removing the candidates it matches, or the other ones if it starts with `!`
+    pub filter: Option<String>,
+    /// -P and -S : added before and after each candidate
+    pub prefix: Option<String>,
+    pub suffix: Option<String>,
+}
+
+/// The line being completed
+#[derive(Debug, Default)]
+pub struct CompletionContext {
+    /// The words of the command as they were written, the last one being the completed word (COMP_WORDS)
+    pub words: Vec<String>,
+    /// The completed word without its quotes
+    pub word: String,
+    /// The line and the position of the cursor in it (COMP_LINE and COMP_POINT)
+    pub line: String,
+    pub point: usize,
+}
+
+/// The arguments of `complete` or `compgen`
+#[derive(Debug, Default)]
+pub struct CompleteArguments {
+    pub spec: CompletionSpec,
+    /// -p : the specifications are printed
+    pub print: bool,
+    /// -r : the specifications are removed
+    pub remove: bool,
+    /// The command names, or the word completed by `compgen`
+    pub names: Vec<String>,
+}
+
+/// The variables set while a completion function or command runs
+const COMPLETION_VARIABLES: [&str; 4] = ["COMP_WORDS", "COMP_CWORD", "COMP_LINE", "COMP_POINT"];
+
+/// Parses the options of `complete` or `compgen`, which may be grouped like `-fo nospace` or `-Wwords`
+pub fn parse_complete_arguments(builtin: &str, args: &[String]) -> Result<CompleteArguments, Box<dyn Error>> {
+
+    let mut arguments = CompleteArguments::default();
+    let spec = &mut arguments.spec;
+
+    let mut position = 0;
+    while let Some(arg) = args.get(position) {
+        position += 1;
+
+        if arg == "--" {
+            break;
+        }
+        let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+            position -= 1;
+            break;
+        };
+
+        for (index, flag) in flags.char_indices() {
+            match flag {
+                'p' if builtin == "complete" => arguments.print = true,
+                'r' if builtin == "complete" => arguments.remove = true,
+                'o' | 'A' | 'W' | 'F' | 'C' | 'X' | 'P' | 'S' => {
+                    // The value is the rest of the argument, or the next argument
+                    let value = match &flags[index + flag.len_utf8()..] {
+                        "" => {
+                            position += 1;
+                            args.get(position - 1).ok_or_else(|| format!("{builtin}: -{flag}: option requires an argument"))?.as_str()
+                        },
+                        rest => rest,
+                    };
+                    set_option_value(spec, builtin, flag, value)?;
+                    break;
+                },
+                letter => match ACTIONS.iter().find(|(_, action_letter, _)| *action_letter == Some(letter)) {
+                    Some((action, _, _)) => spec.actions.push(*action),
+                    None => return Err(format!("{builtin}: -{letter}: invalid option").into()),
+                },
+            }
+        }
+    }
+
+    arguments.names = args[position..].to_vec();
+    Ok(arguments)
+}
+
+fn set_option_value(spec: &mut CompletionSpec, builtin: &str, flag: char, value: &str) -> Result<(), Box<dyn Error>> {
+
+    match flag {
+        'o' => match COMPLETION_OPTIONS.iter().find(|(_, name)| *name == value) {
+            Some((option, _)) => spec.options.push(*option),
+            None => return Err(format!("{builtin}: {value}: invalid option name").into()),
+        },
+        'A' => match ACTIONS.iter().find(|(_, _, name)| *name == value) {
+            Some((action, _, _)) => spec.actions.push(*action),
+            None => return Err(format!("{builtin}: {value}: invalid action name").into()),
+        },
+        'W' => spec.word_list = Some(value.to_string()),
+        'F' => spec.function = Some(value.to_string()),
+        'C' => spec.command = Some(value.to_string()),
+        'X' => spec.filter = Some(value.to_string()),
+        'P' => spec.prefix = Some(value.to_string()),
+        _ => spec.suffix = Some(value.to_string()),
+    }
+
+    Ok(())
+}
+
+impl CompletionSpec {
+
+    /// Returns true if the candidates are files, whose directories get a trailing `/`
+    pub fn completes_files(&self) -> bool {
+        self.options.contains(&CompletionOption::FileNames) || self.actions.iter().any(|action| matches!(action, Action::File | Action::Directory))
+    }
+
+    /// Returns the `complete` command defining this specification for the command, like `complete -o nospace -F _git git`
+    pub fn to_command(&self, name: &str) -> String {
+
+        let mut command = String::from("complete");
+        for option in &self.options {
+            let (_, option_name) = COMPLETION_OPTIONS.iter().find(|(known, _)| known == option).unwrap_or(&COMPLETION_OPTIONS[0]);
+            command.push_str(&format!(" -o {option_name}"));
+        }
+        for action in &self.actions {
+            match ACTIONS.iter().find(|(known, _, _)| known == action) {
+                Some((_, Some(letter), _)) => command.push_str(&format!(" -{letter}")),
+                Some((_, None, action_name)) => command.push_str(&format!(" -A {action_name}")),
+                None => (),
+            }
+        }
+        let values = [('W', &self.word_list), ('X', &self.filter), ('P', &self.prefix), ('S', &self.suffix), ('F', &self.function), ('C', &self.command)];
+        for (flag, value) in values {
+            if let Some(value) = value {
+                command.push_str(&format!(" -{flag} {}", quote_if_needed(value)));
+            }
+        }
+
+        format!("{command} {}", quote_if_needed(name))
+    }
+
+    /// Returns the candidates completing the word, sorted
+    pub fn generate(&self, context: &CompletionContext, shell: &mut Shell) -> Result<Vec<String>, ExecutionError> {
+
+        let word = context.word.as_str();
+        let mut candidates = BTreeSet::new();
+
+        for action in &self.actions {
+            candidates.extend(action_candidates(*action, word, shell));
+        }
+        if let Some(list) = &self.word_list {
+            candidates.extend(expand_word_list(list, shell)?.into_iter().filter(|candidate| candidate.starts_with(word)));
+        }
+
+        if self.function.is_some() || self.command.is_some() {
+            set_completion_variables(context, shell);
+            let generated = self.run_generators(context, shell);
+            for name in COMPLETION_VARIABLES {
+                shell.unset_variable(name);
+            }
+            candidates.extend(generated?);
+        }
+
+        if let Some(filter) = &self.filter {
+            // `&` in the pattern stands for the completed word
+            let (keep_matching, pattern) = match filter.strip_prefix('!') {
+                Some(pattern) => (true, pattern.replace('&', word)),
+                None => (false, filter.replace('&', word)),
+            };
+            candidates.retain(|candidate| glob::matches(&pattern, candidate) == keep_matching);
+        }
+
+        let (prefix, suffix) = (self.prefix.as_deref().unwrap_or_default(), self.suffix.as_deref().unwrap_or_default());
+        Ok(candidates.into_iter().map(|candidate| format!("{prefix}{candidate}{suffix}")).collect())
+    }
+
+    /// Runs the completion function and the completion command, which are given the command name, the completed word
+    /// and the word before it
+    fn run_generators(&self, context: &CompletionContext, shell: &mut Shell) -> Result<Vec<String>, ExecutionError> {
+
+        let count = context.words.len();
+        // `compgen` completes a word without a command
+        let command = if count > 1 { context.words[0].as_str() } else { "" };
+        let previous = count.checked_sub(2).map_or("", |index| context.words[index].as_str());
+        let args = vec![single_quote(command), single_quote(&context.word), single_quote(previous)];
+        let mut candidates = Vec::new();
+
+        if let Some(function) = &self.function {
+            if shell.get_function(function).is_none() {
+                return Err(ExecutionError::BuiltinExecError(format!("{function}: function not found")));
+            }
+            shell.unset_variable("COMPREPLY");
+            let call = Command::Simple { cmd_path: single_quote(function), cmd_args: args.clone() };
+            call.execute(shell, IoContext::new())?;
+            candidates.extend(shell.get_array("COMPREPLY").unwrap_or_default());
+        }
+
+        if let Some(command) = &self.command {
+            let input = format!("{command} {}", args.join(" "));
+            let command = convert_to_command_with_aliases(&input, shell.aliases())?;
+            let output = capture_output(&command, shell)?;
+            candidates.extend(output.lines().map(str::to_string));
+        }
+
+        Ok(candidates)
+    }
+}
+
+/// Sets COMP_WORDS, COMP_CWORD, COMP_LINE and COMP_POINT, the last two being exported for the completion command
+fn set_completion_variables(context: &CompletionContext, shell: &mut Shell) {
+
+    shell.set_array("COMP_WORDS", context.words.clone());
+    shell.set_variable("COMP_CWORD", &context.words.len().saturating_sub(1).to_string());
+    shell.set_variable("COMP_LINE", &context.line);
+    shell.set_variable("COMP_POINT", &context.point.to_string());
+    shell.export_variable("COMP_LINE");
+    shell.export_variable("COMP_POINT");
+}
+
+/// Returns the names of the action starting with the word
+fn action_candidates(action: Action, word: &str, shell: &Shell) -> Vec<String> {
+
+    let matching = |names: &mut dyn Iterator<Item = &str>| names.filter(|name| name.starts_with(word)).map(str::to_string).collect();
+
+    match action {
+        Action::Alias => matching(&mut shell.aliases().keys().map(String::as_str)),
+        Action::Builtin => matching(&mut BUILTINS.iter().copied()),
+        Action::Command => command_names(word, shell).into_iter().collect(),
+        Action::Directory => file_names(word, shell).into_iter().filter(|(_, is_directory)| *is_directory).map(|(path, _)| path).collect(),
+        Action::Export => variable_names(word, shell).into_iter().filter(|name| shell.is_exported(name)).collect(),
+        Action::File => file_names(word, shell).into_iter().map(|(path, _)| path).collect(),
+        Action::Function => matching(&mut shell.function_names().into_iter()),
+        Action::Job => job_names(word, shell).into_iter().collect(),
+        Action::Keyword => matching(&mut KEYWORDS.iter().copied()),
+        Action::User => user_names(word).into_iter().collect(),
+        Action::Variable => variable_names(word, shell).into_iter().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arguments(args: &[&str]) -> Result<CompleteArguments, Box<dyn Error>> {
+        parse_complete_arguments("complete", &args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_complete_arguments() {
+        let parsed = arguments(&["-fo", "nospace", "-A", "function", "-Wa b", "-F", "_f", "--", "cmd", "-x"]).unwrap();
+        assert_eq!(vec![Action::File, Action::Function], parsed.spec.actions);
+        assert_eq!(vec![CompletionOption::NoSpace], parsed.spec.options);
+        assert_eq!((Some("a b"), Some("_f")), (parsed.spec.word_list.as_deref(), parsed.spec.function.as_deref()));
+        assert_eq!(vec!["cmd", "-x"], parsed.names);
+        assert_eq!("complete -o nospace -f -A function -W 'a b' -F _f cmd", parsed.spec.to_command("cmd"));
+
+        assert!(arguments(&["-o", "unknown"]).is_err());
+        assert!(arguments(&["-W"]).is_err());
+        assert!(parse_complete_arguments("compgen", &["-p".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_generation() {
+        let mut shell = Shell::new();
+        shell.set_variable("list", "two");
+        let spec = CompletionSpec {
+            word_list: Some("three 'two words' $list tea".to_string()),
+            filter: Some("*a".to_string()),
+            prefix: Some("<".to_string()),
+            ..CompletionSpec::default()
+        };
+        let context = CompletionContext { words: vec!["cmd".to_string(), "t".to_string()], word: "t".to_string(), ..CompletionContext::default() };
+        assert_eq!(vec!["<three", "<two", "<two words"], spec.generate(&context, &mut shell).unwrap());
+
+        let spec = CompletionSpec { actions: vec![Action::Keyword], filter: Some("!&*e".to_string()), ..CompletionSpec::default() };
+        let context = CompletionContext { word: "d".to_string(), ..CompletionContext::default() };
+        assert_eq!(vec!["done"], spec.generate(&context, &mut shell).unwrap());
+    }
+}
@@ -0,0 +1,526 @@
+//!
+//! The configuration of the interactive shell, read from `$XDG_CONFIG_HOME/rust_shell/config.toml` (`~/.config`
+//! by default) when it starts and by `config reload` : the prompt, the colors of the line being edited, the history
+//! policy, the completion, the editing mode and the key bindings, the startup banner and the features to turn off.
+//!
+//! The prompt and the history are set through their variables (PS1, HISTSIZE...) and the editing mode through its
+//! option, before the startup files, which can still change them.
+//!
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use toml::{Table, Value};
+
+use crate::expansion::expand_tilde;
+use crate::expansion::prompt::{DEFAULT_PS1, DEFAULT_PS2, DEFAULT_PS4};
+use crate::key_binding::{EDITOR_FUNCTIONS, KeyAction, KeyBinding, parse_key_sequence};
+use crate::shell::Shell;
+
+/// The values of PS1 chosen by `prompt.theme`
+const PROMPT_THEMES: [(&str, &str); 4] = [
+    ("default", DEFAULT_PS1),
+    ("minimal", "\\$ "),
+    ("classic", "\\u@\\h:\\w\\$ "),
+    ("git", "\\[\\e[1;34m\\]\\w\\[\\e[0m\\] \\[\\e[33m\\]\\g\\[\\e[0m\\]> "),
+];
+
+/// The settings of `[prompt]`, with the variables they set
+const PROMPT_VARIABLES: [(&str, &str); 4] = [("ps1", "PS1"), ("ps2", "PS2"), ("ps4", "PS4"), ("command", "PROMPT_COMMAND")];
+/// The values of the variables set by the configuration when it doesn't set them, the others being unset
+const DEFAULT_VARIABLES: [(&str, &str); 3] = [("PS1", DEFAULT_PS1), ("PS2", DEFAULT_PS2), ("PS4", DEFAULT_PS4)];
+/// The values of the options set by the configuration when it doesn't set them
+const DEFAULT_OPTIONS: [(&str, bool); 3] = [("histexpand", true), ("emacs", true), ("vi", false)];
+/// The values of `history.control`, like in HISTCONTROL
+const HISTORY_CONTROLS: [&str; 4] = ["ignorespace", "ignoredups", "ignoreboth", "erasedups"];
+
+/// The names of the colors and attributes of `[colors]`, with their SGR parameters
+const COLOR_NAMES: [(&str, &str); 13] = [
+    ("bold", "1"), ("dim", "2"), ("italic", "3"), ("underline", "4"), ("reverse", "7"),
+    ("black", "30"), ("red", "31"), ("green", "32"), ("yellow", "33"), ("blue", "34"), ("magenta", "35"), ("cyan", "36"),
+    ("white", "37"),
+];
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    Syntax(String),
+    #[error("{key}: {message}")]
+    Invalid { key: String, message: String },
+}
+
+/// The configuration read from the file, the missing settings having their default value
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// Whether the banner is printed when the shell starts
+    pub banner: bool,
+    /// The variables set by the prompt and history settings, like PS1 or HISTSIZE
+    pub variables: Vec<(String, String)>,
+    /// The options set or unset by the history and editor settings, like `histexpand` or `vi`
+    pub options: Vec<(String, bool)>,
+    pub key_bindings: Vec<KeyBinding>,
+    pub colors: Colors,
+    pub completion: CompletionConfig,
+    pub features: Features,
+}
+
+/// The SGR parameters of the parts of the line being edited, and of the suggestion
+#[derive(Clone, Debug, PartialEq)]
+pub struct Colors {
+    pub command: String,
+    pub unknown_command: String,
+    pub builtin: String,
+    pub keyword: String,
+    pub string: String,
+    pub expansion: String,
+    pub operator: String,
+    pub redirection_target: String,
+    pub unterminated: String,
+    /// Added to the style of the parts of a word naming an existing file
+    pub path: String,
+    pub suggestion: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompletionStyle {
+    /// The candidates are listed, like in bash
+    List,
+    /// Each Tab replaces the word by the next candidate
+    Circular,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompletionConfig {
+    pub style: CompletionStyle,
+    /// Above this number of candidates, the user is asked before they are listed
+    pub prompt_limit: usize,
+    /// Whether the arguments of `git`, `make` and `ssh` are completed without a specification
+    pub native: bool,
+}
+
+/// The interactive features, which are all enabled by default
+#[derive(Clone, Debug, PartialEq)]
+pub struct Features {
+    pub syntax_highlighting: bool,
+    pub suggestions: bool,
+    /// The full-screen search of Ctrl-R, replaced by the search of the line editor when disabled
+    pub fuzzy_history_search: bool,
+    /// The git status of `\g` in the prompt
+    pub git_prompt: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            banner: true,
+            variables: Vec::new(),
+            options: Vec::new(),
+            key_bindings: Vec::new(),
+            colors: Colors::default(),
+            completion: CompletionConfig { style: CompletionStyle::List, prompt_limit: 100, native: true },
+            features: Features { syntax_highlighting: true, suggestions: true, fuzzy_history_search: true, git_prompt: true },
+        }
+    }
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        let color = |style: &str| style.to_string();
+        Colors {
+            command: color("32"),
+            unknown_command: color("31"),
+            builtin: color("36"),
+            keyword: color("1;35"),
+            string: color("33"),
+            expansion: color("35"),
+            operator: color("1;34"),
+            redirection_target: color("34"),
+            unterminated: color("1;31"),
+            path: color("4"),
+            suggestion: color("2"),
+        }
+    }
+}
+
+impl Config {
+
+    /// Reads the configuration file, the default configuration being used if it doesn't exist
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Config::parse(&text),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Parses and validates the configuration, the unknown settings and the values of the wrong type being errors
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+
+        let table: Table = text.parse().map_err(|err: toml::de::Error| ConfigError::Syntax(err.to_string().trim_end().to_string()))?;
+        let mut config = Config::default();
+
+        for (name, value) in &table {
+            match name.as_str() {
+                "banner" => config.banner = boolean(name, value)?,
+                "prompt" => config.parse_prompt(section(name, value)?)?,
+                "colors" => config.parse_colors(section(name, value)?)?,
+                "history" => config.parse_history(section(name, value)?)?,
+                "completion" => config.parse_completion(section(name, value)?)?,
+                "editor" => config.parse_editor(section(name, value)?)?,
+                "key_bindings" => config.parse_key_bindings(section(name, value)?)?,
+                "features" => config.parse_features(section(name, value)?)?,
+                _ => return Err(invalid(name, "unknown setting")),
+            }
+        }
+
+        Ok(config)
+    }
+
+    fn parse_prompt(&mut self, table: &Table) -> Result<(), ConfigError> {
+
+        // An explicit PS1 takes precedence over the theme
+        if let Some(value) = table.get("theme") {
+            let theme = choice("prompt.theme", value, &PROMPT_THEMES.map(|(name, _)| name))?;
+            if !table.contains_key("ps1") {
+                let (_, ps1) = PROMPT_THEMES.iter().find(|(name, _)| *name == theme).unwrap_or(&PROMPT_THEMES[0]);
+                self.variables.push(("PS1".to_string(), ps1.to_string()));
+            }
+        }
+        for (name, value) in table.iter().filter(|(name, _)| *name != "theme") {
+            let key = format!("prompt.{name}");
+            let (_, variable) = PROMPT_VARIABLES.iter().find(|(setting, _)| setting == name).ok_or_else(|| invalid(&key, "unknown setting"))?;
+            self.variables.push((variable.to_string(), string(&key, value)?.to_string()));
+        }
+        Ok(())
+    }
+
+    fn parse_colors(&mut self, table: &Table) -> Result<(), ConfigError> {
+
+        for (name, value) in table {
+            let key = format!("colors.{name}");
+            let colors = &mut self.colors;
+            let color = match name.as_str() {
+                "command" => &mut colors.command,
+                "unknown_command" => &mut colors.unknown_command,
+                "builtin" => &mut colors.builtin,
+                "keyword" => &mut colors.keyword,
+                "string" => &mut colors.string,
+                "expansion" => &mut colors.expansion,
+                "operator" => &mut colors.operator,
+                "redirection_target" => &mut colors.redirection_target,
+                "unterminated" => &mut colors.unterminated,
+                "path" => &mut colors.path,
+                "suggestion" => &mut colors.suggestion,
+                _ => return Err(invalid(&key, "unknown setting")),
+            };
+            *color = parse_color(string(&key, value)?).map_err(|message| invalid(&key, &message))?;
+        }
+        Ok(())
+    }
+
+    fn parse_history(&mut self, table: &Table) -> Result<(), ConfigError> {
+
+        for (name, value) in table {
+            let key = format!("history.{name}");
+            let (variable, variable_value) = match name.as_str() {
+                "file" => ("HISTFILE", string(&key, value)?.to_string()),
+                // A negative size means no limit, like for the variables
+                "size" => ("HISTSIZE", integer(&key, value)?.to_string()),
+                "file_size" => ("HISTFILESIZE", integer(&key, value)?.to_string()),
+                "control" => {
+                    let controls = strings(&key, value)?;
+                    if let Some(control) = controls.iter().find(|control| !HISTORY_CONTROLS.contains(&control.as_str())) {
+                        return Err(invalid(&key, &format!("unknown value `{control}' (expected {})", HISTORY_CONTROLS.join(", "))));
+                    }
+                    ("HISTCONTROL", controls.join(":"))
+                },
+                "ignore" => ("HISTIGNORE", strings(&key, value)?.join(":")),
+                "expansion" => {
+                    self.options.push(("histexpand".to_string(), boolean(&key, value)?));
+                    continue;
+                },
+                _ => return Err(invalid(&key, "unknown setting")),
+            };
+            self.variables.push((variable.to_string(), variable_value));
+        }
+        Ok(())
+    }
+
+    fn parse_completion(&mut self, table: &Table) -> Result<(), ConfigError> {
+
+        for (name, value) in table {
+            let key = format!("completion.{name}");
+            match name.as_str() {
+                "style" => self.completion.style = match choice(&key, value, &["list", "circular"])? {
+                    "list" => CompletionStyle::List,
+                    _ => CompletionStyle::Circular,
+                },
+                "prompt_limit" => self.completion.prompt_limit = usize::try_from(integer(&key, value)?)
+                    .map_err(|_| invalid(&key, "expected a positive number"))?,
+                "native" => self.completion.native = boolean(&key, value)?,
+                _ => return Err(invalid(&key, "unknown setting")),
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_editor(&mut self, table: &Table) -> Result<(), ConfigError> {
+
+        for (name, value) in table {
+            let key = format!("editor.{name}");
+            match name.as_str() {
+                "mode" => {
+                    let mode = choice(&key, value, &["emacs", "vi"])?;
+                    self.options.push(("emacs".to_string(), mode == "emacs"));
+                    self.options.push(("vi".to_string(), mode == "vi"));
+                },
+                _ => return Err(invalid(&key, "unknown setting")),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the bindings like `'\C-a' = "end-of-line"`, `'\eg' = { insert = "git " }` or `'\C-g' = { command = "git status" }`
+    fn parse_key_bindings(&mut self, table: &Table) -> Result<(), ConfigError> {
+
+        for (sequence, value) in table {
+            let key = format!("key_bindings.'{sequence}'");
+            let keys = parse_key_sequence(sequence).map_err(|message| invalid(&key, &message))?;
+            let action = match value {
+                Value::String(function) if EDITOR_FUNCTIONS.contains(&function.as_str()) => KeyAction::Function(function.clone()),
+                Value::String(function) => return Err(invalid(&key, &format!("unknown function name `{function}' (see `bind -l')"))),
+                Value::Table(action) => match action.iter().collect::<Vec<_>>().as_slice() {
+                    [(name, text)] if *name == "insert" => KeyAction::Macro(string(&key, text)?.to_string()),
+                    [(name, command)] if *name == "command" => KeyAction::Command(string(&key, command)?.to_string()),
+                    _ => return Err(invalid(&key, "expected { insert = \"text\" } or { command = \"command\" }")),
+                },
+                value => return Err(invalid(&key, &format!("expected a function name or a table, found {}", value.type_str()))),
+            };
+            self.key_bindings.push(KeyBinding { keys, action });
+        }
+        Ok(())
+    }
+
+    fn parse_features(&mut self, table: &Table) -> Result<(), ConfigError> {
+
+        for (name, value) in table {
+            let key = format!("features.{name}");
+            let features = &mut self.features;
+            let feature = match name.as_str() {
+                "syntax_highlighting" => &mut features.syntax_highlighting,
+                "suggestions" => &mut features.suggestions,
+                "fuzzy_history_search" => &mut features.fuzzy_history_search,
+                "git_prompt" => &mut features.git_prompt,
+                _ => return Err(invalid(&key, "unknown feature")),
+            };
+            *feature = boolean(&key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Sets the variables, the options and the key bindings of the configuration in the shell, which keeps it
+    /// for the line editor. The settings of the previous configuration are first reset to their default value,
+    /// unless they were changed since, and its key bindings are removed
+    pub fn apply(mut self, shell: &mut Shell) {
+
+        let previous = std::mem::take(&mut shell.config);
+        for (name, value) in &previous.variables {
+            if shell.get_variable(name) == Some(value.as_str()) {
+                match DEFAULT_VARIABLES.iter().find(|(variable, _)| variable == name) {
+                    Some((_, default)) => shell.set_variable(name, default),
+                    None => shell.unset_variable(name),
+                }
+            }
+        }
+        for (name, enabled) in &previous.options {
+            let default = DEFAULT_OPTIONS.iter().find(|(option, _)| option == name).is_some_and(|(_, default)| *default);
+            if shell.is_option_set(name) == *enabled {
+                shell.set_option(name, default);
+            }
+        }
+
+        for (name, value) in &mut self.variables {
+            // The file of the history may start with ~, the expanded value being kept to know if it is changed
+            let expanded = value.split_once('/').filter(|(prefix, _)| name == "HISTFILE" && prefix.starts_with('~'))
+                .and_then(|(prefix, rest)| expand_tilde(prefix, shell).map(|home| format!("{home}/{rest}")));
+            if let Some(expanded) = expanded {
+                *value = expanded;
+            }
+            shell.set_variable(name, value);
+        }
+        for (name, enabled) in &self.options {
+            shell.set_option(name, *enabled);
+        }
+
+        let previous_bindings: Vec<KeyBinding> = previous.key_bindings.into_iter()
+            .filter(|binding| shell.key_bindings().contains(binding)).collect();
+        for binding in previous_bindings {
+            shell.unbind_keys(&binding.keys);
+        }
+        for binding in &self.key_bindings {
+            shell.bind_keys(binding.clone());
+        }
+
+        shell.config = self;
+    }
+}
+
+/// Returns the file of the configuration : `rust_shell/config.toml` in the configuration directory
+/// (`$XDG_CONFIG_HOME`, `~/.config` by default)
+pub fn config_path(shell: &Shell) -> Option<PathBuf> {
+    let config_home = shell.get_variable("XDG_CONFIG_HOME").filter(|directory| directory.starts_with('/')).map(PathBuf::from)
+        .or_else(|| shell.get_variable("HOME").map(|home| Path::new(home).join(".config")))?;
+    Some(config_home.join("rust_shell/config.toml"))
+}
+
+/// Returns the SGR parameters of a color, given by names like `bold green`, by `#rrggbb`, or by its parameters like `1;32`
+fn parse_color(color: &str) -> Result<String, String> {
+
+    if !color.is_empty() && color.chars().all(|c| c.is_ascii_digit() || c == ';') {
+        return Ok(color.to_string());
+    }
+
+    let mut parameters = Vec::new();
+    for word in color.split_whitespace() {
+        let bright = word.strip_prefix("bright-").or_else(|| word.strip_prefix("bright_"));
+        let parameter = match (word.strip_prefix('#'), bright) {
+            (Some(hex), _) if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+                let component = |index: usize| u8::from_str_radix(&hex[index..index + 2], 16).unwrap_or_default();
+                format!("38;2;{};{};{}", component(0), component(2), component(4))
+            },
+            (_, Some(name)) => match COLOR_NAMES.iter().find(|(color, _)| *color == name).and_then(|(_, code)| code.parse::<u8>().ok()) {
+                Some(code) if code >= 30 => (code + 60).to_string(),
+                _ => return Err(format!("unknown color `{word}'")),
+            },
+            _ => match COLOR_NAMES.iter().find(|(name, _)| *name == word) {
+                Some((_, code)) => code.to_string(),
+                None => return Err(format!("unknown color `{word}' (expected names like `bold green', `#rrggbb' or SGR parameters like `1;32')")),
+            },
+        };
+        parameters.push(parameter);
+    }
+
+    match parameters.is_empty() {
+        true => Err("empty color".to_string()),
+        false => Ok(parameters.join(";")),
+    }
+}
+
+fn invalid(key: &str, message: &str) -> ConfigError {
+    ConfigError::Invalid { key: key.to_string(), message: message.to_string() }
+}
+
+fn expected(key: &str, expected: &str, value: &Value) -> ConfigError {
+    invalid(key, &format!("expected {expected}, found {}", value.type_str()))
+}
+
+fn section<'a>(key: &str, value: &'a Value) -> Result<&'a Table, ConfigError> {
+    value.as_table().ok_or_else(|| expected(key, "a table", value))
+}
+
+fn boolean(key: &str, value: &Value) -> Result<bool, ConfigError> {
+    value.as_bool().ok_or_else(|| expected(key, "true or false", value))
+}
+
+fn integer(key: &str, value: &Value) -> Result<i64, ConfigError> {
+    value.as_integer().ok_or_else(|| expected(key, "an integer", value))
+}
+
+fn string<'a>(key: &str, value: &'a Value) -> Result<&'a str, ConfigError> {
+    value.as_str().ok_or_else(|| expected(key, "a string", value))
+}
+
+/// A list of strings, or a single string
+fn strings(key: &str, value: &Value) -> Result<Vec<String>, ConfigError> {
+    match value {
+        Value::String(text) => Ok(vec![text.clone()]),
+        Value::Array(values) => values.iter().map(|value| string(key, value).map(str::to_string)).collect(),
+        value => Err(expected(key, "a list of strings", value)),
+    }
+}
+
+/// One of the strings of the choices
+fn choice<'a>(key: &str, value: &'a Value, choices: &[&str]) -> Result<&'a str, ConfigError> {
+    let text = string(key, value)?;
+    match choices.contains(&text) {
+        true => Ok(text),
+        false => Err(invalid(key, &format!("unknown value `{text}' (expected {})", choices.join(", ")))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_binding::{Key, KeyCode};
+
+    #[test]
+    fn test_config() {
+        let config = Config::parse(r##"
+            banner = false
+
+            [prompt]
+            theme = "minimal"
+            ps2 = "... "
+
+            [colors]
+            keyword = "bold bright-magenta"
+            string = "#ff8000"
+            suggestion = "2;3"
+
+            [history]
+            size = 500
+            control = ["ignoreboth", "erasedups"]
+            ignore = "ls"
+            expansion = false
+
+            [completion]
+            style = "circular"
+
+            [editor]
+            mode = "vi"
+
+            [key_bindings]
+            '\C-a' = "end-of-line"
+            'Meta-g' = { insert = "git " }
+            '\C-g' = { command = "git status" }
+
+            [features]
+            suggestions = false
+        "##).unwrap();
+
+        assert!(!config.banner);
+        // The tables are read in the order of their names
+        let variables = [("HISTCONTROL", "ignoreboth:erasedups"), ("HISTIGNORE", "ls"), ("HISTSIZE", "500"), ("PS1", "\\$ "), ("PS2", "... ")];
+        assert_eq!(variables.map(|(name, value)| (name.to_string(), value.to_string())).to_vec(), config.variables);
+        assert_eq!(vec![("emacs".to_string(), false), ("vi".to_string(), true), ("histexpand".to_string(), false)], config.options);
+        assert_eq!("1;95", config.colors.keyword);
+        assert_eq!("38;2;255;128;0", config.colors.string);
+        assert_eq!("2;3", config.colors.suggestion);
+        assert_eq!(CompletionStyle::Circular, config.completion.style);
+        assert_eq!(KeyAction::Macro("git ".to_string()), config.key_bindings[0].action);
+        assert_eq!(vec![Key { code: KeyCode::Char('g'), control: true, meta: false }], config.key_bindings[2].keys);
+        assert!(!config.features.suggestions && config.features.syntax_highlighting);
+
+        assert_eq!(Config::default(), Config::parse("").unwrap());
+    }
+
+    #[test]
+    fn test_config_errors() {
+        for (text, message) in [
+            ("banner = 1", "banner: expected true or false, found integer"),
+            ("[prompt]\ntheme = \"fancy\"", "prompt.theme: unknown value `fancy' (expected default, minimal, classic, git)"),
+            ("[history]\nsise = 10", "history.sise: unknown setting"),
+            ("[history]\ncontrol = [\"ignorespace\", 1]", "history.control: expected a string, found integer"),
+            ("[colors]\nstring = \"yelow\"", "colors.string: unknown color `yelow' (expected names like `bold green', `#rrggbb' or SGR parameters like `1;32')"),
+            ("[key_bindings]\n'\\C-a' = \"nothing\"", "key_bindings.'\\C-a': unknown function name `nothing' (see `bind -l')"),
+            ("[key_bindings]\n'\\C-a' = { run = \"ls\" }", "key_bindings.'\\C-a': expected { insert = \"text\" } or { command = \"command\" }"),
+            ("[completion]\nprompt_limit = -1", "completion.prompt_limit: expected a positive number"),
+            ("[themes]", "themes: unknown setting"),
+        ] {
+            assert_eq!(message, Config::parse(text).unwrap_err().to_string());
+        }
+        assert!(Config::parse("[history\nsize = 1").unwrap_err().to_string().contains("line 1"));
+    }
+}
@@ -0,0 +1,713 @@
+//!
+//! Expansion of the words written in a command into the arguments given to the program :
+//! tilde (`~`, `~user`, `~N`...), parameters (`$name`, `${name}`, `$1`, `$@`...), arithmetic (`$(( ))`),
+//! command substitution (`$( )` and backquotes), field splitting, filename expansion and quote removal.
+//!
+
+use crate::arithmetic::{self, ArithmeticError};
+use crate::command::builtin::directory_stack::stack_entry;
+use crate::command::execution::capture_output;
+use crate::parsing::{ParsingError, Token, convert_to_command_with_aliases};
+use crate::parsing::lexer::tokenize_input;
+use crate::shell::{Shell, is_valid_variable_name};
+
+pub mod glob;
+pub mod prompt;
+
+/// Expands the words into fields, one word may give zero, one or several fields.
+///
+/// For example, with `$list` being "a b", the words `echo $list "$list"` give the fields `echo`, `a`, `b` and `a b`
+pub fn expand_words(words: &[String], shell: &mut Shell) -> Result<Vec<String>, ExpansionError> {
+
+    let mut fields = Vec::new();
+    for word in words {
+        let segments = expand_segments(word, shell)?;
+
+        for field in split_fields(segments, shell) {
+            // A field with unquoted special characters is replaced by the matching file names, if any
+            let pattern = to_pattern(&field);
+            let paths = match glob::has_glob_chars(&pattern) {
+                true => glob::expand_pathname(&pattern),
+                false => Vec::new(),
+            };
+
+            if paths.is_empty() {
+                fields.push(field.into_iter().map(|(c, _)| c).collect());
+            } else {
+                fields.extend(paths);
+            }
+        }
+    }
+
+    Ok(fields)
+}
+
+/// Splits the text into words like a command line, then expands them like the arguments of a command.
+///
+/// It gives the elements of `name=(elements)` and the words of `compgen -W`
+pub fn expand_word_list(text: &str, shell: &mut Shell) -> Result<Vec<String>, ExpansionError> {
+
+    let words: Vec<String> = tokenize_input(text)?.into_iter()
+        .filter_map(|token| match token {
+            Token::Word(word) => Some(word),
+            _ => None,
+        })
+        .collect();
+
+    expand_words(&words, shell)
+}
+
+/// Expands a word into a glob pattern, in which the quoted special characters are escaped.
+///
+/// For instance `"*".txt` gives the pattern `\*.txt` which only matches the file named `*.txt`
+pub fn expand_pattern(word: &str, shell: &mut Shell) -> Result<String, ExpansionError> {
+    Ok(to_pattern(&expand_marking_quotes(word, shell)?))
+}
+
+/// Expands a word into an extended regular expression, in which the quoted special characters are escaped.
+///
+/// For instance `'a.b'*` gives the regular expression `a\.b*`
+pub fn expand_regex(word: &str, shell: &mut Shell) -> Result<String, ExpansionError> {
+    Ok(escape_quoted(&expand_marking_quotes(word, shell)?, |c| "\\.[]()*+?{}|^$".contains(c)))
+}
+
+/// Expands a word without field splitting, each character being marked as quoted or not
+fn expand_marking_quotes(word: &str, shell: &mut Shell) -> Result<Vec<(char, bool)>, ExpansionError> {
+
+    let segments = expand_segments(word, shell)?;
+    Ok(segments.into_iter().flat_map(|segment| match segment {
+        Segment::Text { text, quoted, .. } => text.chars().map(|c| (c, quoted)).collect(),
+        Segment::FieldBreak => vec![(' ', true)],
+    }).collect())
+}
+
+/// Converts expanded characters into a glob pattern, escaping the quoted ones
+fn to_pattern(chars: &[(char, bool)]) -> String {
+    escape_quoted(chars, |c| matches!(c, '*' | '?' | '[' | ']' | '\\'))
+}
+
+/// Joins the expanded characters, the quoted ones being escaped with a backslash if they are special
+fn escape_quoted(chars: &[(char, bool)], is_special: fn(char) -> bool) -> String {
+
+    let mut text = String::new();
+    for &(c, quoted) in chars {
+        if quoted && is_special(c) {
+            text.push('\\');
+        }
+        text.push(c);
+    }
+
+    text
+}
+
+/// Expands a word into a single string, without field splitting, for instance for the file of a redirection
+pub fn expand_word(word: &str, shell: &mut Shell) -> Result<String, ExpansionError> {
+
+    let segments = expand_segments(word, shell)?;
+    let fields: Vec<String> = segments.into_iter().filter_map(|segment| match segment {
+        Segment::Text { text, .. } => Some(text),
+        Segment::FieldBreak => None,
+    }).collect();
+
+    // "$@" gives a field per parameter, which are joined back with spaces
+    Ok(fields.concat())
+}
+
+/// Expands the value of an assignment into a single string, the tilde being also expanded after each `:`
+/// like in `PATH=~/bin:~user/bin`
+pub fn expand_assignment_value(value: &str, shell: &mut Shell) -> Result<String, ExpansionError> {
+
+    let segments = expand_segments_in(value, shell, true)?;
+    Ok(segments.into_iter().filter_map(|segment| match segment {
+        Segment::Text { text, .. } => Some(text),
+        Segment::FieldBreak => None,
+    }).collect())
+}
+
+/// A part of a word after the parameters expansion and the quote removal
+#[derive(Debug, PartialEq)]
+enum Segment {
+    /// `splittable` is true for the results of unquoted expansions, which are subject to the field splitting
+    Text { text: String, quoted: bool, splittable: bool },
+    /// Forces the end of the current field, used by `"$@"`
+    FieldBreak,
+}
+
+impl Segment {
+
+    fn literal(text: impl Into<String>, quoted: bool) -> Self {
+        Segment::Text { text: text.into(), quoted, splittable: false }
+    }
+}
+
+/// Expands the tilde and the parameters of the word and removes its quotes
+fn expand_segments(word: &str, shell: &mut Shell) -> Result<Vec<Segment>, ExpansionError> {
+    expand_segments_in(word, shell, false)
+}
+
+/// Expands the word like `expand_segments`, the tilde being also expanded after the `:` of an assignment
+fn expand_segments_in(word: &str, shell: &mut Shell, assignment: bool) -> Result<Vec<Segment>, ExpansionError> {
+
+    let chars: Vec<char> = word.chars().collect();
+    let mut segments = Vec::new();
+    let mut position = 0;
+
+    while position < chars.len() {
+        let starts_tilde_prefix = position == 0 || assignment && chars[position - 1] == ':';
+        match chars[position] {
+            '~' if starts_tilde_prefix => {
+                let end = chars[position..].iter().position(|&c| c == '/' || assignment && c == ':')
+                    .map_or(chars.len(), |length| position + length);
+                let prefix: String = chars[position + 1..end].iter().collect();
+
+                match expand_tilde(&prefix, shell) {
+                    // The directory is not subject to the field splitting nor to the filename expansion
+                    Some(directory) => {
+                        segments.push(Segment::literal(directory, true));
+                        position = end;
+                    },
+                    None => {
+                        segments.push(Segment::literal('~', false));
+                        position += 1;
+                    },
+                }
+            },
+            '\'' => {
+                let end = find_char(&chars, position + 1, '\'');
+                segments.push(Segment::literal(chars[position + 1..end].iter().collect::<String>(), true));
+                position = end + 1;
+            },
+            '"' => {
+                position = expand_double_quoted(&chars, position + 1, shell, &mut segments)?;
+            },
+            '\\' => {
+                if let Some(&c) = chars.get(position + 1) {
+                    segments.push(Segment::literal(c, true));
+                }
+                position += 2;
+            },
+            '`' => {
+                let (output, end) = expand_backquoted(&chars, position, shell)?;
+                segments.push(Segment::Text { text: output, quoted: false, splittable: true });
+                position = end;
+            },
+            '$' => {
+                let (expansion, end) = expand_parameter(&chars, position, shell)?;
+                match expansion {
+                    Expansion::Value(value) => segments.push(Segment::Text { text: value, quoted: false, splittable: true }),
+                    Expansion::Fields(values) => push_fields(&mut segments, values, false),
+                    Expansion::NotParameter => segments.push(Segment::literal('$', false)),
+                }
+                position = end;
+            },
+            c => {
+                segments.push(Segment::literal(c, false));
+                position += 1;
+            }
+        }
+    }
+
+    Ok(segments)
+}
+
+/// Returns the directory given by the characters following a tilde : the home directory (of the user if any),
+/// the working directory (`+`), the previous one (`-`) or an entry of the directory stack (`N`, `+N`, `-N`).
+///
+/// Returns None if the prefix is quoted or if there is no such directory, the tilde being kept then
+pub fn expand_tilde(prefix: &str, shell: &Shell) -> Option<String> {
+
+    if prefix.contains(['\'', '"', '\\', '$']) {
+        return None;
+    }
+
+    match prefix {
+        "" => shell.get_variable("HOME").map(str::to_string).or_else(|| home_directory(None)),
+        "+" => shell.get_variable("PWD").map(str::to_string),
+        "-" => shell.get_variable("OLDPWD").map(str::to_string),
+        _ if prefix.trim_start_matches(['+', '-']).starts_with(|c: char| c.is_ascii_digit()) => stack_entry(shell, prefix),
+        user => home_directory(Some(user)),
+    }
+}
+
+/// Returns the home directory of the user from the password database, the current user by default
+fn home_directory(user: Option<&str>) -> Option<String> {
+    lookup_user(user).map(|(_, home)| home)
+}
+
+/// Returns the name of the user running the shell, from the password database
+pub fn current_user_name() -> Option<String> {
+    lookup_user(None).map(|(name, _)| name)
+}
+
+/// Returns the name and the home directory of the user from the password database, the current user by default
+fn lookup_user(user: Option<&str>) -> Option<(String, String)> {
+
+    // SAFETY: passwd is a plain C struct filled by getpwnam_r or getpwuid_r
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let mut buffer = vec![0 as libc::c_char; 4096];
+
+    // SAFETY: the functions write the entry in the given struct, its strings in the given buffer
+    let status = match user {
+        Some(user) => {
+            let user = std::ffi::CString::new(user).ok()?;
+            unsafe { libc::getpwnam_r(user.as_ptr(), &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut result) }
+        },
+        None => unsafe { libc::getpwuid_r(libc::getuid(), &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut result) },
+    };
+    if status != 0 || result.is_null() || entry.pw_name.is_null() || entry.pw_dir.is_null() {
+        return None;
+    }
+
+    // SAFETY: the strings point to nul terminated strings in the buffer, which is still alive
+    let (name, directory) = unsafe { (std::ffi::CStr::from_ptr(entry.pw_name), std::ffi::CStr::from_ptr(entry.pw_dir)) };
+    Some((name.to_string_lossy().into_owned(), directory.to_string_lossy().into_owned()))
+}
+
+/// Expands the content of a double quoted string starting at `position`, returns the position after the closing quote
+fn expand_double_quoted(chars: &[char], mut position: usize, shell: &mut Shell, segments: &mut Vec<Segment>) -> Result<usize, ExpansionError> {
+
+    // Empty quotes still give an (empty) field, except "$@" when there are no parameters
+    let mut gives_field = true;
+
+    while position < chars.len() && chars[position] != '"' {
+        match chars[position] {
+            // In double quotes, the backslash only escapes the characters that have a special meaning
+            '\\' if chars.get(position + 1).is_some_and(|c| matches!(c, '$' | '`' | '"' | '\\' | '\n')) => {
+                segments.push(Segment::literal(chars[position + 1], true));
+                position += 2;
+            },
+            '`' => {
+                let (output, end) = expand_backquoted(chars, position, shell)?;
+                segments.push(Segment::literal(output, true));
+                position = end;
+            },
+            '$' => {
+                let (expansion, end) = expand_parameter(chars, position, shell)?;
+                match expansion {
+                    Expansion::Value(value) => segments.push(Segment::literal(value, true)),
+                    Expansion::Fields(values) => {
+                        gives_field = false;
+                        push_fields(segments, values, true);
+                    },
+                    Expansion::NotParameter => segments.push(Segment::literal('$', true)),
+                }
+                position = end;
+            },
+            c => {
+                segments.push(Segment::literal(c, true));
+                position += 1;
+            }
+        }
+    }
+
+    if gives_field {
+        segments.push(Segment::literal("", true));
+    }
+
+    Ok(position + 1)
+}
+
+/// Pushes each value in its own field
+fn push_fields(segments: &mut Vec<Segment>, values: Vec<String>, quoted: bool) {
+    for (i, value) in values.into_iter().enumerate() {
+        if i > 0 {
+            segments.push(Segment::FieldBreak);
+        }
+        segments.push(Segment::Text { text: value, quoted, splittable: !quoted });
+    }
+}
+
+/// Returns the position of the next `c` from `start`, or the end of the chars if there is none
+fn find_char(chars: &[char], start: usize, c: char) -> usize {
+    chars[start..].iter().position(|&other| other == c).map_or(chars.len(), |offset| start + offset)
+}
+
+/// The result of an expansion starting with `$`
+enum Expansion {
+    Value(String),
+    /// `$@` and `$*` give a field per positional parameter
+    Fields(Vec<String>),
+    /// The `$` is not followed by a parameter, it is kept as is
+    NotParameter,
+}
+
+/// Expands the parameter starting with the `$` at `position`, returns its value and the position after it
+fn expand_parameter(chars: &[char], position: usize, shell: &mut Shell) -> Result<(Expansion, usize), ExpansionError> {
+
+    let start = position + 1;
+
+    if chars.get(start) == Some(&'(') && chars.get(start + 1) == Some(&'(') {
+        return expand_arithmetic(chars, start + 2, shell);
+    }
+
+    if chars.get(start) == Some(&'(') {
+        let Some(end) = find_closing_parenthesis(chars, start + 1) else {
+            return Err(ExpansionError::BadSubstitution(chars[position..].iter().collect()));
+        };
+        let command: String = chars[start + 1..end].iter().collect();
+        return Ok((Expansion::Value(substitute_command(&command, shell)?), end + 1));
+    }
+
+    let (name, end) = match chars.get(start) {
+        Some('{') => {
+            let end = find_char(chars, start, '}');
+            let content: String = chars[start + 1..end].iter().collect();
+            return Ok((expand_braced_parameter(&content, shell)?, end + 1));
+        },
+        Some(c) if is_special_parameter(*c) || c.is_ascii_digit() => (c.to_string(), start + 1),
+        Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+            let length = chars[start..].iter().take_while(|c| c.is_ascii_alphanumeric() || **c == '_').count();
+            (chars[start..start + length].iter().collect(), start + length)
+        },
+        _ => return Ok((Expansion::NotParameter, start)),
+    };
+
+    let expansion = match name.as_str() {
+        "@" | "*" => Expansion::Fields(shell.positional_parameters.clone()),
+        _ => Expansion::Value(get_parameter(&name, shell)),
+    };
+
+    Ok((expansion, end))
+}
+
+/// Returns the position of the `)` closing the parenthesis opened before `start`, skipping the quoted parts
+fn find_closing_parenthesis(chars: &[char], start: usize) -> Option<usize> {
+
+    let mut depth = 0;
+    let mut position = start;
+
+    while let Some(&c) = chars.get(position) {
+        match c {
+            '\\' => position += 1,
+            '\'' | '`' => position = find_char(chars, position + 1, c),
+            '"' => {
+                position += 1;
+                while chars.get(position).is_some_and(|&c| c != '"') {
+                    position += if chars[position] == '\\' { 2 } else { 1 };
+                }
+            },
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(position),
+            ')' => depth -= 1,
+            _ => (),
+        }
+        position += 1;
+    }
+
+    None
+}
+
+/// Expands the backquoted command substitution starting at `position`, returns its output and the position after it.
+/// In the command, a backslash only escapes `$`, `` ` `` and `\`
+fn expand_backquoted(chars: &[char], position: usize, shell: &mut Shell) -> Result<(String, usize), ExpansionError> {
+
+    let mut command = String::new();
+    let mut end = position + 1;
+
+    loop {
+        match (chars.get(end), chars.get(end + 1)) {
+            (None, _) => return Err(ExpansionError::BadSubstitution(chars[position..].iter().collect())),
+            (Some('`'), _) => break,
+            (Some('\\'), Some(&c)) if matches!(c, '$' | '`' | '\\') => {
+                command.push(c);
+                end += 2;
+            },
+            (Some(&c), _) => {
+                command.push(c);
+                end += 1;
+            },
+        }
+    }
+
+    Ok((substitute_command(&command, shell)?, end + 1))
+}
+
+/// Executes the command in a subshell and returns its output, without its trailing newlines
+fn substitute_command(command: &str, shell: &mut Shell) -> Result<String, ExpansionError> {
+
+    let command = convert_to_command_with_aliases(command, shell.aliases())
+        .map_err(|err| ExpansionError::CommandSubstitution(err.to_string()))?;
+    let output = capture_output(&command, shell)
+        .map_err(|err| ExpansionError::CommandSubstitution(err.to_string()))?;
+
+    Ok(output.trim_end_matches('\n').to_string())
+}
+
+/// Expands the content of `${...}` : a parameter, an array element `name[index]` or all of them `name[@]`,
+/// preceded by a `#` to get the length of the value or the number of elements
+fn expand_braced_parameter(content: &str, shell: &mut Shell) -> Result<Expansion, ExpansionError> {
+
+    let bad_substitution = || ExpansionError::BadSubstitution(format!("${{{content}}}"));
+
+    // ${#} is the number of positional parameters, not the length of a parameter
+    let (length, parameter) = match content.strip_prefix('#') {
+        Some(parameter) if !parameter.is_empty() => (true, parameter),
+        _ => (false, content),
+    };
+
+    let expansion = match parameter.split_once('[') {
+        Some((name, subscript)) => {
+            let subscript = subscript.strip_suffix(']').filter(|_| is_valid_variable_name(name)).ok_or_else(bad_substitution)?;
+            let values = shell.get_array(name).unwrap_or_default();
+
+            match subscript {
+                "@" | "*" => Expansion::Fields(values),
+                index => {
+                    let index = arithmetic::evaluate(&expand_word(index, shell)?, shell)?;
+                    // A negative index counts from the end of the array
+                    let index = if index < 0 { index + values.len() as i64 } else { index };
+                    let value = usize::try_from(index).ok().and_then(|index| values.get(index));
+                    Expansion::Value(value.cloned().unwrap_or_default())
+                },
+            }
+        },
+        None if is_parameter_name(parameter) => match parameter {
+            "@" | "*" => Expansion::Fields(shell.positional_parameters.clone()),
+            _ => Expansion::Value(get_parameter(parameter, shell)),
+        },
+        None => return Err(bad_substitution()),
+    };
+
+    Ok(match (length, expansion) {
+        (true, Expansion::Fields(values)) => Expansion::Value(values.len().to_string()),
+        (true, Expansion::Value(value)) => Expansion::Value(value.chars().count().to_string()),
+        (_, expansion) => expansion,
+    })
+}
+
+/// Evaluates the arithmetic expansion whose expression starts at `start`, after the `$((`.
+/// Returns its value and the position after the closing `))`
+fn expand_arithmetic(chars: &[char], start: usize, shell: &mut Shell) -> Result<(Expansion, usize), ExpansionError> {
+
+    let mut depth = 0;
+    let mut end = start;
+
+    // The closing )) is the first one outside of nested parentheses
+    loop {
+        match (chars.get(end), chars.get(end + 1)) {
+            (None, _) => {
+                let text: String = chars[start - 3..].iter().collect();
+                return Err(ExpansionError::BadSubstitution(text));
+            },
+            (Some(')'), Some(')')) if depth == 0 => break,
+            (Some('('), _) => depth += 1,
+            (Some(')'), _) => depth -= 1,
+            _ => (),
+        }
+        end += 1;
+    }
+
+    // The parameters of the expression are expanded before its evaluation
+    let expression = expand_word(&chars[start..end].iter().collect::<String>(), shell)?;
+    let value = arithmetic::evaluate(&expression, shell)?;
+
+    Ok((Expansion::Value(value.to_string()), end + 2))
+}
+
+/// Special parameters are made of a single character, for instance `$?`
+fn is_special_parameter(c: char) -> bool {
+    matches!(c, '@' | '*' | '#' | '?' | '$' | '0')
+}
+
+fn is_parameter_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if is_special_parameter(c) => true,
+        _ => is_valid_variable_name(name) || name.chars().all(|c| c.is_ascii_digit()) && !name.is_empty(),
+    }
+}
+
+/// Returns the value of a parameter that is not `$@` or `$*`, an unset parameter is empty
+fn get_parameter(name: &str, shell: &Shell) -> String {
+    match name {
+        "#" => shell.positional_parameters.len().to_string(),
+        "?" => shell.last_status.to_string(),
+        "$" => shell.process_id.to_string(),
+        "0" => String::from("rust_shell"),
+        _ if name.chars().all(|c| c.is_ascii_digit()) => {
+            let index: usize = name.parse().unwrap_or(0);
+            shell.positional_parameters.get(index.wrapping_sub(1)).cloned().unwrap_or_default()
+        },
+        _ => shell.get_variable(name).unwrap_or_default().to_string(),
+    }
+}
+
+/// Splits the results of the unquoted expansions on the characters of `$IFS`, and groups the segments into fields.
+/// The characters of the fields are marked as quoted or not, for the filename expansion
+fn split_fields(segments: Vec<Segment>, shell: &Shell) -> Vec<Vec<(char, bool)>> {
+
+    let ifs: Vec<char> = shell.get_variable("IFS").unwrap_or(" \t\n").chars().collect();
+
+    let mut fields = Vec::new();
+    let mut current = Vec::new();
+    // An empty field is kept if it comes from quotes, like ""
+    let mut current_is_quoted = false;
+    // A non whitespace separator following a whitespace one doesn't delimit an empty field, as in "a : b"
+    let mut after_whitespace_separator = false;
+
+    for segment in segments {
+        match segment {
+            Segment::FieldBreak => {
+                fields.push(std::mem::take(&mut current));
+                current_is_quoted = false;
+            },
+            Segment::Text { text, quoted, splittable: false } => {
+                current.extend(text.chars().map(|c| (c, quoted)));
+                current_is_quoted |= quoted;
+                after_whitespace_separator = false;
+            },
+            Segment::Text { text, splittable: true, .. } => {
+                for c in text.chars() {
+                    if !ifs.contains(&c) {
+                        current.push((c, false));
+                        after_whitespace_separator = false;
+                        continue;
+                    }
+
+                    let has_field = !current.is_empty() || current_is_quoted;
+                    if has_field || !(c.is_whitespace() || after_whitespace_separator) {
+                        fields.push(std::mem::take(&mut current));
+                        current_is_quoted = false;
+                    }
+                    after_whitespace_separator = c.is_whitespace();
+                }
+            },
+        }
+    }
+
+    if !current.is_empty() || current_is_quoted {
+        fields.push(current);
+    }
+
+    fields
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ExpansionError {
+
+    #[error("{0}: bad substitution")]
+    BadSubstitution(String),
+
+    #[error("arithmetic: {0}")]
+    ArithmeticError(#[from] ArithmeticError),
+
+    #[error("command substitution: {0}")]
+    CommandSubstitution(String),
+
+    #[error("{0}")]
+    ParsingError(#[from] ParsingError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shell_with(variables: &[(&str, &str)], parameters: &[&str]) -> Shell {
+        let mut shell = Shell::new();
+        for (name, value) in variables {
+            shell.set_variable(name, value);
+        }
+        shell.positional_parameters = parameters.iter().map(|p| p.to_string()).collect();
+        shell
+    }
+
+    fn expand(words: &[&str], shell: &mut Shell) -> Vec<String> {
+        let words: Vec<String> = words.iter().map(|w| w.to_string()).collect();
+        expand_words(&words, shell).unwrap()
+    }
+
+    #[test]
+    fn test_tilde_expansion() {
+        let mut shell = shell_with(&[("HOME", "/home/me"), ("PWD", "/work"), ("OLDPWD", "/old")], &[]);
+        shell.directory_stack = vec!["/first".to_string(), "/second dir".to_string()];
+
+        let fields = expand(&["~", "~/a", "~+", "~-/b", "~1", "~-0", "a~", "'~'", "~\"x\"", "~2/c"], &mut shell);
+        assert_eq!(vec!["/home/me", "/home/me/a", "/work", "/old/b", "/first", "/second dir", "a~", "~", "~x", "/second dir/c"], fields);
+        assert_eq!("/home/me/bin:/work", expand_assignment_value("~/bin:~+", &mut shell).unwrap());
+        assert_eq!(None, home_directory(Some("no such user")));
+    }
+
+    #[test]
+    fn test_quote_removal() {
+        let mut shell = shell_with(&[], &[]);
+        let fields = expand(&["'a b'", "\"c d\"", "e\\ f", "''"], &mut shell);
+        assert_eq!(vec!["a b", "c d", "e f", ""], fields);
+    }
+
+    #[test]
+    fn test_variable_field_splitting() {
+        let mut shell = shell_with(&[("LIST", " a  b "), ("EMPTY", "")], &[]);
+        let fields = expand(&["$LIST", "\"$LIST\"", "${LIST}x", "$EMPTY"], &mut shell);
+        assert_eq!(vec!["a", "b", " a  b ", "a", "b", "x"], fields);
+    }
+
+    #[test]
+    fn test_ifs_non_whitespace_separators() {
+        let mut shell = shell_with(&[("IFS", ": "), ("PATHS", "a::b : c")], &[]);
+        let fields = expand(&["$PATHS"], &mut shell);
+        assert_eq!(vec!["a", "", "b", "c"], fields);
+    }
+
+    #[test]
+    fn test_positional_parameters() {
+        let mut shell = shell_with(&[], &["one", "two words"]);
+        let fields = expand(&["$#", "$1", "\"$@\"", "$*"], &mut shell);
+        assert_eq!(vec!["2", "one", "one", "two words", "one", "two", "words"], fields);
+    }
+
+    #[test]
+    fn test_quoted_at_without_parameters_gives_no_field() {
+        let mut shell = shell_with(&[], &[]);
+        let fields = expand(&["\"$@\"", "\"\""], &mut shell);
+        assert_eq!(vec![""], fields);
+    }
+
+    #[test]
+    fn test_dollar_without_parameter_is_kept() {
+        let mut shell = shell_with(&[], &[]);
+        let fields = expand(&["$", "a$", "'$HOME'"], &mut shell);
+        assert_eq!(vec!["$", "a$", "$HOME"], fields);
+    }
+
+    #[test]
+    fn test_quoted_characters_are_escaped_in_patterns() {
+        let mut shell = shell_with(&[("STAR", "*")], &[]);
+        assert_eq!("a*\\*\\?", expand_pattern("a$STAR'*'\\?", &mut shell).unwrap());
+        assert_eq!("\\*", expand_pattern("\"$STAR\"", &mut shell).unwrap());
+    }
+
+    #[test]
+    fn test_arithmetic_expansion() {
+        let mut shell = shell_with(&[("x", "4")], &["3"]);
+        let fields = expand(&["$((x * $1 + (1 + 1)))", "\"$(( x > 2 ? 10 : 20 ))\"", "a$((x=$x+1))b"], &mut shell);
+        assert_eq!(vec!["14", "10", "a5b"], fields);
+        assert_eq!(Some("5"), shell.get_variable("x"));
+
+        let words = vec!["$((1 / 0))".to_string()];
+        assert!(matches!(expand_words(&words, &mut shell), Err(ExpansionError::ArithmeticError(_))));
+    }
+
+    #[test]
+    fn test_quoted_characters_are_escaped_in_regex() {
+        let mut shell = shell_with(&[("DOT", ".")], &[]);
+        assert_eq!("^a\\.b\\$DOT.", expand_regex("^'a.b'\\$DOT$DOT", &mut shell).unwrap());
+    }
+
+    #[test]
+    fn test_arrays() {
+        let mut shell = shell_with(&[], &[]);
+        shell.set_array("list", vec!["a".to_string(), "b c".to_string(), "d".to_string()]);
+
+        let fields = expand(&["$list", "${list[1]}", "${list[-1]}", "${list[5]}", "${#list[@]}", "\"${list[@]}\""], &mut shell);
+        assert_eq!(vec!["a", "b", "c", "d", "3", "a", "b c", "d"], fields);
+        assert_eq!(vec!["3"], expand(&["${#list[1]}"], &mut shell));
+    }
+
+    #[test]
+    fn test_unmatched_glob_is_kept() {
+        let mut shell = shell_with(&[], &[]);
+        let fields = expand(&["/nonexistent_rust_shell_dir/*", "'/*'"], &mut shell);
+        assert_eq!(vec!["/nonexistent_rust_shell_dir/*", "/*"], fields);
+    }
+}
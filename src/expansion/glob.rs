@@ -0,0 +1,285 @@
+//!
+//! Glob patterns (`*`, `?`, `[...]`) used by the filename expansion and by the `case` command.
+//!
+//! In a pattern, a character preceded by a backslash is matched literally, this is how the quoted
+//! characters of a word lose their special meaning.
+//!
+
+use std::fs;
+use std::path::Path;
+
+/// Returns true if the text entirely matches the pattern
+pub fn matches(pattern: &str, text: &str) -> bool {
+
+    let pattern = compile(pattern);
+    let text: Vec<char> = text.chars().collect();
+
+    match_tokens(&pattern, &text)
+}
+
+/// Returns true if the pattern contains a special character that is not escaped
+pub fn has_glob_chars(pattern: &str) -> bool {
+    compile(pattern).iter().any(|token| !matches!(token, PatternToken::Literal(_)))
+}
+
+/// Returns the paths matching the pattern, sorted, or an empty vec if there is none.
+///
+/// The special characters do not match a `/`, and a leading `.` in a file name must be matched explicitly
+pub fn expand_pathname(pattern: &str) -> Vec<String> {
+
+    let absolute = pattern.starts_with('/');
+    let components: Vec<&str> = pattern.split('/').filter(|component| !component.is_empty()).collect();
+
+    // The paths matching the components processed so far
+    let mut paths = vec![String::from(if absolute { "/" } else { "" })];
+
+    for component in components {
+        let mut next_paths = Vec::new();
+
+        for path in paths {
+            if !has_glob_chars(component) {
+                next_paths.push(join(&path, &unescape(component)));
+                continue;
+            }
+
+            let directory = if path.is_empty() { "." } else { path.as_str() };
+            let Ok(entries) = fs::read_dir(directory) else { continue };
+
+            let mut names: Vec<String> = entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .filter(|name| !name.starts_with('.') || component.starts_with('.') || component.starts_with("\\."))
+                .filter(|name| matches(component, name))
+                .collect();
+            names.sort();
+
+            next_paths.extend(names.iter().map(|name| join(&path, name)));
+        }
+
+        paths = next_paths;
+    }
+
+    // The literal components may not exist
+    paths.retain(|path| !path.is_empty() && Path::new(path).symlink_metadata().is_ok());
+    paths
+}
+
+fn join(path: &str, name: &str) -> String {
+    match path {
+        "" => name.to_string(),
+        "/" => format!("/{name}"),
+        _ => format!("{path}/{name}"),
+    }
+}
+
+/// Removes the backslashes escaping the characters of the pattern
+fn unescape(pattern: &str) -> String {
+    compile(pattern).into_iter().map(|token| match token {
+        PatternToken::Literal(c) => c,
+        _ => '?',
+    }).collect()
+}
+
+#[derive(Debug)]
+enum PatternToken {
+    Literal(char),
+    AnyChar,   // ?
+    AnyString, // *
+    Bracket { negated: bool, items: Vec<BracketItem> }, // [...]
+}
+
+#[derive(Debug)]
+enum BracketItem {
+    Char(char),
+    Range(char, char),
+    Class(String), // [:alpha:]
+}
+
+impl BracketItem {
+
+    fn matches(&self, c: char) -> bool {
+        match self {
+            BracketItem::Char(other) => c == *other,
+            BracketItem::Range(start, end) => (*start..=*end).contains(&c),
+            BracketItem::Class(class) => match class.as_str() {
+                "alpha" => c.is_alphabetic(),
+                "digit" => c.is_ascii_digit(),
+                "alnum" => c.is_alphanumeric(),
+                "upper" => c.is_uppercase(),
+                "lower" => c.is_lowercase(),
+                "space" => c.is_whitespace(),
+                "blank" => c == ' ' || c == '\t',
+                "punct" => c.is_ascii_punctuation(),
+                "xdigit" => c.is_ascii_hexdigit(),
+                "cntrl" => c.is_control(),
+                "print" => !c.is_control(),
+                "graph" => !c.is_control() && !c.is_whitespace(),
+                _ => false,
+            },
+        }
+    }
+}
+
+fn compile(pattern: &str) -> Vec<PatternToken> {
+
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut tokens = Vec::new();
+    let mut position = 0;
+
+    while position < chars.len() {
+        let token = match chars[position] {
+            '\\' if position + 1 < chars.len() => {
+                position += 1;
+                PatternToken::Literal(chars[position])
+            },
+            '?' => PatternToken::AnyChar,
+            '*' => PatternToken::AnyString,
+            '[' => match compile_bracket(&chars, position + 1) {
+                Some((token, end)) => {
+                    position = end;
+                    token
+                },
+                // An unclosed bracket is a literal [
+                None => PatternToken::Literal('['),
+            },
+            c => PatternToken::Literal(c),
+        };
+        tokens.push(token);
+        position += 1;
+    }
+
+    tokens
+}
+
+/// Compiles the bracket expression starting after the `[`, returns it with the position of its closing `]`
+fn compile_bracket(chars: &[char], mut position: usize) -> Option<(PatternToken, usize)> {
+
+    let negated = matches!(chars.get(position), Some('!' | '^'));
+    if negated {
+        position += 1;
+    }
+
+    let mut items = Vec::new();
+    let start = position;
+
+    loop {
+        let mut c = *chars.get(position)?;
+
+        // a ] at the start of the expression is a literal one
+        if c == ']' && position > start {
+            return Some((PatternToken::Bracket { negated, items }, position));
+        }
+
+        if c == '[' && chars.get(position + 1) == Some(&':') {
+            let class: String = chars[position + 2..].iter().take_while(|c| c.is_ascii_alphabetic()).collect();
+            let end = position + 2 + class.len();
+            if chars.get(end) == Some(&':') && chars.get(end + 1) == Some(&']') {
+                items.push(BracketItem::Class(class));
+                position = end + 2;
+                continue;
+            }
+        }
+
+        if c == '\\' {
+            position += 1;
+            c = *chars.get(position)?;
+        }
+
+        match (chars.get(position + 1), chars.get(position + 2)) {
+            (Some('-'), Some(&end)) if end != ']' => {
+                items.push(BracketItem::Range(c, end));
+                position += 3;
+            },
+            _ => {
+                items.push(BracketItem::Char(c));
+                position += 1;
+            }
+        }
+    }
+}
+
+/// Matches the text against the tokens, `*` being matched by backtracking to the last one seen
+fn match_tokens(pattern: &[PatternToken], text: &[char]) -> bool {
+
+    let (mut p, mut t) = (0, 0);
+    // The position of the last `*` in the pattern and of the text it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        let matched = match pattern.get(p) {
+            Some(PatternToken::AnyString) => {
+                backtrack = Some((p, t));
+                p += 1;
+                continue;
+            },
+            Some(PatternToken::AnyChar) => true,
+            Some(PatternToken::Literal(c)) => *c == text[t],
+            Some(PatternToken::Bracket { negated, items }) => items.iter().any(|item| item.matches(text[t])) != *negated,
+            None => false,
+        };
+
+        if matched {
+            p += 1;
+            t += 1;
+        } else if let Some((star, star_text)) = backtrack {
+            // The last * absorbs one more character
+            p = star + 1;
+            t = star_text + 1;
+            backtrack = Some((star, star_text + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|token| matches!(token, PatternToken::AnyString))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcards() {
+        assert!(matches("*.txt", "notes.txt"));
+        assert!(!matches("*.txt", "notes.txt.bak"));
+        assert!(matches("a?c", "abc"));
+        assert!(matches("*a*b*", "xxaxxbxx"));
+        assert!(matches("*", ""));
+        assert!(!matches("?", ""));
+    }
+
+    #[test]
+    fn test_brackets() {
+        assert!(matches("[abc]x", "bx"));
+        assert!(matches("[a-c][!0-9]", "ca"));
+        assert!(!matches("[a-c][!0-9]", "c1"));
+        assert!(matches("[]x]", "]"));
+        assert!(matches("[[:digit:]][[:upper:]]", "1A"));
+        assert!(matches("[", "["));
+    }
+
+    #[test]
+    fn test_escaped_characters_are_literal() {
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(!has_glob_chars("a\\*b"));
+        assert!(has_glob_chars("a*b"));
+    }
+
+    #[test]
+    fn test_pathname_expansion() {
+        let directory = std::env::temp_dir().join(format!("rust_shell_glob_{}", std::process::id()));
+        fs::create_dir_all(directory.join("sub")).unwrap();
+        for name in ["b.txt", "a.txt", ".hidden.txt", "c.log", "sub/d.txt"] {
+            fs::write(directory.join(name), "").unwrap();
+        }
+        let prefix = directory.to_string_lossy();
+
+        let expected = vec![format!("{prefix}/a.txt"), format!("{prefix}/b.txt")];
+        assert_eq!(expected, expand_pathname(&format!("{prefix}/*.txt")));
+        assert_eq!(vec![format!("{prefix}/sub/d.txt")], expand_pathname(&format!("{prefix}/s*/*")));
+        assert!(expand_pathname(&format!("{prefix}/*.none")).is_empty());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+//!
+//! Expansion of the prompt strings : PS1 before each command, PS2 before the continuation lines,
+//! and PS4 before the commands traced by `set -x`.
+//!
+//! The backslash escapes (`\u`, `\w`, `\t`, `\g` for the git status...) are decoded first, then the result
+//! goes through the parameter expansion, the command substitution, the arithmetic expansion and the quote removal.
+//!
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::command::builtin::directory_stack::abbreviate_home;
+use crate::command::builtin::format::{current_timestamp, format_local_time};
+use crate::expansion::{current_user_name, expand_word};
+use crate::git::{DEFAULT_GIT_PROMPT_TIMEOUT, git_status};
+use crate::shell::Shell;
+
+/// The primary prompt when PS1 is not set : the working directory in bold blue
+pub const DEFAULT_PS1: &str = "$ \\[\\e[1;34m\\]\\w\\[\\e[0m\\]> ";
+/// The prompt of the continuation lines when PS2 is not set
+pub const DEFAULT_PS2: &str = "> ";
+/// The prefix of the traced commands when PS4 is not set
+pub const DEFAULT_PS4: &str = "+ ";
+
+/// The markers of the start and the end of a non-printing sequence (`\[` and `\]`) in an expanded prompt, like in readline
+pub const PROMPT_START_IGNORE: char = '\x01';
+pub const PROMPT_END_IGNORE: char = '\x02';
+
+/// Expands the prompt string. If its expansion fails (like on an unterminated quote), the prompt is used with its
+/// escapes decoded only.
+///
+/// Its non-printing sequences are enclosed in PROMPT_START_IGNORE and PROMPT_END_IGNORE, see `split_prompt`
+pub fn expand_prompt(prompt: &str, shell: &mut Shell) -> String {
+
+    let (decoded, plain) = decode_prompt_escapes(prompt, shell);
+    // The tilde is not expanded in a prompt
+    let decoded = match decoded.strip_prefix('~') {
+        Some(rest) => format!("\\~{rest}"),
+        None => decoded,
+    };
+
+    expand_word(&decoded, shell).unwrap_or(plain)
+}
+
+/// Replaces the backslash escapes of the prompt by their value.
+///
+/// Returns the prompt whose values are escaped with backslashes, so that the expansion that follows keeps them
+/// as they are, and the prompt with the values only
+fn decode_prompt_escapes(prompt: &str, shell: &Shell) -> (String, String) {
+
+    let chars: Vec<char> = prompt.chars().collect();
+    let mut decoded = String::new();
+    let mut plain = String::new();
+    let mut position = 0;
+
+    while position < chars.len() {
+        let (c, next) = (chars[position], chars.get(position + 1).copied());
+        if c != '\\' || next.is_none() {
+            decoded.push(c);
+            plain.push(c);
+            position += 1;
+            continue;
+        }
+        position += 2;
+
+        let value = match next.unwrap_or_default() {
+            'a' => "\x07".to_string(),
+            'e' => "\x1b".to_string(),
+            'n' => "\n".to_string(),
+            'r' => "\r".to_string(),
+            '\\' => "\\".to_string(),
+            '[' => PROMPT_START_IGNORE.to_string(),
+            ']' => PROMPT_END_IGNORE.to_string(),
+            'u' => shell.get_variable("USER").map(str::to_string).or_else(current_user_name).unwrap_or_default(),
+            'h' => host_name().split('.').next().unwrap_or_default().to_string(),
+            'H' => host_name(),
+            's' => "rust_shell".to_string(),
+            'v' | 'V' => env!("CARGO_PKG_VERSION").to_string(),
+            'w' => abbreviate_home(shell.get_variable("PWD").unwrap_or_default(), shell),
+            'W' => match shell.get_variable("PWD").unwrap_or_default() {
+                directory if abbreviate_home(directory, shell) == "~" => "~".to_string(),
+                "/" => "/".to_string(),
+                directory => directory.rsplit('/').next().unwrap_or_default().to_string(),
+            },
+            // SAFETY: geteuid has no precondition and always succeeds
+            '$' => if unsafe { libc::geteuid() } == 0 { "#" } else { "$" }.to_string(),
+            '?' => shell.last_status.to_string(),
+            'g' if shell.config.features.git_prompt => git_segment(shell),
+            'g' => String::new(),
+            // There is no job control
+            'j' => "0".to_string(),
+            'd' => format_local_time(current_timestamp(), "%a %b %d"),
+            't' => format_local_time(current_timestamp(), "%H:%M:%S"),
+            'T' => format_local_time(current_timestamp(), "%I:%M:%S"),
+            '@' => format_local_time(current_timestamp(), "%I:%M %p"),
+            'A' => format_local_time(current_timestamp(), "%H:%M"),
+            'D' if chars.get(position) == Some(&'{') => {
+                let end = chars[position..].iter().position(|&c| c == '}').map_or(chars.len(), |length| position + length);
+                let format: String = chars[position + 1..end].iter().collect();
+                position = end + 1;
+                format_local_time(current_timestamp(), if format.is_empty() { "%X" } else { &format })
+            },
+            // Up to three octal digits give the character of that code
+            digit @ '0'..='7' => {
+                let mut code = digit.to_digit(8).unwrap_or_default();
+                for _ in 0..2 {
+                    match chars.get(position).and_then(|c| c.to_digit(8)) {
+                        Some(digit) => code = code * 8 + digit,
+                        None => break,
+                    }
+                    position += 1;
+                }
+                char::from_u32(code).map(String::from).unwrap_or_default()
+            },
+            // An unknown escape is kept as it is
+            other => format!("\\{other}"),
+        };
+
+        for c in value.chars() {
+            if matches!(c, '\\' | '$' | '`' | '"' | '\'') {
+                decoded.push('\\');
+            }
+            decoded.push(c);
+        }
+        plain.push_str(&value);
+    }
+
+    (decoded, plain)
+}
+
+/// Splits an expanded prompt into the text written on the terminal, without the markers of its non-printing sequences,
+/// and its visible text, without these sequences (like a terminal title), which is the one whose width the line editor counts
+pub fn split_prompt(prompt: &str) -> (String, String) {
+
+    let mut displayed = String::new();
+    let mut visible = String::new();
+    let mut non_printing = false;
+
+    for c in prompt.chars() {
+        match c {
+            PROMPT_START_IGNORE => non_printing = true,
+            PROMPT_END_IGNORE => non_printing = false,
+            c => {
+                displayed.push(c);
+                if !non_printing {
+                    visible.push(c);
+                }
+            },
+        }
+    }
+
+    (displayed, visible)
+}
+
+/// Returns the git status of the working directory in parentheses, like `(main *+)`, or nothing outside a repository.
+///
+/// Its time budget is GIT_PROMPT_TIMEOUT milliseconds
+fn git_segment(shell: &Shell) -> String {
+
+    let budget = shell.get_variable("GIT_PROMPT_TIMEOUT").and_then(|timeout| timeout.parse().ok())
+        .map_or(DEFAULT_GIT_PROMPT_TIMEOUT, Duration::from_millis);
+    let directory = shell.get_variable("PWD").map(PathBuf::from).or_else(|| std::env::current_dir().ok()).unwrap_or_default();
+
+    git_status(&directory, budget).map(|status| format!("({status})")).unwrap_or_default()
+}
+
+/// Returns the name of the machine
+fn host_name() -> String {
+
+    let mut buffer = vec![0u8; 256];
+    // SAFETY: gethostname writes at most buffer.len() bytes in the buffer
+    if unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) } != 0 {
+        return String::new();
+    }
+    let length = buffer.iter().position(|&byte| byte == 0).unwrap_or(buffer.len());
+
+    String::from_utf8_lossy(&buffer[..length]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prompt_escapes() {
+        let mut shell = Shell::new();
+        shell.set_variable("HOME", "/home/me");
+        shell.set_variable("PWD", "/home/me/src/project");
+        shell.set_variable("USER", "me");
+        shell.last_status = 3;
+
+        assert_eq!("me ~/src/project project 3 \\ A\x01\x1b[0m\x02", expand_prompt("\\u \\w \\W \\? \\\\ \\101\\[\\e[0m\\]", &mut shell));
+        assert_eq!("~ /", {
+            shell.set_variable("PWD", "/home/me");
+            let home = expand_prompt("\\W ", &mut shell);
+            shell.set_variable("PWD", "/");
+            home + &expand_prompt("\\W", &mut shell)
+        });
+        assert_eq!(format_local_time(current_timestamp(), "%Y"), expand_prompt("\\D{%Y}", &mut shell));
+        assert_eq!("\\q", expand_prompt("\\q", &mut shell));
+    }
+
+    #[test]
+    fn test_non_printing_sequences() {
+        let mut shell = Shell::new();
+        shell.set_variable("USER", "me");
+
+        // The terminal title is an OSC sequence, which the line editor would count as visible text
+        let prompt = expand_prompt("\\[\\e]0;\\u\\a\\]\\u \\[\\e[1m\\]>\\[\\e[0m\\] ", &mut shell);
+        let (displayed, visible) = split_prompt(&prompt);
+        assert_eq!("\x1b]0;me\x07me \x1b[1m>\x1b[0m ", displayed);
+        assert_eq!("me > ", visible);
+    }
+
+    #[test]
+    fn test_prompt_expansions() {
+        let mut shell = Shell::new();
+        shell.set_variable("name", "value");
+        shell.set_variable("PWD", "/tmp/$name");
+
+        // The values of the escapes are not expanded again
+        assert_eq!("value /tmp/$name 3 sub ~", expand_prompt("$name \\w $((1 + 2)) $(echo sub) ~", &mut shell));
+        assert_eq!("~x", expand_prompt("~x", &mut shell));
+
+        // A prompt failing to expand is kept with its escapes decoded, without the backslashes escaping their values
+        shell.set_variable("USER", "me");
+        assert_eq!("me /tmp/$name $(date ", expand_prompt("\\u \\w $(date ", &mut shell));
+    }
+}
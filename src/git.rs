@@ -0,0 +1,657 @@
+//!
+//! The git status of the working directory, shown in the prompt by `\g` : the branch, the changes
+//! and the operation in progress.
+//!
+//! The repository is read directly from its `.git` directory instead of running `git` before each prompt,
+//! and the slow parts of the status stop when its time budget is exhausted, so that a huge repository
+//! doesn't stall the prompt.
+//!
+
+pub mod ignore;
+pub mod index;
+pub mod objects;
+
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::fmt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::git::ignore::IgnoreRules;
+use crate::git::index::{Index, read_index};
+use crate::git::objects::{ObjectId, ObjectStore, TREE_MODE};
+
+/// The time budget of the status when GIT_PROMPT_TIMEOUT (in milliseconds) is not set
+pub const DEFAULT_GIT_PROMPT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The symbolic references are followed up to this depth
+const MAX_SYMBOLIC_DEPTH: usize = 5;
+
+/// The number of commits walked after the common history is reached, when counting the commits ahead and behind
+const AHEAD_BEHIND_SLOP: usize = 5;
+
+/// The modes of the index entries
+const SYMLINK_MODE: u32 = 0o120000;
+const GITLINK_MODE: u32 = 0o160000;
+
+/// The state of a repository, as shown in the prompt
+#[derive(Debug, Default, PartialEq)]
+pub struct GitStatus {
+    /// The current branch, or the abbreviated commit if the HEAD is detached
+    pub branch: String,
+    /// The operation in progress, like `MERGING` or `REBASE 2/5`
+    pub operation: Option<String>,
+    /// Some tracked files differ from the index (`*`)
+    pub unstaged: bool,
+    /// The index differs from the HEAD commit (`+`)
+    pub staged: bool,
+    /// Some files are neither tracked nor ignored (`%`)
+    pub untracked: bool,
+    /// The number of commits ahead and behind the upstream branch
+    pub ahead_behind: Option<(usize, usize)>,
+    /// The time budget was exhausted before the status was complete (`?`)
+    pub incomplete: bool,
+}
+
+impl fmt::Display for GitStatus {
+
+    /// Formats the status like `main *+% ↑1↓2|MERGING`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+
+        write!(f, "{}", self.branch)?;
+
+        let markers: String = [(self.unstaged, '*'), (self.staged, '+'), (self.untracked, '%'), (self.incomplete, '?')]
+            .iter().filter(|(set, _)| *set).map(|(_, marker)| *marker).collect();
+        if !markers.is_empty() {
+            write!(f, " {markers}")?;
+        }
+
+        if let Some((ahead, behind)) = self.ahead_behind && (ahead, behind) != (0, 0) {
+            write!(f, " ")?;
+            if ahead > 0 {
+                write!(f, "↑{ahead}")?;
+            }
+            if behind > 0 {
+                write!(f, "↓{behind}")?;
+            }
+        }
+
+        match &self.operation {
+            Some(operation) => write!(f, "|{operation}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returns the status of the repository containing the directory, or None outside a repository.
+///
+/// The parts of the status not computed within the budget are left out, and the status is marked as incomplete
+pub fn git_status(directory: &Path, budget: Duration) -> Option<GitStatus> {
+
+    let deadline = Instant::now() + budget;
+    let mut repository = Repository::discover(directory)?;
+
+    let head = repository.read_head()?;
+    let head_commit = repository.resolve_reference("HEAD");
+    let (operation, rebased_branch) = repository.operation();
+
+    let mut status = GitStatus { operation, ..GitStatus::default() };
+    let branch = match head.strip_prefix("ref: ") {
+        Some(reference) => Some(reference.to_string()),
+        // A rebase detaches the HEAD from the branch being rebased
+        None => rebased_branch,
+    };
+    status.branch = match &branch {
+        Some(reference) => reference.strip_prefix("refs/heads/").unwrap_or(reference).to_string(),
+        None => format!("{}...", head.get(..7).unwrap_or(&head)),
+    };
+
+    // A corrupted index or object is shown as no change
+    let index = read_index(&repository.git_dir.join("index")).unwrap_or_default();
+    let index_time = std::fs::metadata(repository.git_dir.join("index")).map(|metadata| (metadata.mtime(), metadata.mtime_nsec())).ok();
+
+    // Each check returns None when the deadline is reached, the next ones being skipped
+    let complete = (|| {
+        status.staged = repository.has_staged_changes(head_commit, &index, deadline)?;
+        status.unstaged = repository.has_unstaged_changes(&index, index_time, deadline)?;
+
+        let upstream = branch.as_ref().and_then(|branch| repository.upstream(branch)).and_then(|upstream| repository.resolve_reference(&upstream));
+        if let (Some(head_commit), Some(upstream)) = (head_commit, upstream) {
+            status.ahead_behind = Some(repository.count_ahead_behind(head_commit, upstream, deadline)?);
+        }
+
+        status.untracked = repository.has_untracked_files(&index, deadline)?;
+        Some(())
+    })();
+    status.incomplete = complete.is_none();
+
+    Some(status)
+}
+
+/// Returns the names of the branches, the remote branches (like `origin/main`) and the tags of the repository
+/// containing the directory, sorted
+pub fn reference_names(directory: &Path) -> Vec<String> {
+
+    let Some(repository) = Repository::discover(directory) else { return Vec::new() };
+    let names: BTreeSet<String> = ["refs/heads", "refs/remotes", "refs/tags"].into_iter()
+        .flat_map(|references| repository.reference_names(references))
+        .filter(|name| !name.ends_with("/HEAD"))
+        .collect();
+
+    names.into_iter().collect()
+}
+
+/// Returns the names of the remotes of the repository containing the directory, sorted
+pub fn remote_names(directory: &Path) -> Vec<String> {
+
+    let Some(repository) = Repository::discover(directory) else { return Vec::new() };
+    let names: BTreeSet<String> = read_config(&repository.common_dir.join("config")).into_iter()
+        .filter_map(|(section, _, _)| section.strip_prefix("remote \"")?.strip_suffix('"').map(str::to_string))
+        .collect();
+
+    names.into_iter().collect()
+}
+
+/// Returns the names of the aliases defined in the configuration of the user and of the repository containing the directory, sorted
+pub fn alias_names(directory: &Path, home: Option<&Path>) -> Vec<String> {
+
+    let mut files: Vec<PathBuf> = Vec::new();
+    if let Some(home) = home {
+        let config_home = std::env::var_os("XDG_CONFIG_HOME").map_or_else(|| home.join(".config"), PathBuf::from);
+        files.extend([config_home.join("git/config"), home.join(".gitconfig")]);
+    }
+    files.extend(Repository::discover(directory).map(|repository| repository.common_dir.join("config")));
+
+    let names: BTreeSet<String> = files.iter()
+        .flat_map(|file| read_config(file))
+        .filter(|(section, _, _)| is_config_section(section, "alias"))
+        .map(|(_, key, _)| key)
+        .collect();
+
+    names.into_iter().collect()
+}
+
+/// Reads the entries of a configuration file : their section (like `branch "main"`), their key and their value
+fn read_config(path: &Path) -> Vec<(String, String, String)> {
+
+    let config = std::fs::read_to_string(path).unwrap_or_default();
+    let mut section = String::new();
+    let mut entries = Vec::new();
+
+    for line in config.lines().map(str::trim) {
+        if let Some(header) = line.strip_prefix('[').and_then(|line| line.split(']').next()) {
+            section = header.to_string();
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            entries.push((section.clone(), key.trim().to_string(), value.trim().trim_matches('"').to_string()));
+        }
+    }
+
+    entries
+}
+
+/// Returns true if the section of a configuration file is the expected one.
+/// Its name is case insensitive, unlike the subsection in quotes
+fn is_config_section(section: &str, expected: &str) -> bool {
+
+    let (name, subsection) = section.split_once(' ').unwrap_or((section, ""));
+    let (expected_name, expected_subsection) = expected.split_once(' ').unwrap_or((expected, ""));
+    name.eq_ignore_ascii_case(expected_name) && subsection.trim() == expected_subsection
+}
+
+/// A repository with a working tree
+struct Repository {
+    /// The `.git` directory, or the directory of a linked working tree in the `.git` directory of the main one
+    git_dir: PathBuf,
+    /// The directory shared by the working trees, with the objects, the references and the configuration
+    common_dir: PathBuf,
+    work_tree: PathBuf,
+    objects: ObjectStore,
+}
+
+impl Repository {
+
+    /// Finds the repository of the directory, looking for a `.git` directory (or file) in it and in its parents
+    fn discover(directory: &Path) -> Option<Repository> {
+
+        let directory = directory.canonicalize().ok()?;
+
+        for work_tree in directory.ancestors() {
+            let dot_git = work_tree.join(".git");
+            let git_dir = match std::fs::read_to_string(&dot_git) {
+                // A file `gitdir: path` is used by the linked working trees and the submodules
+                Ok(content) => match content.trim_end().strip_prefix("gitdir: ") {
+                    Some(path) => work_tree.join(path),
+                    None => continue,
+                },
+                Err(_) if dot_git.join("HEAD").is_file() => dot_git,
+                Err(_) => continue,
+            };
+
+            let common_dir = match std::fs::read_to_string(git_dir.join("commondir")) {
+                Ok(path) => git_dir.join(path.trim_end()),
+                Err(_) => git_dir.clone(),
+            };
+            let objects = ObjectStore::new(&common_dir.join("objects"));
+
+            return Some(Repository { git_dir, common_dir, work_tree: work_tree.to_path_buf(), objects });
+        }
+
+        None
+    }
+
+    /// Returns the content of HEAD : `ref: refs/heads/branch`, or a commit if it is detached
+    fn read_head(&self) -> Option<String> {
+        std::fs::read_to_string(self.git_dir.join("HEAD")).ok().map(|head| head.trim_end().to_string())
+    }
+
+    /// Returns the commit of the reference (like `HEAD` or `refs/heads/main`), following the symbolic references
+    fn resolve_reference(&self, name: &str) -> Option<ObjectId> {
+
+        let mut name = name.to_string();
+        for _ in 0..MAX_SYMBOLIC_DEPTH {
+            // HEAD and the other pseudo references are in the directory of the working tree
+            let loose = std::fs::read_to_string(self.git_dir.join(&name))
+                .or_else(|_| std::fs::read_to_string(self.common_dir.join(&name)));
+
+            match loose {
+                Ok(content) => match content.trim_end().strip_prefix("ref: ") {
+                    Some(target) => name = target.to_string(),
+                    None => return ObjectId::from_hex(content.trim_end()),
+                },
+                Err(_) => return self.packed_reference(&name),
+            }
+        }
+
+        None
+    }
+
+    /// Looks for the reference in `packed-refs`, whose lines are `id name`
+    fn packed_reference(&self, name: &str) -> Option<ObjectId> {
+
+        let packed = std::fs::read_to_string(self.common_dir.join("packed-refs")).ok()?;
+        packed.lines()
+            .filter_map(|line| line.split_once(' '))
+            .find(|(_, reference)| *reference == name)
+            .and_then(|(id, _)| ObjectId::from_hex(id))
+    }
+
+    /// Returns the value of a key of the configuration, like `branch "main"` `remote`
+    fn config_value(&self, section: &str, key: &str) -> Option<String> {
+
+        // The last value wins
+        read_config(&self.common_dir.join("config")).into_iter()
+            .filter(|entry| is_config_section(&entry.0, section) && entry.1.eq_ignore_ascii_case(key))
+            .map(|(_, _, value)| value)
+            .next_back()
+    }
+
+    /// Returns the names of the references under the directory of `refs` (like `refs/heads`), loose or packed, without it
+    fn reference_names(&self, directory: &str) -> Vec<String> {
+
+        let mut names = Vec::new();
+        let mut pending = vec![self.common_dir.join(directory)];
+        while let Some(path) = pending.pop() {
+            let Ok(entries) = std::fs::read_dir(&path) else { continue };
+            for entry in entries.filter_map(Result::ok) {
+                match entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                    true => pending.push(entry.path()),
+                    false => if let Ok(name) = entry.path().strip_prefix(self.common_dir.join(directory)) {
+                        names.push(name.to_string_lossy().into_owned());
+                    },
+                }
+            }
+        }
+
+        // The lines of `packed-refs` are `id name`, the comments start with `#` and the peeled tags with `^`
+        let prefix = format!("{directory}/");
+        let packed = std::fs::read_to_string(self.common_dir.join("packed-refs")).unwrap_or_default();
+        names.extend(packed.lines()
+            .filter_map(|line| line.split_once(' '))
+            .filter_map(|(_, reference)| reference.strip_prefix(&prefix))
+            .map(str::to_string));
+
+        names
+    }
+
+    /// Returns the remote tracking reference of the branch, from `branch.<name>.remote` and `branch.<name>.merge`
+    fn upstream(&self, branch: &str) -> Option<String> {
+
+        let name = branch.strip_prefix("refs/heads/")?;
+        let section = format!("branch \"{name}\"");
+        let remote = self.config_value(&section, "remote")?;
+        let merge = self.config_value(&section, "merge")?;
+
+        match remote.as_str() {
+            // The upstream is a local branch
+            "." => Some(merge),
+            remote => Some(format!("refs/remotes/{remote}/{}", merge.strip_prefix("refs/heads/").unwrap_or(&merge))),
+        }
+    }
+
+    /// Returns the operation in progress, and the branch being rebased if it is a rebase
+    fn operation(&self) -> (Option<String>, Option<String>) {
+
+        let read = |name: &str| std::fs::read_to_string(self.git_dir.join(name)).ok().map(|content| content.trim().to_string());
+        let progress = |step: Option<String>, total: Option<String>| match (step, total) {
+            (Some(step), Some(total)) => format!(" {step}/{total}"),
+            _ => String::new(),
+        };
+
+        if self.git_dir.join("rebase-merge").is_dir() {
+            let operation = format!("REBASE{}", progress(read("rebase-merge/msgnum"), read("rebase-merge/end")));
+            return (Some(operation), read("rebase-merge/head-name"));
+        }
+        if self.git_dir.join("rebase-apply").is_dir() {
+            let name = match (self.git_dir.join("rebase-apply/rebasing").exists(), self.git_dir.join("rebase-apply/applying").exists()) {
+                (true, _) => "REBASE",
+                (_, true) => "AM",
+                _ => "AM/REBASE",
+            };
+            let operation = format!("{name}{}", progress(read("rebase-apply/next"), read("rebase-apply/last")));
+            return (Some(operation), read("rebase-apply/head-name"));
+        }
+
+        let operation = [
+            ("MERGE_HEAD", "MERGING"), ("CHERRY_PICK_HEAD", "CHERRY-PICKING"), ("REVERT_HEAD", "REVERTING"), ("BISECT_LOG", "BISECTING"),
+        ].iter().find(|(file, _)| self.git_dir.join(file).is_file()).map(|(_, operation)| operation.to_string());
+
+        (operation, None)
+    }
+
+    /// Returns true if the index differs from the tree of the HEAD commit, None if the deadline is reached
+    fn has_staged_changes(&mut self, head_commit: Option<ObjectId>, index: &Index, deadline: Instant) -> Option<bool> {
+
+        // Before the first commit, everything in the index is staged
+        let Some(head_commit) = head_commit else { return Some(!index.entries.is_empty()) };
+        let Ok(commit) = self.objects.read_commit(&head_commit) else { return Some(false) };
+
+        // The cache of the trees of the index avoids reading the trees
+        if let Some(root_tree) = index.root_tree {
+            return Some(root_tree != commit.tree);
+        }
+
+        let mut head_files = Vec::new();
+        let mut trees = vec![(Vec::new(), commit.tree)];
+        while let Some((prefix, tree)) = trees.pop() {
+            if Instant::now() >= deadline {
+                return None;
+            }
+            let Ok(entries) = self.objects.read_tree(&tree) else { return Some(false) };
+
+            for entry in entries {
+                let mut path = prefix.clone();
+                path.extend_from_slice(&entry.name);
+                match entry.mode {
+                    TREE_MODE => {
+                        path.push(b'/');
+                        trees.push((path, entry.id));
+                    },
+                    mode => head_files.push((path, mode, entry.id)),
+                }
+            }
+        }
+        head_files.sort();
+
+        let mut index_files: Vec<(Vec<u8>, u32, ObjectId)> = index.entries.iter()
+            .map(|entry| (entry.path.clone(), entry.mode, entry.id))
+            .collect();
+        index_files.sort();
+        index_files.dedup_by(|a, b| a.0 == b.0);
+
+        Some(head_files != index_files)
+    }
+
+    /// Returns true if a tracked file differs from the index, None if the deadline is reached.
+    ///
+    /// The metadata of a file is compared with the one saved in the index, and its content is only hashed if they
+    /// differ or if the file was modified after the index (its changes may not change its size and its time)
+    fn has_unstaged_changes(&self, index: &Index, index_time: Option<(i64, i64)>, deadline: Instant) -> Option<bool> {
+
+        for entry in &index.entries {
+            if Instant::now() >= deadline {
+                return None;
+            }
+            // A file in conflict is not merged yet
+            if entry.stage != 0 {
+                return Some(true);
+            }
+            if entry.assume_unchanged || entry.mode == GITLINK_MODE {
+                continue;
+            }
+
+            let path = self.work_tree.join(std::ffi::OsStr::from_bytes(&entry.path));
+            let Ok(metadata) = path.symlink_metadata() else { return Some(true) };
+
+            let is_symlink = metadata.file_type().is_symlink();
+            let executable = metadata.permissions().mode() & 0o111 != 0;
+            let mode_changed = match entry.mode {
+                SYMLINK_MODE => !is_symlink,
+                mode => is_symlink || !metadata.is_file() || (mode & 0o111 != 0) != executable,
+            };
+            if mode_changed || metadata.size() as u32 != entry.size {
+                return Some(true);
+            }
+
+            let mtime = (metadata.mtime(), metadata.mtime_nsec());
+            let same_time = mtime == (entry.mtime.0 as i64, entry.mtime.1 as i64);
+            if same_time && index_time.is_some_and(|index_time| mtime < index_time) {
+                continue;
+            }
+
+            let content = match is_symlink {
+                true => std::fs::read_link(&path).map(|target| target.as_os_str().as_bytes().to_vec()),
+                false => std::fs::read(&path),
+            };
+            match content {
+                Ok(content) if ObjectId::for_blob(&content) == entry.id => (),
+                _ => return Some(true),
+            }
+        }
+
+        Some(false)
+    }
+
+    /// Counts the commits reachable from the local commit but not from the upstream one (ahead),
+    /// and conversely (behind), None if the deadline is reached.
+    ///
+    /// The commits are walked from the most recent, and the walk stops when only the common history remains
+    fn count_ahead_behind(&mut self, local: ObjectId, upstream: ObjectId, deadline: Instant) -> Option<(usize, usize)> {
+
+        const LOCAL: u8 = 1;
+        const UPSTREAM: u8 = 2;
+        const BOTH: u8 = LOCAL | UPSTREAM;
+
+        // The flags say from which side each commit was reached
+        let mut flags: HashMap<ObjectId, u8> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+
+        for (id, flag) in [(local, LOCAL), (upstream, UPSTREAM)] {
+            *flags.entry(id).or_default() |= flag;
+            let time = self.objects.read_commit(&id).map(|commit| commit.time).unwrap_or_default();
+            queue.push((time, id));
+        }
+
+        // The older commits are reachable from both sides once all the queued ones are. Like git, a few more
+        // commits are walked after that, for the commits having the same date or a wrong clock
+        let mut slop = AHEAD_BEHIND_SLOP;
+        while let Some((_, id)) = queue.pop() {
+            if Instant::now() >= deadline {
+                return None;
+            }
+            if flags[&id] == BOTH && queue.iter().all(|(_, id)| flags[id] == BOTH) {
+                if slop == 0 {
+                    break;
+                }
+                slop -= 1;
+            } else {
+                slop = AHEAD_BEHIND_SLOP;
+            }
+            let flag = flags[&id];
+            let Ok(commit) = self.objects.read_commit(&id) else { continue };
+
+            for parent in commit.parents {
+                let parent_flag = flags.entry(parent).or_default();
+                if *parent_flag | flag == *parent_flag {
+                    continue;
+                }
+                *parent_flag |= flag;
+                let time = self.objects.read_commit(&parent).map(|commit| commit.time).unwrap_or_default();
+                queue.push((time, parent));
+            }
+        }
+
+        let ahead = flags.values().filter(|flag| **flag == LOCAL).count();
+        let behind = flags.values().filter(|flag| **flag == UPSTREAM).count();
+        Some((ahead, behind))
+    }
+
+    /// Returns true if a file of the working tree is neither tracked nor ignored, None if the deadline is reached
+    fn has_untracked_files(&self, index: &Index, deadline: Instant) -> Option<bool> {
+
+        let tracked: HashSet<&[u8]> = index.entries.iter().map(|entry| entry.path.as_slice()).collect();
+        let mut rules = IgnoreRules::default();
+        rules.add_file(&self.common_dir.join("info/exclude"), "");
+        if let Some(config_home) = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from).or_else(|| std::env::home_dir().map(|home| home.join(".config"))) {
+            rules.add_file(&config_home.join("git/ignore"), "");
+        }
+
+        self.find_untracked_file("", &tracked, &mut rules, deadline)
+    }
+
+    /// Looks for an untracked file in the directory, relative to the working tree, and its subdirectories
+    fn find_untracked_file(&self, directory: &str, tracked: &HashSet<&[u8]>, rules: &mut IgnoreRules, deadline: Instant) -> Option<bool> {
+
+        let path = self.work_tree.join(directory);
+        let Ok(entries) = std::fs::read_dir(&path) else { return Some(false) };
+
+        let rules_length = rules.len();
+        rules.add_file(&path.join(".gitignore"), directory);
+
+        let mut found = Some(false);
+        for entry in entries.filter_map(Result::ok) {
+            if Instant::now() >= deadline {
+                found = None;
+                break;
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name == ".git" {
+                continue;
+            }
+            let relative = match directory {
+                "" => name,
+                directory => format!("{directory}/{name}"),
+            };
+            let is_directory = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+            if tracked.contains(relative.as_bytes()) || rules.is_ignored(&relative, is_directory) {
+                continue;
+            }
+
+            let untracked = match is_directory {
+                // Another repository inside the working tree is untracked as a whole
+                true if entry.path().join(".git").exists() => Some(true),
+                true => self.find_untracked_file(&relative, tracked, rules, deadline),
+                false => Some(true),
+            };
+            if untracked != Some(false) {
+                found = untracked;
+                break;
+            }
+        }
+
+        rules.truncate(rules_length);
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_format() {
+        let mut status = GitStatus { branch: "main".to_string(), ..GitStatus::default() };
+        assert_eq!("main", status.to_string());
+
+        status.ahead_behind = Some((0, 0));
+        assert_eq!("main", status.to_string());
+
+        status.unstaged = true;
+        status.untracked = true;
+        status.ahead_behind = Some((2, 1));
+        status.operation = Some("REBASE 1/3".to_string());
+        assert_eq!("main *% ↑2↓1|REBASE 1/3", status.to_string());
+    }
+
+    #[test]
+    fn test_no_repository() {
+        assert_eq!(None, git_status(Path::new("/"), DEFAULT_GIT_PROMPT_TIMEOUT));
+    }
+
+    #[test]
+    fn test_broken_repository() {
+        let directory = std::env::temp_dir().join(format!("rust_shell_broken_git_{}", std::process::id()));
+        std::fs::create_dir_all(directory.join(".git/objects/pack")).unwrap();
+
+        // A detached HEAD that is not an identifier, a truncated index and a truncated pack
+        std::fs::write(directory.join(".git/HEAD"), "ééééé\n").unwrap();
+        std::fs::write(directory.join(".git/index"), b"DIRC\0\0\0\x02\0\0\0\x05\0\0").unwrap();
+        std::fs::write(directory.join(".git/objects/pack/pack-a.idx"), b"\xfftOc\0\0\0\x02\xff\xff").unwrap();
+        std::fs::write(directory.join(".git/objects/pack/pack-a.pack"), b"PACK").unwrap();
+
+        let status = git_status(&directory, DEFAULT_GIT_PROMPT_TIMEOUT);
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!("ééééé...", status.unwrap().to_string());
+    }
+
+    #[test]
+    fn test_repository_status() {
+        let directory = std::env::temp_dir().join(format!("rust_shell_git_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let git = |args: &str| std::process::Command::new("git").args(args.split(' ')).current_dir(&directory)
+            .stdout(std::process::Stdio::null()).stderr(std::process::Stdio::null()).status();
+        // The repository is created by git, which may not be installed
+        if git("init -q -b main").is_err() {
+            std::fs::remove_dir_all(&directory).unwrap();
+            return;
+        }
+
+        let commands = [
+            "config user.email a@b", "config user.name a", "commit -q --allow-empty -m 1", "branch upstream",
+            "commit -q --allow-empty -m 2", "commit -q --allow-empty -m 3", "branch -q --set-upstream-to=upstream", "gc -q",
+        ];
+        for command in commands {
+            git(command).unwrap();
+        }
+        std::fs::write(directory.join("staged"), "staged").unwrap();
+        git("add staged").unwrap();
+        std::fs::write(directory.join("untracked"), "").unwrap();
+        std::fs::write(directory.join(".gitignore"), "ignored\n").unwrap();
+        git("add .gitignore").unwrap();
+        std::fs::write(directory.join("ignored"), "").unwrap();
+
+        let status = git_status(&directory.join(".git"), DEFAULT_GIT_PROMPT_TIMEOUT);
+        let expected = GitStatus {
+            branch: "main".to_string(), staged: true, untracked: true, ahead_behind: Some((2, 0)), ..GitStatus::default()
+        };
+        assert_eq!(Some(expected), status);
+
+        std::fs::write(directory.join("staged"), "changed").unwrap();
+        std::fs::write(directory.join(".git/MERGE_HEAD"), "").unwrap();
+        assert_eq!("main *+% ↑2|MERGING", git_status(&directory, DEFAULT_GIT_PROMPT_TIMEOUT).unwrap().to_string());
+
+        // Without time, only the branch and the operation are known
+        assert_eq!("main ?|MERGING", git_status(&directory, Duration::ZERO).unwrap().to_string());
+
+        git("tag v1").unwrap();
+        git("remote add origin /nowhere").unwrap();
+        git("config alias.st status").unwrap();
+        assert_eq!(vec!["main", "upstream", "v1"], reference_names(&directory));
+        assert_eq!(vec!["origin"], remote_names(&directory));
+        assert_eq!(vec!["st"], alias_names(&directory, None));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
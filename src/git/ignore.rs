@@ -0,0 +1,147 @@
+//!
+//! The rules of the `.gitignore` files, used to find the untracked files of a working tree.
+//!
+//! A rule is a glob pattern, matched against the file name if it contains no slash (except a trailing one),
+//! and against the path from the directory of its `.gitignore` otherwise, `**` matching any number of directories.
+//! The last matching rule wins, and a `!` rule includes a file again.
+//!
+
+use std::path::Path;
+
+use crate::expansion::glob::matches;
+
+#[derive(Debug)]
+struct IgnoreRule {
+    /// The components of the pattern, only one for the patterns matching the file name
+    components: Vec<String>,
+    /// The directory of the `.gitignore` file, relative to the working tree (empty for the root)
+    base: String,
+    /// The rule is anchored to its directory instead of matching the file name at any depth
+    anchored: bool,
+    negated: bool,
+    directories_only: bool,
+}
+
+/// The rules which apply to a path of the working tree, from its `.gitignore` files and the exclude files
+#[derive(Debug, Default)]
+pub struct IgnoreRules {
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreRules {
+
+    /// Adds the rules of the file, which applies to the `base` directory of the working tree
+    pub fn add_file(&mut self, path: &Path, base: &str) {
+        if let Ok(content) = std::fs::read_to_string(path) {
+            self.add_rules(&content, base);
+        }
+    }
+
+    /// Adds the rules, one per line, empty lines and comments being ignored
+    pub fn add_rules(&mut self, content: &str, base: &str) {
+
+        for line in content.lines() {
+            // The trailing spaces are ignored unless they are escaped
+            let line = match line.ends_with("\\ ") {
+                true => line,
+                false => line.trim_end(),
+            };
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (negated, pattern) = match line.strip_prefix('!') {
+                Some(pattern) => (true, pattern),
+                None => (false, line),
+            };
+            let (directories_only, pattern) = match pattern.strip_suffix('/') {
+                Some(pattern) => (true, pattern),
+                None => (false, pattern),
+            };
+            let anchored = pattern.contains('/');
+            let components = pattern.trim_start_matches('/').split('/').map(str::to_string).collect();
+
+            self.rules.push(IgnoreRule { components, base: base.to_string(), anchored, negated, directories_only });
+        }
+    }
+
+    /// Returns the number of rules, to remove the rules of a directory once it is processed
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn truncate(&mut self, length: usize) {
+        self.rules.truncate(length);
+    }
+
+    /// Returns true if the path, relative to the working tree, is ignored
+    pub fn is_ignored(&self, path: &str, is_directory: bool) -> bool {
+
+        let name = path.rsplit('/').next().unwrap_or(path);
+
+        for rule in self.rules.iter().rev() {
+            if rule.directories_only && !is_directory {
+                continue;
+            }
+
+            let matched = match rule.anchored {
+                false => matches(&rule.components[0], name),
+                true => {
+                    let relative = match rule.base.is_empty() {
+                        true => Some(path),
+                        false => path.strip_prefix(rule.base.as_str()).and_then(|path| path.strip_prefix('/')),
+                    };
+                    relative.is_some_and(|relative| {
+                        let path_components: Vec<&str> = relative.split('/').collect();
+                        match_components(&rule.components, &path_components)
+                    })
+                },
+            };
+
+            if matched {
+                return !rule.negated;
+            }
+        }
+
+        false
+    }
+}
+
+/// Matches the components of the path with the components of the pattern, `**` matching any number of them
+fn match_components(pattern: &[String], path: &[&str]) -> bool {
+
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => (0..=path.len()).any(|skipped| match_components(rest, &path[skipped..])),
+        Some((first, rest)) => path.split_first()
+            .is_some_and(|(component, path)| matches(first, component) && match_components(rest, path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ignore_rules() {
+        let mut rules = IgnoreRules::default();
+        rules.add_rules("# comment\n*.log\n!keep.log\n/target\nbuild/\ndocs/**/*.tmp\n", "");
+        rules.add_rules("local\n/only_here\n", "sub");
+
+        assert!(rules.is_ignored("a/b/c.log", false));
+        assert!(!rules.is_ignored("a/keep.log", false));
+        assert!(rules.is_ignored("target", true));
+        assert!(!rules.is_ignored("a/target", true));
+        assert!(rules.is_ignored("a/build", true));
+        assert!(!rules.is_ignored("a/build", false));
+        assert!(rules.is_ignored("docs/x.tmp", false));
+        assert!(rules.is_ignored("docs/a/b/x.tmp", false));
+        assert!(rules.is_ignored("sub/only_here", false));
+        assert!(!rules.is_ignored("only_here", false));
+        assert!(rules.is_ignored("sub/x/local", false));
+    }
+}
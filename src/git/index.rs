@@ -0,0 +1,221 @@
+//!
+//! The index of a git repository (`.git/index`), listing the files staged for the next commit
+//! with the metadata they had when they were staged.
+//!
+
+use std::io;
+use std::path::Path;
+
+use crate::git::objects::{ObjectId, invalid_data};
+
+/// A file of the index
+#[derive(Debug)]
+pub struct IndexEntry {
+    /// The path from the root of the working tree, with `/` separators
+    pub path: Vec<u8>,
+    pub id: ObjectId,
+    pub mode: u32,
+    /// 0 for a merged file, 1 to 3 for the versions of a file in conflict
+    pub stage: u8,
+    pub mtime: (u32, u32),
+    pub size: u32,
+    /// The file is not checked out or must be assumed unchanged (`skip-worktree`, `assume-unchanged`)
+    pub assume_unchanged: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Index {
+    pub entries: Vec<IndexEntry>,
+    /// The tree of the staged files, if the cache of the trees of the index is up to date
+    pub root_tree: Option<ObjectId>,
+}
+
+/// The flags of an entry
+const ASSUME_VALID_FLAG: u16 = 0x8000;
+const EXTENDED_FLAG: u16 = 0x4000;
+const SKIP_WORKTREE_FLAG: u16 = 0x4000;
+const NAME_LENGTH_MASK: u16 = 0x0fff;
+
+/// Reads the index file (versions 2 to 4), an empty index if it doesn't exist
+pub fn read_index(path: &Path) -> io::Result<Index> {
+
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Index::default()),
+        Err(err) => return Err(err),
+    };
+    parse_index(&data)
+}
+
+fn parse_index(data: &[u8]) -> io::Result<Index> {
+
+    let corrupted = || invalid_data("corrupted index".to_string());
+    let word = |offset: usize| -> io::Result<u32> {
+        data.get(offset..offset + 4).map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap_or_default())).ok_or_else(corrupted)
+    };
+    let half_word = |offset: usize| -> io::Result<u16> {
+        data.get(offset..offset + 2).map(|bytes| u16::from_be_bytes(bytes.try_into().unwrap_or_default())).ok_or_else(corrupted)
+    };
+
+    let version = word(4)?;
+    if data.get(..4) != Some(b"DIRC".as_slice()) || !(2..=4).contains(&version) {
+        return Err(corrupted());
+    }
+    let count = word(8)? as usize;
+    // The file ends with the checksum of its content
+    let end = data.len().checked_sub(20).ok_or_else(corrupted)?;
+
+    let mut index = Index::default();
+    let mut position = 12;
+    let mut previous_path: Vec<u8> = Vec::new();
+
+    for _ in 0..count {
+        let start = position;
+        // ctime, mtime, dev, ino, mode, uid, gid and size on 32 bits, the identifier then the flags
+        let mtime = (word(start + 8)?, word(start + 12)?);
+        let mode = word(start + 24)?;
+        let size = word(start + 36)?;
+        let id = ObjectId(data.get(start + 40..start + 60).ok_or_else(corrupted)?.try_into().map_err(|_| corrupted())?);
+        let flags = half_word(start + 60)?;
+        position = start + 62;
+
+        let extended_flags = match flags & EXTENDED_FLAG != 0 && version >= 3 {
+            true => {
+                position += 2;
+                half_word(start + 62)?
+            },
+            false => 0,
+        };
+
+        let path = match version {
+            // The path is the end of the previous one without N bytes, followed by a NUL terminated suffix
+            4 => {
+                let mut removed = 0usize;
+                loop {
+                    let byte = *data.get(position).ok_or_else(corrupted)?;
+                    position += 1;
+                    removed = (removed << 7) | (byte & 0x7f) as usize;
+                    // The number can't be more than the length of the previous path, which keeps it from overflowing
+                    if removed > previous_path.len() {
+                        return Err(corrupted());
+                    }
+                    if byte & 0x80 == 0 {
+                        break;
+                    }
+                    removed += 1;
+                }
+                let suffix_length = data[position..].iter().position(|&byte| byte == 0).ok_or_else(corrupted)?;
+                let kept = previous_path.len().checked_sub(removed).ok_or_else(corrupted)?;
+                let mut path = previous_path[..kept].to_vec();
+                path.extend_from_slice(&data[position..position + suffix_length]);
+                position += suffix_length + 1;
+                path
+            },
+            // The path is NUL terminated, and the entry is padded to a multiple of 8 bytes
+            _ => {
+                let length = match flags & NAME_LENGTH_MASK {
+                    NAME_LENGTH_MASK => data[position..].iter().position(|&byte| byte == 0).ok_or_else(corrupted)?,
+                    length => length as usize,
+                };
+                let path = data.get(position..position + length).ok_or_else(corrupted)?.to_vec();
+                position = start + (position - start + length + 8) / 8 * 8;
+                path
+            },
+        };
+        if position > end {
+            return Err(corrupted());
+        }
+
+        previous_path = path.clone();
+        index.entries.push(IndexEntry {
+            path,
+            id,
+            mode,
+            stage: ((flags >> 12) & 0x3) as u8,
+            mtime,
+            size,
+            assume_unchanged: flags & ASSUME_VALID_FLAG != 0 || extended_flags & SKIP_WORKTREE_FLAG != 0,
+        });
+    }
+
+    // The extensions follow the entries, each one with a signature and a size
+    while position + 8 <= end {
+        let signature = &data[position..position + 4];
+        let size = word(position + 4)? as usize;
+        let content = data.get(position + 8..position + 8 + size).ok_or_else(corrupted)?;
+        if signature == b"TREE" {
+            index.root_tree = parse_root_tree(content);
+        }
+        position += 8 + size;
+    }
+
+    Ok(index)
+}
+
+/// Returns the tree of the root from the cache of the trees, which starts with the root :
+/// its empty path, NUL, its number of entries (-1 if invalid), a space, its number of subtrees, a newline, then its identifier
+fn parse_root_tree(content: &[u8]) -> Option<ObjectId> {
+
+    let rest = content.strip_prefix(b"\0")?;
+    let newline = rest.iter().position(|&byte| byte == b'\n')?;
+    let counts = std::str::from_utf8(&rest[..newline]).ok()?;
+    let entry_count: i64 = counts.split(' ').next()?.parse().ok()?;
+    if entry_count < 0 {
+        return None;
+    }
+
+    Some(ObjectId(rest.get(newline + 1..newline + 21)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds an index of version 2 with these paths, and the cache of the trees
+    fn build_index(paths: &[&str], root_tree: Option<&[u8; 20]>) -> Vec<u8> {
+        let mut data = b"DIRC\0\0\0\x02".to_vec();
+        data.extend_from_slice(&(paths.len() as u32).to_be_bytes());
+        for path in paths {
+            let start = data.len();
+            data.extend_from_slice(&[0; 24]);
+            data.extend_from_slice(&0o100644u32.to_be_bytes());
+            data.extend_from_slice(&[0; 8]);
+            data.extend_from_slice(&5u32.to_be_bytes());
+            data.extend_from_slice(&[7; 20]);
+            data.extend_from_slice(&(path.len() as u16).to_be_bytes());
+            data.extend_from_slice(path.as_bytes());
+            data.resize(start + (data.len() - start + 8) / 8 * 8, 0);
+        }
+        if let Some(tree) = root_tree {
+            data.extend_from_slice(b"TREE\0\0\0\x19\x001 0\n");
+            data.extend_from_slice(tree);
+        }
+        data.extend_from_slice(&[0; 20]);
+        data
+    }
+
+    #[test]
+    fn test_index_parsing() {
+        let index = parse_index(&build_index(&["a.txt", "dir/longer_name.rs"], Some(&[9; 20]))).unwrap();
+        let paths: Vec<&[u8]> = index.entries.iter().map(|entry| entry.path.as_slice()).collect();
+        assert_eq!(vec![b"a.txt".as_slice(), b"dir/longer_name.rs"], paths);
+        assert_eq!((0o100644, 5, ObjectId([7; 20])), (index.entries[1].mode, index.entries[1].size, index.entries[1].id));
+        assert_eq!(Some(ObjectId([9; 20])), index.root_tree);
+
+        assert!(parse_index(b"DIRC\0\0\0\x09").is_err());
+    }
+
+    #[test]
+    fn test_corrupted_index() {
+        let index = build_index(&["a.txt", "b.txt"], None);
+        assert!(parse_index(&index[..80]).ok().is_none());
+
+        // A version 4 entry removing more bytes of the previous path than an integer can count
+        let mut index = b"DIRC\0\0\0\x04\0\0\0\x01".to_vec();
+        index.extend_from_slice(&[0; 62]);
+        index.extend_from_slice(&[0xff; 16]);
+        index.extend_from_slice(b"\0a\0");
+        index.extend_from_slice(&[0; 20]);
+        assert!(parse_index(&index).ok().is_none());
+    }
+}
@@ -0,0 +1,500 @@
+//!
+//! The object database of a git repository : the loose objects (one zlib compressed file per object)
+//! and the pack files (many objects compressed together, some stored as a delta against another object).
+//!
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use miniz_oxide::inflate::{TINFLStatus, decompress_to_vec_zlib_with_limit};
+
+/// The SHA-1 identifier of an object
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ObjectId(pub [u8; 20]);
+
+impl ObjectId {
+
+    /// Parses the 40 hexadecimal digits of an identifier
+    pub fn from_hex(hex: &str) -> Option<ObjectId> {
+
+        let hex = hex.as_bytes();
+        if hex.len() != 40 {
+            return None;
+        }
+        let mut id = [0; 20];
+        for (index, byte) in id.iter_mut().enumerate() {
+            let digits = std::str::from_utf8(&hex[index * 2..index * 2 + 2]).ok()?;
+            *byte = u8::from_str_radix(digits, 16).ok()?;
+        }
+
+        Some(ObjectId(id))
+    }
+
+    /// Returns the identifier of a blob having this content, as computed by `git hash-object`
+    pub fn for_blob(content: &[u8]) -> ObjectId {
+        let mut hasher = sha1_smol::Sha1::new();
+        hasher.update(format!("blob {}\0", content.len()).as_bytes());
+        hasher.update(content);
+        ObjectId(hasher.digest().bytes())
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ObjectKind {
+    Commit,
+    Tree,
+    Blob,
+    Tag,
+}
+
+impl ObjectKind {
+    fn from_pack_type(code: u8) -> Option<ObjectKind> {
+        match code {
+            1 => Some(ObjectKind::Commit),
+            2 => Some(ObjectKind::Tree),
+            3 => Some(ObjectKind::Blob),
+            4 => Some(ObjectKind::Tag),
+            _ => None,
+        }
+    }
+}
+
+/// The fields of a commit used to walk the history
+#[derive(Debug)]
+pub struct Commit {
+    pub tree: ObjectId,
+    pub parents: Vec<ObjectId>,
+    /// The date of the commit, in seconds since the epoch
+    pub time: i64,
+}
+
+/// An entry of a tree : a file (blob), a directory (tree) or a submodule (commit)
+#[derive(Debug)]
+pub struct TreeEntry {
+    pub mode: u32,
+    pub name: Vec<u8>,
+    pub id: ObjectId,
+}
+
+/// The mode of the tree entries that are directories
+pub const TREE_MODE: u32 = 0o40000;
+
+/// Reads the objects of a repository, from its `objects` directory and its alternates
+pub struct ObjectStore {
+    directories: Vec<PathBuf>,
+    /// The pack files, loaded the first time an object is not found in the loose objects
+    packs: Option<Vec<Pack>>,
+}
+
+impl ObjectStore {
+
+    pub fn new(objects_directory: &Path) -> ObjectStore {
+
+        let mut directories = vec![objects_directory.to_path_buf()];
+        // The alternates are other object directories, one per line, relative to this one
+        if let Ok(alternates) = std::fs::read_to_string(objects_directory.join("info/alternates")) {
+            let lines = alternates.lines().filter(|line| !line.is_empty() && !line.starts_with('#'));
+            directories.extend(lines.map(|line| objects_directory.join(line)));
+        }
+
+        ObjectStore { directories, packs: None }
+    }
+
+    /// Returns the kind and the content of the object
+    pub fn read(&mut self, id: &ObjectId) -> io::Result<(ObjectKind, Vec<u8>)> {
+
+        let hex = id.to_string();
+        for directory in &self.directories {
+            if let Ok(compressed) = std::fs::read(directory.join(&hex[..2]).join(&hex[2..])) {
+                return parse_loose_object(&compressed);
+            }
+        }
+
+        let packs = match &mut self.packs {
+            Some(packs) => packs,
+            None => self.packs.insert(load_packs(&self.directories)),
+        };
+        for pack in packs.iter() {
+            if let Some(offset) = pack.find(id) {
+                return pack.read_at(offset, 0);
+            }
+        }
+
+        Err(invalid_data(format!("object {hex} not found")))
+    }
+
+    pub fn read_commit(&mut self, id: &ObjectId) -> io::Result<Commit> {
+        match self.read(id)? {
+            (ObjectKind::Commit, content) => parse_commit(&content),
+            _ => Err(invalid_data(format!("object {id} is not a commit"))),
+        }
+    }
+
+    pub fn read_tree(&mut self, id: &ObjectId) -> io::Result<Vec<TreeEntry>> {
+        match self.read(id)? {
+            (ObjectKind::Tree, content) => parse_tree(&content),
+            _ => Err(invalid_data(format!("object {id} is not a tree"))),
+        }
+    }
+}
+
+pub fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Decompresses a loose object, made of a header `kind size\0` followed by the content
+fn parse_loose_object(compressed: &[u8]) -> io::Result<(ObjectKind, Vec<u8>)> {
+
+    let data = miniz_oxide::inflate::decompress_to_vec_zlib(compressed)
+        .map_err(|err| invalid_data(format!("corrupted object: {:?}", err.status)))?;
+    let header_end = data.iter().position(|&byte| byte == 0).ok_or_else(|| invalid_data("corrupted object".to_string()))?;
+
+    let kind = match data[..header_end].split(|&byte| byte == b' ').next() {
+        Some(b"commit") => ObjectKind::Commit,
+        Some(b"tree") => ObjectKind::Tree,
+        Some(b"blob") => ObjectKind::Blob,
+        Some(b"tag") => ObjectKind::Tag,
+        _ => return Err(invalid_data("unknown object kind".to_string())),
+    };
+
+    Ok((kind, data[header_end + 1..].to_vec()))
+}
+
+fn parse_commit(content: &[u8]) -> io::Result<Commit> {
+
+    let text = String::from_utf8_lossy(content);
+    let mut tree = None;
+    let mut parents = Vec::new();
+    let mut time = 0;
+
+    // The headers end at the first empty line, before the message
+    for line in text.lines().take_while(|line| !line.is_empty()) {
+        match line.split_once(' ') {
+            Some(("tree", hex)) => tree = ObjectId::from_hex(hex),
+            Some(("parent", hex)) => parents.extend(ObjectId::from_hex(hex)),
+            // committer Name <email> 1700000000 +0100
+            Some(("committer", identity)) => {
+                time = identity.rsplit(' ').nth(1).and_then(|time| time.parse().ok()).unwrap_or_default();
+            },
+            _ => (),
+        }
+    }
+
+    let tree = tree.ok_or_else(|| invalid_data("commit without tree".to_string()))?;
+    Ok(Commit { tree, parents, time })
+}
+
+/// Parses the entries of a tree, each one being `mode name\0` followed by the 20 bytes of the identifier
+fn parse_tree(content: &[u8]) -> io::Result<Vec<TreeEntry>> {
+
+    let corrupted = || invalid_data("corrupted tree".to_string());
+    let mut entries = Vec::new();
+    let mut rest = content;
+
+    while !rest.is_empty() {
+        let space = rest.iter().position(|&byte| byte == b' ').ok_or_else(corrupted)?;
+        let nul = rest.iter().position(|&byte| byte == 0).ok_or_else(corrupted)?;
+        if nul < space || rest.len() < nul + 21 {
+            return Err(corrupted());
+        }
+
+        let mode = std::str::from_utf8(&rest[..space]).ok().and_then(|mode| u32::from_str_radix(mode, 8).ok()).ok_or_else(corrupted)?;
+        let id = ObjectId(rest[nul + 1..nul + 21].try_into().map_err(|_| corrupted())?);
+        entries.push(TreeEntry { mode, name: rest[space + 1..nul].to_vec(), id });
+        rest = &rest[nul + 21..];
+    }
+
+    Ok(entries)
+}
+
+/// The types of the pack entries which are a delta against another object
+const OFS_DELTA: u8 = 6;
+const REF_DELTA: u8 = 7;
+/// A delta chain longer than this is considered corrupted
+const MAX_DELTA_DEPTH: usize = 64;
+
+/// A pack file with its index (version 2) giving the offset of each object
+struct Pack {
+    file: File,
+    /// The identifiers in the index, sorted, and their offset in the pack
+    ids: Vec<ObjectId>,
+    offsets: Vec<u64>,
+}
+
+fn load_packs(directories: &[PathBuf]) -> Vec<Pack> {
+
+    let mut packs = Vec::new();
+    for directory in directories {
+        let Ok(entries) = std::fs::read_dir(directory.join("pack")) else { continue };
+        let indexes = entries.filter_map(Result::ok).map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "idx"));
+        packs.extend(indexes.filter_map(|index| Pack::open(&index).ok()));
+    }
+
+    packs
+}
+
+impl Pack {
+
+    fn open(index_path: &Path) -> io::Result<Pack> {
+
+        let index = std::fs::read(index_path)?;
+        let corrupted = || invalid_data(format!("{}: corrupted pack index", index_path.display()));
+        let word = |offset: usize| -> io::Result<u32> {
+            index.get(offset..offset + 4).map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap_or_default())).ok_or_else(corrupted)
+        };
+
+        // Magic number, version 2, then the 256 cumulative counts of the identifiers by first byte
+        if index.get(..4) != Some(b"\xfftOc".as_slice()) || word(4)? != 2 {
+            return Err(corrupted());
+        }
+        let count = word(8 + 255 * 4)? as usize;
+        let ids_start = 8 + 256 * 4;
+        let offsets_start = ids_start + count * 24;
+        let large_offsets_start = offsets_start + count * 4;
+        // The count is checked against the length of the index before the tables are allocated
+        if index.len() < large_offsets_start {
+            return Err(corrupted());
+        }
+
+        let mut ids = Vec::with_capacity(count);
+        let mut offsets = Vec::with_capacity(count);
+        for position in 0..count {
+            let id = index.get(ids_start + position * 20..ids_start + position * 20 + 20).ok_or_else(corrupted)?;
+            ids.push(ObjectId(id.try_into().map_err(|_| corrupted())?));
+
+            // The offsets above 2^31 are in a table of 64 bits offsets
+            let offset = word(offsets_start + position * 4)?;
+            let offset = match offset & 0x8000_0000 {
+                0 => offset as u64,
+                _ => {
+                    let position = large_offsets_start + (offset & 0x7fff_ffff) as usize * 8;
+                    let bytes = index.get(position..position + 8).ok_or_else(corrupted)?;
+                    u64::from_be_bytes(bytes.try_into().map_err(|_| corrupted())?)
+                },
+            };
+            offsets.push(offset);
+        }
+
+        Ok(Pack { file: File::open(index_path.with_extension("pack"))?, ids, offsets })
+    }
+
+    fn find(&self, id: &ObjectId) -> Option<u64> {
+        self.ids.binary_search(id).ok().map(|position| self.offsets[position])
+    }
+
+    /// Reads the object at this offset, resolving its deltas
+    fn read_at(&self, offset: u64, depth: usize) -> io::Result<(ObjectKind, Vec<u8>)> {
+
+        if depth > MAX_DELTA_DEPTH {
+            return Err(invalid_data("delta chain too long".to_string()));
+        }
+
+        // The header is the type and the size in a variable length integer, followed by the base of a delta
+        let mut header = [0; 32];
+        let length = self.file.read_at(&mut header, offset)?;
+        let header = &header[..length];
+        let corrupted = || invalid_data("corrupted pack".to_string());
+
+        let mut position = 0;
+        let first = *header.first().ok_or_else(corrupted)?;
+        let code = (first >> 4) & 0x7;
+        let mut size = (first & 0x0f) as usize;
+        let mut shift = 4;
+        let mut byte = first;
+        while byte & 0x80 != 0 {
+            position += 1;
+            byte = *header.get(position).ok_or_else(corrupted)?;
+            size |= varint_bits(byte, shift).ok_or_else(corrupted)?;
+            shift += 7;
+        }
+        position += 1;
+
+        let base = match code {
+            OFS_DELTA => {
+                // A big endian number where each continuation adds one, giving the distance back to the base
+                // The base is before the object, which bounds the distance and keeps it from overflowing
+                let mut byte = *header.get(position).ok_or_else(corrupted)?;
+                let mut distance = (byte & 0x7f) as u64;
+                while byte & 0x80 != 0 && distance < offset {
+                    position += 1;
+                    byte = *header.get(position).ok_or_else(corrupted)?;
+                    distance = ((distance + 1) << 7) | (byte & 0x7f) as u64;
+                }
+                position += 1;
+                Some(offset.checked_sub(distance).filter(|_| byte & 0x80 == 0).ok_or_else(corrupted)?)
+            },
+            REF_DELTA => {
+                let id = ObjectId(header.get(position..position + 20).ok_or_else(corrupted)?.try_into().map_err(|_| corrupted())?);
+                position += 20;
+                Some(self.find(&id).ok_or_else(corrupted)?)
+            },
+            _ => None,
+        };
+
+        let data = self.inflate(offset + position as u64, size)?;
+
+        match base {
+            None => Ok((ObjectKind::from_pack_type(code).ok_or_else(corrupted)?, data)),
+            Some(base_offset) => {
+                let (kind, base) = self.read_at(base_offset, depth + 1)?;
+                Ok((kind, apply_delta(&base, &data)?))
+            },
+        }
+    }
+
+    /// Decompresses the zlib stream starting at this offset, whose decompressed size is known
+    fn inflate(&self, offset: u64, size: usize) -> io::Result<Vec<u8>> {
+
+        // The compressed length is unknown : the input is read again, longer, while it is too short.
+        // It is at most the rest of the file, whatever the size in the header
+        let available = usize::try_from(self.file.metadata()?.len().saturating_sub(offset)).unwrap_or(usize::MAX);
+        let mut length = size.saturating_add(64).min(available);
+        loop {
+            let mut input = vec![0; length];
+            let read = self.file.read_at(&mut input, offset)?;
+            input.truncate(read);
+
+            match decompress_to_vec_zlib_with_limit(&input, size) {
+                Ok(data) if data.len() == size => return Ok(data),
+                Err(err) if err.status == TINFLStatus::FailedCannotMakeProgress && read == length && length < available => {
+                    length = length.saturating_mul(2).min(available);
+                },
+                _ => return Err(invalid_data("corrupted pack object".to_string())),
+            }
+        }
+    }
+}
+
+/// Rebuilds an object from its base and a delta : the sizes of the base and of the result, then instructions
+/// copying a range of the base or inserting the bytes that follow them
+fn apply_delta(base: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
+
+    let corrupted = || invalid_data("corrupted delta".to_string());
+    let mut position = 0;
+    let mut read_size = || -> io::Result<usize> {
+        let mut size = 0;
+        let mut shift = 0;
+        loop {
+            let byte = *delta.get(position).ok_or_else(corrupted)?;
+            position += 1;
+            size |= varint_bits(byte, shift).ok_or_else(corrupted)?;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(size);
+            }
+        }
+    };
+
+    let base_size = read_size()?;
+    let result_size = read_size()?;
+    if base_size != base.len() {
+        return Err(corrupted());
+    }
+
+    // The size read before the instructions is not trusted for the allocation, the result growing if needed
+    let mut result = Vec::with_capacity(result_size.min(base.len() + delta.len()));
+    while let Some(&instruction) = delta.get(position) {
+        position += 1;
+        if result.len() > result_size {
+            return Err(corrupted());
+        }
+
+        if instruction & 0x80 == 0 {
+            let length = instruction as usize;
+            result.extend_from_slice(delta.get(position..position + length).ok_or_else(corrupted)?);
+            position += length;
+            continue;
+        }
+
+        // The bits say which bytes of the offset (4 bytes) and of the size (3 bytes) are present
+        let mut values = [0usize; 2];
+        for (bit, value_index, shift) in (0..7).map(|bit| if bit < 4 { (bit, 0, bit * 8) } else { (bit, 1, (bit - 4) * 8) }) {
+            if instruction & (1 << bit) != 0 {
+                values[value_index] |= (*delta.get(position).ok_or_else(corrupted)? as usize) << shift;
+                position += 1;
+            }
+        }
+        let [offset, size] = values;
+        let size = if size == 0 { 0x10000 } else { size };
+        result.extend_from_slice(base.get(offset..offset.saturating_add(size)).ok_or_else(corrupted)?);
+    }
+
+    match result.len() == result_size {
+        true => Ok(result),
+        false => Err(corrupted()),
+    }
+}
+
+/// Returns the 7 bits of a byte of a variable length integer at their place, None if the shift goes past the integer
+fn varint_bits(byte: u8, shift: u32) -> Option<usize> {
+    ((byte & 0x7f) as usize).checked_shl(shift)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object_ids_and_delta() {
+        let id = ObjectId::for_blob(b"hello\n");
+        assert_eq!("ce013625030ba8dba906f756967f9e9ca394464a", id.to_string());
+        assert_eq!(Some(id), ObjectId::from_hex("ce013625030ba8dba906f756967f9e9ca394464a"));
+        assert_eq!(None, ObjectId::from_hex("ce01"));
+
+        // Copies "hello " from the base, then inserts "git"
+        let delta = [11, 9, 0x80 | 0x01 | 0x10, 0, 6, 3, b'g', b'i', b't'];
+        assert_eq!(b"hello git".to_vec(), apply_delta(b"hello world", &delta).unwrap());
+        assert!(apply_delta(b"short", &delta).is_err());
+    }
+
+    #[test]
+    fn test_corrupted_pack() {
+        let directory = std::env::temp_dir().join(format!("rust_shell_pack_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        // An index announcing more objects than it contains
+        let mut index = b"\xfftOc\0\0\0\x02".to_vec();
+        index.extend_from_slice(&[0xff; 256 * 4]);
+        std::fs::write(directory.join("pack-a.idx"), &index).unwrap();
+
+        // A size too long for an integer, a truncated object announcing a huge size, and a delta whose base is before the pack
+        let mut pack = [0x90].into_iter().chain([0xff; 12]).chain([0]).collect::<Vec<u8>>();
+        pack.extend_from_slice(&[0xbf, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f, 0x78, 0x9c]);
+        pack.extend_from_slice(&[0x60, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f]);
+        std::fs::write(directory.join("pack-a.pack"), &pack).unwrap();
+
+        let file = File::open(directory.join("pack-a.pack")).unwrap();
+        let result = (Pack::open(&directory.join("pack-a.idx")).ok(), Pack { file, ids: Vec::new(), offsets: Vec::new() });
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let (opened, pack) = result;
+        assert!(opened.is_none());
+        for offset in [0, 14, 24] {
+            assert!(pack.read_at(offset, 0).ok().is_none(), "{offset}");
+        }
+        assert!(apply_delta(b"", &[0xff; 12]).ok().is_none());
+    }
+
+    #[test]
+    fn test_commit_and_tree_parsing() {
+        let commit = b"tree ce013625030ba8dba906f756967f9e9ca394464a\nparent ce013625030ba8dba906f756967f9e9ca394464a\n\
+            author A <a@b> 1600000000 +0000\ncommitter A <a@b> 1700000000 +0100\n\nmessage\n";
+        let commit = parse_commit(commit).unwrap();
+        assert_eq!((1, 1700000000), (commit.parents.len(), commit.time));
+
+        let mut tree = b"100644 file\0".to_vec();
+        tree.extend_from_slice(&commit.tree.0);
+        let entries = parse_tree(&tree).unwrap();
+        assert_eq!((0o100644, b"file".to_vec(), commit.tree), (entries[0].mode, entries[0].name.clone(), entries[0].id));
+    }
+}
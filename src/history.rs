@@ -0,0 +1,575 @@
+//!
+//! The history of the commands entered in the shell, with when and where they were run, their exit status,
+//! their duration and the session that ran them.
+//!
+//! It is kept in an append-only file, `$HISTFILE` or `$XDG_STATE_HOME/rust_shell/history`, with one entry per line.
+//! Each shell appends its entries under a lock, so that several shells running at the same time can share the file.
+//! A line without metadata (like in a history file written by another shell) is read as a command only.
+//!
+//! What is kept follows HISTCONTROL, HISTIGNORE, HISTSIZE (in memory) and HISTFILESIZE (in the file, which is shortened
+//! when the shell starts and when it grows past its size by a tenth), and the commands that look like they contain
+//! a secret are not written to the file.
+//!
+
+pub mod expansion;
+pub mod search;
+pub mod secrets;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use crate::command::builtin::format::current_timestamp;
+use crate::expansion::glob;
+use crate::history::secrets::looks_like_secret;
+use crate::shell::Shell;
+
+/// The number of commands kept when HISTSIZE is not set
+const DEFAULT_HISTORY_SIZE: usize = 10_000;
+
+/// A command of the history
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HistoryEntry {
+    /// The command as it was entered, its lines separated by newlines
+    pub command: String,
+    /// When it started, in seconds since the epoch, 0 if it is not known
+    pub timestamp: i64,
+    /// The working directory when it started
+    pub directory: String,
+    /// The exit status, None if it is not known
+    pub status: Option<i32>,
+    pub duration: Duration,
+    /// The session of the shell that ran it
+    pub session: String,
+}
+
+/// What is kept in the history
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HistoryPolicy {
+    /// The commands starting with a space are not kept
+    pub ignore_space: bool,
+    /// A command same as the previous one is not kept
+    pub ignore_dups: bool,
+    /// The previous occurrences of a command are removed from memory when it is added
+    pub erase_dups: bool,
+    /// The patterns of the commands not kept, `&` matching the previous command
+    pub ignore_patterns: Vec<String>,
+    /// The maximum number of commands in memory, None without limit
+    pub size: Option<usize>,
+    /// The maximum number of commands in the file, None without limit
+    pub file_size: Option<usize>,
+}
+
+impl HistoryPolicy {
+
+    /// Reads the policy from HISTCONTROL (`ignorespace`, `ignoredups`, `ignoreboth`, `erasedups`, separated by `:`),
+    /// HISTIGNORE (patterns separated by `:`), HISTSIZE and HISTFILESIZE (HISTSIZE by default), a negative size being no limit
+    pub fn from_variables(shell: &Shell) -> Self {
+
+        let control: Vec<&str> = shell.get_variable("HISTCONTROL").unwrap_or_default().split(':').collect();
+        let ignore_patterns = shell.get_variable("HISTIGNORE").unwrap_or_default().split(':')
+            .filter(|pattern| !pattern.is_empty()).map(str::to_string).collect();
+
+        let parse_size = |value: &str| value.parse::<i64>().ok().map(|size| usize::try_from(size).ok());
+        let size = shell.get_variable("HISTSIZE").and_then(parse_size).unwrap_or(Some(DEFAULT_HISTORY_SIZE));
+        let file_size = shell.get_variable("HISTFILESIZE").and_then(parse_size).unwrap_or(size);
+
+        HistoryPolicy {
+            ignore_space: control.contains(&"ignorespace") || control.contains(&"ignoreboth"),
+            ignore_dups: control.contains(&"ignoredups") || control.contains(&"ignoreboth"),
+            erase_dups: control.contains(&"erasedups"),
+            ignore_patterns,
+            size,
+            file_size,
+        }
+    }
+
+    /// Returns true if the command is not kept, following the previous command
+    pub fn ignores(&self, command: &str, previous: Option<&str>) -> bool {
+
+        if self.ignore_space && command.starts_with(char::is_whitespace) {
+            return true;
+        }
+        if self.ignore_dups && previous == Some(command) {
+            return true;
+        }
+
+        // `&` is the previous command, matched literally
+        let previous_pattern: String = previous.unwrap_or_default().chars().flat_map(|c| match "*?[]\\".contains(c) {
+            true => vec!['\\', c],
+            false => vec![c],
+        }).collect();
+        self.ignore_patterns.iter().any(|pattern| glob::matches(&pattern.replace('&', &previous_pattern), command))
+    }
+}
+
+/// The entered commands, the oldest first, and the file where they are saved
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    entries: Vec<HistoryEntry>,
+    path: Option<PathBuf>,
+    /// Identifies this shell in the entries it saves
+    session: String,
+    /// Changed when entries are removed, other than the oldest ones beyond the size, so that the copies of the history
+    /// know to copy it again
+    revision: u64,
+    /// The number of entries it ever had, the ones read when it was opened included, so that its copies know which
+    /// ones are new
+    added: u64,
+    /// The number of lines of the file when it was read, plus the ones this shell appended since
+    file_lines: usize,
+}
+
+/// The history of a shell, shared with its copies (like the one the line editor completes with) and with the search
+/// of the line editor, rather than copied with the shell
+#[derive(Clone, Debug, Default)]
+pub struct SharedHistory(Arc<Mutex<History>>);
+
+impl SharedHistory {
+
+    /// Returns the history, locked until the guard is dropped
+    pub fn lock(&self) -> MutexGuard<'_, History> {
+        // A panic while it was locked doesn't leave the history invalid
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl History {
+
+    /// Reads the history from the file, which is created with its directory if it doesn't exist, and shortened
+    /// to the size of the file of the policy. Without a file, the history is kept in memory only
+    pub fn open(path: Option<PathBuf>, policy: &HistoryPolicy) -> io::Result<Self> {
+
+        let session = format!("{:x}-{}", current_timestamp(), std::process::id());
+        let mut history = History { entries: Vec::new(), path, session, revision: 0, added: 0, file_lines: 0 };
+
+        if let Some(path) = &history.path {
+            if let Some(directory) = path.parent() {
+                std::fs::create_dir_all(directory)?;
+            }
+            history.entries = load_history_file(path, policy.file_size)?;
+            history.file_lines = history.entries.len();
+        }
+        if policy.erase_dups {
+            let mut seen = std::collections::HashSet::new();
+            let mut entries = std::mem::take(&mut history.entries);
+            entries.reverse();
+            entries.retain(|entry| seen.insert(entry.command.clone()));
+            entries.reverse();
+            history.entries = entries;
+        }
+        history.truncate(policy.size);
+        history.added = history.entries.len() as u64;
+
+        Ok(history)
+    }
+
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    pub fn session(&self) -> &str {
+        &self.session
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn added(&self) -> u64 {
+        self.added
+    }
+
+    /// Adds a command run by this shell unless the policy ignores it, and appends it to the file
+    pub fn add(&mut self, mut entry: HistoryEntry, policy: &HistoryPolicy) -> io::Result<()> {
+
+        if policy.ignores(&entry.command, self.entries.last().map(|entry| entry.command.as_str())) {
+            return Ok(());
+        }
+        if policy.erase_dups && self.entries.iter().any(|previous| previous.command == entry.command) {
+            self.entries.retain(|previous| previous.command != entry.command);
+            self.revision += 1;
+        }
+
+        entry.session = self.session.clone();
+        let result = match self.path.clone() {
+            Some(path) => self.append_to_file(&path, std::slice::from_ref(&entry), policy.file_size),
+            None => Ok(()),
+        };
+        self.entries.push(entry);
+        self.added += 1;
+        self.truncate(policy.size);
+
+        result
+    }
+
+    /// Appends the entries to the file of the history, which is shortened to the size once it is a tenth longer
+    /// (the other shells appending to it too), rather than rewritten for each entry
+    fn append_to_file(&mut self, path: &Path, entries: &[HistoryEntry], size: Option<usize>) -> io::Result<()> {
+
+        self.file_lines += append_history_file(path, entries)?;
+        if let Some(size) = size.filter(|size| self.file_lines > size + size / 10) {
+            self.file_lines = load_history_file(path, Some(size))?.len();
+        }
+        Ok(())
+    }
+
+    /// Removes the oldest entries beyond the size, which the copies of the history drop by themselves
+    fn truncate(&mut self, size: Option<usize>) {
+        if let Some(size) = size.filter(|size| self.entries.len() > *size) {
+            self.entries.drain(..self.entries.len() - size);
+        }
+    }
+
+    /// Removes all the entries, the file being unchanged
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.revision += 1;
+    }
+
+    /// Removes the entry at the index, the file being unchanged
+    pub fn remove(&mut self, index: usize) -> Option<HistoryEntry> {
+        if index >= self.entries.len() {
+            return None;
+        }
+        self.revision += 1;
+        Some(self.entries.remove(index))
+    }
+
+    /// Adds the entries of the file after the current ones
+    pub fn read_file(&mut self, path: &Path) -> io::Result<()> {
+        let entries = read_history_file(path)?;
+        self.added += entries.len() as u64;
+        self.entries.extend(entries);
+        Ok(())
+    }
+
+    /// Replaces the content of the file by the entries
+    pub fn write_file(&self, path: &Path) -> io::Result<()> {
+
+        let file = open_locked(path, OpenOptions::new().create(true).write(true).truncate(false))?;
+        // The file is emptied once it is locked, so that a shell appending to it meanwhile is not lost
+        file.set_len(0)?;
+
+        (&file).write_all(format_saved_entries(&self.entries).as_bytes())
+    }
+
+    /// Appends the entries of this session to the file, unless it is the file of the history where they already are,
+    /// keeping only its last lines if there are more than the size
+    pub fn append_session(&self, path: &Path, size: Option<usize>) -> io::Result<()> {
+
+        if self.path.as_deref() == Some(path) {
+            return Ok(());
+        }
+        let entries: Vec<HistoryEntry> = self.entries.iter().filter(|entry| entry.session == self.session).cloned().collect();
+        append_history_file(path, &entries)?;
+        match size {
+            Some(size) => load_history_file(path, Some(size)).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the most recent command starting with the prefix (and longer than it), preferring the ones run
+    /// in the directory, then the ones that succeeded
+    pub fn suggest(&self, prefix: &str, directory: &str) -> Option<&str> {
+
+        let mut best: Option<(u8, &str)> = None;
+
+        for entry in self.entries.iter().rev() {
+            if entry.command.len() <= prefix.len() || !entry.command.starts_with(prefix) {
+                continue;
+            }
+            let rank = 2 * (entry.directory == directory) as u8 + (entry.status == Some(0)) as u8;
+            if best.is_none_or(|(best_rank, _)| rank > best_rank) {
+                best = Some((rank, &entry.command));
+            }
+            if rank == 3 {
+                break;
+            }
+        }
+
+        best.map(|(_, command)| command)
+    }
+}
+
+/// Returns the file of the history : HISTFILE, or `rust_shell/history` in the state directory
+/// (`$XDG_STATE_HOME`, `~/.local/state` by default). An empty HISTFILE disables the file
+pub fn history_path(shell: &Shell) -> Option<PathBuf> {
+
+    match shell.get_variable("HISTFILE") {
+        Some("") => None,
+        Some(path) => Some(PathBuf::from(path)),
+        None => {
+            let state_home = shell.get_variable("XDG_STATE_HOME").filter(|directory| directory.starts_with('/')).map(PathBuf::from)
+                .or_else(|| shell.get_variable("HOME").map(|home| Path::new(home).join(".local/state")))?;
+            Some(state_home.join("rust_shell/history"))
+        },
+    }
+}
+
+/// Locks the whole file until it is closed, shared for reading or exclusive for writing
+fn lock_file(file: &File, exclusive: bool) -> io::Result<()> {
+
+    let operation = if exclusive { libc::LOCK_EX } else { libc::LOCK_SH };
+    // SAFETY: flock only uses the file descriptor, which is open
+    match unsafe { libc::flock(file.as_raw_fd(), operation) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Opens the file and locks it for writing, opening it again if another shell replaced it while this one waited
+/// for the lock
+fn open_locked(path: &Path, options: &OpenOptions) -> io::Result<File> {
+    loop {
+        let file = options.open(path)?;
+        lock_file(&file, true)?;
+        let (opened, current) = (file.metadata()?, std::fs::metadata(path));
+        match current {
+            Ok(current) if current.dev() == opened.dev() && current.ino() == opened.ino() => return Ok(file),
+            Ok(_) => continue,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Reads the entries of the history file, and keeps only the last ones in it if there are more than the size.
+/// The shortened file is written aside then renamed, so that it is never left half written
+fn load_history_file(path: &Path, size: Option<usize>) -> io::Result<Vec<HistoryEntry>> {
+
+    // The lock is held until the file is replaced, so that the entries appended meanwhile are not lost
+    let mut file = open_locked(path, OpenOptions::new().create(true).read(true).append(true))?;
+
+    let mut content = Vec::new();
+    file.read_to_end(&mut content)?;
+    let content = String::from_utf8_lossy(&content);
+    let mut lines: Vec<&str> = content.lines().filter(|line| !line.is_empty()).collect();
+
+    if let Some(size) = size.filter(|size| lines.len() > *size) {
+        lines.drain(..lines.len() - size);
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let shortened = path.with_file_name(format!(".{file_name}.{}", std::process::id()));
+        let text: String = lines.iter().map(|line| format!("{line}\n")).collect();
+        let result = std::fs::write(&shortened, text)
+            .and_then(|()| std::fs::set_permissions(&shortened, file.metadata()?.permissions()))
+            .and_then(|()| std::fs::rename(&shortened, path));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&shortened);
+        }
+        result?;
+    }
+
+    Ok(lines.into_iter().map(parse_entry).collect())
+}
+
+/// Reads the entries of the history file, none if it doesn't exist
+fn read_history_file(path: &Path) -> io::Result<Vec<HistoryEntry>> {
+
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    lock_file(&file, false)?;
+
+    let mut content = Vec::new();
+    file.read_to_end(&mut content)?;
+
+    Ok(String::from_utf8_lossy(&content).lines().filter(|line| !line.is_empty()).map(parse_entry).collect())
+}
+
+/// Appends the entries to the history file in one write, while holding its lock. Returns the number of lines written
+fn append_history_file(path: &Path, entries: &[HistoryEntry]) -> io::Result<usize> {
+
+    let file = open_locked(path, OpenOptions::new().create(true).append(true))?;
+    let text = format_saved_entries(entries);
+
+    (&file).write_all(text.as_bytes())?;
+    Ok(text.lines().count())
+}
+
+/// Formats the lines of the entries written to a file, except the ones that look like they contain a secret
+fn format_saved_entries(entries: &[HistoryEntry]) -> String {
+    entries.iter().filter(|entry| !looks_like_secret(&entry.command)).map(|entry| format_entry(entry) + "\n").collect()
+}
+
+/// Formats the entry as a line of the file : the timestamp, the duration in milliseconds, the status, the session,
+/// the directory and the command, separated by tabs
+fn format_entry(entry: &HistoryEntry) -> String {
+
+    let status = entry.status.map(|status| status.to_string()).unwrap_or_default();
+    format!("{}\t{}\t{status}\t{}\t{}\t{}", entry.timestamp, entry.duration.as_millis(), escape_field(&entry.session),
+        escape_field(&entry.directory), escape_field(&entry.command))
+}
+
+/// Parses a line of the file, a line without all the fields being a command without metadata
+fn parse_entry(line: &str) -> HistoryEntry {
+
+    let fields: Vec<&str> = line.splitn(6, '\t').collect();
+    let [timestamp, duration, status, session, directory, command] = fields[..] else {
+        return HistoryEntry { command: line.to_string(), ..HistoryEntry::default() };
+    };
+    let Ok(timestamp) = timestamp.parse() else {
+        return HistoryEntry { command: line.to_string(), ..HistoryEntry::default() };
+    };
+
+    HistoryEntry {
+        command: unescape_field(command),
+        timestamp,
+        directory: unescape_field(directory),
+        status: status.parse().ok(),
+        duration: Duration::from_millis(duration.parse().unwrap_or_default()),
+        session: unescape_field(session),
+    }
+}
+
+/// Escapes the backslashes, the tabs and the newlines of a field
+fn escape_field(field: &str) -> String {
+    field.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+fn unescape_field(field: &str) -> String {
+
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        match (c, c == '\\') {
+            (_, true) => match chars.next() {
+                Some('t') => unescaped.push('\t'),
+                Some('n') => unescaped.push('\n'),
+                Some(other) => unescaped.push(other),
+                None => unescaped.push('\\'),
+            },
+            (c, false) => unescaped.push(c),
+        }
+    }
+
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(command: &str, directory: &str, status: i32) -> HistoryEntry {
+        HistoryEntry { command: command.to_string(), directory: directory.to_string(), status: Some(status), ..HistoryEntry::default() }
+    }
+
+    #[test]
+    fn test_suggestions() {
+        let mut history = History::default();
+        for added in [entry("make test", "/project", 0), entry("make build", "/project", 2), entry("make install", "/other", 0), entry("make", "/project", 0)] {
+            history.add(added, &HistoryPolicy::default()).unwrap();
+        }
+
+        assert_eq!(Some("make test"), history.suggest("make", "/project"));
+        assert_eq!(Some("make build"), history.suggest("make b", "/project"));
+        assert_eq!(Some("make"), history.suggest("ma", "/elsewhere"));
+        assert_eq!(Some("make install"), history.suggest("make i", "/elsewhere"));
+        assert_eq!(None, history.suggest("make test", "/project"));
+        assert_eq!(None, history.suggest("cargo", "/project"));
+    }
+
+    #[test]
+    fn test_policy() {
+        let mut shell = Shell::new();
+        shell.set_variable("HISTCONTROL", "ignoreboth:erasedups");
+        shell.set_variable("HISTIGNORE", "ls:cd *:&x");
+        shell.set_variable("HISTSIZE", "3");
+        let policy = HistoryPolicy::from_variables(&shell);
+        assert_eq!(Some(3), policy.file_size);
+
+        let mut history = History::default();
+        for command in [" secret", "ls", "cd /tmp", "make", "make", "makex", "git status", "make", "vim", "git status"] {
+            history.add(entry(command, "/", 0), &policy).unwrap();
+        }
+        let commands: Vec<&str> = history.entries().iter().map(|entry| entry.command.as_str()).collect();
+        assert_eq!(vec!["make", "vim", "git status"], commands);
+
+        // The oldest entries beyond the size are dropped without a new revision, the copies being given the new ones only
+        let (revision, added) = (history.revision(), history.added());
+        history.add(entry("cargo build", "/", 0), &policy).unwrap();
+        assert_eq!((revision, added + 1, 3), (history.revision(), history.added(), history.entries().len()));
+
+        shell.set_variable("HISTSIZE", "-1");
+        assert_eq!(None, HistoryPolicy::from_variables(&shell).size);
+    }
+
+    #[test]
+    fn test_entry_format() {
+        let full = HistoryEntry {
+            command: "for x in a\\tb; do\n\techo $x\ndone".to_string(),
+            timestamp: 1700000000,
+            directory: "/tmp/with\ttab".to_string(),
+            status: Some(1),
+            duration: Duration::from_millis(1500),
+            session: "s1".to_string(),
+        };
+        assert_eq!(full, parse_entry(&format_entry(&full)));
+        assert_eq!(entry("ls -l", "", 0).command, parse_entry("ls -l").command);
+        assert_eq!(None, parse_entry("ls -l").status);
+    }
+
+    #[test]
+    fn test_shared_file() {
+        let path = std::env::temp_dir().join(format!("rust_shell_history_{}/history", std::process::id()));
+
+        // Two shells append to the same file
+        let mut first = History::open(Some(path.clone()), &HistoryPolicy::default()).unwrap();
+        let mut second = History::open(Some(path.clone()), &HistoryPolicy::default()).unwrap();
+        first.add(entry("echo 1", "/", 0), &HistoryPolicy::default()).unwrap();
+        second.add(entry("echo 2", "/tmp", 3), &HistoryPolicy::default()).unwrap();
+        first.add(entry("echo 3", "/", 0), &HistoryPolicy::default()).unwrap();
+
+        let reopened = History::open(Some(path.clone()), &HistoryPolicy::default()).unwrap();
+        let commands: Vec<(&str, Option<i32>)> = reopened.entries().iter().map(|entry| (entry.command.as_str(), entry.status)).collect();
+        assert_eq!(vec![("echo 1", Some(0)), ("echo 2", Some(3)), ("echo 3", Some(0))], commands);
+        assert_eq!(second.session(), reopened.entries()[1].session);
+        assert_ne!(first.session(), "");
+
+        // The file is rewritten with the entries left
+        let mut rewritten = reopened.clone();
+        rewritten.remove(0);
+        rewritten.write_file(&path).unwrap();
+        second.clear();
+        second.read_file(&path).unwrap();
+        assert_eq!(vec!["echo 2", "echo 3"], second.entries().iter().map(|entry| entry.command.as_str()).collect::<Vec<_>>());
+
+        // The secrets stay in memory only, and the file keeps the last commands of its size
+        let policy = HistoryPolicy { file_size: Some(2), ..HistoryPolicy::default() };
+        second.add(entry("export API_TOKEN=abc", "/", 0), &policy).unwrap();
+        second.add(entry("echo 4", "/", 0), &policy).unwrap();
+        assert_eq!(4, second.entries().len());
+        let shortened = History::open(Some(path.clone()), &policy).unwrap();
+        assert_eq!(vec!["echo 3", "echo 4"], shortened.entries().iter().map(|entry| entry.command.as_str()).collect::<Vec<_>>());
+        assert_eq!(2, std::fs::read_to_string(&path).unwrap().lines().count());
+
+        // The file is shortened again once it is a tenth longer than its size
+        let policy = HistoryPolicy { file_size: Some(10), ..HistoryPolicy::default() };
+        let mut third = History::open(Some(path.clone()), &policy).unwrap();
+        for index in 5..14 {
+            third.add(entry(&format!("echo {index}"), "/", 0), &policy).unwrap();
+        }
+        assert_eq!(11, read_history_file(&path).unwrap().len());
+        third.add(entry("echo 14", "/", 0), &policy).unwrap();
+        let commands: Vec<String> = read_history_file(&path).unwrap().into_iter().map(|entry| entry.command).collect();
+        assert_eq!((5..15).map(|index| format!("echo {index}")).collect::<Vec<_>>(), commands);
+
+        // The session appended to another file, which keeps the last commands of its size
+        let other = path.with_file_name("other_history");
+        std::fs::write(&other, "echo 0\n").unwrap();
+        second.append_session(&other, Some(2)).unwrap();
+        assert_eq!(vec!["echo 3", "echo 4"], read_history_file(&other).unwrap().iter().map(|entry| entry.command.as_str()).collect::<Vec<_>>());
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
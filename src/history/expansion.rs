@@ -0,0 +1,391 @@
+//!
+//! The history expansion of the entered lines, done before they are parsed : `!` refers to a command of the history
+//! (`!!`, `!n`, `!-n`, `!prefix`, `!?text?`), optionally followed by a selection of its words (`:n`, `:x-y`, `$`, `*`...)
+//! and by modifiers (`:h`, `:t`, `:r`, `:e`, `:s/old/new/`), and `^old^new` replaces a text in the previous command.
+//!
+//! Like in bash, there is no expansion in single quotes, after a backslash, or when `!` is followed by a space,
+//! `=` or `(`. `$!`, `${!name}` and `[!...]` keep their meaning.
+//!
+
+use thiserror::Error;
+
+use crate::history::HistoryEntry;
+use crate::parsing::Token;
+use crate::parsing::lexer::tokenize_with_positions;
+
+/// The characters ending a `!prefix` reference, besides the spaces
+const PREFIX_END: &str = ":;&|<>()'\"";
+
+/// Returns the line with its history references replaced, or None if it has none
+pub fn expand_history(line: &str, history: &[HistoryEntry]) -> Result<Option<String>, HistoryExpansionError> {
+
+    let mut expanded = String::with_capacity(line.len());
+    let mut position = 0;
+
+    // `^old^new^` is a substitution in the previous command
+    if line.starts_with('^') {
+        let command = previous_command(history).ok_or_else(|| HistoryExpansionError::EventNotFound(line.to_string()))?;
+        let (substitution, end) = parse_substitution(line, 0);
+        expanded.push_str(&substitution.apply(command, false).ok_or_else(|| HistoryExpansionError::SubstitutionFailed(line[..end].to_string()))?);
+        position = end;
+    }
+
+    let (mut single_quoted, mut double_quoted) = (false, false);
+    while let Some(c) = line[position..].chars().next() {
+        match c {
+            '\\' if !single_quoted => {
+                let escaped_length = line[position + 1..].chars().next().map_or(0, char::len_utf8);
+                expanded.push_str(&line[position..position + 1 + escaped_length]);
+                position += 1 + escaped_length;
+                continue;
+            },
+            '\'' if !double_quoted => single_quoted = !single_quoted,
+            '"' if !single_quoted => double_quoted = !double_quoted,
+            '!' if !single_quoted && is_reference(line, position, double_quoted) => {
+                let (text, end) = expand_reference(line, position, history)?;
+                expanded.push_str(&text);
+                position = end;
+                continue;
+            },
+            _ => (),
+        }
+        expanded.push(c);
+        position += c.len_utf8();
+    }
+
+    Ok((expanded != line).then_some(expanded))
+}
+
+/// Returns true if the `!` at the position starts a history reference
+fn is_reference(line: &str, position: usize, double_quoted: bool) -> bool {
+
+    let before = &line[..position];
+    match line[position + 1..].chars().next() {
+        None => false,
+        Some(next) if next.is_whitespace() || next == '=' || next == '(' => false,
+        Some('"') if double_quoted => false,
+        _ => !before.ends_with('$') && !before.ends_with('[') && !before.ends_with("${"),
+    }
+}
+
+fn previous_command(history: &[HistoryEntry]) -> Option<&str> {
+    history.last().map(|entry| entry.command.as_str())
+}
+
+/// Expands the reference starting with `!` at the position.
+///
+/// Returns its expansion and the position following it
+fn expand_reference(line: &str, start: usize, history: &[HistoryEntry]) -> Result<(String, usize), HistoryExpansionError> {
+
+    let not_found = |end: usize| HistoryExpansionError::EventNotFound(line[start..end].to_string());
+    let rest = &line[start + 1..];
+    let digits = |text: &str| text.bytes().take_while(u8::is_ascii_digit).count();
+
+    // The event designator
+    let (command, mut position) = match rest.as_bytes()[0] {
+        b'!' => (previous_command(history).ok_or_else(|| not_found(start + 2))?, start + 2),
+        // `!$`, `!*`, `!^` and `!:n` refer to the words of the previous command
+        b'$' | b'*' | b'^' | b':' => (previous_command(history).ok_or_else(|| not_found(start + 2))?, start + 1),
+        b'-' if digits(&rest[1..]) > 0 => {
+            let end = start + 2 + digits(&rest[1..]);
+            let offset: usize = line[start + 2..end].parse().map_err(|_| not_found(end))?;
+            let index = history.len().checked_sub(offset).ok_or_else(|| not_found(end))?;
+            (history[index].command.as_str(), end)
+        },
+        b'0'..=b'9' => {
+            let end = start + 1 + digits(rest);
+            let number: usize = line[start + 1..end].parse().map_err(|_| not_found(end))?;
+            let entry = number.checked_sub(1).and_then(|index| history.get(index)).ok_or_else(|| not_found(end))?;
+            (entry.command.as_str(), end)
+        },
+        b'?' => {
+            let (text, end) = match rest[1..].find('?') {
+                Some(length) => (&rest[1..1 + length], start + length + 3),
+                None => (&rest[1..], line.len()),
+            };
+            let entry = history.iter().rev().find(|entry| entry.command.contains(text)).ok_or_else(|| not_found(end))?;
+            (entry.command.as_str(), end)
+        },
+        _ => {
+            let length = rest.find(|c: char| c.is_whitespace() || PREFIX_END.contains(c)).unwrap_or(rest.len());
+            let end = start + 1 + length;
+            let entry = history.iter().rev().find(|entry| entry.command.starts_with(&rest[..length])).ok_or_else(|| not_found(end))?;
+            (entry.command.as_str(), end)
+        },
+    };
+
+    // The word designator, whose `:` may be omitted before `$`, `*` and `^`
+    let mut text = command.to_string();
+    let designator_start = match line[position..].chars().next() {
+        Some('$' | '*' | '^') => Some(position),
+        Some(':') if line[position + 1..].starts_with(|c: char| c.is_ascii_digit() || "$*^-".contains(c)) => Some(position + 1),
+        _ => None,
+    };
+    if let Some(designator_start) = designator_start {
+        let (words, end) = select_words(command, line, designator_start)
+            .ok_or_else(|| HistoryExpansionError::BadWordSpecifier(line[start..].split_whitespace().next().unwrap_or_default().to_string()))?;
+        text = words;
+        position = end;
+    }
+
+    // The modifiers
+    while line[position..].starts_with(':') {
+        let modifier = &line[position + 1..];
+        text = match modifier.chars().next() {
+            Some('h') => text.rsplit_once('/').map_or(text.clone(), |(head, _)| head.to_string()),
+            Some('t') => text.rsplit_once('/').map_or(text.clone(), |(_, tail)| tail.to_string()),
+            Some('r') => match text.rfind('.') {
+                Some(dot) if !text[dot..].contains('/') => text[..dot].to_string(),
+                _ => text,
+            },
+            Some('e') => match text.rfind('.') {
+                Some(dot) if !text[dot..].contains('/') => text[dot..].to_string(),
+                _ => String::new(),
+            },
+            Some('s' | 'g') if modifier.starts_with('s') || modifier.starts_with("gs") => {
+                let global = modifier.starts_with('g');
+                let substitution_start = position + 1 + global as usize;
+                let (substitution, end) = parse_substitution(line, substitution_start + 1);
+                let substituted = substitution.apply(&text, global)
+                    .ok_or_else(|| HistoryExpansionError::SubstitutionFailed(line[position..end].to_string()))?;
+                position = end;
+                text = substituted;
+                continue;
+            },
+            // Another `:` is a character
+            _ => break,
+        };
+        position += 2;
+    }
+
+    Ok((text, position))
+}
+
+/// Selects the words of the command designated at the position of the line : `n`, `^` (the first argument),
+/// `$` (the last word), `x-y`, `x-` (until the word before the last), `x*` (until the last word), `*` (all the arguments).
+///
+/// Returns the words separated by spaces and the position following the designator, or None if there are no such words
+fn select_words(command: &str, line: &str, start: usize) -> Option<(String, usize)> {
+
+    let words = command_words(command);
+    let last = words.len().checked_sub(1)?;
+    let designator = &line[start..];
+
+    let number = |text: &str| -> Option<(usize, usize)> {
+        match text.chars().next()? {
+            '^' => Some((1, 1)),
+            '$' => Some((last, 1)),
+            _ => {
+                let length = text.bytes().take_while(u8::is_ascii_digit).count();
+                Some((text[..length].parse().ok()?, length))
+            },
+        }
+    };
+
+    let (first, range_last, length) = match designator.chars().next()? {
+        '*' => (1, last, 1),
+        // `-y` is `0-y`
+        '-' => {
+            let (range_last, length) = number(&designator[1..])?;
+            (0, range_last, 1 + length)
+        },
+        _ => {
+            let (first, length) = number(designator)?;
+            let after = &designator[length..];
+            match after.chars().next() {
+                Some('*') => (first, last, length + 1),
+                Some('-') => match number(&after[1..]) {
+                    Some((range_last, range_length)) => (first, range_last, length + 1 + range_length),
+                    None => (first, last.checked_sub(1)?, length + 1),
+                },
+                _ => (first, first, length),
+            }
+        },
+    };
+
+    // `*` gives nothing when there are no arguments
+    if first > range_last {
+        return (first == range_last + 1 && designator.starts_with('*')).then(|| (String::new(), start + length));
+    }
+    let selected = words.get(first..=range_last)?;
+    Some((selected.join(" "), start + length))
+}
+
+/// Splits the command into its words and operators like the parser
+fn command_words(command: &str) -> Vec<&str> {
+
+    let (tokens, error) = tokenize_with_positions(command);
+    let mut words: Vec<&str> = tokens.iter().filter(|token| token.token != Token::Newline).map(|token| &command[token.start..token.end]).collect();
+    // An unterminated word goes to the end of the command
+    if let Some((_, start)) = error {
+        words.push(command[start..].trim());
+    }
+
+    words
+}
+
+/// The replacement of a text by another
+struct Substitution {
+    old: String,
+    new: String,
+}
+
+impl Substitution {
+
+    /// Replaces the first occurrence of the old text by the new one, or all of them if `global` is true.
+    /// Returns None if there is none
+    fn apply(&self, text: &str, global: bool) -> Option<String> {
+
+        if self.old.is_empty() || !text.contains(&self.old) {
+            return None;
+        }
+        match global {
+            true => Some(text.replace(&self.old, &self.new)),
+            false => Some(text.replacen(&self.old, &self.new, 1)),
+        }
+    }
+}
+
+/// Parses `<d>old<d>new<d>` at the position, the delimiter being its first character and the last delimiter
+/// being optional at the end of the line. In the new text, `&` is the old one.
+///
+/// Returns the substitution and the position following it
+fn parse_substitution(line: &str, start: usize) -> (Substitution, usize) {
+
+    let Some(delimiter) = line[start..].chars().next() else {
+        return (Substitution { old: String::new(), new: String::new() }, start);
+    };
+
+    // Reads a part until the delimiter, a backslash escaping it. Returns the part, the position following it and whether it ended with the delimiter
+    let read_part = |from: usize| -> (String, usize, bool) {
+        let mut part = String::new();
+        let mut chars = line[from..].char_indices();
+        while let Some((offset, c)) = chars.next() {
+            match c {
+                // The escapes are kept, to be removed once the part is read
+                '\\' => {
+                    part.push('\\');
+                    if let Some((_, escaped)) = chars.next() {
+                        part.push(escaped);
+                    }
+                },
+                c if c == delimiter => return (part, from + offset + c.len_utf8(), true),
+                c => part.push(c),
+            }
+        }
+        (part, line.len(), false)
+    };
+
+    let (old, end, terminated) = read_part(start + delimiter.len_utf8());
+    let old = unescape_substitution_part(&old, delimiter, false, "");
+    if !terminated {
+        return (Substitution { old, new: String::new() }, end);
+    }
+    let (new, end, _) = read_part(end);
+    let new = unescape_substitution_part(&new, delimiter, true, &old);
+
+    (Substitution { old, new }, end)
+}
+
+/// Removes the backslashes escaping the delimiter (and `&` in the new text), and replaces `&` by the old text in the new one
+fn unescape_substitution_part(part: &str, delimiter: char, is_new: bool, old: &str) -> String {
+
+    let mut unescaped = String::with_capacity(part.len());
+    let mut chars = part.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped) if escaped == delimiter || (is_new && escaped == '&') => unescaped.push(escaped),
+                Some(escaped) => {
+                    unescaped.push('\\');
+                    unescaped.push(escaped);
+                },
+                None => unescaped.push('\\'),
+            },
+            '&' if is_new => unescaped.push_str(old),
+            c => unescaped.push(c),
+        }
+    }
+
+    unescaped
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum HistoryExpansionError {
+
+    #[error("{0}: event not found")]
+    EventNotFound(String),
+
+    #[error("{0}: bad word specifier")]
+    BadWordSpecifier(String),
+
+    #[error("{0}: substitution failed")]
+    SubstitutionFailed(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(commands: &[&str]) -> Vec<HistoryEntry> {
+        commands.iter().map(|command| HistoryEntry { command: command.to_string(), ..HistoryEntry::default() }).collect()
+    }
+
+    fn expand(line: &str) -> Result<Option<String>, HistoryExpansionError> {
+        expand_history(line, &history(&["cd /usr/lib", "tar xzf archive.tar.gz -C /tmp/out", "echo one two | wc -l"]))
+    }
+
+    #[test]
+    fn test_events() {
+        assert_eq!(Ok(Some("sudo echo one two | wc -l".to_string())), expand("sudo !!"));
+        assert_eq!(Ok(Some("cd /usr/lib".to_string())), expand("!1"));
+        assert_eq!(Ok(Some("tar xzf archive.tar.gz -C /tmp/out".to_string())), expand("!-2"));
+        assert_eq!(Ok(Some("tar xzf archive.tar.gz -C /tmp/out; ls".to_string())), expand("!ta; ls"));
+        assert_eq!(Ok(Some("cd /usr/lib && x".to_string())), expand("!?usr? && x"));
+        assert_eq!(Err(HistoryExpansionError::EventNotFound("!ssh".to_string())), expand("!ssh"));
+        assert_eq!(Err(HistoryExpansionError::EventNotFound("!9".to_string())), expand("!9"));
+    }
+
+    #[test]
+    fn test_no_expansion() {
+        for line in ["echo 'a!!b' \\!! ! x", "a != b", "echo $! ${!name} [!a]*", "echo \"hi!\"", "!(x)"] {
+            assert_eq!(Ok(None), expand(line));
+        }
+        assert_eq!(Ok(Some("echo \"-l\"".to_string())), expand("echo \"!$\""));
+    }
+
+    #[test]
+    fn test_word_designators() {
+        assert_eq!(Ok(Some("ls -l".to_string())), expand("ls !$"));
+        assert_eq!(Ok(Some("x one two | wc -l".to_string())), expand("x !*"));
+        assert_eq!(Ok(Some("one".to_string())), expand("!^"));
+        assert_eq!(Ok(Some("|".to_string())), expand("!:3"));
+        assert_eq!(Ok(Some("archive.tar.gz -C".to_string())), expand("!tar:2-3"));
+        assert_eq!(Ok(Some("xzf archive.tar.gz -C".to_string())), expand("!tar:1-"));
+        assert_eq!(Ok(Some("-C /tmp/out".to_string())), expand("!tar:3*"));
+        assert_eq!(Ok(Some("tar xzf".to_string())), expand("!tar:-1"));
+        assert_eq!(Ok(Some(String::new())), expand_history("!*", &history(&["ls"])));
+        assert!(matches!(expand("!:9"), Err(HistoryExpansionError::BadWordSpecifier(_))));
+    }
+
+    #[test]
+    fn test_modifiers() {
+        assert_eq!(Ok(Some("/usr".to_string())), expand("!cd:$:h"));
+        assert_eq!(Ok(Some("lib".to_string())), expand("!cd:$:t"));
+        assert_eq!(Ok(Some("archive.tar".to_string())), expand("!tar:2:r"));
+        assert_eq!(Ok(Some("archive".to_string())), expand("!tar:2:r:r"));
+        assert_eq!(Ok(Some(".gz".to_string())), expand("!tar:2:e"));
+        assert_eq!(Ok(Some("echo three two | wc -l".to_string())), expand("!!:s/one/three/"));
+        assert_eq!(Ok(Some("cd /lib/lib".to_string())), expand("!cd:gs/usr/lib"));
+        assert_eq!(Ok(Some("cd [/usr]/lib".to_string())), expand("!cd:s,/usr,[&],"));
+        assert_eq!(Ok(Some("x:y".to_string())), expand_history("!!:y", &history(&["x"])));
+        assert!(matches!(expand("!!:s/none/x/"), Err(HistoryExpansionError::SubstitutionFailed(_))));
+    }
+
+    #[test]
+    fn test_quick_substitution() {
+        assert_eq!(Ok(Some("echo one three | wc -l".to_string())), expand("^two^three"));
+        assert_eq!(Ok(Some("echo one  | wc -l && ls".to_string())), expand("^two^^ && ls"));
+        assert_eq!(Err(HistoryExpansionError::SubstitutionFailed("^four^five^".to_string())), expand("^four^five^"));
+    }
+}
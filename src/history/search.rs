@@ -0,0 +1,193 @@
+//!
+//! The fuzzy search in the history : the characters of the query must appear in the command in the same order,
+//! the commands where they are consecutive or start words coming first, then the most recent ones.
+//!
+//! The search ignores the case unless the query has an upper case letter, and goes through all the commands,
+//! the ones run in a directory or the ones run by a session.
+//!
+
+use std::collections::HashSet;
+
+use crate::history::HistoryEntry;
+
+/// The score of a matched character, and the bonuses when it follows the previous one or starts a word
+const MATCH_SCORE: i64 = 16;
+const CONSECUTIVE_BONUS: i64 = 12;
+const WORD_START_BONUS: i64 = 8;
+/// The penalty of each character skipped between two matched ones, up to the maximum
+const GAP_PENALTY: i64 = 1;
+const MAX_GAP_PENALTY: i64 = 8;
+
+/// The commands searched
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SearchScope {
+    #[default]
+    All,
+    Directory,
+    Session,
+}
+
+impl SearchScope {
+
+    pub const ALL: [SearchScope; 3] = [SearchScope::All, SearchScope::Directory, SearchScope::Session];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SearchScope::All => "all",
+            SearchScope::Directory => "this directory",
+            SearchScope::Session => "this session",
+        }
+    }
+
+    /// Returns the scope following this one, the last one being followed by the first
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|scope| *scope == self).unwrap_or_default();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+/// A command matching the query, with the indexes of its characters matching the ones of the query
+#[derive(Debug, PartialEq)]
+pub struct SearchMatch<'a> {
+    pub entry: &'a HistoryEntry,
+    pub score: i64,
+    pub positions: Vec<usize>,
+}
+
+/// Returns the commands of the scope matching the query, the best first, each command appearing once with its last run
+pub fn search_history<'a>(entries: &'a [HistoryEntry], query: &str, scope: SearchScope, directory: &str, session: &str) -> Vec<SearchMatch<'a>> {
+
+    let mut seen = HashSet::new();
+    let mut matches: Vec<SearchMatch> = entries.iter().rev()
+        .filter(|entry| match scope {
+            SearchScope::All => true,
+            SearchScope::Directory => entry.directory == directory,
+            SearchScope::Session => entry.session == session,
+        })
+        .filter(|entry| seen.insert(entry.command.as_str()))
+        .filter_map(|entry| {
+            let (score, positions) = fuzzy_match(query, &entry.command)?;
+            Some(SearchMatch { entry, score, positions })
+        })
+        .collect();
+
+    // The sort is stable, the most recent commands staying first among the ones of the same score
+    matches.sort_by_key(|found| std::cmp::Reverse(found.score));
+    matches
+}
+
+/// Matches the characters of the query in the text, in the same order.
+///
+/// Returns the score of the best match and the indexes of the matched characters of the text, or None if it doesn't match
+pub fn fuzzy_match(query: &str, text: &str) -> Option<(i64, Vec<usize>)> {
+
+    let case_sensitive = query.chars().any(char::is_uppercase);
+    let normalize = |c: char| if case_sensitive { c } else { c.to_lowercase().next().unwrap_or(c) };
+    let query: Vec<char> = query.chars().map(normalize).collect();
+    let text: Vec<char> = text.chars().map(normalize).collect();
+
+    let Some(&first) = query.first() else {
+        return Some((0, Vec::new()));
+    };
+
+    // Each occurrence of the first character is tried as the start of the match, the next characters being found greedily
+    let mut best: Option<(i64, Vec<usize>)> = None;
+    for start in (0..text.len()).filter(|index| text[*index] == first) {
+        let mut positions = vec![start];
+        for c in &query[1..] {
+            let previous = *positions.last().unwrap_or(&start);
+            match text[previous + 1..].iter().position(|text_char| text_char == c) {
+                Some(offset) => positions.push(previous + 1 + offset),
+                None => break,
+            }
+        }
+        if positions.len() < query.len() {
+            // The later starts have fewer characters left
+            break;
+        }
+
+        let score = match_score(&text, &positions);
+        if best.as_ref().is_none_or(|(best_score, _)| score > *best_score) {
+            best = Some((score, positions));
+        }
+    }
+
+    best
+}
+
+/// Returns the score of the characters matched at the positions of the text
+fn match_score(text: &[char], positions: &[usize]) -> i64 {
+
+    let mut score = 0;
+    for (index, &position) in positions.iter().enumerate() {
+        score += MATCH_SCORE;
+        if position == 0 || !text[position - 1].is_alphanumeric() {
+            score += WORD_START_BONUS;
+        }
+        if index > 0 {
+            let gap = (position - positions[index - 1] - 1) as i64;
+            score += match gap {
+                0 => CONSECUTIVE_BONUS,
+                gap => -(gap * GAP_PENALTY).min(MAX_GAP_PENALTY),
+            };
+        }
+    }
+
+    score
+}
+
+/// Returns the time elapsed since the timestamp, like `5m` or `3d`, or an empty string if it is not known
+pub fn format_age(timestamp: i64, now: i64) -> String {
+
+    if timestamp == 0 {
+        return String::new();
+    }
+    let seconds = (now - timestamp).max(0);
+    match seconds {
+        0..60 => format!("{seconds}s"),
+        60..3600 => format!("{}m", seconds / 60),
+        3600..86400 => format!("{}h", seconds / 3600),
+        86400..31_536_000 => format!("{}d", seconds / 86400),
+        _ => format!("{}y", seconds / 31_536_000),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(command: &str, directory: &str, session: &str) -> HistoryEntry {
+        HistoryEntry { command: command.to_string(), directory: directory.to_string(), session: session.to_string(), ..HistoryEntry::default() }
+    }
+
+    #[test]
+    fn test_fuzzy_match() {
+        assert_eq!(Some(vec![0, 4, 5]), fuzzy_match("gst", "git status").map(|(_, positions)| positions));
+        assert_eq!(None, fuzzy_match("gts", "git"));
+        assert_eq!(None, fuzzy_match("Git", "git status"));
+        assert!(fuzzy_match("GIT", "GIT_DIR=x").is_some());
+        // The consecutive characters are preferred to the first occurrence
+        assert_eq!(Some(vec![7, 8, 9]), fuzzy_match("abc", "a-b-c; abc").map(|(_, positions)| positions));
+        assert!(fuzzy_match("cat", "cat file").unwrap().0 > fuzzy_match("cat", "c a t").unwrap().0);
+    }
+
+    #[test]
+    fn test_search_scopes() {
+        let entries = [entry("make test", "/a", "s1"), entry("cargo test", "/b", "s1"), entry("make test", "/b", "s2"), entry("ls", "/a", "s2")];
+        let commands = |matches: Vec<SearchMatch>| matches.iter().map(|found| found.entry.command.clone()).collect::<Vec<_>>();
+
+        assert_eq!(vec!["ls", "make test", "cargo test"], commands(search_history(&entries, "", SearchScope::All, "/a", "s1")));
+        assert_eq!(vec!["make test", "cargo test"], commands(search_history(&entries, "test", SearchScope::All, "/a", "s1")));
+        assert_eq!(vec!["ls", "make test"], commands(search_history(&entries, "", SearchScope::Directory, "/a", "s1")));
+        assert_eq!(vec!["cargo test", "make test"], commands(search_history(&entries, "", SearchScope::Session, "/a", "s1")));
+        assert_eq!(SearchScope::All, SearchScope::Session.next());
+    }
+
+    #[test]
+    fn test_ages() {
+        assert_eq!("", format_age(0, 100));
+        assert_eq!("42s", format_age(58, 100));
+        assert_eq!("2h", format_age(1000, 1000 + 7300));
+        assert_eq!("3d", format_age(1000, 1000 + 3 * 86400 + 5));
+    }
+}
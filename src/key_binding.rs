@@ -0,0 +1,353 @@
+//!
+//! The key bindings defined with the `bind` builtin, written like in the inputrc of readline :
+//! `"\C-x\C-e": function-name`, `"\eg": "inserted text"`, or `Control-g: function-name`.
+//!
+//! A key sequence is bound to a function of the line editor, to a macro whose text is inserted, or (with `bind -x`)
+//! to a shell command. The line editor handles single keys and the sequences starting with Ctrl-X.
+//!
+
+/// The functions of the line editor which can be bound to a key
+pub const EDITOR_FUNCTIONS: [&str; 36] = [
+    "abort", "accept-line", "backward-char", "backward-delete-char", "backward-kill-line", "backward-kill-word",
+    "backward-word", "beginning-of-history", "beginning-of-line", "capitalize-word", "clear-screen", "complete",
+    "delete-char", "downcase-word", "end-of-history", "end-of-line", "forward-char", "forward-search-history",
+    "forward-word", "history-search-backward", "history-search-forward", "kill-line", "kill-whole-line", "kill-word",
+    "next-history", "previous-history", "quoted-insert", "reverse-search-history", "transpose-chars", "transpose-words",
+    "undo", "unix-line-discard", "unix-word-rubout", "upcase-word", "yank", "yank-pop",
+];
+
+/// The names of the keys in the unquoted form, like `Control-Space`, in lower case
+const KEY_NAMES: [(&str, KeyCode); 16] = [
+    ("tab", KeyCode::Tab), ("return", KeyCode::Enter), ("ret", KeyCode::Enter), ("newline", KeyCode::Enter),
+    ("lfd", KeyCode::Enter), ("escape", KeyCode::Escape), ("esc", KeyCode::Escape), ("rubout", KeyCode::Backspace),
+    ("del", KeyCode::Backspace), ("space", KeyCode::Char(' ')), ("spc", KeyCode::Char(' ')), ("up", KeyCode::Up),
+    ("down", KeyCode::Down), ("left", KeyCode::Left), ("right", KeyCode::Right), ("delete", KeyCode::Delete),
+];
+
+/// The sequences sent by the terminal for the special keys, after the escape character
+const ESCAPE_SEQUENCES: [(&str, KeyCode); 12] = [
+    ("[A", KeyCode::Up), ("[B", KeyCode::Down), ("[C", KeyCode::Right), ("[D", KeyCode::Left),
+    ("[H", KeyCode::Home), ("[F", KeyCode::End), ("[3~", KeyCode::Delete), ("OA", KeyCode::Up),
+    ("OB", KeyCode::Down), ("OC", KeyCode::Right), ("OD", KeyCode::Left), ("OH", KeyCode::Home),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyCode {
+    Char(char),
+    Tab,
+    Enter,
+    Escape,
+    Backspace,
+    Delete,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+}
+
+/// A key pressed with its modifiers, the character of a control key being in lower case
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Key {
+    pub code: KeyCode,
+    pub control: bool,
+    pub meta: bool,
+}
+
+/// What a key sequence does
+#[derive(Clone, Debug, PartialEq)]
+pub enum KeyAction {
+    /// A function of the line editor, one of EDITOR_FUNCTIONS
+    Function(String),
+    /// A text inserted in the line
+    Macro(String),
+    /// A shell command, which may change the line through READLINE_LINE and READLINE_POINT
+    Command(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyBinding {
+    pub keys: Vec<Key>,
+    pub action: KeyAction,
+}
+
+impl Key {
+
+    fn new(code: KeyCode, control: bool, meta: bool) -> Self {
+        let code = match code {
+            KeyCode::Char(c) if control => KeyCode::Char(c.to_ascii_lowercase()),
+            code => code,
+        };
+        Key { code, control, meta }
+    }
+
+    /// Returns the key sent as a character, a control character being the key of its letter with Control
+    fn from_char(c: char) -> Self {
+        match c {
+            '\t' => Key::new(KeyCode::Tab, false, false),
+            '\r' | '\n' => Key::new(KeyCode::Enter, false, false),
+            '\x1b' => Key::new(KeyCode::Escape, false, false),
+            '\x7f' => Key::new(KeyCode::Backspace, false, false),
+            '\0'..='\x1f' => Key::new(KeyCode::Char((c as u8 + b'@') as char), true, false),
+            c => Key::new(KeyCode::Char(c), false, false),
+        }
+    }
+}
+
+impl KeyBinding {
+
+    /// Returns the definition of the binding, which `bind` (or `bind -x` for a command) reads back
+    pub fn to_definition(&self) -> String {
+        let action = match &self.action {
+            KeyAction::Function(name) => name.clone(),
+            KeyAction::Macro(text) | KeyAction::Command(text) => format!("\"{}\"", escape_text(text)),
+        };
+        format!("\"{}\": {action}", format_key_sequence(&self.keys))
+    }
+}
+
+/// Parses the definition of a binding, `keyseq: function-name` or `keyseq: "macro"`, or `keyseq: shell-command`
+/// for a command, the key sequence being quoted (like `"\C-g"`) or the name of a key (like `Control-g`)
+pub fn parse_binding(definition: &str, command: bool) -> Result<KeyBinding, String> {
+
+    let definition = definition.trim_start();
+    let (keys, rest) = match definition.strip_prefix('"') {
+        Some(quoted) => {
+            let end = closing_quote(quoted).ok_or_else(|| format!("{definition}: no closing `\"' in key binding"))?;
+            (parse_quoted_sequence(&quoted[..end])?, &quoted[end + 1..])
+        },
+        None => {
+            let name = definition.split(':').next().unwrap_or_default();
+            (vec![parse_key_name(name.trim())?], &definition[name.len()..])
+        },
+    };
+    let action = rest.trim_start().strip_prefix(':').ok_or_else(|| format!("{definition}: missing `:' in key binding"))?.trim();
+
+    let action = match action.strip_prefix('"') {
+        Some(quoted) => {
+            let text = unescape_text(&quoted[..closing_quote(quoted).unwrap_or(quoted.len())]);
+            if command { KeyAction::Command(text) } else { KeyAction::Macro(text) }
+        },
+        None if command => KeyAction::Command(action.to_string()),
+        None if EDITOR_FUNCTIONS.contains(&action) => KeyAction::Function(action.to_string()),
+        None => return Err(format!("`{action}': unknown function name")),
+    };
+    if matches!(&action, KeyAction::Command(command) if command.is_empty()) {
+        return Err(format!("{definition}: missing command"));
+    }
+
+    Ok(KeyBinding { keys, action })
+}
+
+/// Parses a key sequence alone, quoted or not, like the argument of `bind -r`
+pub fn parse_key_sequence(text: &str) -> Result<Vec<Key>, String> {
+    match text.strip_prefix('"') {
+        Some(quoted) => parse_quoted_sequence(quoted.strip_suffix('"').unwrap_or(quoted)),
+        // The quotes are optional when the sequence contains a backslash, like \C-g
+        None if text.contains('\\') => parse_quoted_sequence(text),
+        None => parse_key_name(text).map(|key| vec![key]),
+    }
+}
+
+/// Returns the key sequence written like in a quoted definition
+pub fn format_key_sequence(keys: &[Key]) -> String {
+
+    let mut text = String::new();
+    for key in keys {
+        if key.meta {
+            text.push_str("\\e");
+        }
+        if key.control {
+            text.push_str("\\C-");
+        }
+        match key.code {
+            KeyCode::Char('"') => text.push_str("\\\""),
+            KeyCode::Char('\\') => text.push_str("\\\\"),
+            KeyCode::Char(c) => text.push(c),
+            KeyCode::Tab => text.push_str("\\t"),
+            KeyCode::Enter => text.push_str("\\r"),
+            KeyCode::Escape => text.push_str("\\e"),
+            KeyCode::Backspace => text.push_str("\\d"),
+            code => {
+                let sequence = ESCAPE_SEQUENCES.iter().find(|(_, sequence_code)| *sequence_code == code).map(|(sequence, _)| *sequence);
+                text.push_str(&format!("\\e{}", sequence.unwrap_or_default()));
+            },
+        }
+    }
+    text
+}
+
+/// Returns the index of the first unescaped double quote
+fn closing_quote(text: &str) -> Option<usize> {
+
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        match c {
+            '"' if !escaped => return Some(index),
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+/// Parses the keys of a sequence written between double quotes, with the escapes of readline :
+/// `\C-` for Control, `\M-` or `\e` for Meta, `\t`, `\r`, `\n`, `\d` (rubout), `\\`, `\"` and `\'`
+fn parse_quoted_sequence(text: &str) -> Result<Vec<Key>, String> {
+
+    let chars: Vec<char> = text.chars().collect();
+    let mut keys = Vec::new();
+    let (mut control, mut meta) = (false, false);
+    let mut index = 0;
+
+    while let Some(&c) = chars.get(index) {
+        index += 1;
+        let key = match c {
+            '\\' => {
+                let escaped = *chars.get(index).ok_or_else(|| format!("\"{text}\": trailing backslash in key sequence"))?;
+                index += 1;
+                match escaped {
+                    'C' | 'M' if chars.get(index) == Some(&'-') => {
+                        index += 1;
+                        if escaped == 'C' { control = true } else { meta = true }
+                        continue;
+                    },
+                    'e' => {
+                        let rest: String = chars[index..].iter().collect();
+                        match ESCAPE_SEQUENCES.iter().find(|(sequence, _)| rest.starts_with(sequence)) {
+                            Some((sequence, code)) => {
+                                index += sequence.len();
+                                Key::new(*code, control, meta)
+                            },
+                            // Escape followed by a key is the key with Meta
+                            None if index < chars.len() => {
+                                meta = true;
+                                continue;
+                            },
+                            None => Key::new(KeyCode::Escape, control, meta),
+                        }
+                    },
+                    't' => Key::new(KeyCode::Tab, control, meta),
+                    'r' | 'n' => Key::new(KeyCode::Enter, control, meta),
+                    'd' => Key::new(KeyCode::Backspace, control, meta),
+                    c => Key::new(KeyCode::Char(c), control, meta),
+                }
+            },
+            c => {
+                let key = Key::from_char(c);
+                Key::new(key.code, control || key.control, meta)
+            },
+        };
+        keys.push(key);
+        (control, meta) = (false, false);
+    }
+
+    if keys.is_empty() || control || meta {
+        return Err(format!("\"{text}\": incomplete key sequence"));
+    }
+    // The line editor reads the sequences of several keys only after Ctrl-X
+    if keys.len() > 1 && keys[0] != Key::new(KeyCode::Char('x'), true, false) {
+        return Err(format!("\"{text}\": only a key or a sequence starting with \\C-x can be bound"));
+    }
+    Ok(keys)
+}
+
+/// Parses the name of a key, like `Control-g`, `C-M-f`, `Meta-Rubout` or `Tab`
+fn parse_key_name(name: &str) -> Result<Key, String> {
+
+    let (mut control, mut meta) = (false, false);
+    let mut rest = name;
+    loop {
+        let lower = rest.to_ascii_lowercase();
+        if let Some(prefix) = ["control-", "c-"].iter().find(|prefix| lower.starts_with(*prefix) && rest.len() > prefix.len()) {
+            control = true;
+            rest = &rest[prefix.len()..];
+        } else if let Some(prefix) = ["meta-", "m-"].iter().find(|prefix| lower.starts_with(*prefix) && rest.len() > prefix.len()) {
+            meta = true;
+            rest = &rest[prefix.len()..];
+        } else {
+            break;
+        }
+    }
+
+    let mut chars = rest.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) => KeyCode::Char(c),
+        _ => KEY_NAMES.iter().find(|(key_name, _)| rest.eq_ignore_ascii_case(key_name)).map(|(_, code)| *code)
+            .ok_or_else(|| format!("`{name}': unknown key name"))?,
+    };
+    Ok(Key::new(code, control, meta))
+}
+
+/// Replaces the escapes of a quoted macro or command : `\n`, `\t`, `\\`, `\"` and `\'`
+fn unescape_text(text: &str) -> String {
+
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => unescaped.push('\n'),
+                Some('t') => unescaped.push('\t'),
+                Some(c) => unescaped.push(c),
+                None => unescaped.push('\\'),
+            },
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n").replace('\t', "\\t")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(c: char, control: bool, meta: bool) -> Key {
+        Key::new(KeyCode::Char(c), control, meta)
+    }
+
+    #[test]
+    fn test_key_sequences() {
+        assert_eq!(Ok(vec![key('g', true, false)]), parse_key_sequence("\"\\C-g\""));
+        assert_eq!(Ok(vec![key('g', true, false)]), parse_key_sequence("\\C-G"));
+        assert_eq!(Ok(vec![key('f', false, true)]), parse_key_sequence("\"\\ef\""));
+        assert_eq!(Ok(vec![key('x', true, true)]), parse_key_sequence("\"\\M-\\C-x\""));
+        assert_eq!(Ok(vec![key('x', true, false), key('e', true, false)]), parse_key_sequence("\"\\C-x\\C-e\""));
+        assert_eq!(Ok(vec![Key::new(KeyCode::Up, false, false)]), parse_key_sequence("\"\\e[A\""));
+        assert_eq!(Ok(vec![key('g', true, false)]), parse_key_sequence("Control-g"));
+        assert_eq!(Ok(vec![Key::new(KeyCode::Backspace, false, true)]), parse_key_sequence("M-Rubout"));
+        assert!(parse_key_sequence("\"ab\"").is_err());
+        assert!(parse_key_sequence("\"\\C-\"").is_err());
+        assert!(parse_key_sequence("Control-nothing").is_err());
+
+        for sequence in ["\\C-g", "\\ef", "\\e\\C-x", "\\C-x\\C-e", "\\t", "\\e[A", "\\e[H", "\\\""] {
+            assert_eq!(sequence, format_key_sequence(&parse_key_sequence(sequence).unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_bindings() {
+        let binding = parse_binding("\"\\C-a\": end-of-line", false).unwrap();
+        assert_eq!(KeyAction::Function("end-of-line".to_string()), binding.action);
+        assert_eq!("\"\\C-a\": end-of-line", binding.to_definition());
+
+        let binding = parse_binding("\"\\eg\": \"git \\\"log\\\"\"", false).unwrap();
+        assert_eq!(KeyAction::Macro("git \"log\"".to_string()), binding.action);
+        assert_eq!(Ok(binding.clone()), parse_binding(&binding.to_definition(), false));
+
+        let binding = parse_binding("Control-g: git status", true).unwrap();
+        assert_eq!(vec![key('g', true, false)], binding.keys);
+        assert_eq!(KeyAction::Command("git status".to_string()), binding.action);
+        assert_eq!(Ok(binding.clone()), parse_binding(&binding.to_definition(), true));
+
+        assert_eq!(Err("`nothing': unknown function name".to_string()), parse_binding("\"\\C-a\": nothing", false));
+        assert!(parse_binding("\"\\C-a\" end-of-line", false).is_err());
+        assert!(parse_binding("\"\\C-a: end-of-line", false).is_err());
+        assert!(parse_binding("\"\\C-g\":", true).is_err());
+    }
+}
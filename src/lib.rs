@@ -1,6 +1,13 @@
-
 pub mod cli;
 pub mod parsing;
 pub mod command;
+pub mod shell;
+pub mod expansion;
+pub mod arithmetic;
+pub mod git;
+pub mod completion;
+pub mod history;
+pub mod key_binding;
+pub mod config;
 
-pub use cli::run_cli;
\ No newline at end of file
+pub use cli::run_cli;
@@ -1,10 +1,18 @@
+use std::collections::HashMap;
+use std::fmt;
 
 use crate::command::{Command};
 
 // Use the RedirectionType enum for both the tokens (in the lexing) and the AST (in the Command enum)
 use crate::command::RedirectionType;
 
-#[derive(Clone)]
+pub mod alias;
+mod compound;
+pub mod lexer;
+
+use lexer::tokenize_input;
+
+#[derive(Clone, PartialEq, Debug)]
 pub enum Token {
     Word(String),
     RedirectOp(RedirectionType),
@@ -12,121 +20,327 @@ pub enum Token {
     Separator,
     And,
     Or,
+    Newline,
+    Arithmetic(String), // (( expression ))
+    LeftParen,
+    RightParen,
+    CaseBreak,       // ;;
+    CaseFallThrough, // ;&
+    CaseContinue,    // ;;&
+}
+
+impl fmt::Display for Token {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{word}"),
+            Token::RedirectOp(RedirectionType::In) => write!(f, "<"),
+            Token::RedirectOp(RedirectionType::Out) => write!(f, ">"),
+            Token::RedirectOp(RedirectionType::Append) => write!(f, ">>"),
+            Token::RedirectOp(RedirectionType::Err) => write!(f, "2>"),
+            Token::Pipe => write!(f, "|"),
+            Token::Separator => write!(f, ";"),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
+            Token::Newline => write!(f, "newline"),
+            Token::Arithmetic(expression) => write!(f, "(({expression}))"),
+            Token::LeftParen => write!(f, "("),
+            Token::RightParen => write!(f, ")"),
+            Token::CaseBreak => write!(f, ";;"),
+            Token::CaseFallThrough => write!(f, ";&"),
+            Token::CaseContinue => write!(f, ";;&"),
+        }
+    }
 }
 
 /// Converts a string representing a command into a Command structure
 /// For example "ls /home" gives SimpleCommand("ls", ["/home"])
 pub fn convert_to_command(input: &str) -> Result<Command, Box<dyn std::error::Error>>  {
-    
-    // Turns the input in a vec of Strings 
-    let input_tokens = tokenize_input(input);
+
+    // Turns the input in a vec of tokens
+    let input_tokens = tokenize_input(input)?;
     // Turns the tokens into a command structure
     let command = parse(&input_tokens)?;
 
     Ok(command)
 }
 
-/// Converts an input string into a vec of tokens
-fn tokenize_input(input: &str) -> Vec<Token> { 
+/// Converts a string representing a command into a Command structure, after replacing the aliases it uses
+pub fn convert_to_command_with_aliases(input: &str, aliases: &HashMap<String, String>) -> Result<Command, Box<dyn std::error::Error>>  {
 
-    let mut tokens: Vec<Token> = Vec::new();
+    let input_tokens = alias::expand_aliases(tokenize_input(input)?, aliases)?;
+    let command = parse(&input_tokens)?;
 
-    for word in input.split_whitespace() {
-        tokens.push(match word {   
-            "<" => Token::RedirectOp(RedirectionType::In), 
-            ">" => Token::RedirectOp(RedirectionType::Out),
-            ">>" => Token::RedirectOp(RedirectionType::Append),
-            "2>" => Token::RedirectOp(RedirectionType::Err),
-            "|" => Token::Pipe,
-            ";" =>Token::Separator,
-            "||" => Token::Or,
-            "&&" => Token::And,
-            _ => Token::Word(word.to_string())
-        }); 
-    }
+    Ok(command)
+}
 
-    tokens
+/// Returns true if the input contains no command, only blanks, newlines or comments
+pub fn is_blank_input(input: &str) -> bool {
+    tokenize_input(input).is_ok_and(|tokens| tokens.iter().all(|token| *token == Token::Newline))
 }
 
+/// Returns true if the error comes from an input that ended in the middle of a command,
+/// which could be completed by the next lines
+pub fn is_incomplete_input(err: &(dyn std::error::Error + 'static)) -> bool {
+    matches!(err.downcast_ref::<ParsingError>(), Some(ParsingError::IncompleteInput(_)))
+}
+
+/// Words that start or end a compound command, and that can't be used as a command name
+const RESERVED_WORDS: [&str; 9] = ["do", "done", "then", "elif", "else", "fi", "esac", "}", "]]"];
+
 fn parse(tokens: &[Token]) -> Result<Command, ParsingError> {
 
-    let mut visited_tokens: Vec<Token> = Vec::new();
+    let mut parser = Parser { tokens, position: 0 };
+    let command = parser.parse_list(&[])?;
+
+    // A list stops at the first token it can't use, which is an error at the top level
+    if let Some(token) = parser.peek() {
+        return Err(ParsingError::UnexpectedToken(format!("`{token}`")));
+    }
+
+    Ok(command)
+}
+
+/// Recursive descent parser building the command from the tokens, by order of priority :
+/// - lists, separated by `;` or newlines
+/// - `&&` and `||` operators, which have the same priority
+/// - pipes
+/// - simple and compound commands, with their redirections
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl Parser<'_> {
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    /// Returns true if the next token is the word `keyword`
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word == keyword)
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek() == Some(&Token::Newline) {
+            self.position += 1;
+        }
+    }
+
+    /// Consumes the word `keyword` or returns an error if the next token is something else
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), ParsingError> {
+        match self.peek() {
+            Some(Token::Word(word)) if word == keyword => {
+                self.position += 1;
+                Ok(())
+            },
+            Some(token) => Err(ParsingError::UnexpectedToken(format!("`{token}`, expected `{keyword}`"))),
+            None => Err(ParsingError::IncompleteInput(format!("expected `{keyword}`"))),
+        }
+    }
+
+    /// Parses commands separated by `;` or newlines until the end of the tokens or one of the `terminators` keywords
+    fn parse_list(&mut self, terminators: &[&str]) -> Result<Command, ParsingError> {
+
+        self.skip_newlines();
+        let left = self.parse_and_or()?;
+
+        if !matches!(self.peek(), Some(Token::Separator | Token::Newline)) {
+            return Ok(left);
+        }
+        self.position += 1;
+        self.skip_newlines();
+
+        // The separator may end the list, for example "while true; do ls; done"
+        let list_ended = match self.peek() {
+            None => true,
+            Some(Token::Word(word)) => terminators.contains(&word.as_str()),
+            Some(Token::CaseBreak | Token::CaseFallThrough | Token::CaseContinue | Token::RightParen) => true,
+            Some(_) => false,
+        };
+        if list_ended {
+            return Ok(left);
+        }
+
+        create_separator_command(left, self.parse_list(terminators)?)
+    }
+
+    /// Parses pipelines separated by `&&` or `||`, from left to right
+    fn parse_and_or(&mut self) -> Result<Command, ParsingError> {
+
+        let mut command = self.parse_pipeline()?;
 
-    for (i, token) in tokens.iter().enumerate() {
+        loop {
+            let operator: fn(Box<Command>, Box<Command>) -> Command = match self.peek() {
+                Some(Token::Or) => |l, r| Command::LogicalOr { left: l, right: r },
+                Some(Token::And) => |l, r| Command::LogicalAnd { left: l, right: r },
+                _ => return Ok(command),
+            };
+            self.position += 1;
+            self.skip_newlines();
 
-        let right_tokens = &tokens[i+1..];
+            command = create_logical_command(command, self.parse_pipeline()?, operator)?;
+        }
+    }
+
+    fn parse_pipeline(&mut self) -> Result<Command, ParsingError> {
+
+        let left = self.parse_command()?;
+
+        if self.peek() != Some(&Token::Pipe) {
+            return Ok(left);
+        }
+        self.position += 1;
+        self.skip_newlines();
+
+        create_pipe_command(left, self.parse_pipeline()?)
+    }
+
+    /// Parses a simple command or a compound one (loops, conditions...) followed by its redirections
+    fn parse_command(&mut self) -> Result<Command, ParsingError> {
+
+        let command = match self.peek() {
+            Some(Token::Word(word)) => match word.as_str() {
+                "while" => self.parse_while(false)?,
+                "until" => self.parse_while(true)?,
+                "for" => self.parse_for()?,
+                "if" => self.parse_if()?,
+                "case" => self.parse_case()?,
+                "{" => self.parse_group()?,
+                "[[" => self.parse_conditional()?,
+                // The function definition handles its redirections itself
+                "function" => return self.parse_function(true),
+                reserved if RESERVED_WORDS.contains(&reserved) => {
+                    return Err(ParsingError::UnexpectedToken(format!("`{reserved}`")));
+                },
+                _ if self.tokens.get(self.position + 1) == Some(&Token::LeftParen) => return self.parse_function(false),
+                _ => return self.parse_simple_command(),
+            },
+            Some(Token::LeftParen) => self.parse_subshell()?,
+            Some(Token::Arithmetic(expression)) => {
+                let expression = expression.clone();
+                self.position += 1;
+                Command::Arithmetic { expression }
+            },
+            None if self.position > 0 => {
+                return Err(ParsingError::IncompleteInput("expected a command".to_string()));
+            },
+            _ => return self.parse_simple_command(),
+        };
 
-        match token {
-            // If no special operator, simply put the word in the tokens group, and treat them later
-            Token::Word(_) => visited_tokens.push(token.clone()), 
+        let redirections = self.parse_redirections()?;
 
-            Token::RedirectOp(operator) => return create_redirection_command(operator,&visited_tokens, right_tokens),
-            Token::Pipe => return create_pipe_command(&visited_tokens, right_tokens),
-            Token::Separator => return create_separator_command(&visited_tokens, right_tokens),
-            Token::Or => return create_logical_command(&visited_tokens, right_tokens, |l, r| Command::LogicalOr { left: l, right: r }),
-            Token::And => return create_logical_command(&visited_tokens, right_tokens,  |l, r| Command::LogicalAnd { left: l, right: r }),
-        }   
+        Ok(create_redirection_commands(command, redirections))
     }
 
-    // If there is no more tokens to process, it's a simple command
-    create_simple_command(&visited_tokens)
+    /// Parses the redirections following a compound command
+    fn parse_redirections(&mut self) -> Result<Vec<(RedirectionType, String)>, ParsingError> {
+
+        let mut redirections = Vec::new();
+        while let Some(Token::RedirectOp(kind)) = self.peek() {
+            let kind = kind.clone();
+            self.position += 1;
+            redirections.push((kind, self.parse_redirection_file()?));
+        }
+
+        Ok(redirections)
+    }
+
+    /// Parses the assignments, the words and the redirections of a simple command
+    fn parse_simple_command(&mut self) -> Result<Command, ParsingError> {
+
+        let mut assignments = Vec::new();
+        let mut words: Vec<String> = Vec::new();
+        let mut redirections = Vec::new();
+
+        loop {
+            match self.peek() {
+                Some(Token::Word(word)) => {
+                    // The assignments are the words like `name=value` before the command name
+                    match split_assignment(word).filter(|_| words.is_empty()) {
+                        Some(assignment) => assignments.push(assignment),
+                        None => words.push(word.clone()),
+                    }
+                    self.position += 1;
+                },
+                Some(Token::RedirectOp(kind)) => {
+                    let kind = kind.clone();
+                    self.position += 1;
+                    redirections.push((kind, self.parse_redirection_file()?));
+                },
+                _ => break,
+            }
+        }
+
+        let command = match (assignments.is_empty(), words.is_empty()) {
+            (false, true) => Command::Assignment { assignments, command: None },
+            (false, false) => Command::Assignment { assignments, command: Some(Box::new(create_simple_command(words)?)) },
+            (true, _) => create_simple_command(words)?,
+        };
+        Ok(create_redirection_commands(command, redirections))
+    }
+
+    fn parse_redirection_file(&mut self) -> Result<String, ParsingError> {
+
+        let Token::Word(file_path) = self.peek().ok_or(ParsingError::MissingToken("expected a file path".to_string()))? else {
+            return Err(ParsingError::UnexpectedToken("file path should be a word".to_string()));
+        };
+        let file_path = file_path.clone();
+        self.position += 1;
+
+        Ok(file_path)
+    }
 }
 
-// Creates (if the tokens are well formed) a simple command
-fn create_simple_command(tokens: &[Token]) -> Result<Command, ParsingError> {
+// Creates (if the words are well formed) a simple command
+fn create_simple_command(mut words: Vec<String>) -> Result<Command, ParsingError> {
 
-    let Token::Word(cmd_path) = tokens.first().ok_or(ParsingError::MissingToken("expected a command path".to_string()))? else {
-        return Err(ParsingError::UnexpectedToken("command path should be a word".to_string()));
-    };
+    if words.is_empty() {
+        return Err(ParsingError::MissingToken("expected a command path".to_string()));
+    }
+    let cmd_path = words.remove(0);
+
+    Ok(Command::Simple { cmd_path, cmd_args: words })
+}
 
-    let cmd_args: Vec<String> = tokens[1..].iter().map(|token| 
-        match token {
-            Token::Word(arg) => Ok(arg.clone()),// clone because the Command structure needs to own the argument
-            _ => Err(ParsingError::UnexpectedToken("command argument should be a word".to_string())),
-        })
-        .collect::<Result<_, _>>()?;
+/// Splits a word like `name=value` into the variable name and the (unexpanded) value
+fn split_assignment(word: &str) -> Option<(String, String)> {
 
-    Ok(Command::Simple { cmd_path: cmd_path.clone(), cmd_args })
+    let (name, value) = word.split_once('=')?;
+    crate::shell::is_valid_variable_name(name).then(|| (name.to_string(), value.to_string()))
 }
 
-fn create_pipe_command(left_tokens: &[Token], right_tokens: &[Token]) -> Result<Command, ParsingError> {
+fn create_pipe_command(left: Command, right: Command) -> Result<Command, ParsingError> {
 
     Ok(Command::Pipe {
-        // Recursively parse the left and right tokens
-        left: Box::new(parse(left_tokens)?),  
-        right: Box::new(parse(right_tokens)?),
+        left: Box::new(left),
+        right: Box::new(right),
     })
 
 }
-fn create_separator_command(left_tokens: &[Token], right_tokens: &[Token]) -> Result<Command, ParsingError> {
+fn create_separator_command(left: Command, right: Command) -> Result<Command, ParsingError> {
 
    Ok(Command::Separator {
-        left: Box::new(parse(left_tokens)?),  
-        right: Box::new(parse(right_tokens)?),
+        left: Box::new(left),
+        right: Box::new(right),
     })
 }
 
+/// Wraps the command in its redirections, the first one being the outermost so that it is applied first
+fn create_redirection_commands(command: Command, redirections: Vec<(RedirectionType, String)>) -> Command {
 
-fn create_redirection_command(op: &RedirectionType, left_tokens: &[Token], right_tokens: &[Token]) -> Result<Command, ParsingError> {
-
-    let Token::Word(file_path) = right_tokens.first().ok_or(ParsingError::MissingToken("expected a file path".to_string()))? else {
-        return Err(ParsingError::UnexpectedToken("file path should be a word".to_string()));
-    };
-
-    Ok(Command::Redirection { 
-        kind: op.clone(), 
-        // TODO handle commands on the right of the redirection, for example ls > out.txt | wc. because now we simply ignore the right_tokens
-        command: Box::new(parse(left_tokens)?), 
-        file: file_path.to_string()
+    redirections.into_iter().rev().fold(command, |command, (kind, file)| Command::Redirection {
+        kind,
+        command: Box::new(command),
+        file
     })
-
 }
 
-// TODO manage priority with logical and
-fn create_logical_command(left_tokens: &[Token], right_tokens: &[Token], op: impl Fn(Box<Command>, Box<Command>) -> Command) -> Result<Command, ParsingError> {
+fn create_logical_command(left: Command, right: Command, op: impl Fn(Box<Command>, Box<Command>) -> Command) -> Result<Command, ParsingError> {
     Ok(op(
-        Box::new(parse(left_tokens)?),
-        Box::new(parse(right_tokens)?),
+        Box::new(left),
+        Box::new(right),
     ))
 }
 
@@ -138,11 +352,17 @@ pub enum ParsingError {
 
     #[error("Unexpected token: {0}")]
     UnexpectedToken(String),
+
+    #[error("Unexpected end of input: {0}")]
+    IncompleteInput(String),
 }
 
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::command::{CaseItem, CaseTerminator, ConditionalExpression};
+    use std::rc::Rc;
 
     // Tests that a string input returns the correct Command structure form
     #[test]
@@ -303,27 +523,248 @@ mod tests {
         let input = "ls / | cat ; echo hello".to_string();
         let result = convert_to_command(&input).unwrap();
 
-        let expected = Command::Pipe { 
-            left: Box::new(Command::Simple { 
-                cmd_path: "ls".to_string(), 
-                cmd_args: vec!["/".to_string()] 
-            }), 
-            right: Box::new(Command::Separator { 
+        // the separator has a lower priority than the pipe
+        let expected = Command::Separator { 
+            left: Box::new(Command::Pipe { 
                 left: Box::new(Command::Simple { 
-                    cmd_path: "cat".to_string(), 
-                    cmd_args: vec![] 
+                    cmd_path: "ls".to_string(), 
+                    cmd_args: vec!["/".to_string()] 
                 }), 
                 right: Box::new(Command::Simple { 
-                    cmd_path: "echo".to_string(), 
-                    cmd_args: vec!["hello".to_string()] 
+                    cmd_path: "cat".to_string(), 
+                    cmd_args: vec![] 
                 }), 
-            })
+            }),
+            right: Box::new(Command::Simple { 
+                cmd_path: "echo".to_string(), 
+                cmd_args: vec!["hello".to_string()] 
+            }), 
+        };
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_logical_operators_are_left_associative() {
+        let result = convert_to_command("false || true && ls").unwrap();
+
+        let expected = Command::LogicalAnd {
+            left: Box::new(Command::LogicalOr {
+                left: Box::new(Command::Simple { cmd_path: "false".to_string(), cmd_args: vec![] }),
+                right: Box::new(Command::Simple { cmd_path: "true".to_string(), cmd_args: vec![] }),
+            }),
+            right: Box::new(Command::Simple { cmd_path: "ls".to_string(), cmd_args: vec![] }),
+        };
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_redirection_before_pipe() {
+        let result = convert_to_command("cat < input.txt | head").unwrap();
+
+        let expected = Command::Pipe {
+            left: Box::new(Command::Redirection {
+                kind: RedirectionType::In,
+                command: Box::new(Command::Simple { cmd_path: "cat".to_string(), cmd_args: vec![] }),
+                file: "input.txt".to_string(),
+            }),
+            right: Box::new(Command::Simple { cmd_path: "head".to_string(), cmd_args: vec![] }),
+        };
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_while_loop_with_redirection() {
+        let result = convert_to_command("while read line; do echo $line; done < input.txt").unwrap();
+
+        let expected = Command::Redirection {
+            kind: RedirectionType::In,
+            command: Box::new(Command::While {
+                condition: Box::new(Command::Simple { cmd_path: "read".to_string(), cmd_args: vec!["line".to_string()] }),
+                body: Box::new(Command::Simple { cmd_path: "echo".to_string(), cmd_args: vec!["$line".to_string()] }),
+            }),
+            file: "input.txt".to_string(),
+        };
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_for_loops() {
+        let result = convert_to_command("for f in a b\\ c\ndo\n  ls $f\ndone").unwrap();
+        let expected = Command::For {
+            variable: "f".to_string(),
+            words: Some(vec!["a".to_string(), "b\\ c".to_string()]),
+            body: Box::new(Command::Simple { cmd_path: "ls".to_string(), cmd_args: vec!["$f".to_string()] }),
+        };
+        assert_eq!(expected, result);
+
+        // without "in", the loop iterates over "$@"
+        let result = convert_to_command("for arg; do ls $arg; done").unwrap();
+        assert!(matches!(result, Command::For { words: None, .. }));
+
+        let result = convert_to_command("for ((i = 0; i < 3; i++)); do break 1; done").unwrap();
+        let expected = Command::ArithmeticFor {
+            init: "i = 0".to_string(),
+            condition: "i < 3".to_string(),
+            step: "i++".to_string(),
+            body: Box::new(Command::Simple { cmd_path: "break".to_string(), cmd_args: vec!["1".to_string()] }),
         };
         assert_eq!(expected, result);
     }
 
+    #[test]
+    fn test_arithmetic_command() {
+        let result = convert_to_command("(( x > 1 )) && echo $((x + 1))").unwrap();
+        let expected = Command::LogicalAnd {
+            left: Box::new(Command::Arithmetic { expression: "x > 1".to_string() }),
+            right: Box::new(Command::Simple { cmd_path: "echo".to_string(), cmd_args: vec!["$((x + 1))".to_string()] }),
+        };
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_conditional_expression() {
+        let result = convert_to_command("[[ ! -f $file && ( $a < b || $s =~ ^(x|y)+$ ) ]]").unwrap();
+
+        let expected = Command::Conditional {
+            expression: ConditionalExpression::And(
+                Box::new(ConditionalExpression::Not(Box::new(ConditionalExpression::Unary {
+                    operator: "-f".to_string(),
+                    operand: "$file".to_string(),
+                }))),
+                Box::new(ConditionalExpression::Or(
+                    Box::new(ConditionalExpression::Binary { operator: "<".to_string(), left: "$a".to_string(), right: "b".to_string() }),
+                    Box::new(ConditionalExpression::Binary { operator: "=~".to_string(), left: "$s".to_string(), right: "^(x|y)+$".to_string() }),
+                )),
+            ),
+        };
+        assert_eq!(expected, result);
+
+        let error = parse(&tokenize_input("[[ -n x").unwrap()).unwrap_err();
+        assert!(matches!(error, ParsingError::IncompleteInput(_)));
+    }
+
+    #[test]
+    fn test_if_elif_else() {
+        let result = convert_to_command("if a; then b; elif c; then d; else e; fi").unwrap();
+
+        let simple = |name: &str| Box::new(Command::Simple { cmd_path: name.to_string(), cmd_args: vec![] });
+        let expected = Command::If {
+            condition: simple("a"),
+            then_branch: simple("b"),
+            else_branch: Some(Box::new(Command::If {
+                condition: simple("c"),
+                then_branch: simple("d"),
+                else_branch: Some(simple("e")),
+            })),
+        };
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_case_command() {
+        let result = convert_to_command("case $1 in\n  start|stop) echo $1;;\n  (*.txt) ;&\n  *) echo other\nesac").unwrap();
+
+        let expected = Command::Case {
+            word: "$1".to_string(),
+            items: vec![
+                CaseItem {
+                    patterns: vec!["start".to_string(), "stop".to_string()],
+                    body: Some(Command::Simple { cmd_path: "echo".to_string(), cmd_args: vec!["$1".to_string()] }),
+                    terminator: CaseTerminator::Break,
+                },
+                CaseItem { patterns: vec!["*.txt".to_string()], body: None, terminator: CaseTerminator::FallThrough },
+                CaseItem {
+                    patterns: vec!["*".to_string()],
+                    body: Some(Command::Simple { cmd_path: "echo".to_string(), cmd_args: vec!["other".to_string()] }),
+                    terminator: CaseTerminator::Break,
+                },
+            ],
+        };
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn test_function_definitions() {
+        let body = Command::Simple { cmd_path: "echo".to_string(), cmd_args: vec!["$1".to_string()] };
+
+        let result = convert_to_command("greet() { echo $1; }").unwrap();
+        let expected = Command::FunctionDefinition { name: "greet".to_string(), body: Rc::new(Command::Group { body: Box::new(body) }) };
+        assert_eq!(expected, result);
+
+        // The redirections are part of the body
+        let result = convert_to_command("function greet\n{\n  echo $1\n} > out").unwrap();
+        let Command::FunctionDefinition { body, .. } = result else { panic!("expected a function definition") };
+        assert!(matches!(body.as_ref(), Command::Redirection { kind: RedirectionType::Out, .. }));
+
+        let error = parse(&tokenize_input("greet() { echo $1").unwrap()).unwrap_err();
+        assert!(matches!(error, ParsingError::IncompleteInput(_)));
+    }
+
+    #[test]
+    fn test_subshell_and_group() {
+        let echo = |arg: &str| Command::Simple { cmd_path: "echo".to_string(), cmd_args: vec![arg.to_string()] };
+
+        let result = convert_to_command("(echo a; echo b) | { echo c; }").unwrap();
+        let expected = Command::Pipe {
+            left: Box::new(Command::Subshell {
+                body: Box::new(Command::Separator { left: Box::new(echo("a")), right: Box::new(echo("b")) }),
+            }),
+            right: Box::new(Command::Group { body: Box::new(echo("c")) }),
+        };
+        assert_eq!(expected, result);
+
+        // The closing brace must be a separate command word
+        let error = parse(&tokenize_input("{ echo c }").unwrap()).unwrap_err();
+        assert!(matches!(error, ParsingError::IncompleteInput(_)));
+
+        let error = parse(&tokenize_input("(echo a").unwrap()).unwrap_err();
+        assert!(matches!(error, ParsingError::IncompleteInput(_)));
+    }
+
+    #[test]
+    fn test_assignments() {
+        let result = convert_to_command("a=1 b=\"x y\"; LANG=C sort c=d > out").unwrap();
+        let expected = Command::Separator {
+            left: Box::new(Command::Assignment {
+                assignments: vec![("a".to_string(), "1".to_string()), ("b".to_string(), "\"x y\"".to_string())],
+                command: None,
+            }),
+            right: Box::new(Command::Redirection {
+                kind: RedirectionType::Out,
+                command: Box::new(Command::Assignment {
+                    assignments: vec![("LANG".to_string(), "C".to_string())],
+                    command: Some(Box::new(Command::Simple { cmd_path: "sort".to_string(), cmd_args: vec!["c=d".to_string()] })),
+                }),
+                file: "out".to_string(),
+            }),
+        };
+        assert_eq!(expected, result);
+
+        // A word that is not a valid name is a command
+        let result = convert_to_command("1a=2").unwrap();
+        assert_eq!(Command::Simple { cmd_path: "1a=2".to_string(), cmd_args: vec![] }, result);
+    }
+
+    #[test]
+    fn test_blank_and_incomplete_inputs() {
+        assert!(is_blank_input("  # comment\n\n"));
+        assert!(!is_blank_input("# comment\nls"));
+
+        for input in ["echo 'a", "ls |", "if true; then", "f() {"] {
+            assert!(is_incomplete_input(convert_to_command(input).unwrap_err().as_ref()), "{input}");
+        }
+        assert!(!is_incomplete_input(convert_to_command("ls )").unwrap_err().as_ref()));
+    }
+
+    #[test]
+    fn test_unterminated_loop_is_incomplete() {
+        let error = parse(&tokenize_input("until false; do ls").unwrap()).unwrap_err();
+        assert!(matches!(error, ParsingError::IncompleteInput(_)));
+
+        let error = parse(&tokenize_input("ls; done").unwrap()).unwrap_err();
+        assert!(matches!(error, ParsingError::UnexpectedToken(_)));
+    }
+
     // TODO test cases that should raise an error
-    // TODO when implemented, test redirection before a pipe : cat < input.txt | head
-    // TODO when implemented, test sticked pipe or redirection : echo hello|cat or echo hello>test.txt
 
 }
\ No newline at end of file
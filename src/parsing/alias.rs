@@ -0,0 +1,125 @@
+//!
+//! Replaces the aliases in the tokens of the input, before they are parsed.
+//!
+//! An alias is only replaced in command position. If its value ends with a blank, the next word is
+//! also checked for an alias, and an alias is never replaced again inside its own value.
+//!
+
+use std::collections::HashMap;
+
+use crate::parsing::lexer::tokenize_input;
+use crate::parsing::{ParsingError, Token, split_assignment};
+
+/// The reserved words after which a command is expected
+const COMMAND_PREFIX_WORDS: [&str; 9] = ["if", "then", "else", "elif", "while", "until", "do", "{", "!"];
+
+/// Returns the tokens with the aliases replaced by the tokens of their value
+pub fn expand_aliases(tokens: Vec<Token>, aliases: &HashMap<String, String>) -> Result<Vec<Token>, ParsingError> {
+
+    if aliases.is_empty() {
+        return Ok(tokens);
+    }
+
+    let mut expander = AliasExpander { aliases, expanding: Vec::new(), output: Vec::new() };
+    expander.expand(tokens, true)?;
+
+    Ok(expander.output)
+}
+
+/// Returns true if the name can be given to an alias : not empty and without special or quoting characters
+pub fn is_valid_alias_name(name: &str) -> bool {
+    !name.is_empty() && !name.chars().any(|c| c.is_whitespace() || "=/$`'\"\\|&;<>()".contains(c))
+}
+
+/// Where the next word of a command line is, following its tokens : in command position (a command name is expected),
+/// and/or right after a redirection operator (a file is expected, which doesn't change the command position)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WordPosition {
+    pub command: bool,
+    pub redirection_target: bool,
+}
+
+impl WordPosition {
+
+    /// The position at the start of a command line
+    pub fn start() -> Self {
+        WordPosition { command: true, redirection_target: false }
+    }
+
+    /// Moves the position after the token
+    pub fn advance(&mut self, token: &Token) {
+        match token {
+            _ if self.redirection_target => self.redirection_target = false,
+            Token::Word(word) => {
+                self.command = self.command && (COMMAND_PREFIX_WORDS.contains(&word.as_str()) || split_assignment(word).is_some());
+            },
+            Token::RedirectOp(_) => self.redirection_target = true,
+            Token::Arithmetic(_) => self.command = false,
+            _ => self.command = true,
+        }
+    }
+}
+
+struct AliasExpander<'a> {
+    aliases: &'a HashMap<String, String>,
+    /// The aliases whose value is being expanded, which are not replaced again
+    expanding: Vec<&'a str>,
+    output: Vec<Token>,
+}
+
+impl<'a> AliasExpander<'a> {
+
+    /// Expands the aliases of the tokens into the output, starting in command position or not.
+    ///
+    /// Returns true if the token following them is in command position
+    fn expand(&mut self, tokens: Vec<Token>, command_position: bool) -> Result<bool, ParsingError> {
+
+        let mut position = WordPosition { command: command_position, redirection_target: false };
+
+        for token in tokens {
+            if let Token::Word(word) = &token && position.command && !position.redirection_target
+                && let Some((name, value)) = self.aliases.get_key_value(word.as_str())
+                && !self.expanding.contains(&name.as_str())
+            {
+                self.expanding.push(name);
+                let value_command_position = self.expand(tokenize_input(value)?, true)?;
+                self.expanding.pop();
+
+                // A trailing blank makes the next word checked for an alias too
+                position.command = value_command_position || value.ends_with([' ', '\t']);
+                continue;
+            }
+
+            position.advance(&token);
+            self.output.push(token);
+        }
+
+        Ok(position.command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(input: &str, aliases: &[(&str, &str)]) -> Vec<Token> {
+        let aliases = aliases.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        expand_aliases(tokenize_input(input).unwrap(), &aliases).unwrap()
+    }
+
+    #[test]
+    fn test_aliases_in_command_position() {
+        let aliases = [("ll", "ls -la"), ("g", "git")];
+        assert_eq!(tokenize_input("ls -la g; git ll | ls -la && x=1 git").unwrap(), expand("ll g; g ll | ll && x=1 g", &aliases));
+        assert_eq!(tokenize_input("if ls -la; then > out ls -la; fi").unwrap(), expand("if ll; then > out ll; fi", &aliases));
+        // A quoted word is not an alias
+        assert_eq!(tokenize_input("'ll' \\g").unwrap(), expand("'ll' \\g", &aliases));
+    }
+
+    #[test]
+    fn test_trailing_blank_and_recursion() {
+        let aliases = [("sudo", "sudo "), ("ll", "ls -la"), ("ls", "ls --color"), ("a", "b"), ("b", "a")];
+        assert_eq!(tokenize_input("sudo ls --color -la").unwrap(), expand("sudo ll", &aliases));
+        assert_eq!(tokenize_input("a; b").unwrap(), expand("a; b", &aliases));
+    }
+}
@@ -0,0 +1,420 @@
+//!
+//! Parsing of the compound commands (loops, conditions, case, groups and function definitions), which contain lists of commands,
+//! and of the expressions of `[[ ]]`
+//!
+
+use std::rc::Rc;
+
+use crate::command::builtin::condition::{BINARY_OPERATORS, UNARY_OPERATORS};
+use crate::command::{CaseItem, CaseTerminator, Command, ConditionalExpression, RedirectionType};
+use crate::parsing::{Parser, ParsingError, RESERVED_WORDS, Token, create_redirection_commands};
+use crate::shell::is_valid_variable_name;
+
+impl Parser<'_> {
+
+    /// Parses `while list; do list; done`, or the `until` loop if `until` is true
+    pub(super) fn parse_while(&mut self, until: bool) -> Result<Command, ParsingError> {
+
+        // skip the while/until keyword
+        self.position += 1;
+
+        let condition = Box::new(self.parse_list(&["do"])?);
+        let body = Box::new(self.parse_do_group()?);
+
+        Ok(match until {
+            true => Command::Until { condition, body },
+            false => Command::While { condition, body },
+        })
+    }
+
+    /// Parses `for name in words; do list; done`, `for name; do list; done` and `for ((init; condition; step)); do list; done`
+    pub(super) fn parse_for(&mut self) -> Result<Command, ParsingError> {
+
+        // skip the for keyword
+        self.position += 1;
+
+        let variable = match self.peek() {
+            Some(Token::Word(name)) if is_valid_variable_name(name) => name.clone(),
+            Some(Token::Arithmetic(expression)) => {
+                let expression = expression.clone();
+                self.position += 1;
+                return self.parse_arithmetic_for(&expression);
+            },
+            Some(token) => return Err(ParsingError::UnexpectedToken(format!("`{token}`, expected a variable name"))),
+            None => return Err(ParsingError::IncompleteInput("expected a variable name".to_string())),
+        };
+        self.position += 1;
+        self.skip_newlines();
+
+        // Without the "in" keyword, the loop iterates over the positional parameters
+        let mut words = None;
+        if self.is_keyword("in") {
+            self.position += 1;
+
+            let mut in_words = Vec::new();
+            while let Some(Token::Word(word)) = self.peek() {
+                in_words.push(word.clone());
+                self.position += 1;
+            }
+            words = Some(in_words);
+            self.skip_list_separator()?;
+        } else if self.peek() == Some(&Token::Separator) {
+            self.position += 1;
+        }
+
+        let body = Box::new(self.parse_do_group()?);
+
+        Ok(Command::For { variable, words, body })
+    }
+
+    fn parse_arithmetic_for(&mut self, expression: &str) -> Result<Command, ParsingError> {
+
+        let [init, condition, step]: [&str; 3] = expression.split(';').collect::<Vec<_>>().try_into()
+            .map_err(|_| ParsingError::UnexpectedToken(format!("`(({expression}))`, expected `((init; condition; step))`")))?;
+
+        if self.peek() == Some(&Token::Separator) {
+            self.position += 1;
+        }
+        let body = Box::new(self.parse_do_group()?);
+
+        Ok(Command::ArithmeticFor {
+            init: init.trim().to_string(),
+            condition: condition.trim().to_string(),
+            step: step.trim().to_string(),
+            body
+        })
+    }
+
+    /// Parses `if list; then list; [elif list; then list;]... [else list;] fi`
+    pub(super) fn parse_if(&mut self) -> Result<Command, ParsingError> {
+
+        // skip the if/elif keyword
+        self.position += 1;
+
+        let condition = Box::new(self.parse_list(&["then"])?);
+        self.expect_keyword("then")?;
+        let then_branch = Box::new(self.parse_list(&["elif", "else", "fi"])?);
+
+        let else_branch = if self.is_keyword("elif") {
+            // the elif branch is a nested if which also consumes the fi keyword
+            return Ok(Command::If { condition, then_branch, else_branch: Some(Box::new(self.parse_if()?)) });
+        } else if self.is_keyword("else") {
+            self.position += 1;
+            Some(Box::new(self.parse_list(&["fi"])?))
+        } else {
+            None
+        };
+        self.expect_keyword("fi")?;
+
+        Ok(Command::If { condition, then_branch, else_branch })
+    }
+
+    /// Parses `case word in pattern | pattern) list;; ... esac`, the branches ending with `;;`, `;&` or `;;&`
+    pub(super) fn parse_case(&mut self) -> Result<Command, ParsingError> {
+
+        // skip the case keyword
+        self.position += 1;
+
+        let word = match self.peek() {
+            Some(Token::Word(word)) => word.clone(),
+            Some(token) => return Err(ParsingError::UnexpectedToken(format!("`{token}`, expected a word"))),
+            None => return Err(ParsingError::IncompleteInput("expected a word".to_string())),
+        };
+        self.position += 1;
+        self.skip_newlines();
+        self.expect_keyword("in")?;
+        self.skip_newlines();
+
+        let mut items = Vec::new();
+        while !self.is_keyword("esac") {
+            items.push(self.parse_case_item()?);
+            self.skip_newlines();
+        }
+        // skip the esac keyword
+        self.position += 1;
+
+        Ok(Command::Case { word, items })
+    }
+
+    fn parse_case_item(&mut self) -> Result<CaseItem, ParsingError> {
+
+        // The patterns may be preceded by an opening parenthesis
+        if self.peek() == Some(&Token::LeftParen) {
+            self.position += 1;
+        }
+
+        let mut patterns = Vec::new();
+        loop {
+            match self.peek() {
+                Some(Token::Word(pattern)) => patterns.push(pattern.clone()),
+                Some(token) => return Err(ParsingError::UnexpectedToken(format!("`{token}`, expected a pattern"))),
+                None => return Err(ParsingError::IncompleteInput("expected `esac`".to_string())),
+            }
+            self.position += 1;
+
+            match self.peek() {
+                Some(Token::Pipe) => self.position += 1,
+                Some(Token::RightParen) => {
+                    self.position += 1;
+                    break;
+                },
+                Some(token) => return Err(ParsingError::UnexpectedToken(format!("`{token}`, expected `)`"))),
+                None => return Err(ParsingError::IncompleteInput("expected `)`".to_string())),
+            }
+        }
+
+        self.skip_newlines();
+        let body = match self.peek() {
+            Some(Token::CaseBreak | Token::CaseFallThrough | Token::CaseContinue) => None,
+            _ if self.is_keyword("esac") => None,
+            _ => Some(self.parse_list(&["esac"])?),
+        };
+
+        // The last branch may omit its terminator
+        let terminator = match self.peek() {
+            Some(Token::CaseBreak) => CaseTerminator::Break,
+            Some(Token::CaseFallThrough) => CaseTerminator::FallThrough,
+            Some(Token::CaseContinue) => CaseTerminator::Continue,
+            _ if self.is_keyword("esac") => return Ok(CaseItem { patterns, body, terminator: CaseTerminator::Break }),
+            Some(token) => return Err(ParsingError::UnexpectedToken(format!("`{token}`, expected `;;`"))),
+            None => return Err(ParsingError::IncompleteInput("expected `esac`".to_string())),
+        };
+        self.position += 1;
+
+        Ok(CaseItem { patterns, body, terminator })
+    }
+
+    /// Parses `( list )`, executed in a subshell
+    pub(super) fn parse_subshell(&mut self) -> Result<Command, ParsingError> {
+
+        // skip the (
+        self.position += 1;
+
+        let body = Box::new(self.parse_list(&[])?);
+        match self.peek() {
+            Some(Token::RightParen) => self.position += 1,
+            Some(token) => return Err(ParsingError::UnexpectedToken(format!("`{token}`, expected `)`"))),
+            None => return Err(ParsingError::IncompleteInput("expected `)`".to_string())),
+        }
+
+        Ok(Command::Subshell { body })
+    }
+
+    /// Parses `{ list; }`, executed in the current shell
+    pub(super) fn parse_group(&mut self) -> Result<Command, ParsingError> {
+
+        // skip the {
+        self.position += 1;
+
+        let body = Box::new(self.parse_list(&["}"])?);
+        self.expect_keyword("}")?;
+
+        Ok(Command::Group { body })
+    }
+
+    /// Parses `[[ expression ]]`, whose operators are by increasing priority : `||`, `&&`, `!`, then the tests and the parentheses
+    pub(super) fn parse_conditional(&mut self) -> Result<Command, ParsingError> {
+
+        // skip the [[
+        self.position += 1;
+
+        let expression = self.parse_conditional_or()?;
+        self.expect_keyword("]]")?;
+
+        Ok(Command::Conditional { expression })
+    }
+
+    fn parse_conditional_or(&mut self) -> Result<ConditionalExpression, ParsingError> {
+
+        let mut expression = self.parse_conditional_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            expression = ConditionalExpression::Or(Box::new(expression), Box::new(self.parse_conditional_and()?));
+        }
+
+        Ok(expression)
+    }
+
+    fn parse_conditional_and(&mut self) -> Result<ConditionalExpression, ParsingError> {
+
+        let mut expression = self.parse_conditional_not()?;
+        while self.peek() == Some(&Token::And) {
+            self.position += 1;
+            expression = ConditionalExpression::And(Box::new(expression), Box::new(self.parse_conditional_not()?));
+        }
+
+        Ok(expression)
+    }
+
+    fn parse_conditional_not(&mut self) -> Result<ConditionalExpression, ParsingError> {
+
+        if self.is_keyword("!") {
+            self.position += 1;
+            return Ok(ConditionalExpression::Not(Box::new(self.parse_conditional_not()?)));
+        }
+
+        self.parse_conditional_primary()
+    }
+
+    fn parse_conditional_primary(&mut self) -> Result<ConditionalExpression, ParsingError> {
+
+        let word = match self.peek() {
+            Some(Token::LeftParen) => {
+                self.position += 1;
+                let expression = self.parse_conditional_or()?;
+                return match self.peek() {
+                    Some(Token::RightParen) => {
+                        self.position += 1;
+                        Ok(expression)
+                    },
+                    Some(token) => Err(ParsingError::UnexpectedToken(format!("`{token}`, expected `)`"))),
+                    None => Err(ParsingError::IncompleteInput("expected `)`".to_string())),
+                };
+            },
+            Some(Token::Word(word)) if word != "]]" => word.clone(),
+            Some(token) => return Err(ParsingError::UnexpectedToken(format!("`{token}`, expected a conditional expression"))),
+            None => return Err(ParsingError::IncompleteInput("expected `]]`".to_string())),
+        };
+        self.position += 1;
+
+        if let Some(operator) = self.conditional_binary_operator() {
+            self.position += 1;
+            let right = match operator.as_str() {
+                "=~" => self.parse_regex()?,
+                _ => self.parse_conditional_operand(&operator)?,
+            };
+            return Ok(ConditionalExpression::Binary { operator, left: word, right });
+        }
+
+        if UNARY_OPERATORS.contains(&word.as_str()) {
+            let operand = self.parse_conditional_operand(&word)?;
+            return Ok(ConditionalExpression::Unary { operator: word, operand });
+        }
+
+        Ok(ConditionalExpression::Word(word))
+    }
+
+    /// Returns the binary operator of `[[ ]]` that is the next token, if it is one
+    fn conditional_binary_operator(&self) -> Option<String> {
+        match self.peek() {
+            Some(Token::Word(word)) if BINARY_OPERATORS.contains(&word.as_str()) || word == "=~" => Some(word.clone()),
+            // < and > are lexed as redirections
+            Some(Token::RedirectOp(RedirectionType::In)) => Some(String::from("<")),
+            Some(Token::RedirectOp(RedirectionType::Out)) => Some(String::from(">")),
+            _ => None,
+        }
+    }
+
+    fn parse_conditional_operand(&mut self, operator: &str) -> Result<String, ParsingError> {
+        match self.peek() {
+            Some(Token::Word(word)) if word != "]]" => {
+                let word = word.clone();
+                self.position += 1;
+                Ok(word)
+            },
+            Some(token) => Err(ParsingError::UnexpectedToken(format!("`{token}`, expected an operand for `{operator}`"))),
+            None => Err(ParsingError::IncompleteInput(format!("expected an operand for `{operator}`"))),
+        }
+    }
+
+    /// Parses the right operand of `=~`, in which the unquoted parentheses and `|` are part of the regular expression
+    fn parse_regex(&mut self) -> Result<String, ParsingError> {
+
+        let mut regex = String::new();
+        let mut depth = 0;
+
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Word(word) if word == "]]" && depth == 0 => break,
+                Token::And | Token::Or | Token::RightParen if depth == 0 => break,
+                Token::Newline | Token::Separator => break,
+                Token::LeftParen => depth += 1,
+                Token::RightParen => depth -= 1,
+                _ => (),
+            }
+            regex.push_str(&token.to_string());
+            self.position += 1;
+        }
+
+        if regex.is_empty() {
+            return Err(match self.peek() {
+                Some(token) => ParsingError::UnexpectedToken(format!("`{token}`, expected an operand for `=~`")),
+                None => ParsingError::IncompleteInput("expected an operand for `=~`".to_string()),
+            });
+        }
+
+        Ok(regex)
+    }
+
+    /// Parses `name() { list; }`, or `function name { list; }` if `keyword` is true.
+    /// The body may also be a subshell `( list )`, and the redirections following it are applied each time the function is called
+    pub(super) fn parse_function(&mut self, keyword: bool) -> Result<Command, ParsingError> {
+
+        // skip the function keyword
+        if keyword {
+            self.position += 1;
+        }
+
+        let name = match self.peek() {
+            Some(Token::Word(name)) if is_valid_function_name(name) => name.clone(),
+            Some(token) => return Err(ParsingError::UnexpectedToken(format!("`{token}`, expected a function name"))),
+            None => return Err(ParsingError::IncompleteInput("expected a function name".to_string())),
+        };
+        self.position += 1;
+
+        // The parentheses are optional after the function keyword
+        if !keyword || self.peek() == Some(&Token::LeftParen) {
+            for expected in [Token::LeftParen, Token::RightParen] {
+                match self.peek() {
+                    Some(token) if *token == expected => self.position += 1,
+                    Some(token) => return Err(ParsingError::UnexpectedToken(format!("`{token}`, expected `{expected}`"))),
+                    None => return Err(ParsingError::IncompleteInput(format!("expected `{expected}`"))),
+                }
+            }
+        }
+
+        self.skip_newlines();
+        let body = match self.peek() {
+            Some(Token::Word(word)) if word == "{" => self.parse_group()?,
+            Some(Token::LeftParen) => self.parse_subshell()?,
+            Some(token) => return Err(ParsingError::UnexpectedToken(format!("`{token}`, expected `{{`"))),
+            None => return Err(ParsingError::IncompleteInput("expected `{`".to_string())),
+        };
+
+        let redirections = self.parse_redirections()?;
+        let body = Rc::new(create_redirection_commands(body, redirections));
+
+        Ok(Command::FunctionDefinition { name, body })
+    }
+
+    /// Parses the `do list; done` body of a loop
+    fn parse_do_group(&mut self) -> Result<Command, ParsingError> {
+
+        self.skip_newlines();
+        self.expect_keyword("do")?;
+        let body = self.parse_list(&["done"])?;
+        self.expect_keyword("done")?;
+
+        Ok(body)
+    }
+
+    /// Consumes the `;` or the newlines ending a list of words
+    fn skip_list_separator(&mut self) -> Result<(), ParsingError> {
+        match self.peek() {
+            Some(Token::Separator | Token::Newline) => {
+                self.position += 1;
+                self.skip_newlines();
+                Ok(())
+            },
+            Some(token) => Err(ParsingError::UnexpectedToken(format!("`{token}`"))),
+            None => Err(ParsingError::IncompleteInput("expected `do`".to_string())),
+        }
+    }
+}
+
+/// Returns true if the word can name a function : letters, digits and `_-.:`, which must not be a reserved word
+fn is_valid_function_name(name: &str) -> bool {
+    !name.is_empty()
+        && !RESERVED_WORDS.contains(&name)
+        && name.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':'))
+}
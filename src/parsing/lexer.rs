@@ -0,0 +1,390 @@
+//!
+//! Converts the user input into tokens.
+//!
+//! The words are kept as they were written (quotes, `$` and backslashes included) because they are
+//! expanded only when the command is executed.
+//!
+
+use crate::command::RedirectionType;
+use crate::parsing::{ParsingError, Token};
+
+/// Converts an input string into a vec of tokens
+pub fn tokenize_input(input: &str) -> Result<Vec<Token>, ParsingError> {
+
+    let mut lexer = Lexer { chars: input.chars().collect(), position: 0 };
+    let mut tokens: Vec<Token> = Vec::new();
+
+    while let Some(token) = lexer.next_token()? {
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+/// A token with the range of bytes of the input it was read from
+#[derive(Debug, PartialEq)]
+pub struct PositionedToken {
+    pub token: Token,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Converts the input into tokens with their position, as far as it can be read (the input being edited may be incomplete).
+///
+/// Returns the tokens read before the first error, and that error with the position of the token causing it,
+/// like the start of a word with an unterminated quote
+pub fn tokenize_with_positions(input: &str) -> (Vec<PositionedToken>, Option<(ParsingError, usize)>) {
+
+    let mut lexer = Lexer { chars: input.chars().collect(), position: 0 };
+    let offsets: Vec<usize> = input.char_indices().map(|(offset, _)| offset).chain([input.len()]).collect();
+    let mut tokens = Vec::new();
+
+    loop {
+        lexer.skip_blanks_and_comments();
+        let start = offsets[lexer.position];
+        match lexer.next_token() {
+            Ok(Some(token)) => tokens.push(PositionedToken { token, start, end: offsets[lexer.position] }),
+            Ok(None) => return (tokens, None),
+            Err(err) => return (tokens, Some((err, start))),
+        }
+    }
+}
+
+/// Returns true if the character ends an unquoted word
+fn is_word_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '|' | '&' | ';' | '<' | '>' | '(' | ')')
+}
+
+/// Returns true if the word read so far is `name=`, which starts an array assignment if `(` follows
+fn is_array_assignment_start(word: &str) -> bool {
+    word.strip_suffix('=').is_some_and(crate::shell::is_valid_variable_name)
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Lexer {
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.position + offset).copied()
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, ParsingError> {
+
+        self.skip_blanks_and_comments();
+
+        let Some(c) = self.peek() else {
+            return Ok(None);
+        };
+
+        let token = match (c, self.peek_at(1)) {
+            ('\n', _) => { self.position += 1; Token::Newline },
+            ('|', Some('|')) => { self.position += 2; Token::Or },
+            ('|', _) => { self.position += 1; Token::Pipe },
+            ('&', Some('&')) => { self.position += 2; Token::And },
+            ('&', _) => return Err(ParsingError::UnexpectedToken("`&` is not supported".to_string())),
+            (';', Some(';')) if self.peek_at(2) == Some('&') => { self.position += 3; Token::CaseContinue },
+            (';', Some(';')) => { self.position += 2; Token::CaseBreak },
+            (';', Some('&')) => { self.position += 2; Token::CaseFallThrough },
+            (';', _) => { self.position += 1; Token::Separator },
+            ('<', _) => { self.position += 1; Token::RedirectOp(RedirectionType::In) },
+            ('>', Some('>')) => { self.position += 2; Token::RedirectOp(RedirectionType::Append) },
+            ('>', _) => { self.position += 1; Token::RedirectOp(RedirectionType::Out) },
+            ('2', Some('>')) if self.peek_at(2) != Some('>') => { self.position += 2; Token::RedirectOp(RedirectionType::Err) },
+            ('(', Some('(')) => self.read_arithmetic()?,
+            ('(', _) => { self.position += 1; Token::LeftParen },
+            (')', _) => { self.position += 1; Token::RightParen },
+            _ => Token::Word(self.read_word()?),
+        };
+
+        Ok(Some(token))
+    }
+
+    /// Skips the spaces, the escaped newlines and the comments, but not the newlines which are tokens
+    fn skip_blanks_and_comments(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                '\n' => break,
+                '\\' if self.peek_at(1) == Some('\n') => self.position += 2,
+                '#' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.position += 1;
+                    }
+                },
+                c if c.is_whitespace() => self.position += 1,
+                _ => break,
+            }
+        }
+    }
+
+    /// Reads an arithmetic command `(( expression ))` and returns the expression
+    fn read_arithmetic(&mut self) -> Result<Token, ParsingError> {
+
+        // skip the opening ((
+        self.position += 2;
+        let mut expression = String::new();
+        let mut depth = 0;
+
+        loop {
+            match (self.peek(), self.peek_at(1)) {
+                (None, _) => return Err(ParsingError::IncompleteInput("expected `))`".to_string())),
+                (Some(')'), Some(')')) if depth == 0 => {
+                    self.position += 2;
+                    return Ok(Token::Arithmetic(expression.trim().to_string()));
+                },
+                (Some(c), _) => {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => (),
+                    }
+                    expression.push(c);
+                    self.position += 1;
+                }
+            }
+        }
+    }
+
+    /// Reads a word until an unquoted delimiter, keeping the quotes and the escape characters
+    fn read_word(&mut self) -> Result<String, ParsingError> {
+
+        let mut word = String::new();
+
+        while let Some(c) = self.peek() {
+            match c {
+                '(' if is_array_assignment_start(&word) => self.read_array_elements(&mut word)?,
+                c if is_word_delimiter(c) => break,
+                '\'' => self.read_single_quoted(&mut word)?,
+                '"' => self.read_double_quoted(&mut word)?,
+                '\\' => self.read_escaped(&mut word),
+                '$' => self.read_dollar(&mut word)?,
+                '`' => self.read_backquoted(&mut word)?,
+                _ => {
+                    word.push(c);
+                    self.position += 1;
+                }
+            }
+        }
+
+        Ok(word)
+    }
+
+    /// Reads the `(elements)` of an array assignment, which may contain blanks and newlines
+    fn read_array_elements(&mut self, word: &mut String) -> Result<(), ParsingError> {
+
+        word.push('(');
+        self.position += 1;
+
+        loop {
+            let c = self.peek().ok_or(ParsingError::IncompleteInput("expected `)`".to_string()))?;
+            match c {
+                '\'' => self.read_single_quoted(word)?,
+                '"' => self.read_double_quoted(word)?,
+                '\\' => self.read_escaped(word),
+                '`' => self.read_backquoted(word)?,
+                '$' => self.read_dollar(word)?,
+                _ => {
+                    word.push(c);
+                    self.position += 1;
+                    if c == ')' {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    fn read_escaped(&mut self, word: &mut String) {
+        word.push('\\');
+        self.position += 1;
+        if let Some(c) = self.peek() {
+            word.push(c);
+            self.position += 1;
+        }
+    }
+
+    fn read_single_quoted(&mut self, word: &mut String) -> Result<(), ParsingError> {
+
+        word.push('\'');
+        self.position += 1;
+
+        loop {
+            let c = self.peek().ok_or(ParsingError::IncompleteInput("unterminated quote `'`".to_string()))?;
+            word.push(c);
+            self.position += 1;
+            if c == '\'' {
+                return Ok(());
+            }
+        }
+    }
+
+    fn read_double_quoted(&mut self, word: &mut String) -> Result<(), ParsingError> {
+
+        word.push('"');
+        self.position += 1;
+
+        loop {
+            let c = self.peek().ok_or(ParsingError::IncompleteInput("unterminated quote `\"`".to_string()))?;
+            match c {
+                '"' => {
+                    word.push(c);
+                    self.position += 1;
+                    return Ok(());
+                },
+                '\\' => self.read_escaped(word),
+                '$' => self.read_dollar(word)?,
+                '`' => self.read_backquoted(word)?,
+                _ => {
+                    word.push(c);
+                    self.position += 1;
+                }
+            }
+        }
+    }
+
+    fn read_backquoted(&mut self, word: &mut String) -> Result<(), ParsingError> {
+
+        word.push('`');
+        self.position += 1;
+
+        loop {
+            let c = self.peek().ok_or(ParsingError::IncompleteInput("unterminated backquote".to_string()))?;
+            match c {
+                '\\' => self.read_escaped(word),
+                _ => {
+                    word.push(c);
+                    self.position += 1;
+                    if c == '`' {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads a `$` and, if any, the `$( )`, `$(( ))` or `${ }` construct that follows it
+    fn read_dollar(&mut self, word: &mut String) -> Result<(), ParsingError> {
+
+        word.push('$');
+        self.position += 1;
+
+        let (open, close) = match self.peek() {
+            Some('(') => ('(', ')'),
+            Some('{') => ('{', '}'),
+            _ => return Ok(()),
+        };
+
+        word.push(open);
+        self.position += 1;
+        let mut depth = 1;
+
+        while depth > 0 {
+            let c = self.peek().ok_or(ParsingError::IncompleteInput(format!("expected `{close}`")))?;
+            match c {
+                '\'' => self.read_single_quoted(word)?,
+                '"' => self.read_double_quoted(word)?,
+                '\\' => self.read_escaped(word),
+                '`' => self.read_backquoted(word)?,
+                '$' => self.read_dollar(word)?,
+                _ => {
+                    if c == open {
+                        depth += 1;
+                    } else if c == close {
+                        depth -= 1;
+                    }
+                    word.push(c);
+                    self.position += 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(s: &str) -> Token {
+        Token::Word(s.to_string())
+    }
+
+    #[test]
+    fn test_operators_without_spaces() {
+        let tokens = tokenize_input("echo hello|cat>out.txt;ls&&pwd").unwrap();
+
+        let expected = vec![
+            word("echo"), word("hello"), Token::Pipe, word("cat"),
+            Token::RedirectOp(RedirectionType::Out), word("out.txt"), Token::Separator,
+            word("ls"), Token::And, word("pwd"),
+        ];
+        assert_eq!(expected, tokens);
+    }
+
+    #[test]
+    fn test_quotes_are_kept_in_words() {
+        let tokens = tokenize_input("echo 'a b' \"c | d\" e\\ f").unwrap();
+
+        let expected = vec![word("echo"), word("'a b'"), word("\"c | d\""), word("e\\ f")];
+        assert_eq!(expected, tokens);
+    }
+
+    #[test]
+    fn test_comments_and_newlines() {
+        let tokens = tokenize_input("ls # a comment\npwd").unwrap();
+
+        let expected = vec![word("ls"), Token::Newline, word("pwd")];
+        assert_eq!(expected, tokens);
+    }
+
+    #[test]
+    fn test_arithmetic_command() {
+        let tokens = tokenize_input("for ((i=0; i<3; i++))").unwrap();
+
+        let expected = vec![word("for"), Token::Arithmetic("i=0; i<3; i++".to_string())];
+        assert_eq!(expected, tokens);
+    }
+
+    #[test]
+    fn test_case_operators() {
+        let tokens = tokenize_input("(a|b) x;; *) y;& z;;&").unwrap();
+
+        let expected = vec![
+            Token::LeftParen, word("a"), Token::Pipe, word("b"), Token::RightParen, word("x"), Token::CaseBreak,
+            word("*"), Token::RightParen, word("y"), Token::CaseFallThrough, word("z"), Token::CaseContinue,
+        ];
+        assert_eq!(expected, tokens);
+    }
+
+    #[test]
+    fn test_array_assignment_is_one_word() {
+        assert_eq!(vec![word("a=(x 'y )' $(echo z)\n)"), Token::Separator, word("b="), Token::LeftParen],
+            tokenize_input("a=(x 'y )' $(echo z)\n); b= (").unwrap());
+    }
+
+    #[test]
+    fn test_token_positions() {
+        let (tokens, error) = tokenize_with_positions("é 'a b'>out ;");
+        let positions: Vec<(usize, usize)> = tokens.iter().map(|token| (token.start, token.end)).collect();
+        assert_eq!(vec![(0, 2), (3, 8), (8, 9), (9, 12), (13, 14)], positions);
+        assert_eq!(word("'a b'"), tokens[1].token);
+        assert!(error.is_none());
+
+        let (tokens, error) = tokenize_with_positions("echo \"$(ls");
+        assert_eq!(1, tokens.len());
+        assert!(matches!(error, Some((ParsingError::IncompleteInput(_), 5))));
+    }
+
+    #[test]
+    fn test_unterminated_quote_is_incomplete() {
+        let result = tokenize_input("echo 'hello");
+        assert!(matches!(result, Err(ParsingError::IncompleteInput(_))));
+    }
+}
@@ -0,0 +1,397 @@
+//!
+//! State of the shell that lives across the executed commands (variables, positional parameters, last exit status...)
+//!
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::command::Command;
+use crate::command::builtin::get_logical_working_directory;
+use crate::completion::spec::CompletionSpec;
+use crate::config::Config;
+use crate::history::SharedHistory;
+use crate::key_binding::{Key, KeyBinding};
+
+/// Represents the state kept by the shell between two commands.
+///
+/// A forked child (for instance a pipeline stage) gets a copy of it, so the modifications made there do not
+/// reach the parent shell. The copies share the variables, the functions, the aliases and the completions
+/// until one of them changes them, so that the line editor can be given a copy before each prompt.
+#[derive(Clone, Debug)]
+pub struct Shell {
+    variables: Rc<HashMap<String, String>>,
+    /// The indexed arrays, like `BASH_REMATCH`, whose first element is also their value as a variable
+    arrays: Rc<HashMap<String, Vec<String>>>,
+    /// The parameters accessible with `$1`, `$2`, `$@` etc...
+    pub positional_parameters: Vec<String>,
+    /// The exit status of the last executed command, accessible with `$?`
+    pub last_status: i32,
+    /// The number of loops currently being executed, used by `break` and `continue`
+    pub loop_depth: usize,
+    /// The id of the shell process, accessible with `$$` (even in forked children)
+    pub process_id: u32,
+    /// The number of files being executed by `source`, in which `return` can be used
+    pub source_depth: usize,
+    /// The exit status of the last command substitution, which is the status of a command made of assignments only
+    pub substitution_status: Option<i32>,
+    /// The names of the options enabled with `set -o` (or their letter), like `xtrace`
+    options: HashSet<String>,
+    /// The functions defined with `name() { ...; }`, by name
+    functions: Rc<HashMap<String, Rc<Command>>>,
+    /// For each function being executed, the variables hidden by its `local` variables
+    local_scopes: Vec<HashMap<String, HiddenVariable>>,
+    /// The names of the variables passed in the environment of the executed programs
+    exported: Rc<HashSet<String>>,
+    /// The names of the variables made read-only with `readonly`
+    readonly: HashSet<String>,
+    /// The directories saved by `pushd`, the most recent first, the working directory excluded
+    pub directory_stack: Vec<String>,
+    /// The values of the aliases, by name
+    aliases: Rc<HashMap<String, String>>,
+    /// The completions defined with `complete`, by command name
+    completion_specs: Rc<HashMap<String, CompletionSpec>>,
+    /// The key bindings defined with `bind`, in the order of their definition
+    key_bindings: Vec<KeyBinding>,
+    /// The commands entered interactively
+    pub history: SharedHistory,
+    /// The configuration of the interactive features, read from `config.toml`
+    pub config: Config,
+}
+
+impl Shell {
+
+    /// Creates a shell state whose variables are initialized (and exported) from the process environment.
+    /// PWD is replaced by the working directory if it doesn't lead to it
+    pub fn new() -> Self {
+        let variables: HashMap<String, String> = std::env::vars().collect();
+        let mut shell = Shell {
+            exported: Rc::new(variables.keys().cloned().collect()),
+            readonly: HashSet::new(),
+            directory_stack: Vec::new(),
+            aliases: Rc::default(),
+            completion_specs: Rc::default(),
+            key_bindings: Vec::new(),
+            history: SharedHistory::default(),
+            config: Config::default(),
+            variables: Rc::new(variables),
+            arrays: Rc::default(),
+            positional_parameters: Vec::new(),
+            last_status: 0,
+            loop_depth: 0,
+            process_id: std::process::id(),
+            source_depth: 0,
+            substitution_status: None,
+            options: HashSet::new(),
+            functions: Rc::default(),
+            local_scopes: Vec::new(),
+        };
+
+        if let Ok(directory) = get_logical_working_directory(&shell) {
+            shell.set_variable("PWD", &directory);
+        }
+        shell
+    }
+
+    pub fn get_variable(&self, name: &str) -> Option<&str> {
+        match self.arrays.get(name) {
+            Some(array) => array.first().map(String::as_str),
+            None => self.variables.get(name).map(String::as_str),
+        }
+    }
+
+    /// Sets the value of a variable, or the first element of the array if it is one
+    pub fn set_variable(&mut self, name: &str, value: &str) {
+        let array = match self.arrays.contains_key(name) {
+            true => Rc::make_mut(&mut self.arrays).get_mut(name),
+            false => None,
+        };
+        match array {
+            Some(array) if array.is_empty() => array.push(value.to_string()),
+            Some(array) => array[0] = value.to_string(),
+            None => {
+                Rc::make_mut(&mut self.variables).insert(name.to_string(), value.to_string());
+            },
+        }
+    }
+
+    /// Sets the value of a variable like `set_variable`, unless it is read-only
+    pub fn assign_variable(&mut self, name: &str, value: &str) -> Result<(), ReadonlyVariableError> {
+        if self.is_readonly(name) {
+            return Err(ReadonlyVariableError(name.to_string()));
+        }
+        self.set_variable(name, value);
+        Ok(())
+    }
+
+    /// Removes a variable or an array, which is not exported anymore
+    pub fn unset_variable(&mut self, name: &str) {
+        remove_shared(&mut self.variables, name);
+        remove_shared(&mut self.arrays, name);
+        self.unexport_variable(name);
+    }
+
+    /// Returns the variables (the arrays excluded) sorted by name
+    pub fn variables(&self) -> Vec<(&str, &str)> {
+        let mut variables: Vec<(&str, &str)> = self.variables.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+        variables.sort();
+        variables
+    }
+
+    /// Returns the arrays sorted by name
+    pub fn arrays(&self) -> Vec<(&str, &[String])> {
+        let mut arrays: Vec<(&str, &[String])> = self.arrays.iter().map(|(name, values)| (name.as_str(), values.as_slice())).collect();
+        arrays.sort();
+        arrays
+    }
+
+    /// Marks a variable to be passed in the environment of the executed programs, even if it is not set yet
+    pub fn export_variable(&mut self, name: &str) {
+        if !self.exported.contains(name) {
+            Rc::make_mut(&mut self.exported).insert(name.to_string());
+        }
+    }
+
+    /// Stops passing a variable in the environment of the executed programs, the variable being kept
+    pub fn unexport_variable(&mut self, name: &str) {
+        if self.exported.contains(name) {
+            Rc::make_mut(&mut self.exported).remove(name);
+        }
+    }
+
+    pub fn is_exported(&self, name: &str) -> bool {
+        self.exported.contains(name)
+    }
+
+    /// Returns the exported variables which are set, sorted by name : the environment of the executed programs
+    pub fn exported_variables(&self) -> Vec<(&str, &str)> {
+        let mut variables: Vec<(&str, &str)> = self.exported.iter()
+            .filter_map(|name| Some((name.as_str(), self.get_variable(name)?)))
+            .collect();
+        variables.sort();
+        variables
+    }
+
+    /// Prevents the variable from being assigned or unset for the rest of the session
+    pub fn make_readonly(&mut self, name: &str) {
+        self.readonly.insert(name.to_string());
+    }
+
+    pub fn is_readonly(&self, name: &str) -> bool {
+        self.readonly.contains(name)
+    }
+
+    /// Returns the names of the read-only variables, sorted
+    pub fn readonly_variables(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.readonly.iter().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+
+    /// Returns the elements of an array, a variable being an array of one element
+    pub fn get_array(&self, name: &str) -> Option<Vec<String>> {
+        match self.arrays.get(name) {
+            Some(array) => Some(array.clone()),
+            None => self.variables.get(name).map(|value| vec![value.clone()]),
+        }
+    }
+
+    /// Sets the elements of an array, which replaces the variable of the same name
+    pub fn set_array(&mut self, name: &str, values: Vec<String>) {
+        remove_shared(&mut self.variables, name);
+        Rc::make_mut(&mut self.arrays).insert(name.to_string(), values);
+    }
+
+    /// Sets the elements of an array like `set_array`, unless it is read-only
+    pub fn assign_array(&mut self, name: &str, values: Vec<String>) -> Result<(), ReadonlyVariableError> {
+        if self.is_readonly(name) {
+            return Err(ReadonlyVariableError(name.to_string()));
+        }
+        self.set_array(name, values);
+        Ok(())
+    }
+
+    pub fn get_function(&self, name: &str) -> Option<Rc<Command>> {
+        self.functions.get(name).cloned()
+    }
+
+    pub fn define_function(&mut self, name: &str, body: Rc<Command>) {
+        Rc::make_mut(&mut self.functions).insert(name.to_string(), body);
+    }
+
+    /// Returns the names of the defined functions, sorted
+    pub fn function_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.functions.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+
+    /// Removes a function, returns false if it was not defined
+    pub fn remove_function(&mut self, name: &str) -> bool {
+        remove_shared(&mut self.functions, name).is_some()
+    }
+
+    pub fn is_option_set(&self, name: &str) -> bool {
+        self.options.contains(name)
+    }
+
+    pub fn set_option(&mut self, name: &str, enabled: bool) {
+        match enabled {
+            true => self.options.insert(name.to_string()),
+            false => self.options.remove(name),
+        };
+    }
+
+    pub fn aliases(&self) -> &HashMap<String, String> {
+        &self.aliases
+    }
+
+    pub fn define_alias(&mut self, name: &str, value: &str) {
+        Rc::make_mut(&mut self.aliases).insert(name.to_string(), value.to_string());
+    }
+
+    /// Removes an alias, returns false if it was not defined
+    pub fn remove_alias(&mut self, name: &str) -> bool {
+        remove_shared(&mut self.aliases, name).is_some()
+    }
+
+    pub fn clear_aliases(&mut self) {
+        self.aliases = Rc::default();
+    }
+
+    pub fn completion_spec(&self, command: &str) -> Option<&CompletionSpec> {
+        self.completion_specs.get(command)
+    }
+
+    pub fn define_completion_spec(&mut self, command: &str, spec: CompletionSpec) {
+        Rc::make_mut(&mut self.completion_specs).insert(command.to_string(), spec);
+    }
+
+    /// Removes the completion of a command, returns false if it was not defined
+    pub fn remove_completion_spec(&mut self, command: &str) -> bool {
+        remove_shared(&mut self.completion_specs, command).is_some()
+    }
+
+    pub fn clear_completion_specs(&mut self) {
+        self.completion_specs = Rc::default();
+    }
+
+    /// Returns the completions defined with `complete`, sorted by command name
+    pub fn completion_specs(&self) -> Vec<(&str, &CompletionSpec)> {
+        let mut specs: Vec<(&str, &CompletionSpec)> = self.completion_specs.iter().map(|(name, spec)| (name.as_str(), spec)).collect();
+        specs.sort_by_key(|(name, _)| *name);
+        specs
+    }
+
+    pub fn key_bindings(&self) -> &[KeyBinding] {
+        &self.key_bindings
+    }
+
+    /// Binds the keys of the binding, replacing their previous binding
+    pub fn bind_keys(&mut self, binding: KeyBinding) {
+        self.unbind_keys(&binding.keys);
+        self.key_bindings.push(binding);
+    }
+
+    /// Removes the binding of the keys, returns false if they were not bound
+    pub fn unbind_keys(&mut self, keys: &[Key]) -> bool {
+        let length = self.key_bindings.len();
+        self.key_bindings.retain(|binding| binding.keys != keys);
+        self.key_bindings.len() < length
+    }
+
+    /// Returns the number of functions currently being executed
+    pub fn function_depth(&self) -> usize {
+        self.local_scopes.len()
+    }
+
+    /// Starts the scope of a function call, in which variables can be declared local
+    pub fn enter_function_scope(&mut self) {
+        self.local_scopes.push(HashMap::new());
+    }
+
+    /// Ends the scope of the current function call, the variables it declared local get back their previous value
+    pub fn leave_function_scope(&mut self) {
+        let Some(scope) = self.local_scopes.pop() else { return };
+
+        for (name, hidden) in scope {
+            self.unset_variable(&name);
+            match hidden.value {
+                Some(HiddenValue::Variable(value)) => self.set_variable(&name, &value),
+                Some(HiddenValue::Array(values)) => self.set_array(&name, values),
+                None => (),
+            }
+            if hidden.exported {
+                self.export_variable(&name);
+            }
+        }
+    }
+
+    /// Declares a variable local to the current function, unset if no value is given.
+    ///
+    /// The scoping is dynamic : the functions called from there also see the local value.
+    /// Returns false if no function is being executed
+    pub fn declare_local(&mut self, name: &str, value: Option<&str>) -> bool {
+        let Some(scope) = self.local_scopes.last_mut() else { return false };
+
+        // Only the value before the first declaration of the scope should be restored,
+        // and declaring it again without a value keeps the local one
+        let declared = scope.contains_key(name);
+        if !declared {
+            let value = match remove_shared(&mut self.arrays, name) {
+                Some(values) => Some(HiddenValue::Array(values)),
+                None => self.variables.get(name).cloned().map(HiddenValue::Variable),
+            };
+            scope.insert(name.to_string(), HiddenVariable { value, exported: self.exported.contains(name) });
+        }
+
+        match value {
+            Some(value) => self.set_variable(name, value),
+            None if !declared => self.unset_variable(name),
+            None => (),
+        }
+        true
+    }
+}
+
+impl Default for Shell {
+
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Removes an entry of a map shared with the copies of the shell, which is copied only if it has the entry
+fn remove_shared<V: Clone>(map: &mut Rc<HashMap<String, V>>, name: &str) -> Option<V> {
+    match map.contains_key(name) {
+        true => Rc::make_mut(map).remove(name),
+        false => None,
+    }
+}
+
+/// A variable hidden by a `local` declaration, restored when the function returns
+#[derive(Clone, Debug)]
+struct HiddenVariable {
+    /// None if it was unset
+    value: Option<HiddenValue>,
+    exported: bool,
+}
+
+#[derive(Clone, Debug)]
+enum HiddenValue {
+    Variable(String),
+    Array(Vec<String>),
+}
+
+/// Error of an assignment to a variable made read-only with `readonly`
+#[derive(thiserror::Error, Debug, PartialEq)]
+#[error("{0}: readonly variable")]
+pub struct ReadonlyVariableError(pub String);
+
+/// Returns true if `name` can be used as a variable name (letters, digits and underscores, not starting with a digit)
+pub fn is_valid_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
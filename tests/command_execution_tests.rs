@@ -1,78 +1,76 @@
-/*mod common;
+mod common;
 
-use std::{env, fs};
+use std::fs;
 
 use rust_shell::command::{Command, RedirectionType};
-use crate::common::{TempFiles, create_test_io_context};
+use rust_shell::config::Colors;
+use rust_shell::expansion::prompt::DEFAULT_PS1;
+use rust_shell::history::{HistoryEntry, HistoryPolicy};
+use rust_shell::shell::Shell;
+use crate::common::{TempFiles, create_test_io_context, run_input};
 
 
-// TODO fix this test, it might fail sometimes due to existing file conflicts
 /// Tests that the "echo -n hello" command writes the correct output on stdout
-/*#[test]
+#[test]
 fn test_echo_with_args_simple_command() {
     let mut temp_files = TempFiles::new();
-    let io_context = create_test_io_context(&mut temp_files);
-    
-    let cmd = Command::SimpleCommand { 
-        path: "echo".to_string(), 
-        args: vec!["-n".to_string(), "hello".to_string()] 
+    let (io_context, stdout_path) = create_test_io_context(&mut temp_files);
+
+    let cmd = Command::Simple {
+        cmd_path: "echo".to_string(),
+        cmd_args: vec!["-n".to_string(), "hello".to_string()]
     };
-    
-    cmd.execute(&io_context).unwrap();
-    drop(io_context);
-    
-    let output = fs::read_to_string(temp_files.stdout_path()).unwrap();
+
+    cmd.execute(&mut Shell::new(), io_context).unwrap();
+
+    let output = fs::read_to_string(stdout_path).unwrap();
     assert_eq!(output, "hello");
-}*/
+}
 
 /// Tests that "echo hello > rust_shell_test.txt" writes on the file
 #[test]
 fn test_echo_redirection_on_file() {
+    let mut temp_files = TempFiles::new();
+    let redirected_file = temp_files.add_unique("redirection");
 
-    let redirected_file = env::temp_dir().join(format!("rust_shell_test_{}.txt", std::process::id()));
-
-    let cmd = Command::Redirection { 
-        kind: RedirectionType::Out, 
-        command: Box::new(Command::SimpleCommand { 
-            path: "echo".to_string(), 
-            args: vec!["hello".to_string()] 
-        }), 
+    let cmd = Command::Redirection {
+        kind: RedirectionType::Out,
+        command: Box::new(Command::Simple {
+            cmd_path: "echo".to_string(),
+            cmd_args: vec!["hello".to_string()]
+        }),
         file: redirected_file.to_string_lossy().to_string(),
     };
 
-    cmd.execute(&create_test_io_context(&mut TempFiles::new())).unwrap();
+    cmd.execute(&mut Shell::new(), create_test_io_context(&mut temp_files).0).unwrap();
 
     // check that the file was created
     assert!(redirected_file.exists(), "File wasn't created");
 
     // check that the content is correct
     let content = fs::read_to_string(&redirected_file).unwrap();
-    assert_eq!(content.trim(), "hello"); 
-    
-    fs::remove_file(redirected_file).ok();
+    assert_eq!(content.trim(), "hello");
 }
 
 /// Tests that the ">" redirection overrides the old content of the file if it exists
 #[test]
 fn test_redirection_overwrite() {
     let mut temp_files = TempFiles::new();
-    let file_path = temp_files.add(
-        std::env::temp_dir().join(format!("test_overwrite_{}.txt", std::process::id()))
-    );
+    let file_path = temp_files.add_unique("overwrite");
 
     // Writes some initial content
     std::fs::write(&file_path, "old content").unwrap();
 
     let cmd = Command::Redirection {
         kind: RedirectionType::Out,
-        command: Box::new(Command::SimpleCommand {
-            path: "echo".to_string(),
-            args: vec!["new content".to_string()],
+        command: Box::new(Command::Simple {
+            cmd_path: "echo".to_string(),
+            cmd_args: vec!["new content".to_string()],
         }),
         file: file_path.to_string_lossy().to_string(),
     };
 
-    cmd.execute(&create_test_io_context(&mut temp_files)).unwrap();
+    cmd.execute(&mut Shell::new(), create_test_io_context(&mut temp_files).0).unwrap();
 
     let output = std::fs::read_to_string(&file_path).unwrap();
     assert_eq!(output.trim(), "new content");
@@ -83,27 +81,539 @@ fn test_redirection_overwrite() {
 #[test]
 fn test_redirection_append() {
     let mut temp_files = TempFiles::new();
-    let file_path = temp_files.add(
-        std::env::temp_dir().join(format!("test_append_{}.txt", std::process::id()))
-    );
+    let file_path = temp_files.add_unique("append");
 
     std::fs::write(&file_path, "first line\n").unwrap();
 
     let cmd = Command::Redirection {
         kind: RedirectionType::Append, // >>
-        command: Box::new(Command::SimpleCommand {
-            path: "echo".to_string(),
-            args: vec!["second line".to_string()],
+        command: Box::new(Command::Simple {
+            cmd_path: "echo".to_string(),
+            cmd_args: vec!["'second line'".to_string()],
         }),
         file: file_path.to_string_lossy().to_string(),
     };
 
-    cmd.execute(&create_test_io_context(&mut temp_files)).unwrap();
+    cmd.execute(&mut Shell::new(), create_test_io_context(&mut temp_files).0).unwrap();
 
     let output = std::fs::read_to_string(&file_path).unwrap();
     assert_eq!(output, "first line\nsecond line\n"); // echo adds \n
-}*/
+}
+
+/// Tests that a redirection before a pipe only applies to the left command
+#[test]
+fn test_chained_pipe_and_redirections() {
+    let mut temp_files = TempFiles::new();
+    let file_path = temp_files.add_unique("pipe_input");
+    std::fs::write(&file_path, "b\na\nc\n").unwrap();
+
+    let output = run_input(&format!("sort < {} | head -n 2 | tr a-z A-Z", file_path.display()), &mut Shell::new());
+    assert_eq!(output, "A\nB\n");
+}
+
+#[test]
+fn test_for_loop_over_words_and_parameters() {
+    let mut shell = Shell::new();
+    assert_eq!(run_input("for x in a 'b c'; do echo $x; done", &mut shell), "a\nb c\n");
+
+    shell.positional_parameters = vec!["first".to_string(), "second".to_string()];
+    assert_eq!(run_input("for arg\ndo\n  echo \"<$arg>\"\ndone", &mut shell), "<first>\n<second>\n");
+}
+
+#[test]
+fn test_arithmetic_for_loop() {
+    let output = run_input("for ((i = 0; i < 3; i++)); do echo $i; done", &mut Shell::new());
+    assert_eq!(output, "0\n1\n2\n");
+}
+
+#[test]
+fn test_while_and_until_loops() {
+    let mut shell = Shell::new();
+    assert_eq!(run_input("while false; do echo never; done; echo after", &mut shell), "after\n");
+    assert_eq!(run_input("until true; do echo never; done; echo after", &mut shell), "after\n");
+
+    let output = run_input("for ((i = 0; ; i++)); do if test $i -ge 2; then break; fi; echo $i; done", &mut shell);
+    assert_eq!(output, "0\n1\n");
+}
+
+#[test]
+fn test_break_and_continue_nested_loops() {
+    let mut shell = Shell::new();
+
+    let output = run_input("for i in 1 2 3; do for j in a b; do if test $j = b; then continue 2; fi; echo $i$j; done; echo never; done", &mut shell);
+    assert_eq!(output, "1a\n2a\n3a\n");
+
+    let output = run_input("for i in 1 2; do while true; do break 2; done; echo never; done; echo end", &mut shell);
+    assert_eq!(output, "end\n");
+    assert_eq!(shell.loop_depth, 0);
+}
+
+/// Tests that a loop is redirected and piped as a whole
+#[test]
+fn test_loop_redirection_and_pipe() {
+    let mut temp_files = TempFiles::new();
+    let file_path = temp_files.add_unique("loop_output");
+
+    let mut shell = Shell::new();
+    run_input(&format!("for x in b a; do echo $x; done > {}", file_path.display()), &mut shell);
+    assert_eq!(fs::read_to_string(&file_path).unwrap(), "b\na\n");
+
+    let output = run_input(&format!("for x in 1 2; do cat; done < {} | sort", file_path.display()), &mut shell);
+    assert_eq!(output, "a\nb\n");
+
+    let output = run_input("echo piped | for x in 1; do cat; echo $x; done", &mut shell);
+    assert_eq!(output, "piped\n1\n");
+}
+
+#[test]
+fn test_case_dispatch_and_fallthrough() {
+    let mut shell = Shell::new();
+    let script = "for arg in start stop other '*'; do case $arg in start|stop) echo run $arg;; '*') echo star;; *) echo unknown $arg;; esac; done";
+    assert_eq!(run_input(script, &mut shell), "run start\nrun stop\nunknown other\nstar\n");
+
+    let output = run_input("case abc in a*) echo a;& x) echo x;;& *c) echo c;; *) echo never;; esac", &mut shell);
+    assert_eq!(output, "a\nx\nc\n");
+
+    let output = run_input("case file.rs in [a-f]*.[!t]?) echo matched;; esac", &mut shell);
+    assert_eq!(output, "matched\n");
+}
+
+#[test]
+fn test_filename_expansion() {
+    let directory = std::env::temp_dir().join(format!("rust_shell_expansion_{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    for name in ["b.txt", "a.txt", "c.log"] {
+        fs::write(directory.join(name), "").unwrap();
+    }
+
+    let mut shell = Shell::new();
+    shell.set_variable("DIR", &directory.to_string_lossy());
+
+    let output = run_input("for f in $DIR/*.txt \"$DIR\"/'*.log' $DIR/*.none; do basename \"$f\"; done", &mut shell);
+    assert_eq!(output, "a.txt\nb.txt\n*.log\n*.none\n");
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn test_functions_with_arguments_and_return() {
+    let mut shell = Shell::new();
+    shell.positional_parameters = vec!["outer".to_string()];
+
+    run_input("greet() { echo \"hello $1 ($#)\"; }\nfunction fail { return 3; echo never; }", &mut shell);
+    assert_eq!(run_input("greet world 'and you'; echo $1", &mut shell), "hello world (2)\nouter\n");
+    assert_eq!(run_input("fail; echo $?", &mut shell), "3\n");
+
+    // Recursion, and a return exiting a loop of the function
+    run_input("grow() { if test $1 = xxx; then return; fi; echo $1; grow x$1; }", &mut shell);
+    run_input("first() { for word in \"$@\"; do return 0; done; echo never; }", &mut shell);
+    assert_eq!(run_input("grow x; first a b && echo ok", &mut shell), "x\nxx\nok\n");
+}
+
+#[test]
+fn test_local_variables_are_dynamically_scoped() {
+    let mut shell = Shell::new();
+    shell.set_variable("x", "global");
+
+    run_input("show() { echo $x; }; outer() { local x=inner y; y=set; show; }", &mut shell);
+    assert_eq!(run_input("outer; show; echo \"<$y>\"", &mut shell), "inner\nglobal\n<>\n");
+
+    // The exported variables and the arrays hidden by a local variable are restored
+    let output = run_input("f(){ local X; }; export X=1; f; env | grep '^X='", &mut shell);
+    assert_eq!(output, "X=1\n");
+    run_input("a=(1 2); g() { local a=3; echo $a; }", &mut shell);
+    assert_eq!(run_input("g; echo ${a[@]}", &mut shell), "3\n1 2\n");
+}
+
+/// Tests that functions can be piped and redirected like any other command
+#[test]
+fn test_function_pipe_and_redirection() {
+    let mut temp_files = TempFiles::new();
+    let file_path = temp_files.add_unique("function_output");
+
+    let mut shell = Shell::new();
+    run_input(&format!("log() {{ echo $1; }} >> {}\nletters() {{ echo b; echo a; }}", file_path.display()), &mut shell);
+
+    assert_eq!(run_input("log one; log two", &mut shell), "");
+    assert_eq!(fs::read_to_string(&file_path).unwrap(), "one\ntwo\n");
+
+    assert_eq!(run_input("letters | sort", &mut shell), "a\nb\n");
+    run_input("reader() { cat; echo end; }", &mut shell);
+    assert_eq!(run_input("echo piped | reader", &mut shell), "piped\nend\n");
+}
+
+#[test]
+fn test_subshell_changes_do_not_leak() {
+    let mut shell = Shell::new();
+    shell.set_variable("x", "before");
+
+    let output = run_input("(for x in after; do cd /; done; pwd; echo $x); echo $x; (false); echo $?", &mut shell);
+    assert_eq!(output, "/\nafter\nbefore\n1\n");
+    assert_ne!(std::env::current_dir().unwrap().to_string_lossy(), "/");
+
+    assert_eq!(run_input("(echo b; echo a) | sort", &mut shell), "a\nb\n");
+}
+
+/// Tests that `exit` leaves the subshell with its argument modulo 256, the last status by default
+#[test]
+fn test_exit_status() {
+    let mut shell = Shell::new();
+
+    let output = run_input("(exit 3); echo $?; (false; exit); echo $?; (exit 258); echo $?; (exit x) 2>/dev/null; echo $?", &mut shell);
+    assert_eq!(output, "3\n1\n2\n2\n");
+
+    assert_eq!(run_input("(exit 1 2; echo still) 2>/dev/null", &mut shell), "still\n");
+}
+
+/// Tests that the errors of the commands are written on their redirected stderr, not on the shell stdout or stderr
+#[test]
+fn test_errors_are_redirected() {
+    let mut temp_files = TempFiles::new();
+    let error_path = temp_files.add_unique("errors");
+
+    let mut shell = Shell::new();
+    let input = format!("{{ nosuch_rust_shell_cmd | wc -l; echo $((1 / 0)); }} 2>{}; echo $?", error_path.display());
+    assert_eq!(run_input(&input, &mut shell).replace(' ', ""), "0\n1\n");
+
+    let errors = fs::read_to_string(&error_path).unwrap();
+    assert_eq!(errors.lines().count(), 2, "{errors}");
+    assert!(errors.ends_with("division by 0\n"), "{errors}");
+}
+
+/// Tests that a group runs in the current shell and is redirected as a whole
+#[test]
+fn test_group_redirection() {
+    let mut temp_files = TempFiles::new();
+    let file_path = temp_files.add_unique("group_output");
+
+    let mut shell = Shell::new();
+    run_input(&format!("{{ echo a; for x in set; do echo b; done; }} > {}", file_path.display()), &mut shell);
+    assert_eq!(fs::read_to_string(&file_path).unwrap(), "a\nb\n");
+    assert_eq!(shell.get_variable("x"), Some("set"));
+
+    assert_eq!(run_input("{ echo 2; echo 1; } | sort", &mut shell), "1\n2\n");
+}
+
+#[test]
+fn test_arithmetic_expansion_and_command() {
+    let mut shell = Shell::new();
+    shell.positional_parameters = vec!["4".to_string()];
+
+    let output = run_input("(( n = 0 )); while (( n < $1 )); do (( n += 2 )); echo $((n * n)); done", &mut shell);
+    assert_eq!(output, "4\n16\n");
+
+    let output = run_input("(( 0 )) || echo zero; (( 1 - 1 + 2 )) && echo nonzero; for ((i = $1; i > 2; i--)); do echo $i; done", &mut shell);
+    assert_eq!(output, "zero\nnonzero\n4\n3\n");
+}
+
+#[test]
+fn test_test_and_bracket_builtins() {
+    let mut shell = Shell::new();
+    let output = run_input("test -d / && echo dir; [ 2 -lt 10 -a abc != abd ] && echo lt; [ -z \"\" ] || echo never; test ! -e /nonexistent_rust_shell_file && echo missing", &mut shell);
+    assert_eq!(output, "dir\nlt\nmissing\n");
+
+    assert_eq!(run_input("[ 1 = 1; echo $?", &mut shell), "2\n");
+}
+
+/// Tests that the malformed conditions are reported on the redirected stderr of the command, with the status 2
+#[test]
+fn test_condition_errors_are_redirected() {
+    let mut temp_files = TempFiles::new();
+    let test_errors = temp_files.add_unique("test_errors");
+    let conditional_errors = temp_files.add_unique("conditional_errors");
 
-//TODO Tests that cat < somefile.txt correctly writes the file content on iocontext's stdout 
-//TODO test ">>" on non existing file
-//TODO test chained pipe and redirections
\ No newline at end of file
+    let mut shell = Shell::new();
+    let input = format!("[ 1 -eq x ] 2>{}; echo $?; re='('; [[ a =~ $re ]] 2>{}; echo $?", test_errors.display(), conditional_errors.display());
+    assert_eq!(run_input(&input, &mut shell), "2\n2\n");
+    assert_eq!(fs::read_to_string(&test_errors).unwrap(), "test: x: integer expression expected\n");
+    assert_eq!(fs::read_to_string(&conditional_errors).unwrap(), "(: invalid regular expression\n");
+}
+
+#[test]
+fn test_double_bracket_conditions() {
+    let mut shell = Shell::new();
+    shell.set_variable("spaced", "a b");
+    shell.set_variable("file", "report-2024.txt");
+
+    // No word splitting, and glob matching on the right of ==
+    let output = run_input("[[ $spaced == 'a b' && $file == *.txt ]] && echo match; [[ $file == '*.txt' ]] || echo literal", &mut shell);
+    assert_eq!(output, "match\nliteral\n");
+
+    let output = run_input("if [[ $file =~ ^([a-z]+)-([0-9]+)\\.txt$ ]]; then echo ${BASH_REMATCH[1]} ${BASH_REMATCH[2]} ${#BASH_REMATCH[@]}; fi", &mut shell);
+    assert_eq!(output, "report 2024 3\n");
+
+    let output = run_input("[[ -z $unset || ( 1+1 -eq 2 && ! b < a ) ]] && echo ok", &mut shell);
+    assert_eq!(output, "ok\n");
+}
+
+#[test]
+fn test_echo_printf_and_status_builtins() {
+    let mut shell = Shell::new();
+
+    let output = run_input("echo -n a; echo -e '\\tb'; printf '%s-%03d\\n' x 7 y 8; true && : && echo ok; false || echo failed", &mut shell);
+    assert_eq!(output, "a\tb\nx-007\ny-008\nok\nfailed\n");
+
+    run_input("printf -v line '%5.1f|%q' 3.14159 'a b'", &mut shell);
+    assert_eq!(shell.get_variable("line"), Some("  3.1|a\\ b"));
+
+    assert_eq!(run_input("printf '%d\\n' 12 abc; echo $?", &mut shell), "12\n0\n1\n");
+}
+
+#[test]
+fn test_read_builtin() {
+    let mut shell = Shell::new();
+
+    let output = run_input("printf ' a  b c \\n' | { read x y; echo \"[$x][$y]\"; }", &mut shell);
+    assert_eq!(output, "[a][b c]\n");
+
+    // Each read consumes one line, the rest staying in the input
+    let output = run_input("printf 'one\\ntwo\\nlast' | { read first; read -r second; read third; echo $? $first $second $third; }", &mut shell);
+    assert_eq!(output, "1 one two last\n");
+
+    let output = run_input("printf 'a\\\\ b c\\n' | { read x y; read -r -n 2 z; echo \"$x|$y\"; }", &mut shell);
+    assert_eq!(output, "a b|c\n");
+
+    let output = run_input("printf 'x:y:z' | { read -d : -a parts; read -a rest; echo ${parts[0]} ${#rest[@]} ${rest[1]}; }", &mut shell);
+    assert_eq!(output, "x 1\n");
+
+    let output = run_input("printf 'abcdef' | { read -n 3 REPLY; read; echo $REPLY; }", &mut shell);
+    assert_eq!(output, "def\n");
+
+    run_input("read -r first < Cargo.toml", &mut shell);
+    assert_eq!(shell.get_variable("first"), Some("[package]"));
+}
+
+#[test]
+fn test_assignments_and_environment() {
+    let mut shell = Shell::new();
+
+    let output = run_input("a=1 b=\"$a 2\"; echo $b; x=out; x=in sh -c 'echo $x'; echo $x", &mut shell);
+    assert_eq!(output, "1 2\nin\nout\n");
+
+    // Only the exported variables and the prefix assignments reach the programs
+    let output = run_input("export x; sh -c 'echo $x'; y=1 x=2 sh -c 'echo $x$y'; export -n x; sh -c 'echo [$x]'", &mut shell);
+    assert_eq!(output, "out\n21\n[]\n");
+    assert_eq!(shell.get_variable("y"), None);
+
+    // A prefix assignment is visible in a function for its duration only
+    let output = run_input("f() { echo $v; }; v=local f; echo \"[$v]\"", &mut shell);
+    assert_eq!(output, "local\n[]\n");
+
+    let output = run_input("printf 'a:b\\n' | { IFS=: read p q; echo $q \"$IFS\"; }", &mut shell);
+    assert_eq!(output, "b \n");
+
+    // The elements of an array assignment are expanded like the arguments of a command
+    let output = run_input("v=x; arr=(\"$v y\" $(echo a b)\n '') ; echo ${#arr[@]} ${arr[0]}", &mut shell);
+    assert_eq!(output, "4 x y\n");
+    assert_eq!(shell.get_array("arr"), Some(vec!["x y".to_string(), "a".to_string(), "b".to_string(), String::new()]));
+}
+
+#[test]
+fn test_unset_readonly_and_set() {
+    let mut shell = Shell::new();
+
+    let output = run_input("readonly r=1; r=2; echo $r; unset r; echo $r; readonly", &mut shell);
+    assert_eq!(output, "1\n1\nreadonly r=1\n");
+
+    // The arithmetic assignments, the variable of a loop and `printf -v` fail on a read-only variable
+    let output = run_input("(( r = 5 )) || echo $?; (( r++ )) || echo $?; for r in x; do echo loop; done || echo $?; printf -v r x || echo $?; echo $r", &mut shell);
+    assert_eq!(output, "1\n1\n1\n1\n1\n");
+
+    let output = run_input("v=1; f() { :; }; unset v f; echo [$v]; f || echo gone; unset -f f", &mut shell);
+    assert_eq!(output, "[]\ngone\n");
+
+    let output = run_input("set -- a 'b c'; echo $# $2; set x; echo $# $1; set --; echo $#", &mut shell);
+    assert_eq!(output, "2 b c\n1 x\n0\n");
+
+    let output = run_input("z='a b'; set | grep ^z=", &mut shell);
+    assert_eq!(output, "z=a\\ b\n");
+}
+
+#[test]
+fn test_cd_and_pwd() {
+    let root = std::env::temp_dir().join(format!("rust_shell_cd_{}", std::process::id()));
+    fs::create_dir_all(root.join("real/sub")).unwrap();
+    std::os::unix::fs::symlink(root.join("real"), root.join("link")).unwrap();
+    let root = root.canonicalize().unwrap();
+    let path = root.to_str().unwrap();
+
+    // The directory changes are made in a subshell, not to change the directory of the other tests
+    let mut shell = Shell::new();
+    let input = format!("( cd {path}/link/sub; pwd; pwd -P; cd ..; pwd; cd -; cd -P ..; pwd; HOME={path} cd; pwd; CDPATH=/none:{path}/real cd sub; echo $OLDPWD )");
+    let output = run_input(&input, &mut shell);
+    fs::remove_dir_all(&root).unwrap();
+
+    let expected = ["link/sub", "real/sub", "link", "link/sub", "real", "", "real/sub", ""]
+        .map(|suffix| format!("{path}/{suffix}").trim_end_matches('/').to_string())
+        .join("\n");
+    assert_eq!(output, expected + "\n");
+}
+
+/// Tests that the errors of cd and of the directory stack are written on the redirected stderr, like bash writes them
+#[test]
+fn test_directory_errors_are_redirected() {
+    let mut temp_files = TempFiles::new();
+    let error_path = temp_files.add_unique("directory_errors");
+
+    let mut shell = Shell::new();
+    let output = run_input(&format!("{{ cd /nonexistent_rust_shell_dir; echo $?; popd; }} 2>{}", error_path.display()), &mut shell);
+    assert_eq!(output, "1\n");
+    assert_eq!(fs::read_to_string(&error_path).unwrap(), "cd: /nonexistent_rust_shell_dir: No such file or directory\npopd: directory stack empty\n");
+}
+
+#[test]
+fn test_directory_stack() {
+    let root = std::env::temp_dir().join(format!("rust_shell_dirs_{}", std::process::id()));
+    fs::create_dir_all(root.join("a")).unwrap();
+    fs::create_dir_all(root.join("b")).unwrap();
+    let root = root.canonicalize().unwrap();
+    let path = root.to_str().unwrap();
+
+    let mut shell = Shell::new();
+    let input = format!("( HOME=/none; cd {path}; pushd a; pushd {path}/b; dirs -v; echo ~1 ~-0; pushd +2; pushd; popd; popd +1; pwd )");
+    let output = run_input(&input, &mut shell);
+    fs::remove_dir_all(&root).unwrap();
+
+    let expected = [
+        "R/a R", "R/b R/a R", " 0  R/b", " 1  R/a", " 2  R", "R/a R", "R R/b R/a", "R/b R R/a", "R R/a", "R", "R",
+    ].map(|line| line.replace('R', path)).join("\n");
+    assert_eq!(output, expected + "\n");
+}
+
+#[test]
+fn test_aliases() {
+    let mut shell = Shell::new();
+
+    // An alias is replaced from the next input
+    run_input("alias say='echo said' quiet='say ' words='a b'", &mut shell);
+    let output = run_input("say hello; quiet words; echo say; alias say; if say x; then say y | cat; fi", &mut shell);
+    assert_eq!(output, "said hello\nsaid a b\nsay\nalias say='echo said'\nsaid x\nsaid y\n");
+
+    let output = run_input("alias echo='echo again'; unalias quiet; alias", &mut shell);
+    assert_eq!(output, "alias echo='echo again'\nalias say='echo said'\nalias words='a b'\n");
+
+    // An alias is not replaced in its own value
+    let output = run_input("echo; unalias -a; alias", &mut shell);
+    assert_eq!(output, "again\n");
+}
+
+#[test]
+fn test_source_builtin() {
+    let mut temp_files = TempFiles::new();
+    let script = temp_files.add_unique("sourced");
+    fs::write(&script, "echo \"args: $# $1\"\nsourced=yes\nalias hi='echo hi'\nif [ \"$1\" = stop ]; then\n  return 5\nfi\necho end\n").unwrap();
+    let path = script.to_str().unwrap();
+
+    let mut shell = Shell::new();
+    let output = run_input(&format!("set -- outer; source {path} stop; echo $? $1 $sourced; . {path}"), &mut shell);
+    assert_eq!(output, "args: 1 stop\n5 outer yes\nargs: 1 outer\nend\n");
+
+    // The aliases defined by the file are used in the next inputs
+    assert_eq!(run_input("hi", &mut shell), "hi\n");
+}
+
+#[test]
+fn test_command_substitution() {
+    let mut shell = Shell::new();
+
+    let output = run_input("x=$(echo a; false); echo $? $x; echo \"$(echo \"$(echo nested)\")\" `echo back`; y=$(printf 'l1\\n\\n'); echo \"[$y]\"", &mut shell);
+    assert_eq!(output, "1 a\nnested back\n[l1]\n");
+
+    // The output is split into fields unless it is quoted
+    let output = run_input("for word in $(echo a b); do echo $word; done; echo \"$(printf 'a   b')\"", &mut shell);
+    assert_eq!(output, "a\nb\na   b\n");
+}
+
+#[test]
+fn test_shell_options() {
+    let mut shell = Shell::new();
+
+    let output = run_input("set -x a b; set -o | grep xtrace; set +o; set +o xtrace; set -o; echo $#", &mut shell);
+    assert_eq!(output, "xtrace         \ton\nset -o xtrace\nset +o histexpand\nset +o emacs\nset +o vi\nxtrace         \toff\nhistexpand     \toff\nemacs          \toff\nvi             \toff\n2\n");
+    assert!(!shell.is_option_set("xtrace"));
+}
+
+#[test]
+fn test_complete_and_compgen() {
+    let mut shell = Shell::new();
+
+    let output = run_input("compgen -W 'alpha beta \"also this\"' al; compgen -A function -k -X '!d*' d; compgen -W x y; echo $?", &mut shell);
+    assert_eq!(output, "alpha\nalso this\ndo\ndone\n1\n");
+
+    let output = run_input("_words() { COMPREPLY=(\"$1:$2\" ${COMP_WORDS[0]}); }; compgen -F _words -P '<' cur", &mut shell);
+    assert_eq!(output, "<:cur\n<cur\n");
+
+    let output = run_input("complete -o nospace -F _words tool; complete -W 'a b' other; complete -p; complete -r tool; complete -p tool; echo $?", &mut shell);
+    assert_eq!(output, "complete -W 'a b' other\ncomplete -o nospace -F _words tool\n1\n");
+    assert!(shell.completion_spec("other").is_some());
+
+    let output = run_input("complete -r; complete; compgen -v COMP_; echo $?", &mut shell);
+    assert_eq!(output, "1\n");
+
+    // No job runs in the background to be completed
+    let output = run_input("compgen -j; echo $?; compgen -A job -W jobs j", &mut shell);
+    assert_eq!(output, "1\njobs\n");
+}
+
+#[test]
+fn test_history_builtin() {
+    let mut shell = Shell::new();
+    for command in ["ls", "echo a\necho b", "pwd"] {
+        shell.history.lock().add(HistoryEntry { command: command.to_string(), timestamp: 86400, ..HistoryEntry::default() }, &HistoryPolicy::default()).unwrap();
+    }
+
+    let output = run_input("history; history 1; HISTTIMEFORMAT='%Y ' history 1", &mut shell);
+    assert_eq!(output, "    1  ls\n    2  echo a\necho b\n    3  pwd\n    3  pwd\n    3  1970 pwd\n");
+
+    let mut temp_files = TempFiles::new();
+    let path = temp_files.add_unique("history");
+    let output = run_input(&format!("history -d -1; history -d 1; history -w {0}; history -c; history; history -r {0}; history", path.display()), &mut shell);
+    assert_eq!(output, "    1  echo a\necho b\n");
+}
+
+#[test]
+fn test_bind_builtin() {
+    let mut shell = Shell::new();
+
+    let output = run_input(r#"bind '"\C-a": end-of-line' 'Meta-g: "git "'; bind -x '"\C-g": git status'; bind -p; bind -X; bind -l | grep -c line"#, &mut shell);
+    assert_eq!(output, "\"\\C-a\": end-of-line\n\"\\eg\": \"git \"\n\"\\C-g\": \"git status\"\n7\n");
+
+    // A new binding of the same keys replaces the previous one
+    let output = run_input(r#"bind '"\C-a": beginning-of-line'; bind -r '\eg'; bind -p; bind '"\C-b": nothing' '"ab": undo'; echo $?; bind -r '\eg'; echo $?"#, &mut shell);
+    assert_eq!(output, "\"\\C-a\": beginning-of-line\n1\n1\n");
+    assert_eq!(shell.key_bindings().len(), 2);
+
+    run_input("set -o emacs; set -o vi", &mut shell);
+    assert!(shell.is_option_set("vi") && !shell.is_option_set("emacs"));
+    run_input("bind 'set editing-mode emacs'", &mut shell);
+    assert!(!shell.is_option_set("vi") && shell.is_option_set("emacs"));
+}
+
+#[test]
+fn test_config_builtin() {
+    let root = std::env::temp_dir().join(format!("rust_shell_config_{}", std::process::id()));
+    fs::create_dir_all(root.join("rust_shell")).unwrap();
+    let path = root.to_str().unwrap();
+    fs::write(root.join("rust_shell/config.toml"), "banner = false\n[prompt]\nps1 = '> '\nps2 = '... '\n[colors]\nkeyword = 'red'\n[editor]\nmode = 'vi'\n[key_bindings]\n'\\C-g' = { command = 'git status' }\n").unwrap();
+    fs::write(root.join("invalid.toml"), "[history]\nsize = 'large'\n").unwrap();
+
+    let mut shell = Shell::new();
+    shell.set_variable("XDG_CONFIG_HOME", path);
+    let output = run_input(&format!("config path; config reload; echo \"$PS1\"; bind -X; config check {path}/invalid.toml || echo $?"), &mut shell);
+    assert_eq!(output, format!("{path}/rust_shell/config.toml\n> \n\"\\C-g\": \"git status\"\n1\n"));
+    assert!(!shell.config.banner && shell.is_option_set("vi") && !shell.is_option_set("emacs"));
+
+    // The settings removed from the file get their default value when it is reloaded, unless they were changed since,
+    // and the bindings of the previous configuration are removed
+    fs::write(root.join("rust_shell/config.toml"), "[features]\nsuggestions = false\n").unwrap();
+    run_input("PS2='+ '; config reload", &mut shell);
+    assert!(shell.key_bindings().is_empty() && !shell.config.features.suggestions && shell.config.banner);
+    assert_eq!(shell.config.colors, Colors::default());
+    assert_eq!((shell.get_variable("PS1"), shell.get_variable("PS2")), (Some(DEFAULT_PS1), Some("+ ")));
+    assert!(shell.is_option_set("emacs") && !shell.is_option_set("vi"));
+
+    fs::write(root.join("rust_shell/config.toml"), "").unwrap();
+    run_input("config reload", &mut shell);
+    fs::remove_dir_all(&root).unwrap();
+    assert!(shell.config.features.suggestions);
+}
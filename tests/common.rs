@@ -1,12 +1,15 @@
 //!
 //! Utils for testing commands execution
-//! 
+//!
 
-/*use rust_shell::command::{IoContext};
+use rust_shell::command::{IoContext};
+use rust_shell::parsing::convert_to_command_with_aliases;
+use rust_shell::shell::Shell;
 
 use std::fs;
+use std::os::fd::OwnedFd;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::fs::{OpenOptions};
 
 // Stores the paths of the temporary test files
@@ -23,9 +26,23 @@ impl TempFiles {
         self.paths.push(path.clone());
         path
     }
+
+    /// Adds a path in the temporary folder, unique for this test process
+    pub fn add_unique(&mut self, name: &str) -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+
+        self.add(std::env::temp_dir().join(format!("rust_shell_{name}_{}_{id}.txt", std::process::id())))
+    }
 }
 
-// Automatic test files clean 
+impl Default for TempFiles {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Automatic test files clean
 impl Drop for TempFiles {
     fn drop(&mut self) {
         for path in &self.paths {
@@ -35,21 +52,31 @@ impl Drop for TempFiles {
 }
 
 /// Creates files that acts as stdout, stdin, stderr
-pub fn create_test_io_context(temp_files: &mut TempFiles) -> IoContext {
-    let pid = std::process::id();
-    
-    let stdin_path = temp_files.add(
-        std::env::temp_dir().join(format!("test_stdin_{}.txt", pid))
-    );
-    let stdout_path = temp_files.add(
-        std::env::temp_dir().join(format!("test_stdout_{}.txt", pid))
-    );
-    let stderr_path = temp_files.add(
-        std::env::temp_dir().join(format!("test_stderr_{}.txt", pid))
-    );
-
-    let stdin = Arc::new(OpenOptions::new().read(true).write(true).create(true).open(&stdin_path).unwrap());
-    let stdout = Arc::new(OpenOptions::new().write(true).create(true).truncate(true).open(&stdout_path).unwrap());
-    let stderr = Arc::new(OpenOptions::new().write(true).create(true).truncate(true).open(&stderr_path).unwrap());
-    IoContext { stdin, stdout, stderr }
-}*/
\ No newline at end of file
+/// Returns the io context and the path of the stdout file
+pub fn create_test_io_context(temp_files: &mut TempFiles) -> (IoContext, PathBuf) {
+
+    let stdin_path = temp_files.add_unique("stdin");
+    let stdout_path = temp_files.add_unique("stdout");
+    let stderr_path = temp_files.add_unique("stderr");
+
+    let stdin = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&stdin_path).unwrap();
+    let stdout = OpenOptions::new().write(true).create(true).truncate(true).open(&stdout_path).unwrap();
+    let stderr = OpenOptions::new().write(true).create(true).truncate(true).open(&stderr_path).unwrap();
+
+    let io_context = IoContext {
+        stdin: Some(OwnedFd::from(stdin)),
+        stdout: Some(OwnedFd::from(stdout)),
+        stderr: Some(OwnedFd::from(stderr))
+    };
+    (io_context, stdout_path)
+}
+
+/// Parses (with the aliases of the shell) and executes the input, returns what the command wrote on stdout
+pub fn run_input(input: &str, shell: &mut Shell) -> String {
+    let mut temp_files = TempFiles::new();
+    let (io_context, stdout_path) = create_test_io_context(&mut temp_files);
+
+    convert_to_command_with_aliases(input, shell.aliases()).unwrap().execute(shell, io_context).unwrap();
+
+    fs::read_to_string(stdout_path).unwrap()
+}